@@ -0,0 +1,52 @@
+//! Renders of the Cornell box with every integrator, the same scene the
+//! global illumination is checked with
+//!
+//! `cargo bench --bench cornell`
+
+extern crate ray_ruster;
+
+use std::time::{Duration, Instant};
+
+use ray_ruster::render::config::Integrator;
+use ray_ruster::render::ray_tracer;
+use ray_ruster::render::scene::Scene;
+use ray_ruster::render::shared::PreparedScene;
+
+const SIZE: u32 = 128;
+const SAMPLES: u32 = 4;
+
+fn main() {
+    let mut scene = Scene::cornell_box();
+    scene.camera.width = SIZE;
+    scene.camera.height = SIZE;
+    scene.rendering.samples_per_pixel = SAMPLES;
+    let prepared = PreparedScene::new(scene).unwrap();
+    println!(
+        "{} triangles, {}x{} pixels, {} samples per pixel",
+        prepared.triangle_count(),
+        SIZE,
+        SIZE,
+        SAMPLES
+    );
+
+    let samples = (SIZE * SIZE * SAMPLES) as f64;
+    for &integrator in Integrator::ALL.iter() {
+        let mut rendering = prepared.scene.rendering.clone();
+        rendering.integrator = integrator;
+        // Fastest of 3 runs
+        let elapsed = (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                ray_tracer::render(&prepared, &prepared.scene.camera, &rendering);
+                start.elapsed()
+            })
+            .min()
+            .unwrap_or(Duration::ZERO);
+        println!(
+            "  {:<18} {:>10.2?} {:>8.2} Msamples/s",
+            integrator.name(),
+            elapsed,
+            samples / elapsed.as_secs_f64() / 1e6
+        );
+    }
+}
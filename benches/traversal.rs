@@ -0,0 +1,140 @@
+//! Closest hits of camera rays on a large terrain, through the kd-tree
+//! traversal and the quantized hierarchies of the same trees
+//!
+//! `cargo bench --bench traversal`
+
+extern crate image;
+extern crate ray_ruster;
+
+use std::mem;
+use std::time::{Duration, Instant};
+
+use ray_ruster::geometry::bvh::{TreeBuild, TreePreset};
+use ray_ruster::geometry::kdtree::{iter_intersect_ray, KdTree};
+use ray_ruster::geometry::mesh::Mesh;
+use ray_ruster::geometry::qbvh::{QuantizedBvh, Quantum};
+use ray_ruster::geometry::ray::Ray;
+use ray_ruster::geometry::terrain::HeightMap;
+use ray_ruster::geometry::types::{Direction, Position};
+use ray_ruster::render::config::CameraConfig;
+use ray_ruster::render::image::primary_ray;
+
+/// Pixels of the height map, about 2 triangles each
+const TERRAIN_SIZE: u32 = 512;
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// Closest hit of `ray` among `triangles`, in units of its direction
+fn closest<I>(mesh: &Mesh, ray: &Ray, triangles: I) -> Option<(f64, usize)>
+where
+    I: IntoIterator<Item = usize>,
+{
+    triangles
+        .into_iter()
+        .filter_map(|t| {
+            let [a, b, c] = mesh.triangles[t].map(|v| mesh.vertices[v]);
+            let (point, _) = ray.intersect_triangle(&a, &b, &c)?;
+            Some(((point - ray.position).norm() / ray.direction.norm(), t))
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+}
+
+fn camera_rays(camera_config: &CameraConfig) -> Vec<Ray> {
+    (0..camera_config.height)
+        .flat_map(|j| (0..camera_config.width).map(move |i| (i as f64, j as f64)))
+        .map(|(i, j)| primary_ray(i, j, camera_config))
+        .collect()
+}
+
+/// Time of the fastest of 3 runs of `f` over all the rays, and its hits
+fn time<F>(rays: &[Ray], f: F) -> (Duration, usize)
+where
+    F: Fn(&Ray) -> Option<(f64, usize)>,
+{
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            let hits = rays.iter().filter(|ray| f(ray).is_some()).count();
+            (start.elapsed(), hits)
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, rays: usize, (elapsed, hits): (Duration, usize), baseline: Duration) {
+    println!(
+        "  {:<12} {:>10.2?} {:>8.2} Mrays/s {:>6.2}x  ({} hits)",
+        name,
+        elapsed,
+        rays as f64 / elapsed.as_secs_f64() / 1e6,
+        baseline.as_secs_f64() / elapsed.as_secs_f64(),
+        hits
+    );
+}
+
+fn quantized<Q: Quantum>(mesh: &Mesh, tree: &KdTree, rays: &[Ray], baseline: Duration) {
+    let bvh = QuantizedBvh::<Q>::from_tree(tree);
+    let timing = time(rays, |ray| {
+        bvh.closest_hit(ray, |triangles| {
+            closest(mesh, ray, triangles.iter().map(|&t| t as usize))
+        })
+    });
+    let name = format!("{}-bit", 8 * mem::size_of::<Q>());
+    report(&name, rays.len(), timing, baseline);
+    println!(
+        "  {:<12} {} nodes of {} bytes, {:.1} MiB",
+        "",
+        bvh.node_count(),
+        QuantizedBvh::<Q>::node_size(),
+        bvh.bytes() as f64 / (1 << 20) as f64
+    );
+}
+
+fn main() {
+    let heights = HeightMap::from_fn(TERRAIN_SIZE, TERRAIN_SIZE, |x, y| {
+        let (x, y) = (x as f64 / 37.0, y as f64 / 23.0);
+        let height = 0.5 + 0.25 * (x.sin() * y.cos() + (2.3 * x + y).sin());
+        image::Luma([(height.clamp(0.0, 1.0) * u16::MAX as f64) as u16])
+    });
+    let mesh = Mesh::terrain(&heights, 1.0, 60.0).unwrap();
+    let camera_config = CameraConfig::look_at(
+        Position::new(-300.0, 200.0, 300.0),
+        Position::new(0.0, 0.0, 0.0),
+        Direction::y(),
+        50.0,
+        WIDTH,
+        HEIGHT,
+    );
+    let rays = camera_rays(&camera_config);
+    println!("{} triangles, {} rays", mesh.triangles.len(), rays.len());
+
+    for &build in TreeBuild::ALL.iter() {
+        let tree = KdTree::build(&mesh, build, TreePreset::Balanced);
+        let stats = tree.stats();
+        println!(
+            "{}: {} nodes, {:.1} MiB",
+            build,
+            stats.nodes,
+            stats.bytes as f64 / (1 << 20) as f64
+        );
+        // Front to back, as the kd-tree tracer of the renderer
+        let timing = time(&rays, |ray| {
+            let mut closest_hit: Option<(f64, usize)> = None;
+            for leaf in iter_intersect_ray(&tree, ray).leaves() {
+                if closest_hit.is_some_and(|(t, _)| leaf.distance > t) {
+                    break;
+                }
+                let triangles = leaf.node.triangle_index.as_ref().unwrap();
+                if let Some(hit) = closest(&mesh, ray, triangles.iter().copied()) {
+                    if closest_hit.is_none_or(|(t, _)| hit.0 < t) {
+                        closest_hit = Some(hit);
+                    }
+                }
+            }
+            closest_hit
+        });
+        report("kd-tree", rays.len(), timing, timing.0);
+        quantized::<u8>(&mesh, &tree, &rays, timing.0);
+        quantized::<u16>(&mesh, &tree, &rays, timing.0);
+    }
+}
@@ -0,0 +1,126 @@
+//! Inside the trees of a terrain: their statistics for every build, a heat
+//! map of the nodes the camera rays visit, and the triangles of the nodes
+//! along the central ray as OBJ files to open in other tools
+//!
+//! `cargo run --example kdtree_debug --release -- --output <dir>`
+
+extern crate image;
+extern crate ray_ruster;
+
+use std::env;
+use std::process;
+
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
+use ray_ruster::geometry::bvh::{TreeBuild, TreePreset};
+use ray_ruster::geometry::import::Unit;
+use ray_ruster::geometry::kdtree::{iter_intersect_ray, KdTree, KdTreeLeafIter};
+use ray_ruster::geometry::mesh::Mesh;
+use ray_ruster::geometry::terrain::HeightMap;
+use ray_ruster::geometry::types::Direction;
+use ray_ruster::render::config::{CameraConfig, DebugView, RenderingConfig};
+use ray_ruster::render::image::{primary_ray, render_image};
+use ray_ruster::render::ray_tracer;
+
+/// Pixels of the height map, about 2 triangles each
+const TERRAIN_SIZE: u32 = 128;
+/// Nodes exported along the central ray, from the root
+const EXPORTED_NODES: usize = 12;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+/// Rolling hills, the same for every run
+fn terrain() -> Mesh {
+    let heights = HeightMap::from_fn(TERRAIN_SIZE, TERRAIN_SIZE, |x, y| {
+        let (x, y) = (x as f64 / 11.0, y as f64 / 7.0);
+        let height = 0.5 + 0.25 * (x.sin() * y.cos() + (2.3 * x + y).sin());
+        image::Luma([(height.clamp(0.0, 1.0) * u16::MAX as f64) as u16])
+    });
+    Mesh::terrain(&heights, 1.0, 15.0).expect("the height map is large enough")
+}
+
+/// Triangles of the leaves under `node`, the vertices of `mesh` being kept
+/// whole since this is for debugging
+fn node_mesh(node: &Box<KdTree>, mesh: &Mesh) -> Mesh {
+    let mut triangle_index: Vec<usize> = KdTreeLeafIter::new(node)
+        .flat_map(|leaf| leaf.triangle_index.iter().flatten().cloned())
+        .collect();
+    triangle_index.sort_unstable();
+    triangle_index.dedup();
+    let triangles = triangle_index.iter().map(|&t| mesh.triangles[t]).collect();
+    Mesh::from_vertices_and_triangles(mesh.vertices.clone(), triangles)
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let output = args.path("--output")?.unwrap_or_else(env::temp_dir);
+    args.finish()?;
+
+    let mesh = terrain();
+    println!("{} triangles", mesh.triangles.len());
+    for &build in TreeBuild::ALL.iter() {
+        let stats = KdTree::build(&mesh, build, TreePreset::Balanced).stats();
+        println!(
+            "  {:<8} {} nodes, {} leaves, depth {}, {} triangles per leaf at most, {:.1} KiB",
+            build.name(),
+            stats.nodes,
+            stats.leaves,
+            stats.depth,
+            stats.max_leaf_triangles,
+            stats.bytes as f64 / 1024.0
+        );
+    }
+
+    let kdt = KdTree::from_mesh(&mesh);
+    // The whole terrain, seen from above its -x +z corner
+    let camera_config = CameraConfig::framing(
+        &kdt.bounding_box,
+        Direction::new(1.0, -1.0, -1.0),
+        Direction::y(),
+        50.0,
+        320,
+        240,
+    );
+    let rendering_config = RenderingConfig {
+        debug_view: Some(DebugView::KdTreeNodes { max: 60 }),
+        ..Default::default()
+    };
+    let img = render_image(
+        ray_tracer::make_kdt_ray_tracer(
+            &mesh,
+            &kdt,
+            &camera_config,
+            &rendering_config,
+            Unit::Meters,
+        ),
+        &camera_config,
+        &rendering_config,
+    );
+    let path = output.join("kdtree_nodes.png");
+    cli::save_image(&img, &path)?;
+    println!("{}", path.display());
+
+    let ray = primary_ray(
+        camera_config.width as f64 / 2.0,
+        camera_config.height as f64 / 2.0,
+        &camera_config,
+    );
+    let branch = iter_intersect_ray(&kdt, &ray).closest_branch();
+    for (depth, node) in branch.take(EXPORTED_NODES).enumerate() {
+        let node_mesh = node_mesh(node.node, &mesh);
+        let path = output.join(format!("node_{}.obj", depth));
+        cli::save_mesh(&node_mesh, &path)?;
+        println!(
+            "{}: {} triangles, {:.2} along the ray",
+            path.display(),
+            node_mesh.triangles.len(),
+            node.distance
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! A scene file written by hand in TOML, loaded and rendered, then saved
+//! back as JSON and rendered again: the scene files keep the whole scene
+//!
+//! `cargo run --example scene_file --release -- --output <dir>`
+
+extern crate ray_ruster;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
+use ray_ruster::render::image::{self, compare_images, RgbImage};
+use ray_ruster::render::ray_tracer;
+use ray_ruster::render::scene::Scene;
+
+/// A glass block and a glossy blue one on a gray floor, lit by the sun
+const SCENE: &str = r#"
+version = 1
+
+[camera]
+camera_position = [0, 2, -6]
+fov = 40
+width = 320
+height = 240
+
+[rendering]
+integrator = { kind = "whitted" }
+samples_per_pixel = 4
+
+[[objects]]
+shape = { kind = "box", min = [-4, -0.1, -4], max = [4, 0, 4] }
+material = { color = [0.8, 0.8, 0.8] }
+
+[[objects]]
+shape = { kind = "box", min = [-0.5, 0, -0.5], max = [0.5, 1.2, 0.5] }
+transform = { translation = [-0.9, 0, 0], rotation = [0, 25, 0] }
+material = { model = "glass", ior = 1.5 }
+
+[[objects]]
+shape = { kind = "box", min = [-0.5, 0, -0.5], max = [0.5, 0.8, 0.5] }
+transform = { translation = [0.9, 0, 0.5], rotation = [0, -15, 0] }
+material = { model = "blinn_phong", color = [0.2, 0.4, 0.8], shininess = 64 }
+
+[[lights]]
+kind = "directional"
+direction = [-1, -2, 1]
+illuminance = 3
+"#;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+/// Load the scene file at `path` and render it
+fn render(path: &Path) -> Result<RgbImage, Error> {
+    let scene = Scene::load(path).map_err(|e| Error::Config(path.to_path_buf(), e))?;
+    let hdr = ray_tracer::render_scene(&scene).map_err(|e| Error::Render(e.to_string()))?;
+    Ok(image::to_display(&hdr, &scene.rendering))
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let output = args.path("--output")?.unwrap_or_else(env::temp_dir);
+    args.finish()?;
+
+    let toml_path = output.join("scene_file.toml");
+    fs::write(&toml_path, SCENE)
+        .map_err(|e| Error::Output(format!("could not write {}: {}", toml_path.display(), e)))?;
+    let img = render(&toml_path)?;
+    let path = output.join("scene_file.png");
+    cli::save_image(&img, &path)?;
+    println!("{}", path.display());
+
+    // The shapes are saved as their parameters, without any mesh file
+    let json_path = output.join("scene_file.json");
+    let scene = Scene::load(&toml_path).map_err(|e| Error::Config(toml_path.clone(), e))?;
+    scene
+        .save(&json_path)
+        .map_err(|e| Error::Config(json_path.clone(), e))?;
+    println!("{}", json_path.display());
+    let difference = compare_images(&img, &render(&json_path)?)
+        .ok_or_else(|| Error::Render("the saved scene changed the image size".to_string()))?;
+    if difference.changed_fraction > 0.0 {
+        return Err(Error::Render(format!(
+            "the saved scene renders differently, {:.1}% of the pixels changed",
+            100.0 * difference.changed_fraction
+        )));
+    }
+    println!("same render from {}", json_path.display());
+    Ok(())
+}
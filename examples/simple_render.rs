@@ -0,0 +1,104 @@
+//! A scene built in code, rendered and saved: the shortest way from the
+//! library to an image
+//!
+//! `cargo run --example simple_render --release -- --output <dir>`
+
+extern crate ray_ruster;
+
+use std::env;
+use std::process;
+
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
+use ray_ruster::geometry::types::{Direction, Position};
+use ray_ruster::render::color::Color;
+use ray_ruster::render::config::{
+    CameraConfig, LightConfig, MaterialConfig, RenderingConfig, ShadingModel, ShapeConfig,
+};
+use ray_ruster::render::image;
+use ray_ruster::render::light::LightUnits;
+use ray_ruster::render::ray_tracer;
+use ray_ruster::render::scene::{Scene, Transform};
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+/// A red block and a mirror cube on a gray floor, under a point light
+fn scene() -> Scene {
+    Scene::builder()
+        .add_shape(ShapeConfig::Box {
+            min: Position::new(-3.0, -0.1, -3.0),
+            max: Position::new(3.0, 0.0, 3.0),
+            inside: false,
+        })
+        .material(MaterialConfig {
+            color: Color::gray(0.6),
+            ..Default::default()
+        })
+        .add_shape(ShapeConfig::Box {
+            min: Position::new(-0.5, 0.0, -0.5),
+            max: Position::new(0.5, 1.5, 0.5),
+            inside: false,
+        })
+        .transform(Transform {
+            translation: Direction::new(-0.8, 0.0, 0.0),
+            rotation: [0.0, 30.0, 0.0],
+            scale: 1.0,
+        })
+        .material(MaterialConfig {
+            color: Color::new(0.7, 0.1, 0.1),
+            ..Default::default()
+        })
+        .add_shape(ShapeConfig::Box {
+            min: Position::new(-0.5, 0.0, -0.5),
+            max: Position::new(0.5, 1.0, 0.5),
+            inside: false,
+        })
+        .transform(Transform {
+            translation: Direction::new(0.9, 0.0, 0.3),
+            rotation: [0.0, -20.0, 0.0],
+            scale: 0.8,
+        })
+        .material(MaterialConfig {
+            model: ShadingModel::Mirror,
+            color: Color::gray(0.9),
+            ..Default::default()
+        })
+        .add_light(LightConfig::Point {
+            position: Position::new(2.0, 4.0, -2.0),
+            intensity: 40.0,
+            units: LightUnits::Candela,
+            color: Color::WHITE,
+        })
+        .camera(CameraConfig::look_at(
+            Position::new(0.0, 2.0, -5.0),
+            Position::new(0.0, 0.5, 0.0),
+            Direction::y(),
+            45.0,
+            320,
+            240,
+        ))
+        .rendering(RenderingConfig {
+            samples_per_pixel: 4,
+            ..Default::default()
+        })
+        .build()
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let output = args.path("--output")?.unwrap_or_else(env::temp_dir);
+    args.finish()?;
+
+    let scene = scene();
+    let hdr = ray_tracer::render_scene(&scene).map_err(|e| Error::Render(e.to_string()))?;
+    let img = image::to_display(&hdr, &scene.rendering);
+    let path = output.join("simple_render.png");
+    cli::save_render(&hdr, &img, &path)?;
+    println!("{}", path.display());
+    Ok(())
+}
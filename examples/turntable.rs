@@ -0,0 +1,86 @@
+//! A camera turning around a model built in code, its frames saved as an
+//! image sequence for an encoder
+//!
+//! `cargo run --example turntable --release -- --output <dir> --frames 24`
+//!
+//! `ffmpeg -framerate 12 -i <dir>/turntable_%04d.png turntable.mp4`
+
+extern crate ray_ruster;
+
+use std::env;
+use std::process;
+
+use ray_ruster::cli::Args;
+use ray_ruster::error::Error;
+use ray_ruster::geometry::types::{Direction, Position};
+use ray_ruster::render::animation;
+use ray_ruster::render::color::Color;
+use ray_ruster::render::config::{CameraConfig, MaterialConfig, RenderingConfig, ShapeConfig};
+use ray_ruster::render::scene::Scene;
+
+/// Steps of the pyramid, from the bottom
+const STEPS: usize = 5;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+/// Stepped pyramid, a box per step, from blue at the bottom to orange at the
+/// top
+fn pyramid() -> Scene {
+    let mut builder = Scene::builder();
+    for step in 0..STEPS {
+        let half = (STEPS - step) as f64 / 2.0;
+        let t = step as f64 / (STEPS - 1) as f64;
+        builder = builder
+            .add_shape(ShapeConfig::Box {
+                min: Position::new(-half, 0.5 * step as f64, -half),
+                max: Position::new(half, 0.5 * (step + 1) as f64, half),
+                inside: false,
+            })
+            .material(MaterialConfig {
+                color: Color::new(0.2 + 0.7 * t, 0.3 + 0.2 * t, 0.8 - 0.6 * t),
+                ..Default::default()
+            });
+    }
+    builder.build()
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let output = args.path("--output")?.unwrap_or_else(env::temp_dir);
+    let frames = args.parse("--frames")?.unwrap_or(24);
+    args.finish()?;
+
+    let scene = pyramid();
+    let mesh = scene.to_mesh();
+    // Seen from above the front, lit from the camera as it turns
+    let camera_config = CameraConfig::framing(
+        &scene.bounding_box(),
+        Direction::new(0.0, -1.0, 2.0),
+        Direction::y(),
+        40.0,
+        240,
+        180,
+    );
+    let rendering_config = RenderingConfig {
+        samples_per_pixel: 4,
+        ..Default::default()
+    };
+    let paths = animation::render_turntable(
+        &mesh,
+        Direction::y(),
+        frames,
+        &camera_config,
+        &rendering_config,
+        &output,
+    )
+    .map_err(|e| Error::Output(format!("could not write the frames: {}", e)))?;
+    for path in paths {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+extern crate ray_ruster;
+
+use std::path::PathBuf;
+use std::process;
+
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
+use ray_ruster::render::bake::{self, BakeConfig, BakeTarget};
+
+const USAGE: &str =
+    "usage: bake <model> <texture> [--target world_normal|tangent_normal|curvature] \
+     [--size <texels>] [--padding <texels>] [--uv-set <n>]";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let target: BakeTarget = args.parse("--target")?.unwrap_or(BakeTarget::WorldNormal);
+    let size: Option<u32> = args.parse("--size")?;
+    let padding: Option<u32> = args.parse("--padding")?;
+    let uv_set: Option<usize> = args.parse("--uv-set")?;
+    let (input, output) = match (args.positional(), args.positional()) {
+        (Some(input), Some(output)) => (PathBuf::from(input), PathBuf::from(output)),
+        _ => return Err(Error::Usage(String::from(USAGE))),
+    };
+    args.finish()?;
+
+    let mut config = BakeConfig::default();
+    if let Some(size) = size {
+        if size == 0 {
+            return Err(Error::Usage(String::from("--size expects a positive size")));
+        }
+        config.width = size;
+        config.height = size;
+    }
+    if let Some(padding) = padding {
+        config.padding = padding;
+    }
+    if let Some(uv_set) = uv_set {
+        config.uv_set = uv_set;
+    }
+
+    let mut mesh = cli::load_mesh(&input)?;
+    if mesh.vertex_tangents.is_none() {
+        mesh.compute_tangents();
+    }
+    let img = bake::bake(&mesh, target, &config)
+        .map_err(|e| Error::Render(format!("{}: {}", input.display(), e)))?;
+    cli::save_image(&img, &output)
+}
@@ -0,0 +1,59 @@
+extern crate gio;
+extern crate gtk;
+extern crate ray_ruster;
+
+use gio::prelude::*;
+use gtk::prelude::*;
+use std::path::PathBuf;
+use std::process;
+
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
+use ray_ruster::viewer::compare_view::CompareView;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let (a_path, b_path) = match (args.positional(), args.positional()) {
+        (Some(a), Some(b)) => (PathBuf::from(a), PathBuf::from(b)),
+        _ => {
+            return Err(Error::Usage(String::from(
+                "usage: compare <image A> <image B>",
+            )))
+        }
+    };
+    args.finish()?;
+
+    let a = cli::load_image(&a_path)?;
+    let b = cli::load_image(&b_path)?;
+
+    let application = gtk::Application::new(Some("main.ray_ruster"), Default::default())
+        .map_err(|e| Error::Output(format!("failed to initialize GTK application: {}", e)))?;
+
+    application.connect_activate(move |app| {
+        let window = gtk::ApplicationWindow::new(app);
+        window.set_title(&format!("{} | {}", a_path.display(), b_path.display()));
+        window.set_default_size(
+            a.width().max(b.width()) as i32,
+            a.height().max(b.height()) as i32 + 40,
+        );
+        let view = CompareView::new();
+        view.set_images(&a, &b);
+        window.add(view.widget());
+        window.show_all();
+    });
+
+    match application.run(&[]) {
+        0 => Ok(()),
+        code => Err(Error::Output(format!(
+            "GTK application exited with {}",
+            code
+        ))),
+    }
+}
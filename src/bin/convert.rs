@@ -0,0 +1,70 @@
+extern crate ray_ruster;
+
+use std::path::PathBuf;
+use std::process;
+
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
+use ray_ruster::geometry::types::Direction;
+
+const USAGE: &str = "usage: convert <input> <output> [--weld <tolerance>] [--recompute-normals] \
+                     [--normalize] [--scale <factor>]";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let weld: Option<f64> = args.parse("--weld")?;
+    let recompute_normals = args.flag("--recompute-normals");
+    let normalize = args.flag("--normalize");
+    let scale: Option<f64> = args.parse("--scale")?;
+    let (input, output) = match (args.positional(), args.positional()) {
+        (Some(input), Some(output)) => (PathBuf::from(input), PathBuf::from(output)),
+        _ => return Err(Error::Usage(String::from(USAGE))),
+    };
+    args.finish()?;
+    if weld.is_some_and(|tolerance| tolerance.is_nan() || tolerance < 0.0) {
+        return Err(Error::Usage(String::from(
+            "--weld expects a tolerance of 0 or more",
+        )));
+    }
+    // A negative factor would turn the triangles inside out
+    if scale.is_some_and(|factor| factor.is_nan() || factor <= 0.0) {
+        return Err(Error::Usage(String::from(
+            "--scale expects a positive factor",
+        )));
+    }
+
+    let mut mesh = cli::load_mesh(&input)?;
+    println!(
+        "{}: {} vertices, {} triangles",
+        input.display(),
+        mesh.vertices.len(),
+        mesh.triangles.len()
+    );
+    if normalize {
+        mesh = mesh.normalized();
+    }
+    if let Some(factor) = scale {
+        mesh.scale(&Direction::repeat(factor));
+    }
+    if let Some(tolerance) = weld {
+        println!("welded {} vertices", mesh.weld(tolerance));
+    }
+    if recompute_normals {
+        mesh.recompute_normals();
+    }
+    cli::save_mesh(&mesh, &output)?;
+    println!(
+        "{}: {} vertices, {} triangles",
+        output.display(),
+        mesh.vertices.len(),
+        mesh.triangles.len()
+    );
+    Ok(())
+}
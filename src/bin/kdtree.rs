@@ -1,60 +1,49 @@
 extern crate gio;
 extern crate gtk;
-extern crate nalgebra as na;
 extern crate rand;
 extern crate ray_ruster;
-extern crate tempfile;
 use gio::prelude::*;
 use gtk::prelude::*;
 
-use rand::prelude::*;
-use std::path::Path;
-use tempfile::tempdir;
+use rand::Rng;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use ray_ruster::geometry::bounding_box::AxisAlignedBoundingBox;
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
 use ray_ruster::geometry::kdtree::{iter_intersect_ray, KdTree};
-use ray_ruster::geometry::mesh::Mesh;
 use ray_ruster::geometry::ray::Ray;
-use ray_ruster::geometry::types::{Direction, Position};
+use ray_ruster::geometry::types::Direction;
+use ray_ruster::render::animation;
+use ray_ruster::render::color::Color;
 use ray_ruster::render::config;
 use ray_ruster::render::config::CameraConfig;
 use ray_ruster::render::image;
-use ray_ruster::render::ray_tracer::clamp_u8;
-
-/// Get the normal of the box face that we hit
-/// This assumes that the intersection lies on the box,
-/// otherwise this will return a 0 vector.
-fn get_box_normal_debug(intersection: &Position, bb: &AxisAlignedBoundingBox) -> Direction {
-    let mut normal = Direction::new(0.0, 0.0, 0.0);
-
-    // This is ugly and should maybe be refactored
-    if (bb.bounds[0][0] - intersection[0]).abs() <= f32::EPSILON.into() {
-        normal = Direction::new(-1.0, 0.0, 0.0);
-    }
-    if (bb.bounds[1][0] - intersection[0]).abs() <= f32::EPSILON.into() {
-        normal = Direction::new(1.0, 0.0, 0.0);
-    }
-    if (bb.bounds[0][1] - intersection[1]).abs() <= f32::EPSILON.into() {
-        normal = Direction::new(0.0, -1.0, 0.0);
-    }
-    if (bb.bounds[1][1] - intersection[1]).abs() <= f32::EPSILON.into() {
-        normal = Direction::new(0.0, 1.0, 0.0);
-    }
-    if (bb.bounds[0][2] - intersection[2]).abs() <= f32::EPSILON.into() {
-        normal = Direction::new(0.0, 0.0, -1.0);
-    }
-    if (bb.bounds[1][2] - intersection[2]).abs() <= f32::EPSILON.into() {
-        normal = Direction::new(0.0, 0.0, 1.0);
-    }
-    normal
+use ray_ruster::render::ray_tracer::SampleKey;
+use ray_ruster::render::rng::SampleRng;
+use ray_ruster::render::video::{FrameFormat, FramePipe};
+
+/// Number identifying a node from one run to the other, unlike its address
+fn node_key(node: &KdTree) -> u64 {
+    node.bounding_box
+        .bounds
+        .iter()
+        .flat_map(|corner| corner.iter())
+        .fold(0, |key, c| key.rotate_left(7) ^ c.to_bits())
 }
 
 fn make_box_tracer<'a>(
     kdt: &'a Box<KdTree>,
     max_depth: usize,
     camera_config: &'a CameraConfig,
-) -> impl Fn(Ray) -> [u8; 3] + 'a {
-    move |ray| {
+    seed: u64,
+) -> impl Fn(Ray, SampleKey) -> Color + 'a {
+    // Hits on the boxes are as precise as the secondary rays of the scene
+    let tolerance = kdt
+        .bounding_box
+        .ray_epsilon()
+        .unwrap_or(f32::EPSILON.into());
+    move |ray, _| {
         let box_iter = iter_intersect_ray(&kdt, &ray).closest_branch();
         let box_intersect = box_iter
             //.inspect(|x| println!("[{:},{:}]looking at: {:?}", i, j, x.bounding_box.bounds))
@@ -67,62 +56,101 @@ fn make_box_tracer<'a>(
             let ref bb = kd_node.bounding_box;
 
             let intersection = ray.position + *hit * ray.direction;
-            let normal = get_box_normal_debug(&intersection, bb);
+            let normal = bb
+                .face_normal_at(&intersection, tolerance)
+                .unwrap_or_else(Direction::zeros);
 
-            // Generate a random color from the box pointer
-            let my_num_ptr: *const KdTree = &***kd_node;
-            let random_seed = my_num_ptr as u64;
-            let mut color_gen = rand::rngs::StdRng::seed_from_u64(random_seed);
+            // Generate a random color from the box, the same on every run
+            let mut color_gen = SampleRng::for_key(seed, node_key(kd_node));
 
-            let color: [u8; 3] = [color_gen.gen(), color_gen.gen(), color_gen.gen()];
+            let color = Color::new(color_gen.gen(), color_gen.gen(), color_gen.gen());
             let shade = (camera_config.camera_position - intersection)
                 .normalize()
                 .dot(&normal);
-            return [
-                clamp_u8(color[0] as f64 * shade),
-                clamp_u8(color[1] as f64 * shade),
-                clamp_u8(color[2] as f64 * shade),
-            ];
+            return color * shade.max(0.0);
         } else {
-            return [0, 0, 0];
+            return Color::BLACK;
         }
     }
 }
 
 fn main() {
-    let mesh = Mesh::load_off_file(Path::new("data/ram.off")).unwrap();
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_env();
+    let input = args
+        .path("--input")?
+        .unwrap_or_else(|| PathBuf::from("data/ram.off"));
+    let pipe = args.path("--pipe")?;
+    let pipe_format = match args.value("--pipe-format")?.as_deref() {
+        None | Some("ppm") => FrameFormat::Ppm,
+        Some("raw") => FrameFormat::Raw,
+        Some(other) => {
+            return Err(Error::Usage(format!(
+                "unknown pipe format {}, expected ppm or raw",
+                other
+            )))
+        }
+    };
+    let seed = args.parse("--seed")?.unwrap_or(0);
+    args.finish()?;
+
+    let mesh = cli::load_mesh(&input)?;
     let kdt = KdTree::from_mesh(&mesh);
 
-    let rot = na::Rotation3::face_towards(
-        &Direction::new(-1.0, 1.0, 0.0),
-        &Direction::new(0.0, 0.0, 1.0),
+    // The whole model, seen from the +x -y diagonal
+    let camera_config = config::CameraConfig::framing(
+        &kdt.bounding_box,
+        Direction::new(-1.0, 1.0, 0.0),
+        Direction::z(),
+        60.0,
+        300,
+        300,
     );
-    let camera_config = config::CameraConfig {
-        camera_position: rot * Position::new(0.0, 0.5, -10.0),
-        x: rot * Direction::new(1.0, 0.0, 0.0),
-        y: rot * Direction::new(0.0, 1.0, 0.0),
-        z: rot * Direction::new(0.0, 0.0, 1.0),
-        fov: 60.0,
-        aspect_ratio: 1.0,
-        width: 300,
-        height: 300,
+    let rendering_config = config::RenderingConfig {
+        seed,
+        ..Default::default()
     };
 
-    // Render all images
-    let dir = tempdir().ok().unwrap();
-    let mut paths = Vec::new();
-
-    for depth in 1..10 {
-        let img = image::render_image(make_box_tracer(&kdt, depth, &camera_config), &camera_config);
-        let file_path = dir
-            .path()
-            .join(format!("render_{depth}.png", depth = depth));
-        let _ = img.save(Path::new(&file_path));
-        paths.push(file_path)
+    // Stream the depth sequence to an encoder instead of displaying it
+    if let Some(pipe_path) = pipe {
+        let pipe_error =
+            |e| Error::Output(format!("could not write to {}: {}", pipe_path.display(), e));
+        let mut frame_pipe = FramePipe::open(&pipe_path, pipe_format).map_err(pipe_error)?;
+        for depth in 1..10 {
+            let img = image::render_image(
+                make_box_tracer(&kdt, depth, &camera_config, rendering_config.seed),
+                &camera_config,
+                &rendering_config,
+            );
+            frame_pipe.write_frame(&img).map_err(pipe_error)?;
+        }
+        return frame_pipe.finish().map_err(pipe_error);
     }
 
+    // Render all images
+    let dir = cli::temp_dir()?;
+    let paths = animation::save_sequence(dir.path(), "render", 9, |frame| {
+        image::render_image(
+            make_box_tracer(
+                &kdt,
+                frame as usize + 1,
+                &camera_config,
+                rendering_config.seed,
+            ),
+            &camera_config,
+            &rendering_config,
+        )
+    })
+    .map_err(|e| Error::Output(format!("could not write the renders: {}", e)))?;
+
     let application = gtk::Application::new(Some("main.ray_ruster"), Default::default())
-        .expect("failed to initialize GTK application");
+        .map_err(|e| Error::Output(format!("failed to initialize GTK application: {}", e)))?;
 
     application.connect_activate(move |app| {
         let window = gtk::ApplicationWindow::new(app);
@@ -137,5 +165,11 @@ fn main() {
         window.show_all();
     });
 
-    application.run(&[]);
+    match application.run(&[]) {
+        0 => Ok(()),
+        code => Err(Error::Output(format!(
+            "GTK application exited with {}",
+            code
+        ))),
+    }
 }
@@ -0,0 +1,65 @@
+extern crate ray_ruster;
+
+use std::path::PathBuf;
+use std::process;
+use std::time::Instant;
+
+use ray_ruster::cli::Args;
+use ray_ruster::error::Error;
+use ray_ruster::render::lidar::{self, SensorConfig};
+use ray_ruster::render::scene::Scene;
+use ray_ruster::render::shared::PreparedScene;
+
+const USAGE: &str = "usage: lidar <scene> <points> [--sensor <path>] [--save-sensor <path>]";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let start = Instant::now();
+    let mut args = Args::from_env();
+    let sensor_path = args.path("--sensor")?;
+    let save_sensor = args.path("--save-sensor")?;
+    let (input, output) = match (args.positional(), args.positional()) {
+        (Some(input), Some(output)) => (PathBuf::from(input), PathBuf::from(output)),
+        _ => return Err(Error::Usage(String::from(USAGE))),
+    };
+    args.finish()?;
+
+    let scene = Scene::load(&input).map_err(|e| Error::Config(input.clone(), e))?;
+    // Without sensor file, the default lidar in the middle of the scene
+    let sensor = match &sensor_path {
+        Some(path) => SensorConfig::load(path).map_err(|e| Error::Config(path.clone(), e))?,
+        None => SensorConfig {
+            position: scene.bounding_box().center,
+            ..Default::default()
+        },
+    };
+    let [near, far] = sensor.range;
+    if !(near >= 0.0 && far > near) {
+        return Err(Error::Usage(String::from(
+            "the range of the sensor must go from 0 or more to farther",
+        )));
+    }
+    if let Some(path) = &save_sensor {
+        sensor
+            .save(path)
+            .map_err(|e| Error::Output(format!("could not write {}: {}", path.display(), e)))?;
+    }
+    let prepared = PreparedScene::new(scene).map_err(|e| Error::Config(input.clone(), e))?;
+    println!("{:?}: prepared the scene", start.elapsed());
+
+    let scan = lidar::scan(prepared.mesh(), prepared.kdtree(), &sensor);
+    println!(
+        "{:?}: {} points hit by {} rays",
+        start.elapsed(),
+        scan.points().count(),
+        scan.ranges.len()
+    );
+    scan.save(&output)
+        .map_err(|e| Error::Output(format!("could not write {}: {}", output.display(), e)))
+}
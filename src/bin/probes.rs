@@ -0,0 +1,57 @@
+extern crate ray_ruster;
+
+use std::path::PathBuf;
+use std::process;
+use std::time::Instant;
+
+use ray_ruster::cli::Args;
+use ray_ruster::error::Error;
+use ray_ruster::render::probes::{self, ProbeConfig};
+use ray_ruster::render::scene::Scene;
+use ray_ruster::render::shared::PreparedScene;
+
+const USAGE: &str = "usage: probes <scene> <grid> [--resolution <probes>] [--samples <rays>]";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let start = Instant::now();
+    let mut args = Args::from_env();
+    let resolution: u32 = args.parse("--resolution")?.unwrap_or(8);
+    let samples: Option<u32> = args.parse("--samples")?;
+    let (input, output) = match (args.positional(), args.positional()) {
+        (Some(input), Some(output)) => (PathBuf::from(input), PathBuf::from(output)),
+        _ => return Err(Error::Usage(String::from(USAGE))),
+    };
+    args.finish()?;
+    if resolution == 0 {
+        return Err(Error::Usage(String::from(
+            "--resolution expects a positive number of probes",
+        )));
+    }
+
+    let scene = Scene::load(&input).map_err(|e| Error::Config(input.clone(), e))?;
+    let mut config = ProbeConfig::fitting(&scene.bounding_box(), resolution);
+    if let Some(samples) = samples {
+        config.samples = samples;
+    }
+    let prepared = PreparedScene::new(scene).map_err(|e| Error::Config(input.clone(), e))?;
+    println!("{:?}: prepared the scene", start.elapsed());
+
+    let grid = probes::bake_probes(&prepared, &config);
+    println!(
+        "{:?}: baked {} probes ({} by {} by {})",
+        start.elapsed(),
+        grid.len(),
+        config.resolution[0],
+        config.resolution[1],
+        config.resolution[2]
+    );
+    grid.save(&output)
+        .map_err(|e| Error::Output(format!("could not write {}: {}", output.display(), e)))
+}
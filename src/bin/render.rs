@@ -1,63 +1,589 @@
 extern crate gio;
+extern crate glib;
 extern crate gtk;
-extern crate nalgebra as na;
 extern crate ray_ruster;
-extern crate tempfile;
 
 use gio::prelude::*;
 use gtk::prelude::*;
-use std::path::Path;
-use std::time::Instant;
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use ray_ruster::geometry::mesh::Mesh;
-use ray_ruster::geometry::types::{Direction, Position};
+use ray_ruster::cli::{self, Args};
+use ray_ruster::error::Error;
+use ray_ruster::geometry::bvh::{TreeBuild, TreePreset};
+use ray_ruster::geometry::import::ImportOptions;
+use ray_ruster::geometry::types::Direction;
 use ray_ruster::render::config;
+use ray_ruster::render::denoise::{self, Denoiser};
+use ray_ruster::render::framebuffer::TileRect;
+use ray_ruster::render::handle::{self, RenderHandle};
 use ray_ruster::render::image;
+use ray_ruster::render::preview::PixelMask;
 use ray_ruster::render::ray_tracer;
+use ray_ruster::render::report::SceneReport;
+use ray_ruster::render::sampler::SamplerKind;
+use ray_ruster::render::scene::Scene;
+use ray_ruster::render::shared::{PreparedScene, SharedScene};
+use ray_ruster::viewer::image_view::ImageView;
 
-use tempfile::tempdir;
+/// How often the watched files are polled for changes
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
-fn main() {
-    let start = Instant::now();
+/// How often the window looks for the image of the render
+const RESULT_POLL_MS: u32 = 100;
+
+struct Options {
+    input: PathBuf,
+    scene: Option<PathBuf>,
+    output: Option<PathBuf>,
+    /// OpenEXR file receiving the depth, normal and albedo passes
+    aovs: Option<PathBuf>,
+    /// Filter removing the noise of the render, guided by its AOVs
+    denoise: Option<Denoiser>,
+    /// Chrome trace file receiving the timings of the pipeline stages
+    trace: Option<PathBuf>,
+    watch: bool,
+    /// Show the statistics of the render over the window from the start
+    stats: bool,
+    /// Explore the scene in a live preview instead of rendering it once
+    preview: bool,
+    /// Pixels traced at every frame of the live preview; without, every
+    /// pixel at a resolution lowered while the camera moves
+    preview_mask: Option<PixelMask>,
+    config: Option<PathBuf>,
+    save_config: Option<PathBuf>,
+    integrator: Option<config::Integrator>,
+    debug_view: Option<config::DebugView>,
+    tree_build: Option<TreeBuild>,
+    tree_preset: Option<TreePreset>,
+    normal_mode: Option<config::NormalMode>,
+    threads: Option<usize>,
+    samples_per_pixel: Option<u32>,
+    sampler: Option<SamplerKind>,
+    seed: Option<u64>,
+    /// Region of the image to render alone
+    crop: Option<TileRect>,
+    projection: Option<config::Projection>,
+    import: ImportOptions,
+}
+
+fn parse_options() -> Result<Options, Error> {
+    let mut args = Args::from_env();
+    let input = args.path("--input")?;
+    let scene = args.path("--scene")?;
+    let config = args.path("--config")?;
+    if scene.is_some() && (input.is_some() || config.is_some()) {
+        return Err(Error::Usage(
+            "--scene already defines the model and the configuration, \
+             it cannot be combined with --input or --config"
+                .to_string(),
+        ));
+    }
+    let options = Options {
+        input: input.unwrap_or_else(|| PathBuf::from("data/ram.off")),
+        scene,
+        config,
+        output: args.path("--output")?,
+        aovs: args.path("--aovs")?,
+        denoise: args.parse("--denoise")?,
+        trace: args.path("--trace")?,
+        watch: args.flag("--watch"),
+        stats: args.flag("--stats"),
+        preview: args.flag("--preview"),
+        preview_mask: args.parse("--preview-mask")?,
+        save_config: args.path("--save-config")?,
+        integrator: args.parse("--integrator")?,
+        debug_view: args.parse("--debug-view")?,
+        tree_build: args.parse("--tree-build")?,
+        tree_preset: args.parse("--tree-preset")?,
+        normal_mode: args.parse("--normal-mode")?,
+        threads: args.parse("--threads")?,
+        samples_per_pixel: args.parse("--samples")?,
+        sampler: args.parse("--sampler")?,
+        seed: args.parse("--seed")?,
+        crop: args.parse("--crop")?,
+        projection: args.parse("--projection")?,
+        import: ImportOptions {
+            units: None,
+            up_axis: args.parse("--up-axis")?,
+            handedness: args.parse("--handedness")?,
+        },
+    };
+    args.finish()?;
+    Ok(options)
+}
+
+/// Configuration from the `--config` file, or the default view framing the
+/// objects of `scene`, with the command line overrides applied
+fn load_config(options: &Options, scene: &Scene) -> Result<config::ConfigFile, Error> {
+    let mut config_file = match &options.config {
+        Some(path) => config::ConfigFile::load(path).map_err(|e| Error::Config(path.clone(), e))?,
+        None => {
+            // The whole model, seen from the +x -y diagonal
+            let camera = config::CameraConfig::default();
+            config::ConfigFile {
+                camera: config::CameraConfig::framing(
+                    &scene.bounding_box(),
+                    Direction::new(-1.0, 1.0, 0.0),
+                    Direction::z(),
+                    camera.fov,
+                    camera.width,
+                    camera.height,
+                ),
+                ..Default::default()
+            }
+        }
+    };
+    apply_overrides(options, &mut config_file.camera, &mut config_file.rendering);
+    Ok(config_file)
+}
+
+fn apply_overrides(
+    options: &Options,
+    camera_config: &mut config::CameraConfig,
+    rendering_config: &mut config::RenderingConfig,
+) {
+    if let Some(projection) = options.projection {
+        camera_config.projection = projection;
+    }
+    if let Some(integrator) = options.integrator {
+        rendering_config.integrator = integrator;
+    }
+    if let Some(debug_view) = options.debug_view {
+        rendering_config.debug_view = Some(debug_view);
+    }
+    if let Some(tree_build) = options.tree_build {
+        rendering_config.tree_build = tree_build;
+    }
+    if let Some(tree_preset) = options.tree_preset {
+        rendering_config.tree_preset = tree_preset;
+    }
+    if let Some(normal_mode) = options.normal_mode {
+        rendering_config.normal_mode = normal_mode;
+    }
+    if let Some(threads) = options.threads {
+        rendering_config.threads = threads;
+    }
+    if let Some(samples_per_pixel) = options.samples_per_pixel {
+        rendering_config.samples_per_pixel = samples_per_pixel;
+    }
+    if let Some(sampler) = options.sampler {
+        rendering_config.sampler = sampler;
+    }
+    if let Some(seed) = options.seed {
+        rendering_config.seed = seed;
+    }
+    if let Some(crop) = options.crop {
+        rendering_config.crop = Some(crop);
+    }
+}
+
+/// The `--scene` file, or the `--input` model seen with the configuration
+fn load_scene(options: &Options, start: &Instant) -> Result<Scene, Error> {
+    let scene = match &options.scene {
+        Some(path) => {
+            let mut scene = Scene::load(path).map_err(|e| Error::Config(path.clone(), e))?;
+            apply_overrides(options, &mut scene.camera, &mut scene.rendering);
+            scene.import = options.import.or(&scene.import);
+            scene
+        }
+        None => {
+            let mut scene = Scene::builder()
+                .add_mesh(cli::load_mesh(&options.input)?)
+                .import(options.import)
+                .build();
+            scene.objects[0].path = Some(options.input.clone());
+            let config_file = load_config(options, &scene)?;
+            scene.camera = config_file.camera;
+            scene.rendering = config_file.rendering;
+            scene
+        }
+    };
+    tracing::info!(objects = scene.objects.len(), "loaded the scene");
+    println!("{:?}: loaded the scene", start.elapsed());
+    Ok(scene)
+}
+
+/// Merge the objects of the scene and build its kd-tree and lights, reusing
+/// what the previous version in `shared` has in common with it and
+/// publishing it there, then report their size
+fn prepare(
+    options: &Options,
+    shared: &mut Option<SharedScene>,
+    scene: Scene,
+    start: &Instant,
+) -> Result<Arc<PreparedScene>, Error> {
+    let path = options.scene.as_ref().unwrap_or(&options.input);
+    let prepared = match shared {
+        Some(shared) => shared.update(|current| *current = scene),
+        None => PreparedScene::new(scene)
+            .map(|prepared| shared.insert(SharedScene::new(prepared)).snapshot()),
+    }
+    .map_err(|e| Error::Config(path.clone(), e))?;
+    if prepared.triangle_count() == 0 {
+        return Err(Error::Render(
+            "the scene does not contain any triangle".to_string(),
+        ));
+    }
+    image::thread_pool(prepared.scene.rendering.threads)
+        .map_err(|e| Error::Render(format!("could not start the rendering threads: {}", e)))?;
+    tracing::info!(triangles = prepared.triangle_count(), "prepared the scene");
+    println!("{:?}: prepared the scene", start.elapsed());
+    let report = SceneReport::new(&prepared);
+    println!("{}", report);
+    for warning in report.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(prepared)
+}
 
-    let mesh = Mesh::load_off_file(Path::new("data/ram.off")).unwrap();
-    println!("{:?}: loaded OFF model", start.elapsed());
-    let rot = na::Rotation3::face_towards(
-        &Direction::new(-1.0, 1.0, 0.0),
-        &Direction::new(0.0, 0.0, 1.0),
-    );
-    let camera_config = config::CameraConfig {
-        camera_position: rot * Position::new(0.0, 0.5, -10.0),
-        x: rot * Direction::new(1.0, 0.0, 0.0),
-        y: rot * Direction::new(0.0, 1.0, 0.0),
-        z: rot * Direction::new(0.0, 0.0, 1.0),
-        fov: 60.0,
-        aspect_ratio: 4.0 / 3.0,
-        width: 400,
-        height: 300,
+/// Files the render depends on
+fn sources(options: &Options, scene: Option<&Scene>) -> Vec<PathBuf> {
+    let mut paths = match &options.scene {
+        Some(path) => vec![path.clone()],
+        None => vec![options.input.clone()],
     };
-    let rendering_config = config::RenderingConfig {
-        normal_mode: config::NormalMode::Phong,
+    paths.extend(options.config.clone());
+    if let (Some(_), Some(scene)) = (&options.scene, scene) {
+        paths.extend(scene.objects.iter().filter_map(|o| o.path.clone()));
+    }
+    paths
+}
+
+/// Linear colors of the render, and their 8-bit display
+///
+/// With `--aovs` or `--denoise`, the depth, normal and albedo passes are
+/// rendered with the colors, to be saved or to guide the denoiser. Otherwise
+/// `render_handle` can stop the render.
+fn render(
+    options: &Options,
+    prepared: &PreparedScene,
+    render_handle: &RenderHandle,
+    start: &Instant,
+) -> Result<(image::HdrRgbImage, image::RgbImage), Error> {
+    let scene = &prepared.scene;
+    let hdr = if options.aovs.is_some() || options.denoise.is_some() {
+        let aovs = image::render_aovs(
+            ray_tracer::make_prepared_sample_tracer(prepared, &scene.camera, &scene.rendering),
+            &scene.camera,
+            &scene.rendering,
+        );
+        if let Some(path) = &options.aovs {
+            aovs.save_exr(path)
+                .map_err(|e| Error::Output(format!("could not write {}: {}", path.display(), e)))?;
+        }
+        match options.denoise {
+            Some(denoiser) => denoise::denoise(&aovs, denoiser).map_err(Error::Render)?,
+            None => aovs.color,
+        }
+    } else {
+        let hdr = handle::render_with_handle(
+            ray_tracer::make_prepared_ray_tracer(prepared, &scene.camera, &scene.rendering),
+            &scene.camera,
+            &scene.rendering,
+            render_handle,
+            |progress| {
+                eprint!(
+                    "\rrendering: {:.0}%, {} rays in {:.1?}",
+                    progress.percent(),
+                    progress.rays,
+                    progress.elapsed
+                );
+            },
+        );
+        eprintln!();
+        hdr.ok_or(Error::Cancelled)?
     };
-    let img = image::render_image(
-        ray_tracer::make_naive_ray_tracer(&mesh, &camera_config, &rendering_config),
-        &camera_config,
-    );
     println!("{:?}: rendering done", start.elapsed());
-    let dir = tempdir().ok().unwrap();
-    let file_path = dir.path().join("render.png");
-    let _ = img.save(Path::new(&file_path));
+    let img = image::to_display(&hdr, &scene.rendering);
+    Ok((hdr, img))
+}
+
+/// Give the objects of `scene` the models `previous` loaded from the same
+/// files, unless they were modified since it was `loaded`, so that preparing
+/// the scene reuses their world coordinates and kd-tree
+fn reuse_models(scene: &mut Scene, previous: &Scene, loaded: SystemTime) {
+    for (object, old) in scene.objects.iter_mut().zip(&previous.objects) {
+        let unchanged = object.path.is_some()
+            && object.path == old.path
+            && object.terrain == old.terrain
+            && object.shape == old.shape
+            && object.displacement == old.displacement
+            && object
+                .path
+                .as_ref()
+                .and_then(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+                .is_some_and(|modified| modified < loaded);
+        if unchanged {
+            object.mesh = old.mesh.clone();
+        }
+    }
+}
+
+/// Latest modification time of the watched files, missing files are ignored
+/// so that editors replacing a file on save do not stop the watch.
+fn last_modified(paths: &[PathBuf]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// Where the render preceding the current output is kept,
+/// e.g. render.previous.png for render.png
+fn previous_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    match output.extension() {
+        Some(extension) => {
+            output.with_file_name(format!("{}.previous.{}", stem, extension.to_string_lossy()))
+        }
+        None => output.with_file_name(format!("{}.previous", stem)),
+    }
+}
+
+/// Re-render the input to the output path every time the input changes
+///
+/// A render whose input changes before it is done is stopped and started
+/// over. Failures are reported without leaving the watch, except for output
+/// errors which would otherwise repeat on every change. Every version of the
+/// scene is derived from the previous one, keeping the models whose file did
+/// not change.
+fn watch(options: &Options, output: &Path) -> Result<(), Error> {
+    let mut watched = sources(options, None);
+    let mut seen = last_modified(&watched);
+    let mut previous: Option<(Duration, image::RgbImage)> = None;
+    let mut shared: Option<SharedScene> = None;
+    // When the models of the current version of `shared` were loaded
+    let mut loaded: Option<SystemTime> = None;
+
+    loop {
+        let start = Instant::now();
+        let render_handle = RenderHandle::new();
+        // The files and modification time the render started from, updated
+        // by the loading so that the poller compares against what was read
+        let polled = Mutex::new((watched.clone(), seen));
+        let done = AtomicBool::new(false);
+        let rendered = thread::scope(|scope| {
+            let poller = scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    thread::park_timeout(WATCH_POLL_INTERVAL);
+                    let polled = polled.lock().unwrap();
+                    if last_modified(&polled.0) != polled.1 {
+                        render_handle.cancel();
+                        return;
+                    }
+                }
+            });
+            let loading = SystemTime::now();
+            let rendered = load_scene(options, &start).and_then(|mut scene| {
+                let files = sources(options, Some(&scene));
+                *polled.lock().unwrap() = (files.clone(), last_modified(&files));
+                if let (Some(shared), Some(loaded)) = (&shared, loaded) {
+                    reuse_models(&mut scene, &shared.snapshot().scene, loaded);
+                }
+                let prepared = prepare(options, &mut shared, scene, &start)?;
+                loaded = Some(loading);
+                render(options, &prepared, &render_handle, &start)
+            });
+            done.store(true, Ordering::Relaxed);
+            poller.thread().unpark();
+            rendered
+        });
+        let (files, modified) = polled.into_inner().unwrap();
+        watched = files;
+        seen = modified;
+        match rendered {
+            Err(Error::Cancelled) => println!("input changed, starting over"),
+            Err(e) => eprintln!("error: {}", e),
+            Ok((hdr, img)) => {
+                let elapsed = start.elapsed();
+                if previous.is_some() {
+                    // Keep the last render around for `compare`
+                    let _ = fs::rename(output, previous_path(output));
+                }
+                cli::save_render(&hdr, &img, output)?;
+                report(&previous, elapsed, &img, output);
+                previous = Some((elapsed, img));
+            }
+        }
+
+        println!("watching {:?} for changes", watched);
+        loop {
+            thread::sleep(WATCH_POLL_INTERVAL);
+            let modified = last_modified(&watched);
+            if modified != seen {
+                seen = modified;
+                break;
+            }
+        }
+    }
+}
+
+/// Print the render time and the difference with the previous render
+fn report(
+    previous: &Option<(Duration, image::RgbImage)>,
+    elapsed: Duration,
+    img: &image::RgbImage,
+    output: &Path,
+) {
+    match previous {
+        Some((previous_elapsed, previous_img)) => {
+            let delta_ms = elapsed.as_secs_f64() * 1e3 - previous_elapsed.as_secs_f64() * 1e3;
+            match image::compare_images(previous_img, img) {
+                Some(diff) => println!(
+                    "re-rendered {:?} in {:?} ({:+.1} ms), rmse {:.3}, {:.2}% pixels changed",
+                    output,
+                    elapsed,
+                    delta_ms,
+                    diff.rmse,
+                    diff.changed_fraction * 100.0
+                ),
+                None => println!(
+                    "re-rendered {:?} in {:?} ({:+.1} ms), image size changed",
+                    output, elapsed, delta_ms
+                ),
+            }
+        }
+        None => println!("rendered {:?} in {:?}", output, elapsed),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let options = parse_options()?;
+    let _trace = options.trace.as_deref().map(cli::init_tracing);
+
+    if let Some(path) = &options.save_config {
+        let scene = load_scene(&options, &Instant::now())?;
+        let config_file = config::ConfigFile {
+            camera: scene.camera,
+            rendering: scene.rendering,
+            ..Default::default()
+        };
+        config_file
+            .save(path)
+            .map_err(|e| Error::Output(format!("could not write {}: {}", path.display(), e)))?;
+    }
+
+    if options.watch {
+        let output = options
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("render.png"));
+        return watch(&options, &output);
+    }
+
+    let start = Instant::now();
+    let scene = load_scene(&options, &start)?;
+    let prepared = prepare(&options, &mut None, scene, &start)?;
+    if let Some(output) = &options.output {
+        let (hdr, img) = render(&options, &prepared, &RenderHandle::new(), &start)?;
+        return cli::save_render(&hdr, &img, output);
+    }
+    if options.preview || options.preview_mask.is_some() {
+        return preview(prepared, options.preview_mask, options.stats);
+    }
+
+    // The window opens at once, the image replacing the empty view once the
+    // render going on in the background is done
+    let camera = prepared.scene.camera.clone();
+    let samples_per_pixel = prepared.scene.rendering.samples_per_pixel.max(1);
+    let stats = options.stats;
+    let render_handle = RenderHandle::new();
+    let (sender, receiver) = mpsc::channel();
+    {
+        let render_handle = render_handle.clone();
+        thread::spawn(move || {
+            let result = render(&options, &prepared, &render_handle, &start);
+            // Nobody to tell once the window is closed
+            let _ = sender.send(result);
+        });
+    }
+    let receiver = RefCell::new(Some(receiver));
+
     let application = gtk::Application::new(Some("main.ray_ruster"), Default::default())
-        .expect("failed to initialize GTK application");
+        .map_err(|e| Error::Output(format!("failed to initialize GTK application: {}", e)))?;
 
     application.connect_activate(move |app| {
         let window = gtk::ApplicationWindow::new(app);
         window.set_title("ray_ruster");
-        window.set_default_size(350, 70);
-        let im = gtk::Image::new_from_file(Path::new(&file_path));
-        window.add(&im);
+        window.set_default_size(camera.width as i32, camera.height as i32 + 30);
+        let view = ImageView::new();
+        view.set_camera(&camera);
+        view.set_stats_visible(stats);
+        view.watch_render(render_handle.clone(), samples_per_pixel);
+        window.add(view.widget());
         window.show_all();
+        let receiver = match receiver.borrow_mut().take() {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        gtk::timeout_add(RESULT_POLL_MS, move || match receiver.try_recv() {
+            Ok(Ok((_, img))) => {
+                view.set_image(&img);
+                glib::Continue(false)
+            }
+            Ok(Err(e)) => {
+                eprintln!("error: {}", e);
+                process::exit(e.exit_code());
+            }
+            Err(TryRecvError::Empty) => glib::Continue(true),
+            Err(TryRecvError::Disconnected) => glib::Continue(false),
+        });
     });
 
-    application.run(&[]);
+    run_application(&application)
+}
+
+/// Window rendering `prepared` live as its camera moves with the keys
+fn preview(
+    prepared: Arc<PreparedScene>,
+    mask: Option<PixelMask>,
+    stats: bool,
+) -> Result<(), Error> {
+    let camera = prepared.scene.camera.clone();
+    // A key press moves the camera by a fiftieth of the scene
+    let step = prepared.scene.bounding_box().extent.norm() / 50.0;
+    let prepared = RefCell::new(Some(prepared));
+
+    let application = gtk::Application::new(Some("main.ray_ruster"), Default::default())
+        .map_err(|e| Error::Output(format!("failed to initialize GTK application: {}", e)))?;
+
+    application.connect_activate(move |app| {
+        let window = gtk::ApplicationWindow::new(app);
+        window.set_title("ray_ruster");
+        window.set_default_size(camera.width as i32, camera.height as i32 + 30);
+        let view = ImageView::new();
+        view.set_stats_visible(stats);
+        window.add(view.widget());
+        window.show_all();
+        if let Some(prepared) = prepared.borrow_mut().take() {
+            view.preview(prepared, step, mask);
+        }
+    });
+
+    run_application(&application)
+}
+
+fn run_application(application: &gtk::Application) -> Result<(), Error> {
+    match application.run(&[]) {
+        0 => Ok(()),
+        code => Err(Error::Output(format!(
+            "GTK application exited with {}",
+            code
+        ))),
+    }
 }
@@ -0,0 +1,150 @@
+extern crate image;
+extern crate tempfile;
+extern crate tracing_chrome;
+extern crate tracing_subscriber;
+
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use tempfile::{tempdir, TempDir};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::prelude::*;
+
+use crate::error::Error;
+use crate::geometry::mesh::{LoadError, Mesh};
+use crate::render::image::{self as render_image, HdrRgbImage, RgbImage};
+
+/// Minimal command line parser shared by the binaries
+///
+/// Options are consumed as they are looked up, `finish` then reports
+/// anything that was not recognized.
+pub struct Args {
+    args: Vec<String>,
+}
+
+impl Args {
+    pub fn from_env() -> Args {
+        Args {
+            args: env::args().skip(1).collect(),
+        }
+    }
+
+    /// Is the `--name` flag present
+    pub fn flag(&mut self, name: &str) -> bool {
+        match self.args.iter().position(|a| a == name) {
+            Some(i) => {
+                self.args.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Value following the `--name` option, if present
+    pub fn value(&mut self, name: &str) -> Result<Option<String>, Error> {
+        match self.args.iter().position(|a| a == name) {
+            Some(i) if i + 1 < self.args.len() => {
+                let value = self.args.remove(i + 1);
+                self.args.remove(i);
+                Ok(Some(value))
+            }
+            Some(_) => Err(Error::Usage(format!("{} expects a value", name))),
+            None => Ok(None),
+        }
+    }
+
+    /// First argument that is not an option
+    pub fn positional(&mut self) -> Option<String> {
+        let i = self.args.iter().position(|a| !a.starts_with("--"))?;
+        Some(self.args.remove(i))
+    }
+
+    /// Value following the `--name` option, parsed with `FromStr`
+    pub fn parse<T>(&mut self, name: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.value(name)? {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|e| Error::Usage(format!("{}: {}", name, e))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn path(&mut self, name: &str) -> Result<Option<PathBuf>, Error> {
+        Ok(self.value(name)?.map(PathBuf::from))
+    }
+
+    /// Fail on any argument that was not consumed
+    pub fn finish(self) -> Result<(), Error> {
+        match self.args.first() {
+            Some(arg) => Err(Error::Usage(format!("unknown argument: {}", arg))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Load the model, picking the format from the file extension, and keep track
+/// of the path for error reporting
+pub fn load_mesh(path: &Path) -> Result<Mesh, Error> {
+    let mesh = Mesh::load_file(path).map_err(|e| Error::Load(path.to_path_buf(), e))?;
+    if mesh.triangles.is_empty() {
+        return Err(Error::Load(
+            path.to_path_buf(),
+            LoadError::String("the model does not contain any triangle"),
+        ));
+    }
+    Ok(mesh)
+}
+
+/// Save a mesh, picking the format from the file extension, see
+/// `Mesh::save_file`
+pub fn save_mesh(mesh: &Mesh, path: &Path) -> Result<(), Error> {
+    mesh.save_file(path)
+        .map_err(|e| Error::Output(format!("could not write {}: {}", path.display(), e)))
+}
+
+/// Load an image, e.g. a previous render
+pub fn load_image(path: &Path) -> Result<RgbImage, Error> {
+    let img = image::open(path).map_err(|e| Error::LoadImage(path.to_path_buf(), e))?;
+    Ok(img.to_rgb8())
+}
+
+/// Save a render, reporting the path on failure
+pub fn save_image(img: &RgbImage, path: &Path) -> Result<(), Error> {
+    let _span = tracing::info_span!("save_image", path = %path.display()).entered();
+    img.save(path)
+        .map_err(|e| Error::Output(format!("could not write {}: {}", path.display(), e)))
+}
+
+/// Save a render, its linear colors to OpenEXR files and its 8-bit display
+/// to the other formats
+pub fn save_render(hdr: &HdrRgbImage, img: &RgbImage, path: &Path) -> Result<(), Error> {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("exr") => render_image::save_exr(hdr, path)
+            .map_err(|e| Error::Output(format!("could not write {}: {}", path.display(), e))),
+        _ => save_image(img, path),
+    }
+}
+
+/// Record the spans of the pipeline to a Chrome trace file, viewable in
+/// `chrome://tracing` or Perfetto, until the returned guard is dropped
+pub fn init_tracing(path: &Path) -> FlushGuard {
+    let (layer, guard) = ChromeLayerBuilder::new()
+        .file(path)
+        .include_args(true)
+        .build();
+    tracing_subscriber::registry().with(layer).init();
+    guard
+}
+
+/// Directory holding the renders displayed by the viewers
+pub fn temp_dir() -> Result<TempDir, Error> {
+    tempdir().map_err(|e| Error::Output(format!("could not create a temporary directory: {}", e)))
+}
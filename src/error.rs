@@ -0,0 +1,64 @@
+extern crate image;
+
+use self::image::ImageError;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::geometry::mesh::LoadError;
+use crate::render::config::ConfigError;
+
+/// Errors surfaced by the binaries, grouped by the stage that failed
+///
+/// Each group maps to its own process exit code so that scripts can tell a
+/// missing model apart from a failed write.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line could not be understood
+    Usage(String),
+    /// The model could not be loaded
+    Load(PathBuf, LoadError),
+    /// An image given as input could not be loaded
+    LoadImage(PathBuf, ImageError),
+    /// A configuration file could not be read
+    Config(PathBuf, ConfigError),
+    /// The scene could not be rendered
+    Render(String),
+    /// The render was stopped before it was done, see `RenderHandle::cancel`
+    Cancelled,
+    /// The result could not be written or displayed
+    Output(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 2,
+            Error::Load(_, _) | Error::LoadImage(_, _) | Error::Config(_, _) => 3,
+            Error::Render(_) | Error::Cancelled => 4,
+            Error::Output(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::Load(path, LoadError::Io(e)) if e.kind() == io::ErrorKind::NotFound => write!(
+                f,
+                "file not found: {} — pass --input <path>",
+                path.display()
+            ),
+            Error::Load(path, e) => write!(f, "could not load {}: {}", path.display(), e),
+            Error::LoadImage(path, e) => write!(f, "could not load {}: {}", path.display(), e),
+            Error::Config(path, e) => write!(f, "could not load {}: {}", path.display(), e),
+            Error::Render(message) => write!(f, "rendering failed: {}", message),
+            Error::Cancelled => write!(f, "rendering cancelled"),
+            Error::Output(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+/// Elements of a mesh an attribute gives a value to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Domain {
+    Vertex,
+    Triangle,
+}
+
+/// Value of an attribute for one element, or interpolated on a triangle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AttributeValue {
+    Scalar(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+}
+
+/// Values of an attribute, one per element of its domain
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValues {
+    Scalar(Vec<f32>),
+    Vec2(Vec<[f32; 2]>),
+    Vec3(Vec<[f32; 3]>),
+}
+
+impl AttributeValues {
+    pub fn len(&self) -> usize {
+        match self {
+            AttributeValues::Scalar(values) => values.len(),
+            AttributeValues::Vec2(values) => values.len(),
+            AttributeValues::Vec3(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> AttributeValue {
+        match self {
+            AttributeValues::Scalar(values) => AttributeValue::Scalar(values[index]),
+            AttributeValues::Vec2(values) => AttributeValue::Vec2(values[index]),
+            AttributeValues::Vec3(values) => AttributeValue::Vec3(values[index]),
+        }
+    }
+
+    /// Values at `indices`, in their order
+    fn select(&self, indices: &[usize]) -> AttributeValues {
+        match self {
+            AttributeValues::Scalar(v) => {
+                AttributeValues::Scalar(indices.iter().map(|&i| v[i]).collect())
+            }
+            AttributeValues::Vec2(v) => {
+                AttributeValues::Vec2(indices.iter().map(|&i| v[i]).collect())
+            }
+            AttributeValues::Vec3(v) => {
+                AttributeValues::Vec3(indices.iter().map(|&i| v[i]).collect())
+            }
+        }
+    }
+
+    /// These values followed by the average of the values of every pair
+    fn with_midpoints(&self, pairs: &[[usize; 2]]) -> AttributeValues {
+        fn extend<const N: usize>(values: &[[f32; N]], pairs: &[[usize; 2]]) -> Vec<[f32; N]> {
+            let midpoints = pairs.iter().map(|&[a, b]| {
+                let mut mid = values[a];
+                for (m, v) in mid.iter_mut().zip(&values[b]) {
+                    *m = (*m + v) / 2.0;
+                }
+                mid
+            });
+            values.iter().cloned().chain(midpoints).collect()
+        }
+        match self {
+            AttributeValues::Scalar(v) => {
+                let midpoints = pairs.iter().map(|&[a, b]| (v[a] + v[b]) / 2.0);
+                AttributeValues::Scalar(v.iter().cloned().chain(midpoints).collect())
+            }
+            AttributeValues::Vec2(v) => AttributeValues::Vec2(extend(v, pairs)),
+            AttributeValues::Vec3(v) => AttributeValues::Vec3(extend(v, pairs)),
+        }
+    }
+
+    /// Every value repeated `times` in a row
+    fn repeated(&self, times: usize) -> AttributeValues {
+        fn repeat<T: Copy>(values: &[T], times: usize) -> Vec<T> {
+            values
+                .iter()
+                .flat_map(|&v| std::iter::repeat_n(v, times))
+                .collect()
+        }
+        match self {
+            AttributeValues::Scalar(v) => AttributeValues::Scalar(repeat(v, times)),
+            AttributeValues::Vec2(v) => AttributeValues::Vec2(repeat(v, times)),
+            AttributeValues::Vec3(v) => AttributeValues::Vec3(repeat(v, times)),
+        }
+    }
+
+    /// Values of the same type, none yet
+    fn empty_like(&self) -> AttributeValues {
+        match self {
+            AttributeValues::Scalar(_) => AttributeValues::Scalar(Vec::new()),
+            AttributeValues::Vec2(_) => AttributeValues::Vec2(Vec::new()),
+            AttributeValues::Vec3(_) => AttributeValues::Vec3(Vec::new()),
+        }
+    }
+
+    fn same_type(&self, other: &AttributeValues) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// Append `other`, or `count` zeros when it is `None`; `other` must have
+    /// the same type
+    fn extend(&mut self, other: Option<&AttributeValues>, count: usize) {
+        match (self, other) {
+            (AttributeValues::Scalar(a), Some(AttributeValues::Scalar(b))) => a.extend(b),
+            (AttributeValues::Vec2(a), Some(AttributeValues::Vec2(b))) => a.extend(b),
+            (AttributeValues::Vec3(a), Some(AttributeValues::Vec3(b))) => a.extend(b),
+            (AttributeValues::Scalar(a), None) => a.resize(a.len() + count, 0.0),
+            (AttributeValues::Vec2(a), None) => a.resize(a.len() + count, [0.0; 2]),
+            (AttributeValues::Vec3(a), None) => a.resize(a.len() + count, [0.0; 3]),
+            _ => panic!("attribute values of different types"),
+        }
+    }
+}
+
+/// Named channel of per vertex or per triangle data
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    pub domain: Domain,
+    pub values: AttributeValues,
+}
+
+/// Arbitrary data attached to the elements of a mesh, e.g. the quality
+/// measured by a scanner or the labels of a segmentation, kept by the loaders
+/// for the shaders and tools that know what to make of it
+///
+/// The geometry itself, normals, colors and texture coordinates have their
+/// own fields in `Mesh`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attributes {
+    channels: BTreeMap<String, Attribute>,
+}
+
+impl Attributes {
+    /// Add or replace an attribute, see `Mesh::set_attribute` which checks
+    /// the number of values
+    pub(crate) fn insert(&mut self, name: &str, attribute: Attribute) {
+        self.channels.insert(name.to_string(), attribute);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Attribute> {
+        self.channels.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Attribute> {
+        self.channels.remove(name)
+    }
+
+    /// Keep the values of the vertices at `kept`, e.g. after merging
+    /// vertices, see `Mesh::weld`
+    pub(crate) fn select_vertices(&mut self, kept: &[usize]) {
+        for attribute in self.channels.values_mut() {
+            if attribute.domain == Domain::Vertex {
+                attribute.values = attribute.values.select(kept);
+            }
+        }
+    }
+
+    /// Follow `Mesh::optimize_layout`: the values of the vertices and
+    /// triangles in their new order, given by their former indices
+    pub(crate) fn reorder(&mut self, vertices: &[usize], triangles: &[usize]) {
+        for attribute in self.channels.values_mut() {
+            attribute.values = match attribute.domain {
+                Domain::Vertex => attribute.values.select(vertices),
+                Domain::Triangle => attribute.values.select(triangles),
+            };
+        }
+    }
+
+    /// Follow `Mesh::subdivide`: the vertices added in the middle of the
+    /// `edges` get the average of their ends, and every triangle is split in
+    /// 4 triangles in a row
+    pub(crate) fn subdivide(&mut self, edges: &[[usize; 2]]) {
+        for attribute in self.channels.values_mut() {
+            attribute.values = match attribute.domain {
+                Domain::Vertex => attribute.values.with_midpoints(edges),
+                Domain::Triangle => attribute.values.repeated(4),
+            };
+        }
+    }
+
+    /// Attributes in the order of their names
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Attribute)> {
+        self.channels.iter().map(|(name, a)| (name.as_str(), a))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Values of a scalar attribute of `domain`
+    pub fn scalar(&self, name: &str, domain: Domain) -> Option<&[f32]> {
+        match self.get(name)? {
+            Attribute {
+                domain: d,
+                values: AttributeValues::Scalar(values),
+            } if *d == domain => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Values of a 2D attribute of `domain`
+    pub fn vec2(&self, name: &str, domain: Domain) -> Option<&[[f32; 2]]> {
+        match self.get(name)? {
+            Attribute {
+                domain: d,
+                values: AttributeValues::Vec2(values),
+            } if *d == domain => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Values of a 3D attribute of `domain`
+    pub fn vec3(&self, name: &str, domain: Domain) -> Option<&[[f32; 3]]> {
+        match self.get(name)? {
+            Attribute {
+                domain: d,
+                values: AttributeValues::Vec3(values),
+            } if *d == domain => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Concatenate the attributes of several meshes, given with their vertex
+    /// and triangle counts, in the order their elements are concatenated
+    ///
+    /// Meshes without an attribute get zeros. Attributes whose type or domain
+    /// differ from one mesh to the other are dropped.
+    pub fn concatenate<'a, I>(parts: I) -> Attributes
+    where
+        I: IntoIterator<Item = (&'a Attributes, usize, usize)> + Clone,
+    {
+        let mut merged = Attributes::default();
+        for (attributes, _, _) in parts.clone() {
+            for (name, attribute) in attributes.iter() {
+                if merged.channels.contains_key(name) {
+                    continue;
+                }
+                let consistent = parts.clone().into_iter().all(|(other, _, _)| {
+                    other.get(name).is_none_or(|o| {
+                        o.domain == attribute.domain && o.values.same_type(&attribute.values)
+                    })
+                });
+                if !consistent {
+                    continue;
+                }
+                let mut values = attribute.values.empty_like();
+                for (other, vertices, triangles) in parts.clone() {
+                    let count = match attribute.domain {
+                        Domain::Vertex => vertices,
+                        Domain::Triangle => triangles,
+                    };
+                    values.extend(other.get(name).map(|o| &o.values), count);
+                }
+                merged.insert(
+                    name,
+                    Attribute {
+                        domain: attribute.domain,
+                        values,
+                    },
+                );
+            }
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concatenation_fills_the_gaps_and_drops_conflicts() {
+        let mut a = Attributes::default();
+        a.insert(
+            "quality",
+            Attribute {
+                domain: Domain::Vertex,
+                values: AttributeValues::Scalar(vec![0.5, 1.0]),
+            },
+        );
+        a.insert(
+            "label",
+            Attribute {
+                domain: Domain::Triangle,
+                values: AttributeValues::Scalar(vec![3.0]),
+            },
+        );
+        let mut b = Attributes::default();
+        b.insert(
+            "label",
+            Attribute {
+                domain: Domain::Vertex,
+                values: AttributeValues::Scalar(vec![1.0, 2.0, 3.0]),
+            },
+        );
+        let none = Attributes::default();
+
+        let merged = Attributes::concatenate(vec![(&a, 2, 1), (&none, 1, 1), (&b, 3, 1)]);
+        assert_eq!(
+            merged.scalar("quality", Domain::Vertex),
+            Some(&[0.5, 1.0, 0.0, 0.0, 0.0, 0.0][..])
+        );
+        assert!(merged.get("label").is_none());
+        assert!(merged.vec3("quality", Domain::Vertex).is_none());
+    }
+}
@@ -1,6 +1,8 @@
 extern crate nalgebra;
+use crate::geometry::mesh::Mesh;
 use crate::geometry::types::{Direction, Position};
 
+#[derive(Clone, Debug)]
 pub struct AxisAlignedBoundingBox {
     pub bounds: [Position; 2],
     pub dim: Position,
@@ -9,7 +11,12 @@ pub struct AxisAlignedBoundingBox {
 }
 
 impl AxisAlignedBoundingBox {
+    /// Smallest box containing the vertices, an empty box at the origin
+    /// when there are none
     pub fn new(vertices: &Vec<Position>) -> Self {
+        if vertices.is_empty() {
+            return Self::from_bounds([Position::origin(), Position::origin()]);
+        }
         let min = vertices
             .iter()
             .fold(vertices[0], |min, vertice| min.inf(vertice));
@@ -76,6 +83,87 @@ impl AxisAlignedBoundingBox {
         ))
     }
 
+    /// Smallest box containing both boxes
+    pub fn union(&self, other: &Self) -> Self {
+        Self::from_bounds([
+            self.bounds[0].inf(&other.bounds[0]),
+            self.bounds[1].sup(&other.bounds[1]),
+        ])
+    }
+
+    /// Is the point inside the box, or on its sides
+    pub fn contains_point(&self, point: &Position) -> bool {
+        (0..3).all(|i| self.bounds[0][i] <= point[i] && point[i] <= self.bounds[1][i])
+    }
+
+    /// Is the other box entirely inside this one
+    pub fn contains_box(&self, other: &Self) -> bool {
+        self.contains_point(&other.bounds[0]) && self.contains_point(&other.bounds[1])
+    }
+
+    /// Area of the six sides, the cost of a node for the surface area
+    /// heuristic
+    pub fn surface_area(&self) -> f64 {
+        2.0 * (self.width() * self.height()
+            + self.height() * self.length()
+            + self.length() * self.width())
+    }
+
+    /// Box grown by `epsilon` on every side, e.g. to keep flat boxes from
+    /// missing the rays along them
+    pub fn expand_by(&self, epsilon: f64) -> Self {
+        let margin = Direction::repeat(epsilon);
+        Self::from_bounds([self.bounds[0] - margin, self.bounds[1] + margin])
+    }
+
+    /// Length of the diagonal, the size of what the box holds
+    pub fn diagonal(&self) -> f64 {
+        self.dim.coords.norm()
+    }
+
+    /// Offset of the origins of secondary rays from the surfaces inside the
+    /// box, to avoid hitting the surface they start from: a millionth of the
+    /// diagonal, more far from the origin where the coordinates are rounded
+    /// more, or none for a box without size
+    pub fn ray_epsilon(&self) -> Option<f64> {
+        let diagonal = self.diagonal();
+        if !(diagonal > 0.0 && diagonal.is_finite()) {
+            return None;
+        }
+        let magnitude = self.bounds[0]
+            .coords
+            .abs()
+            .sup(&self.bounds[1].coords.abs())
+            .max();
+        Some((diagonal * 1e-6).max(magnitude * 1e-12))
+    }
+
+    /// Outward normal of the face of the box `point` lies on, up to
+    /// `tolerance`, e.g. the `ray_epsilon` of the scene: that of the closest
+    /// face on the edges and corners, none when the point is off the box
+    pub fn face_normal_at(&self, point: &Position, tolerance: f64) -> Option<Direction> {
+        if !self.expand_by(tolerance).contains_point(point) {
+            return None;
+        }
+        let (axis, side, _) = (0..3)
+            .flat_map(|axis| (0..2).map(move |side| (axis, side)))
+            .map(|(axis, side)| (axis, side, (point[axis] - self.bounds[side][axis]).abs()))
+            .filter(|&(_, _, distance)| distance <= tolerance)
+            .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap())?;
+        let mut normal = Direction::zeros();
+        normal[axis] = if side == 0 { -1.0 } else { 1.0 };
+        Some(normal)
+    }
+
+    /// Distance from `point` to the farthest point of the box, beyond which
+    /// the rays from `point` cannot hit anything inside it
+    pub fn farthest_distance(&self, point: &Position) -> f64 {
+        (self.bounds[0] - point)
+            .abs()
+            .sup(&(self.bounds[1] - point).abs())
+            .norm()
+    }
+
     fn projected_radius(&self, axis: &Direction) -> f64 {
         self.extent.dot(&axis.abs())
     }
@@ -170,6 +258,151 @@ impl AxisAlignedBoundingBox {
     }
 }
 
+/// Sphere containing a set of points
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Position,
+    pub radius: f64,
+}
+
+impl BoundingSphere {
+    /// Sphere containing the points, a few percent larger than the smallest
+    /// one (Ritter's algorithm); a point at the origin when there are none
+    ///
+    /// # Reference
+    /// * Jack Ritter, An Efficient Bounding Sphere, Graphics Gems (1990)
+    pub fn from_points(points: &[Position]) -> Self {
+        let first = match points.first() {
+            Some(first) => first,
+            None => {
+                return BoundingSphere {
+                    center: Position::origin(),
+                    radius: 0.0,
+                }
+            }
+        };
+        // Start from two points far apart
+        let farthest = |from: &Position| {
+            *points
+                .iter()
+                .max_by(|a, b| {
+                    (*a - from)
+                        .norm_squared()
+                        .total_cmp(&(*b - from).norm_squared())
+                })
+                .unwrap()
+        };
+        let a = farthest(first);
+        let b = farthest(&a);
+        let mut sphere = BoundingSphere {
+            center: nalgebra::center(&a, &b),
+            radius: (b - a).norm() / 2.0,
+        };
+        // Grow it just enough to include the points outside
+        for point in points {
+            let distance = (point - sphere.center).norm();
+            if distance > sphere.radius {
+                let radius = (sphere.radius + distance) / 2.0;
+                sphere.center += (point - sphere.center) * ((distance - radius) / distance);
+                sphere.radius = radius;
+            }
+        }
+        sphere
+    }
+
+    pub fn contains(&self, point: &Position) -> bool {
+        (point - self.center).norm() <= self.radius
+    }
+}
+
+/// Box aligned with the principal axes of a set of points, usually much
+/// tighter than the `AxisAlignedBoundingBox` of rotated or elongated objects
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrientedBoundingBox {
+    pub center: Position,
+    /// Orthonormal and right handed, from the largest spread of the points to
+    /// the smallest
+    pub axes: [Direction; 3],
+    /// Half of the size of the box along each axis
+    pub half_extents: Direction,
+}
+
+impl OrientedBoundingBox {
+    /// Box along the eigenvectors of the covariance of the points (principal
+    /// component analysis); an empty box at the origin when there are none
+    pub fn from_points(points: &[Position]) -> Self {
+        let mut axes = [
+            Direction::new(1.0, 0.0, 0.0),
+            Direction::new(0.0, 1.0, 0.0),
+            Direction::new(0.0, 0.0, 1.0),
+        ];
+        if points.is_empty() {
+            return OrientedBoundingBox {
+                center: Position::origin(),
+                axes,
+                half_extents: Direction::zeros(),
+            };
+        }
+        let count = points.len() as f64;
+        let mean = points.iter().map(|p| p.coords).sum::<Direction>() / count;
+        let covariance = points
+            .iter()
+            .map(|p| (p.coords - mean) * (p.coords - mean).transpose())
+            .sum::<nalgebra::Matrix3<f64>>()
+            / count;
+        let eigen = covariance.symmetric_eigen();
+        let mut order = [0, 1, 2];
+        order.sort_by(|&i, &j| eigen.eigenvalues[j].total_cmp(&eigen.eigenvalues[i]));
+        if eigen.eigenvalues.iter().all(|v| v.is_finite()) {
+            axes[0] = eigen.eigenvectors.column(order[0]).normalize();
+            axes[1] = eigen.eigenvectors.column(order[1]).normalize();
+            axes[2] = axes[0].cross(&axes[1]);
+        }
+
+        let mut min = Direction::repeat(f64::INFINITY);
+        let mut max = Direction::repeat(f64::NEG_INFINITY);
+        for point in points {
+            for (i, axis) in axes.iter().enumerate() {
+                let projection = axis.dot(&point.coords);
+                min[i] = min[i].min(projection);
+                max[i] = max[i].max(projection);
+            }
+        }
+        let middle = (min + max) / 2.0;
+        OrientedBoundingBox {
+            center: Position::from(axes[0] * middle.x + axes[1] * middle.y + axes[2] * middle.z),
+            axes,
+            half_extents: (max - min) / 2.0,
+        }
+    }
+
+    pub fn contains(&self, point: &Position) -> bool {
+        let offset = point - self.center;
+        self.axes
+            .iter()
+            .zip(self.half_extents.iter())
+            .all(|(axis, half_extent)| axis.dot(&offset).abs() <= *half_extent)
+    }
+
+    pub fn volume(&self) -> f64 {
+        8.0 * self.half_extents.x * self.half_extents.y * self.half_extents.z
+    }
+}
+
+impl Mesh {
+    pub fn bounding_box(&self) -> AxisAlignedBoundingBox {
+        AxisAlignedBoundingBox::new(&self.vertices)
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(&self.vertices)
+    }
+
+    pub fn oriented_bounding_box(&self) -> OrientedBoundingBox {
+        OrientedBoundingBox::from_points(&self.vertices)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,4 +459,114 @@ mod tests {
 
         assert!(aabb.intersect_triangle(t0, t1, t2, None));
     }
+
+    #[test]
+    fn ray_tolerances_follow_the_size_of_the_box() {
+        let unit = AxisAlignedBoundingBox::from_bounds([
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 2.0, 2.0),
+        ]);
+        assert_eq!(unit.diagonal(), 3.0);
+        let epsilon = unit.ray_epsilon().unwrap();
+        assert!((epsilon - 3e-6).abs() < 1e-18);
+        // Scaled with the scene
+        let tiny = AxisAlignedBoundingBox::from_bounds([
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1e-9, 2e-9, 2e-9),
+        ]);
+        assert!((tiny.ray_epsilon().unwrap() / epsilon - 1e-9).abs() < 1e-12);
+        // Far from the origin, the rounding of the coordinates wins
+        let far = AxisAlignedBoundingBox::from_bounds([
+            Position::new(1e9, 0.0, 0.0),
+            Position::new(1e9 + 1.0, 2.0, 2.0),
+        ]);
+        assert!(far.ray_epsilon().unwrap() > 1e-4);
+        assert_eq!(AxisAlignedBoundingBox::new(&Vec::new()).ray_epsilon(), None);
+
+        assert_eq!(unit.farthest_distance(&Position::new(0.0, 0.0, 0.0)), 3.0);
+        assert_eq!(unit.farthest_distance(&Position::new(0.5, 1.0, 1.0)), 1.5);
+        let outside = unit.farthest_distance(&Position::new(-2.0, 0.0, 0.0));
+        assert!((outside - 17f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn face_normals_of_points_on_the_box() {
+        let aabb = AxisAlignedBoundingBox::from_bounds([
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 2.0, 2.0),
+        ]);
+        let normal = |x, y, z| aabb.face_normal_at(&Position::new(x, y, z), 1e-9);
+        assert_eq!(normal(0.0, 1.0, 1.0), Some(-Direction::x()));
+        assert_eq!(normal(1.0, 1.0, 1.0), Some(Direction::x()));
+        assert_eq!(normal(0.5, 2.0, 1.0), Some(Direction::y()));
+        assert_eq!(normal(0.5, 1.0, 0.0), Some(-Direction::z()));
+        // Rounded off the face, on either side
+        assert_eq!(normal(0.5, 1.0, 2.0 + 1e-12), Some(Direction::z()));
+        assert_eq!(normal(0.5, 1.0, 2.0 - 1e-12), Some(Direction::z()));
+        // The closest face on an edge
+        assert_eq!(normal(1.0 - 1e-10, 2.0, 1.0), Some(Direction::y()));
+        // Inside, outside, or in the plane of a face but off it
+        assert_eq!(normal(0.5, 1.0, 1.0), None);
+        assert_eq!(normal(0.5, 1.0, 3.0), None);
+        assert_eq!(normal(0.0, 3.0, 1.0), None);
+    }
+
+    #[test]
+    fn union_containment_and_surface_area() {
+        let a = AxisAlignedBoundingBox::from_bounds([
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 2.0, 3.0),
+        ]);
+        let b = AxisAlignedBoundingBox::from_bounds([
+            Position::new(-1.0, 1.0, 1.0),
+            Position::new(0.5, 4.0, 2.0),
+        ]);
+        let union = a.union(&b);
+        assert_eq!(union.bounds[0], Position::new(-1.0, 0.0, 0.0));
+        assert_eq!(union.bounds[1], Position::new(1.0, 4.0, 3.0));
+        assert!(union.contains_box(&a) && union.contains_box(&b));
+        assert!(!a.contains_box(&b));
+        assert!(a.contains_point(&Position::new(1.0, 0.5, 3.0)));
+        assert!(!a.contains_point(&Position::new(1.0, -0.5, 3.0)));
+        assert_eq!(a.surface_area(), 22.0);
+
+        let expanded = a.expand_by(0.5);
+        assert!(expanded.contains_box(&a));
+        assert_eq!(expanded.bounds[0], Position::new(-0.5, -0.5, -0.5));
+        assert_eq!(expanded.surface_area(), 2.0 * (6.0 + 12.0 + 8.0));
+    }
+
+    #[test]
+    fn sphere_and_oriented_box_of_a_rotated_slab() {
+        // 4 x 1 x 0.5 slab turned by 30° around z and moved away
+        let rotation =
+            nalgebra::Rotation3::from_axis_angle(&Direction::z_axis(), std::f64::consts::FRAC_PI_6);
+        let offset = Direction::new(5.0, -2.0, 1.0);
+        let mut points = Vec::new();
+        for &x in &[-2.0, 2.0] {
+            for &y in &[-0.5, 0.5] {
+                for &z in &[-0.25, 0.25] {
+                    points.push(rotation * Position::new(x, y, z) + offset);
+                }
+            }
+        }
+
+        let sphere = BoundingSphere::from_points(&points);
+        assert!(points
+            .iter()
+            .all(|p| (p - sphere.center).norm() <= sphere.radius + 1e-9));
+        // Half of the diagonal
+        assert!((sphere.radius - 4.3125f64.sqrt()).abs() < 1e-9);
+
+        let obb = OrientedBoundingBox::from_points(&points);
+        assert!((obb.center - Position::from(offset)).norm() < 1e-9);
+        assert!((obb.half_extents - Direction::new(2.0, 0.5, 0.25)).norm() < 1e-9);
+        assert!(obb.axes[0].dot(&(rotation * Direction::x())).abs() > 1.0 - 1e-9);
+        assert!((obb.volume() - 2.0).abs() < 1e-9);
+        let aabb = AxisAlignedBoundingBox::new(&points);
+        assert!(aabb.width() * aabb.height() * aabb.length() > 2.5 * obb.volume());
+        assert!(points
+            .iter()
+            .all(|p| obb.contains(&(p + (obb.center - p) * 1e-9))));
+    }
 }
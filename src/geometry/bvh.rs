@@ -0,0 +1,606 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::kdtree::KdTree;
+use crate::geometry::layout::morton_code;
+use crate::geometry::mesh::Mesh;
+use crate::geometry::types::Position;
+
+/// Triangles under which the halves of a node are built on the same thread
+const PARALLEL_SIZE: usize = 4096;
+
+/// How the tree of the boxes around the triangles is built, trading the
+/// speed of the renders for the time of the build
+///
+/// Spelled in snake case on the command line and in configuration files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TreeBuild {
+    /// Kd-tree split at the median of the vertices, see `KdTree::from_mesh`
+    #[default]
+    KdTree,
+    /// Bounding volume hierarchy split by the surface area heuristic: the
+    /// fastest renders, the slowest build
+    Sah,
+    /// Linear bounding volume hierarchy split along a Morton curve: the
+    /// fastest build, e.g. for previews of scenes being edited
+    Lbvh,
+}
+
+impl TreeBuild {
+    pub const ALL: [TreeBuild; 3] = [TreeBuild::KdTree, TreeBuild::Sah, TreeBuild::Lbvh];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TreeBuild::KdTree => "kd_tree",
+            TreeBuild::Sah => "sah",
+            TreeBuild::Lbvh => "lbvh",
+        }
+    }
+}
+
+impl fmt::Display for TreeBuild {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TreeBuild {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TreeBuild::ALL
+            .iter()
+            .cloned()
+            .find(|build| build.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = TreeBuild::ALL.iter().map(|b| b.name()).collect();
+                format!(
+                    "unknown tree build {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Trade between the time of the tree builds and the speed of the renders,
+/// whatever the `TreeBuild`, without tuning the parameters of the builders
+///
+/// Spelled in snake case on the command line and in configuration files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TreePreset {
+    /// Large leaves and coarse splits, e.g. for scenes being edited
+    FastestBuild,
+    #[default]
+    Balanced,
+    /// Small leaves and fine splits, e.g. for final renders
+    FastestTrace,
+}
+
+impl TreePreset {
+    pub const ALL: [TreePreset; 3] = [
+        TreePreset::FastestBuild,
+        TreePreset::Balanced,
+        TreePreset::FastestTrace,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TreePreset::FastestBuild => "fastest_build",
+            TreePreset::Balanced => "balanced",
+            TreePreset::FastestTrace => "fastest_trace",
+        }
+    }
+
+    pub fn parameters(self) -> TreeParameters {
+        match self {
+            TreePreset::FastestBuild => TreeParameters {
+                kd_tree_leaf_vertices: 32,
+                leaf_triangles: 8,
+                sah_bins: 4,
+            },
+            TreePreset::Balanced => TreeParameters {
+                kd_tree_leaf_vertices: 10,
+                leaf_triangles: 4,
+                sah_bins: 16,
+            },
+            TreePreset::FastestTrace => TreeParameters {
+                kd_tree_leaf_vertices: 6,
+                leaf_triangles: 2,
+                sah_bins: 32,
+            },
+        }
+    }
+}
+
+impl fmt::Display for TreePreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TreePreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TreePreset::ALL
+            .iter()
+            .cloned()
+            .find(|preset| preset.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = TreePreset::ALL.iter().map(|p| p.name()).collect();
+                format!(
+                    "unknown tree preset {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Parameters of the tree builders, usually those of a `TreePreset`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TreeParameters {
+    /// Vertices under which a kd-tree node is not split
+    pub kd_tree_leaf_vertices: usize,
+    /// Triangles under which a node of a bounding volume hierarchy is not
+    /// split
+    pub leaf_triangles: usize,
+    /// Candidate splits per axis of the surface area heuristic
+    pub sah_bins: usize,
+}
+
+impl Default for TreeParameters {
+    fn default() -> Self {
+        TreePreset::default().parameters()
+    }
+}
+
+/// Bounds and center of a triangle, what the hierarchies are built from
+struct Primitive {
+    index: usize,
+    bounds: AxisAlignedBoundingBox,
+    center: Position,
+}
+
+impl KdTree {
+    /// Tree of `mesh` built the way `build` says, with the parameters of
+    /// `preset`
+    pub fn build(mesh: &Mesh, build: TreeBuild, preset: TreePreset) -> Box<KdTree> {
+        let parameters = preset.parameters();
+        match build {
+            TreeBuild::KdTree => KdTree::from_mesh_with(mesh, &parameters),
+            TreeBuild::Sah => KdTree::from_mesh_sah_with(mesh, &parameters),
+            TreeBuild::Lbvh => KdTree::from_mesh_lbvh_with(mesh, &parameters),
+        }
+    }
+
+    /// Bounding volume hierarchy of `mesh`, traversed as the kd-tree
+    ///
+    /// Every triangle is in a single leaf, whose box fits its triangles, the
+    /// boxes of siblings possibly overlapping. Nodes are split where the
+    /// surface area heuristic expects the fewest intersection tests, among
+    /// 16 planes per axis through the centers of the triangles.
+    pub fn from_mesh_sah(mesh: &Mesh) -> Box<KdTree> {
+        KdTree::from_mesh_sah_with(mesh, &TreeParameters::default())
+    }
+
+    /// Same as `from_mesh_sah`, with leaves of `parameters.leaf_triangles`
+    /// and `parameters.sah_bins` planes per axis
+    pub fn from_mesh_sah_with(mesh: &Mesh, parameters: &TreeParameters) -> Box<KdTree> {
+        let _span = tracing::info_span!("build_sah", triangles = mesh.triangles.len()).entered();
+        let primitives = primitives(mesh);
+        Box::new(build_sah(Some(mesh), primitives, parameters))
+    }
+
+    /// Bounding volume hierarchy of `boxes`, e.g. of the objects of a scene,
+    /// split as `from_mesh_sah_with` splits triangles
+    ///
+    /// Its leaves list the indices of their boxes in `triangle_index`, and no
+    /// vertices.
+    pub fn from_boxes_sah_with(
+        boxes: &[AxisAlignedBoundingBox],
+        parameters: &TreeParameters,
+    ) -> Box<KdTree> {
+        let primitives = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, bounds)| Primitive {
+                index,
+                bounds: AxisAlignedBoundingBox::from_bounds(bounds.bounds),
+                center: bounds.center,
+            })
+            .collect();
+        Box::new(build_sah(None, primitives, parameters))
+    }
+
+    /// Linear bounding volume hierarchy of `mesh`, as `from_mesh_sah` but
+    /// much faster to build and slower to traverse
+    ///
+    /// The triangles are sorted along a Morton curve through their centers,
+    /// with a parallel radix sort, and every node is split where the codes
+    /// of its triangles first differ.
+    pub fn from_mesh_lbvh(mesh: &Mesh) -> Box<KdTree> {
+        KdTree::from_mesh_lbvh_with(mesh, &TreeParameters::default())
+    }
+
+    /// Same as `from_mesh_lbvh`, with leaves of `parameters.leaf_triangles`
+    pub fn from_mesh_lbvh_with(mesh: &Mesh, parameters: &TreeParameters) -> Box<KdTree> {
+        let _span = tracing::info_span!("build_lbvh", triangles = mesh.triangles.len()).entered();
+        let primitives = primitives(mesh);
+        let centers: Vec<Position> = primitives.iter().map(|p| p.center).collect();
+        let bounds = AxisAlignedBoundingBox::new(&centers);
+        let mut codes: Vec<(u64, usize)> = centers
+            .par_iter()
+            .enumerate()
+            .map(|(i, center)| (morton_code(center, &bounds), i))
+            .collect();
+        radix_sort(&mut codes);
+        let sorted: Vec<&Primitive> = codes.iter().map(|&(_, i)| &primitives[i]).collect();
+        let codes: Vec<u64> = codes.iter().map(|&(code, _)| code).collect();
+        Box::new(build_lbvh(mesh, &codes, &sorted, parameters.leaf_triangles))
+    }
+}
+
+fn primitives(mesh: &Mesh) -> Vec<Primitive> {
+    mesh.triangles
+        .par_iter()
+        .enumerate()
+        .map(|(index, triangle)| {
+            let corners = triangle.map(|v| mesh.vertices[v]);
+            let bounds = AxisAlignedBoundingBox::from_bounds([
+                corners[0].inf(&corners[1]).inf(&corners[2]),
+                corners[0].sup(&corners[1]).sup(&corners[2]),
+            ]);
+            let center =
+                Position::from((corners[0].coords + corners[1].coords + corners[2].coords) / 3.0);
+            Primitive {
+                index,
+                bounds,
+                center,
+            }
+        })
+        .collect()
+}
+
+/// Leaf of the triangles, with the vertices they use when they are those of
+/// `mesh`
+fn leaf(mesh: Option<&Mesh>, primitives: &[&Primitive]) -> KdTree {
+    let triangles: Vec<usize> = primitives.iter().map(|p| p.index).collect();
+    let mut vertices: Vec<usize> = match mesh {
+        Some(mesh) => triangles.iter().flat_map(|&t| mesh.triangles[t]).collect(),
+        None => Vec::new(),
+    };
+    vertices.sort_unstable();
+    vertices.dedup();
+    KdTree::new_leaf(bounds_of(primitives), vertices, triangles)
+}
+
+/// Box around the triangles, grown a little so that flat boxes do not miss
+/// the rays along them
+fn bounds_of(primitives: &[&Primitive]) -> AxisAlignedBoundingBox {
+    let first = AxisAlignedBoundingBox::from_bounds(primitives[0].bounds.bounds);
+    let bounds = primitives[1..]
+        .iter()
+        .fold(first, |b, p| b.union(&p.bounds));
+    let size = bounds.get_dimension(bounds.largest_dim());
+    bounds.expand_by(size * 1e-9 + 1e-12)
+}
+
+/// Node over both halves, built in parallel when they are large
+fn node<F, G>(left: F, right: G, parallel: bool) -> KdTree
+where
+    F: FnOnce() -> KdTree + Send,
+    G: FnOnce() -> KdTree + Send,
+{
+    let (left, right) = if parallel {
+        rayon::join(left, right)
+    } else {
+        (left(), right())
+    };
+    let bounds = left.bounding_box.union(&right.bounding_box);
+    KdTree::new_node(bounds, Some(Box::new(left)), Some(Box::new(right)))
+}
+
+fn build_sah(
+    mesh: Option<&Mesh>,
+    primitives: Vec<Primitive>,
+    parameters: &TreeParameters,
+) -> KdTree {
+    fn recurse(
+        mesh: Option<&Mesh>,
+        primitives: &mut [&Primitive],
+        parameters: &TreeParameters,
+    ) -> KdTree {
+        let bins_per_axis = parameters.sah_bins.max(2);
+        if primitives.len() <= parameters.leaf_triangles.max(1) {
+            return leaf(mesh, primitives);
+        }
+        let centers: Vec<Position> = primitives.iter().map(|p| p.center).collect();
+        let center_bounds = AxisAlignedBoundingBox::new(&centers);
+        let bin_of = |primitive: &Primitive, axis: usize| {
+            let size = center_bounds.get_dimension(axis);
+            let t = (primitive.center[axis] - center_bounds.bounds[0][axis]) / size;
+            ((t * bins_per_axis as f64) as usize).min(bins_per_axis - 1)
+        };
+
+        // Cheapest split: the area of each side times its triangles
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            if center_bounds.get_dimension(axis) <= 0.0 {
+                continue;
+            }
+            let mut bins: Vec<(usize, Option<AxisAlignedBoundingBox>)> =
+                (0..bins_per_axis).map(|_| (0, None)).collect();
+            for primitive in primitives.iter() {
+                let (count, bounds) = &mut bins[bin_of(primitive, axis)];
+                *count += 1;
+                *bounds = Some(match bounds.take() {
+                    Some(b) => b.union(&primitive.bounds),
+                    None => AxisAlignedBoundingBox::from_bounds(primitive.bounds.bounds),
+                });
+            }
+            let sweep = |bins: &mut dyn Iterator<
+                Item = &(usize, Option<AxisAlignedBoundingBox>),
+            >| {
+                let mut count = 0;
+                let mut bounds: Option<AxisAlignedBoundingBox> = None;
+                let mut costs = Vec::with_capacity(bins_per_axis);
+                for (bin_count, bin_bounds) in bins {
+                    count += bin_count;
+                    if let Some(bin_bounds) = bin_bounds {
+                        bounds = Some(match bounds {
+                            Some(b) => b.union(bin_bounds),
+                            None => AxisAlignedBoundingBox::from_bounds(bin_bounds.bounds),
+                        });
+                    }
+                    costs.push(bounds.as_ref().map_or(0.0, |b| b.surface_area()) * count as f64);
+                }
+                costs
+            };
+            let left = sweep(&mut bins.iter());
+            let mut right = sweep(&mut bins.iter().rev());
+            right.reverse();
+            // Split after bin `split`
+            for split in 0..bins_per_axis - 1 {
+                let cost = left[split] + right[split + 1];
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (axis, split) = match best {
+            Some((_, axis, split)) => (axis, split),
+            // All the centers at the same place
+            None => return leaf(mesh, primitives),
+        };
+        let mut middle = 0;
+        for i in 0..primitives.len() {
+            if bin_of(primitives[i], axis) <= split {
+                primitives.swap(i, middle);
+                middle += 1;
+            }
+        }
+        if middle == 0 || middle == primitives.len() {
+            middle = primitives.len() / 2;
+        }
+        let parallel = primitives.len() > PARALLEL_SIZE;
+        let (left, right) = primitives.split_at_mut(middle);
+        node(
+            || recurse(mesh, left, parameters),
+            || recurse(mesh, right, parameters),
+            parallel,
+        )
+    }
+
+    if primitives.is_empty() {
+        return KdTree::new_leaf(
+            AxisAlignedBoundingBox::new(&Vec::new()),
+            Vec::new(),
+            Vec::new(),
+        );
+    }
+    let mut references: Vec<&Primitive> = primitives.iter().collect();
+    recurse(mesh, &mut references, parameters)
+}
+
+fn build_lbvh(
+    mesh: &Mesh,
+    codes: &[u64],
+    primitives: &[&Primitive],
+    leaf_triangles: usize,
+) -> KdTree {
+    if primitives.is_empty() {
+        return KdTree::new_leaf(
+            AxisAlignedBoundingBox::new(&Vec::new()),
+            Vec::new(),
+            Vec::new(),
+        );
+    }
+    if primitives.len() <= leaf_triangles.max(1) {
+        return leaf(Some(mesh), primitives);
+    }
+    let (first, last) = (codes[0], codes[codes.len() - 1]);
+    let middle = if first == last {
+        codes.len() / 2
+    } else {
+        // First code with the highest differing bit set
+        let bit = 63 - (first ^ last).leading_zeros();
+        codes.partition_point(|&code| code & (1 << bit) == 0)
+    };
+    let parallel = primitives.len() > PARALLEL_SIZE;
+    node(
+        || {
+            build_lbvh(
+                mesh,
+                &codes[..middle],
+                &primitives[..middle],
+                leaf_triangles,
+            )
+        },
+        || {
+            build_lbvh(
+                mesh,
+                &codes[middle..],
+                &primitives[middle..],
+                leaf_triangles,
+            )
+        },
+        parallel,
+    )
+}
+
+/// Sort by the codes: the values are dealt in 256 buckets by the high byte
+/// of their code, then the buckets are sorted in parallel byte after byte
+fn radix_sort(values: &mut Vec<(u64, usize)>) {
+    let digit = |code: u64, byte: u32| (code >> (8 * byte) & 0xff) as usize;
+    let counts = values
+        .par_chunks(PARALLEL_SIZE)
+        .map(|chunk| {
+            let mut counts = [0usize; 256];
+            for &(code, _) in chunk {
+                counts[digit(code, 7)] += 1;
+            }
+            counts
+        })
+        .reduce(
+            || [0; 256],
+            |mut a, b| {
+                a.iter_mut().zip(&b).for_each(|(a, b)| *a += b);
+                a
+            },
+        );
+    let mut offsets = [0; 256];
+    let mut total = 0;
+    for (offset, count) in offsets.iter_mut().zip(&counts) {
+        *offset = total;
+        total += count;
+    }
+    let mut sorted = vec![(0, 0); values.len()];
+    for &value in values.iter() {
+        let bucket = &mut offsets[digit(value.0, 7)];
+        sorted[*bucket] = value;
+        *bucket += 1;
+    }
+
+    let mut buckets = Vec::with_capacity(256);
+    let mut rest = &mut sorted[..];
+    for &count in counts.iter() {
+        let (bucket, tail) = rest.split_at_mut(count);
+        buckets.push(bucket);
+        rest = tail;
+    }
+    buckets.par_iter_mut().for_each(|bucket| {
+        let mut scratch = vec![(0, 0); bucket.len()];
+        for byte in 0..7 {
+            let mut offsets = [0usize; 256];
+            for &(code, _) in bucket.iter() {
+                offsets[digit(code, byte)] += 1;
+            }
+            // Every value has the same digit, nothing moves
+            if offsets.contains(&bucket.len()) {
+                continue;
+            }
+            let mut total = 0;
+            for offset in offsets.iter_mut() {
+                let count = *offset;
+                *offset = total;
+                total += count;
+            }
+            for &value in bucket.iter() {
+                let offset = &mut offsets[digit(value.0, byte)];
+                scratch[*offset] = value;
+                *offset += 1;
+            }
+            bucket.copy_from_slice(&scratch);
+        }
+    });
+    *values = sorted;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::terrain::HeightMap;
+    use crate::geometry::types::Direction;
+    use crate::render::config::{CameraConfig, Integrator, RenderingConfig};
+    use crate::render::image::render_hdr_image;
+    use crate::render::ray_tracer::{make_kdt_ray_tracer, make_naive_ray_tracer};
+
+    #[test]
+    fn every_tree_build_finds_the_same_hits() {
+        let mut values: Vec<(u64, usize)> = (0..20_000)
+            .map(|i: u64| (i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 1, i as usize))
+            .collect();
+        let mut expected = values.clone();
+        expected.sort_unstable();
+        radix_sort(&mut values);
+        assert_eq!(values, expected);
+
+        let heights = HeightMap::from_fn(16, 16, |x, y| {
+            ::image::Luma([((x * 7 + y * 13) % 17 * 3000) as u16])
+        });
+        let mesh = Mesh::terrain(&heights, 1.0, 4.0).unwrap();
+        let camera_config = CameraConfig::look_at(
+            Position::new(-15.0, 20.0, 25.0),
+            Position::new(0.0, 1.0, 0.0),
+            Direction::y(),
+            50.0,
+            48,
+            32,
+        );
+        let rendering_config = RenderingConfig {
+            integrator: Integrator::FacingRatio,
+            ..Default::default()
+        };
+        let tracer =
+            make_naive_ray_tracer(&mesh, &camera_config, &rendering_config, Default::default());
+        let expected = render_hdr_image(tracer, &camera_config, &rendering_config);
+        for &build in TreeBuild::ALL.iter() {
+            let mut nodes = Vec::new();
+            for &preset in TreePreset::ALL.iter() {
+                let tree = KdTree::build(&mesh, build, preset);
+                let stats = tree.stats();
+                if build != TreeBuild::KdTree {
+                    assert_eq!(stats.triangle_references, mesh.triangles.len());
+                    assert!(stats.max_leaf_triangles <= preset.parameters().leaf_triangles);
+                }
+                nodes.push(stats.nodes);
+                let tracer = make_kdt_ray_tracer(
+                    &mesh,
+                    &tree,
+                    &camera_config,
+                    &rendering_config,
+                    Default::default(),
+                );
+                let img = render_hdr_image(tracer, &camera_config, &rendering_config);
+                let different = img
+                    .pixels()
+                    .zip(expected.pixels())
+                    .filter(|(a, b)| (0..3).any(|c| (a[c] - b[c]).abs() > 1e-4))
+                    .count();
+                assert!(
+                    different <= 2,
+                    "{} {}: {} pixels differ",
+                    build,
+                    preset,
+                    different
+                );
+            }
+            // Finer trees for faster traces
+            assert!(nodes[0] < nodes[1] && nodes[1] < nodes[2], "{:?}", nodes);
+        }
+        assert_eq!("lbvh".parse::<TreeBuild>(), Ok(TreeBuild::Lbvh));
+        assert_eq!(
+            "fastest_trace".parse::<TreePreset>(),
+            Ok(TreePreset::FastestTrace)
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use crate::geometry::mesh::{weld_map, Mesh};
+use crate::geometry::types::{Direction, Position};
+use crate::render::material::Texture;
+
+impl Mesh {
+    /// Split every triangle in 4 at the middle of its edges, without changing
+    /// the shape, e.g. for `displace` to follow finer details
+    ///
+    /// The vertices added in the middle of an edge get the average normal,
+    /// colors, texture coordinates and attributes of its ends, tangents are
+    /// computed again, and the triangles keep their material and attributes.
+    /// `polygons` are dropped.
+    pub fn subdivide(&mut self) {
+        let mut midpoints: HashMap<[usize; 2], usize> = HashMap::new();
+        let mut edges: Vec<[usize; 2]> = Vec::new();
+        let first = self.vertices.len();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry([a.min(b), a.max(b)]).or_insert_with(|| {
+                edges.push([a, b]);
+                first + edges.len() - 1
+            })
+        };
+        let triangles: Vec<[usize; 3]> = self
+            .triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+            })
+            .collect();
+
+        fn average<T: Copy>(values: &mut Vec<T>, edges: &[[usize; 2]], mid: impl Fn(T, T) -> T) {
+            let midpoints: Vec<T> = edges
+                .iter()
+                .map(|&[a, b]| mid(values[a], values[b]))
+                .collect();
+            values.extend(midpoints);
+        }
+        average(&mut self.vertices, &edges, |a, b| nalgebra::center(&a, &b));
+        average(&mut self.vertex_normals, &edges, |a, b| {
+            (a + b).try_normalize(1e-12).unwrap_or(a)
+        });
+        for colors in self.vertex_colors.iter_mut() {
+            average(colors, &edges, |a, b| {
+                [
+                    (a[0] + b[0]) / 2.0,
+                    (a[1] + b[1]) / 2.0,
+                    (a[2] + b[2]) / 2.0,
+                ]
+            });
+        }
+        for uvs in self.uvs.iter_mut().chain(self.extra_uvs.iter_mut()) {
+            average(uvs, &edges, |a, b| {
+                [(a[0] + b[0]) / 2.0, (a[1] + b[1]) / 2.0]
+            });
+        }
+        self.attributes.subdivide(&edges);
+        self.triangle_materials = self
+            .triangle_materials
+            .as_ref()
+            .map(|materials| materials.iter().flat_map(|&m| [m; 4]).collect());
+        self.triangles = triangles;
+        self.polygons = None;
+        self.triangle_normals = self
+            .triangle_normals
+            .iter()
+            .flat_map(|&normal| [normal; 4])
+            .collect();
+        if self.vertex_tangents.is_some() {
+            self.compute_tangents();
+        }
+    }
+
+    /// Move the vertices along their normals by `scale * (height - midlevel)`,
+    /// the height being read from `height_map` through the first UV set, then
+    /// compute the normals again
+    ///
+    /// Vertices at the same position, e.g. split along the seams of the
+    /// texture coordinates, move together by their average height so that
+    /// the surface does not tear. Fails without texture coordinates.
+    pub fn displace(
+        &mut self,
+        height_map: &Texture,
+        scale: f64,
+        midlevel: f64,
+    ) -> Result<(), String> {
+        let uvs = self
+            .uvs
+            .as_ref()
+            .ok_or("the mesh has no texture coordinates to displace along")?;
+        let (remap, kept) = weld_map(&self.vertices, 0.0);
+        let mut heights = vec![0.0; kept.len()];
+        let mut normals = vec![Direction::zeros(); kept.len()];
+        let mut counts = vec![0.0; kept.len()];
+        for (vertex, &group) in remap.iter().enumerate() {
+            heights[group] += height_map.sample(uvs[vertex]).luminance();
+            normals[group] += self.vertex_normals[vertex];
+            counts[group] += 1.0;
+        }
+        for (vertex, &group) in remap.iter().enumerate() {
+            let normal = (normals[group] / counts[group])
+                .try_normalize(1e-12)
+                .unwrap_or(self.vertex_normals[vertex]);
+            let offset = scale * (heights[group] / counts[group] - midlevel);
+            self.vertices[vertex] = Position::from(self.vertices[vertex].coords + normal * offset);
+        }
+        self.recompute_normals();
+        if self.vertex_tangents.is_some() {
+            self.compute_tangents();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::attributes::{AttributeValues, Domain};
+    use crate::render::image::RgbImage;
+    use std::sync::Arc;
+
+    #[test]
+    fn subdivided_quads_are_displaced_by_the_height_map() {
+        let vertices = vec![
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 0.0, 0.0),
+            Position::new(1.0, 0.0, -1.0),
+            Position::new(0.0, 0.0, -1.0),
+        ];
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, vec![[0, 1, 2], [0, 2, 3]]);
+        mesh.uvs = Some(vec![[0.25, 0.0], [0.75, 0.0], [0.75, 1.0], [0.25, 1.0]]);
+        mesh.set_attribute(
+            "label",
+            Domain::Triangle,
+            AttributeValues::Scalar(vec![1.0, 2.0]),
+        );
+        mesh.subdivide();
+        // 5 edges get a vertex in their middle, the diagonal included
+        assert_eq!(mesh.vertices.len(), 9);
+        assert_eq!(mesh.triangles.len(), 8);
+        let center = mesh
+            .vertices
+            .iter()
+            .position(|v| *v == Position::new(0.5, 0.0, -0.5))
+            .unwrap();
+        assert_eq!(mesh.uvs.as_ref().unwrap()[center], [0.5, 0.5]);
+        let label = mesh.attributes.scalar("label", Domain::Triangle).unwrap();
+        assert_eq!(label, &[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0][..]);
+
+        // Black then white texel centers, at u = 0.25 and u = 0.75
+        let image = RgbImage::from_fn(2, 1, |x, _| ::image::Rgb([(x * 255) as u8; 3]));
+        mesh.displace(&Texture::data(Arc::new(image)), 0.1, 0.5)
+            .unwrap();
+        let height = |x: f64| {
+            let v = mesh
+                .vertices
+                .iter()
+                .find(|v| v.x == x && v.z == 0.0)
+                .unwrap();
+            v.y
+        };
+        assert!((height(0.0) + 0.05).abs() < 1e-9);
+        assert!((height(1.0) - 0.05).abs() < 1e-9);
+        // Tilted toward -x, where the surface is lower
+        assert!(mesh.vertex_normals[center].x < 0.0);
+
+        mesh.uvs = None;
+        assert!(mesh
+            .displace(&Texture::data(Arc::new(RgbImage::new(1, 1))), 1.0, 0.0)
+            .is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::geometry::mesh::Mesh;
+
+impl Mesh {
+    /// Save the mesh as a Wavefront OBJ file
+    ///
+    /// Vertex normals are written as `vn` lines, texture coordinates (when
+    /// present) as `vt` lines and vertex colors (when present) as the
+    /// widespread `v x y z r g b` extension. Faces are the authored polygons
+    /// when the mesh kept them, its triangles otherwise.
+    pub fn save_obj(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_obj(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Save the mesh as an OFF file, or COFF when it has vertex colors, with
+    /// the authored polygons when the mesh kept them
+    pub fn save_off(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_off(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Save the mesh as a binary STL file: its triangles alone, each with
+    /// its normal and its own copy of its vertices
+    pub fn save_stl(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_stl(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Save the mesh, picking the format from the file extension: OBJ, STL
+    /// and OFF for anything else
+    pub fn save_file(&self, path: &Path) -> io::Result<()> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("obj") => self.save_obj(path),
+            Some("stl") => self.save_stl(path),
+            _ => self.save_off(path),
+        }
+    }
+
+    fn write_obj<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "# {} vertices, {} faces",
+            self.vertices.len(),
+            self.faces().count()
+        )?;
+        for (i, v) in self.vertices.iter().enumerate() {
+            match &self.vertex_colors {
+                Some(colors) => {
+                    let c = colors[i];
+                    writeln!(
+                        writer,
+                        "v {} {} {} {} {} {}",
+                        v.x, v.y, v.z, c[0], c[1], c[2]
+                    )?
+                }
+                None => writeln!(writer, "v {} {} {}", v.x, v.y, v.z)?,
+            }
+        }
+        for n in &self.vertex_normals {
+            writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        for uv in self.uvs.iter().flatten() {
+            writeln!(writer, "vt {} {}", uv[0], uv[1])?;
+        }
+        // OBJ indices start at 1
+        let corner = |i: usize| match self.uvs {
+            Some(_) => format!("{0}/{0}/{0}", i + 1),
+            None => format!("{0}//{0}", i + 1),
+        };
+        for face in self.faces() {
+            let corners: Vec<String> = face.iter().map(|&i| corner(i)).collect();
+            writeln!(writer, "f {}", corners.join(" "))?;
+        }
+        Ok(())
+    }
+
+    fn write_off<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let magic = match self.vertex_colors {
+            Some(_) => "COFF",
+            None => "OFF",
+        };
+        writeln!(writer, "{}", magic)?;
+        writeln!(writer, "{} {} 0", self.vertices.len(), self.faces().count())?;
+        for (i, v) in self.vertices.iter().enumerate() {
+            match &self.vertex_colors {
+                Some(colors) => {
+                    let c = colors[i];
+                    writeln!(
+                        writer,
+                        "{} {} {} {} {} {} 1",
+                        v.x, v.y, v.z, c[0], c[1], c[2]
+                    )?
+                }
+                None => writeln!(writer, "{} {} {}", v.x, v.y, v.z)?,
+            }
+        }
+        for face in self.faces() {
+            let indices: Vec<String> = face.iter().map(|i| i.to_string()).collect();
+            writeln!(writer, "{} {}", face.len(), indices.join(" "))?;
+        }
+        Ok(())
+    }
+
+    fn write_stl<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut header = [b' '; 80];
+        let title = b"binary STL written by ray_ruster";
+        header[..title.len()].copy_from_slice(title);
+        writer.write_all(&header)?;
+        writer.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+        for (triangle, normal) in self.triangles.iter().zip(&self.triangle_normals) {
+            let corners = triangle.iter().map(|&i| &self.vertices[i].coords);
+            for v in std::iter::once(normal).chain(corners) {
+                for x in v.iter() {
+                    writer.write_all(&(*x as f32).to_le_bytes())?;
+                }
+            }
+            // Attribute byte count, unused
+            writer.write_all(&[0, 0])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::Position;
+
+    fn colored_quad() -> Mesh {
+        let vertices = vec![
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 0.0, 0.0),
+            Position::new(1.0, 1.0, 0.0),
+            Position::new(0.0, 1.0, 0.25),
+        ];
+        let mut mesh = Mesh::from_vertices_and_polygons(vertices, vec![vec![0, 1, 2, 3]]);
+        mesh.vertex_colors = Some(vec![
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.5; 3],
+        ]);
+        mesh.uvs = Some(vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        mesh
+    }
+
+    #[test]
+    fn off_and_obj_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mesh = colored_quad();
+
+        let off_path = dir.path().join("quad.off");
+        mesh.save_off(&off_path).unwrap();
+        let off = Mesh::load_off_file(&off_path).unwrap();
+        assert_eq!(off.vertices, mesh.vertices);
+        assert_eq!(off.triangles, mesh.triangles);
+        assert_eq!(off.polygons, mesh.polygons);
+        assert_eq!(off.vertex_colors, mesh.vertex_colors);
+
+        let obj_path = dir.path().join("quad.obj");
+        mesh.save_obj(&obj_path).unwrap();
+        let obj = Mesh::load_obj_file(&obj_path).unwrap();
+        assert_eq!(obj.vertices, mesh.vertices);
+        assert_eq!(obj.triangles, mesh.triangles);
+        assert_eq!(obj.polygons, mesh.polygons);
+        assert_eq!(obj.uvs, mesh.uvs);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use crate::geometry::mesh::Mesh;
+
+/// One side of an edge, going around the face on its left
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HalfEdge {
+    /// Vertex the half-edge starts from
+    pub origin: usize,
+    /// Index in `Mesh::faces`, `None` on the outer side of the boundaries
+    pub face: Option<usize>,
+    /// Other side of the edge, going the other way
+    pub twin: usize,
+    /// Following half-edge around the face, or along the boundary
+    pub next: usize,
+    pub prev: usize,
+}
+
+/// Why a mesh has no half-edge structure
+#[derive(Debug, PartialEq, Eq)]
+pub enum TopologyError {
+    /// The edge between the two vertices is shared by more than two faces, or
+    /// by two faces going the same way (inconsistent orientations)
+    NonManifoldEdge(usize, usize),
+    /// The faces around the vertex do not form a single fan, e.g. two cones
+    /// touching at their tips
+    NonManifoldVertex(usize),
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TopologyError::NonManifoldEdge(a, b) => {
+                write!(f, "non-manifold edge between vertices {} and {}", a, b)
+            }
+            TopologyError::NonManifoldVertex(v) => write!(f, "non-manifold vertex {}", v),
+        }
+    }
+}
+
+impl error::Error for TopologyError {}
+
+/// Adjacency of the faces of a manifold mesh, for the topology queries of
+/// subdivision, decimation or repair
+///
+/// Built on demand from the authored faces of a `Mesh` (its polygons when it
+/// kept them), which it does not follow once modified. Boundaries are closed
+/// by half-edges without face, linked into loops.
+#[derive(Clone, Debug)]
+pub struct HalfEdgeMesh {
+    pub half_edges: Vec<HalfEdge>,
+    /// A half-edge leaving every vertex, the boundary one on the boundaries;
+    /// `None` for vertices of no face
+    vertex_half_edges: Vec<Option<usize>>,
+    /// A half-edge of every face
+    face_half_edges: Vec<usize>,
+    /// Half-edge going from the first vertex to the second
+    by_vertices: HashMap<(usize, usize), usize>,
+}
+
+impl HalfEdgeMesh {
+    pub fn new(mesh: &Mesh) -> Result<HalfEdgeMesh, TopologyError> {
+        let mut half_edges: Vec<HalfEdge> = Vec::new();
+        let mut by_vertices: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut face_half_edges: Vec<usize> = Vec::new();
+
+        for (face, vertices) in mesh.faces().enumerate() {
+            let first = half_edges.len();
+            let count = vertices.len();
+            face_half_edges.push(first);
+            for (i, &origin) in vertices.iter().enumerate() {
+                let target = vertices[(i + 1) % count];
+                if by_vertices.insert((origin, target), first + i).is_some() {
+                    return Err(TopologyError::NonManifoldEdge(origin, target));
+                }
+                half_edges.push(HalfEdge {
+                    origin,
+                    face: Some(face),
+                    twin: usize::MAX,
+                    next: first + (i + 1) % count,
+                    prev: first + (i + count - 1) % count,
+                });
+            }
+        }
+
+        // Pair the sides of the edges, the missing ones are on the boundary
+        let mut boundary_from: HashMap<usize, usize> = HashMap::new();
+        for h in 0..half_edges.len() {
+            let origin = half_edges[h].origin;
+            let target = half_edges[half_edges[h].next].origin;
+            match by_vertices.get(&(target, origin)) {
+                Some(&twin) => half_edges[h].twin = twin,
+                None => {
+                    let boundary = half_edges.len();
+                    if boundary_from.insert(target, boundary).is_some() {
+                        return Err(TopologyError::NonManifoldVertex(target));
+                    }
+                    half_edges[h].twin = boundary;
+                    half_edges.push(HalfEdge {
+                        origin: target,
+                        face: None,
+                        twin: h,
+                        next: usize::MAX,
+                        prev: usize::MAX,
+                    });
+                    by_vertices.insert((target, origin), boundary);
+                }
+            }
+        }
+        // A boundary half-edge is followed by the one leaving its target
+        let boundaries: Vec<usize> = boundary_from.values().cloned().collect();
+        for boundary in boundaries {
+            let target = half_edges[half_edges[boundary].twin].origin;
+            let next = boundary_from[&target];
+            half_edges[boundary].next = next;
+            half_edges[next].prev = boundary;
+        }
+
+        let mut vertex_half_edges: Vec<Option<usize>> = vec![None; mesh.vertices.len()];
+        for (h, half_edge) in half_edges.iter().enumerate() {
+            let slot = &mut vertex_half_edges[half_edge.origin];
+            if slot.is_none() || half_edge.face.is_none() {
+                *slot = Some(h);
+            }
+        }
+
+        let half_edge_mesh = HalfEdgeMesh {
+            half_edges,
+            vertex_half_edges,
+            face_half_edges,
+            by_vertices,
+        };
+        // A vertex whose faces form several fans has half-edges out of reach
+        // of the rotation around it
+        let mut reached = vec![false; half_edge_mesh.half_edges.len()];
+        for vertex in 0..mesh.vertices.len() {
+            for h in half_edge_mesh.outgoing(vertex) {
+                reached[h] = true;
+            }
+        }
+        if let Some(h) = reached.iter().position(|&r| !r) {
+            return Err(TopologyError::NonManifoldVertex(
+                half_edge_mesh.half_edges[h].origin,
+            ));
+        }
+        Ok(half_edge_mesh)
+    }
+
+    /// Half-edges leaving `vertex`, rotating around it
+    pub fn outgoing(&self, vertex: usize) -> Vec<usize> {
+        let start = match self.vertex_half_edges[vertex] {
+            Some(h) => h,
+            None => return Vec::new(),
+        };
+        let mut outgoing = vec![start];
+        let mut h = self.half_edges[self.half_edges[start].prev].twin;
+        while h != start {
+            outgoing.push(h);
+            h = self.half_edges[self.half_edges[h].prev].twin;
+        }
+        outgoing
+    }
+
+    /// Faces around `vertex`, in order
+    pub fn vertex_faces(&self, vertex: usize) -> Vec<usize> {
+        self.outgoing(vertex)
+            .iter()
+            .filter_map(|&h| self.half_edges[h].face)
+            .collect()
+    }
+
+    /// Vertices sharing an edge with `vertex`, in order
+    pub fn vertex_neighbors(&self, vertex: usize) -> Vec<usize> {
+        self.outgoing(vertex)
+            .iter()
+            .map(|&h| self.half_edges[self.half_edges[h].twin].origin)
+            .collect()
+    }
+
+    /// Half-edges around `face`
+    pub fn face_half_edges(&self, face: usize) -> Vec<usize> {
+        let start = self.face_half_edges[face];
+        let mut half_edges = vec![start];
+        let mut h = self.half_edges[start].next;
+        while h != start {
+            half_edges.push(h);
+            h = self.half_edges[h].next;
+        }
+        half_edges
+    }
+
+    /// Faces across the edges of `face`, in the order of its vertices; `None`
+    /// across the boundary
+    pub fn face_neighbors(&self, face: usize) -> Vec<Option<usize>> {
+        self.face_half_edges(face)
+            .iter()
+            .map(|&h| self.half_edges[self.half_edges[h].twin].face)
+            .collect()
+    }
+
+    /// Half-edge going from `a` to `b`, when they share an edge
+    pub fn half_edge(&self, a: usize, b: usize) -> Option<usize> {
+        self.by_vertices.get(&(a, b)).cloned()
+    }
+
+    /// Faces on the left and on the right of the edge going from `a` to `b`
+    pub fn edge_faces(&self, a: usize, b: usize) -> Option<[Option<usize>; 2]> {
+        let h = self.half_edge(a, b)?;
+        Some([
+            self.half_edges[h].face,
+            self.half_edges[self.half_edges[h].twin].face,
+        ])
+    }
+
+    pub fn is_boundary_vertex(&self, vertex: usize) -> bool {
+        self.vertex_half_edges[vertex].is_some_and(|h| self.half_edges[h].face.is_none())
+    }
+
+    /// Vertices of every hole of the surface, going around it
+    pub fn boundary_loops(&self) -> Vec<Vec<usize>> {
+        let mut visited = vec![false; self.half_edges.len()];
+        let mut loops = Vec::new();
+        for (start, half_edge) in self.half_edges.iter().enumerate() {
+            if half_edge.face.is_some() || visited[start] {
+                continue;
+            }
+            let mut vertices = Vec::new();
+            let mut h = start;
+            while !visited[h] {
+                visited[h] = true;
+                vertices.push(self.half_edges[h].origin);
+                h = self.half_edges[h].next;
+            }
+            loops.push(vertices);
+        }
+        loops
+    }
+
+    /// Whether the surface has no boundary, e.g. to enclose a volume
+    pub fn is_closed(&self) -> bool {
+        self.half_edges.iter().all(|h| h.face.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::Position;
+
+    #[test]
+    fn strip_of_quads_and_non_manifold_meshes() {
+        // 3---4---5
+        // |   |   |
+        // 0---1---2
+        let vertices: Vec<Position> = (0..6)
+            .map(|i| Position::new((i % 3) as f64, (i / 3) as f64, 0.0))
+            .collect();
+        let strip = Mesh::from_vertices_and_polygons(
+            vertices.clone(),
+            vec![vec![0, 1, 4, 3], vec![1, 2, 5, 4]],
+        );
+        let half_edges = HalfEdgeMesh::new(&strip).unwrap();
+        assert_eq!(half_edges.half_edges.len(), 14);
+        assert_eq!(
+            half_edges.face_neighbors(0),
+            vec![None, Some(1), None, None]
+        );
+        assert_eq!(half_edges.edge_faces(1, 4), Some([Some(0), Some(1)]));
+        assert_eq!(half_edges.edge_faces(0, 4), None);
+        let mut faces = half_edges.vertex_faces(1);
+        faces.sort();
+        assert_eq!(faces, vec![0, 1]);
+        let mut neighbors = half_edges.vertex_neighbors(4);
+        neighbors.sort();
+        assert_eq!(neighbors, vec![1, 3, 5]);
+        assert!(half_edges.is_boundary_vertex(4));
+        assert!(!half_edges.is_closed());
+        let loops = half_edges.boundary_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].len(), 6);
+
+        // Three triangles on the edge 0-1
+        let fin = Mesh::from_vertices_and_triangles(
+            vertices.clone(),
+            vec![[0, 1, 3], [1, 0, 4], [0, 5, 1]],
+        );
+        assert!(HalfEdgeMesh::new(&fin).is_err());
+        // Two triangles touching at vertex 1
+        let bowtie = Mesh::from_vertices_and_triangles(vertices, vec![[0, 1, 3], [1, 2, 5]]);
+        assert_eq!(
+            HalfEdgeMesh::new(&bowtie).unwrap_err(),
+            TopologyError::NonManifoldVertex(1)
+        );
+    }
+}
@@ -0,0 +1,215 @@
+extern crate nalgebra as na;
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Length unit of a scene or of a model file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    #[default]
+    Meters,
+    Centimeters,
+    Millimeters,
+    Inches,
+    Feet,
+}
+
+impl Unit {
+    pub const ALL: [Unit; 5] = [
+        Unit::Meters,
+        Unit::Centimeters,
+        Unit::Millimeters,
+        Unit::Inches,
+        Unit::Feet,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::Meters => "meters",
+            Unit::Centimeters => "centimeters",
+            Unit::Millimeters => "millimeters",
+            Unit::Inches => "inches",
+            Unit::Feet => "feet",
+        }
+    }
+
+    /// Length of one unit in meters
+    pub fn meters(self) -> f64 {
+        match self {
+            Unit::Meters => 1.0,
+            Unit::Centimeters => 0.01,
+            Unit::Millimeters => 0.001,
+            Unit::Inches => 0.0254,
+            Unit::Feet => 0.3048,
+        }
+    }
+
+    /// Factor converting lengths in this unit to `target` units
+    pub fn scale_to(self, target: Unit) -> f64 {
+        self.meters() / target.meters()
+    }
+
+    /// Offset applied to the origin of secondary rays to avoid hitting the
+    /// surface they start from: a tenth of a millimeter, in this unit, for
+    /// scenes without size to scale it to
+    pub fn ray_epsilon(self) -> f64 {
+        1e-4 / self.meters()
+    }
+
+    /// Sensible near and far clipping distances in this unit: from a
+    /// centimeter to ten kilometers
+    pub fn clip_range(self) -> (f64, f64) {
+        (0.01 / self.meters(), 1e4 / self.meters())
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unit = match s {
+            "m" => Some(Unit::Meters),
+            "cm" => Some(Unit::Centimeters),
+            "mm" => Some(Unit::Millimeters),
+            "in" => Some(Unit::Inches),
+            "ft" => Some(Unit::Feet),
+            _ => Unit::ALL.iter().cloned().find(|unit| unit.name() == s),
+        };
+        unit.ok_or_else(|| format!("unknown unit {}, expected m, cm, mm, in or ft", s))
+    }
+}
+
+/// Axis pointing up in a model file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpAxis {
+    /// Scene convention, e.g. OBJ files from most modelers and glTF
+    Y,
+    /// e.g. CAD software and Blender exports without axis conversion
+    Z,
+}
+
+impl FromStr for UpAxis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "y" => Ok(UpAxis::Y),
+            "z" => Ok(UpAxis::Z),
+            _ => Err(format!("unknown up axis {}, expected y or z", s)),
+        }
+    }
+}
+
+/// Handedness of the coordinate system of a model file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Handedness {
+    /// Scene convention
+    Right,
+    Left,
+}
+
+impl FromStr for Handedness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "right" => Ok(Handedness::Right),
+            "left" => Ok(Handedness::Left),
+            _ => Err(format!("unknown handedness {}, expected right or left", s)),
+        }
+    }
+}
+
+/// How a model file is converted to the conventions of the scene: meters (or
+/// the unit of the scene), y up and right handed
+///
+/// Options that are not given fall back on the defaults of the scene, then
+/// on the scene conventions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Unit of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub units: Option<Unit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up_axis: Option<UpAxis>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handedness: Option<Handedness>,
+}
+
+impl ImportOptions {
+    /// These options, completed with `defaults`
+    pub fn or(&self, defaults: &ImportOptions) -> ImportOptions {
+        ImportOptions {
+            units: self.units.or(defaults.units),
+            up_axis: self.up_axis.or(defaults.up_axis),
+            handedness: self.handedness.or(defaults.handedness),
+        }
+    }
+
+    /// Linear map from the file coordinates to the scene coordinates
+    ///
+    /// Left handed files are mirrored along their depth axis, which reverses
+    /// the orientation of the triangles.
+    pub fn conversion(&self, scene_units: Unit) -> na::Matrix3<f64> {
+        let scale = self.units.map_or(1.0, |units| units.scale_to(scene_units));
+        let up_axis = self.up_axis.unwrap_or(UpAxis::Y);
+        let mirror = match (self.handedness.unwrap_or(Handedness::Right), up_axis) {
+            (Handedness::Right, _) => na::Vector3::new(1.0, 1.0, 1.0),
+            (Handedness::Left, UpAxis::Y) => na::Vector3::new(1.0, 1.0, -1.0),
+            (Handedness::Left, UpAxis::Z) => na::Vector3::new(1.0, -1.0, 1.0),
+        };
+        let rotation = match up_axis {
+            UpAxis::Y => na::Matrix3::identity(),
+            // (x, y, z) -> (x, z, -y)
+            UpAxis::Z => na::Matrix3::new(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, -1.0, 0.0),
+        };
+        rotation * na::Matrix3::from_diagonal(&mirror) * scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn z_up_files_are_rotated_to_y_up() {
+        let import = ImportOptions {
+            units: Some(Unit::Centimeters),
+            up_axis: Some(UpAxis::Z),
+            ..Default::default()
+        };
+        let conversion = import.conversion(Unit::Meters);
+        let up = conversion * na::Vector3::new(0.0, 0.0, 100.0);
+        assert!((up - na::Vector3::new(0.0, 1.0, 0.0)).norm() < 1e-12);
+        assert!(conversion.determinant() > 0.0);
+    }
+
+    #[test]
+    fn left_handed_files_are_mirrored() {
+        let import = ImportOptions {
+            handedness: Some(Handedness::Left),
+            ..Default::default()
+        };
+        let scene_defaults = ImportOptions {
+            up_axis: Some(UpAxis::Z),
+            ..Default::default()
+        };
+        let conversion = import.or(&scene_defaults).conversion(Unit::Meters);
+        assert!(conversion.determinant() < 0.0);
+        // Up stays up
+        let up = conversion * na::Vector3::new(0.0, 0.0, 1.0);
+        assert!((up - na::Vector3::new(0.0, 1.0, 0.0)).norm() < 1e-12);
+    }
+}
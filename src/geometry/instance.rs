@@ -0,0 +1,281 @@
+extern crate nalgebra as na;
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::bvh::{TreeBuild, TreePreset};
+use crate::geometry::kdtree::KdTree;
+use crate::geometry::mesh::Mesh;
+use crate::geometry::ray::Ray;
+use crate::geometry::types::{Direction, Position};
+use crate::render::material::Material;
+
+/// Model of `Instances` with the tree of its triangles, both stored once
+/// whatever the number of its instances
+#[derive(Debug)]
+pub struct Prototype {
+    pub mesh: Arc<Mesh>,
+    pub kdtree: Box<KdTree>,
+}
+
+impl Prototype {
+    /// Build the tree of `mesh` as `build` and `preset` say, after reordering
+    /// it with `Mesh::optimize_layout` when `optimize_layout` is set, on a
+    /// copy if the mesh is shared
+    pub fn new(
+        mut mesh: Arc<Mesh>,
+        build: TreeBuild,
+        preset: TreePreset,
+        optimize_layout: bool,
+    ) -> Prototype {
+        if optimize_layout {
+            Arc::make_mut(&mut mesh).optimize_layout();
+        }
+        Prototype {
+            kdtree: KdTree::build(&mesh, build, preset),
+            mesh,
+        }
+    }
+}
+
+/// A placement of one of the models of `Instances`
+#[derive(Clone)]
+pub struct Instance {
+    /// Index of the model in `Instances::prototypes`
+    pub prototype: usize,
+    /// From the coordinates of the model to the scene ones
+    pub to_world: na::Similarity3<f64>,
+    to_object: na::Similarity3<f64>,
+    /// Material of the instance, replacing the ones of the model
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Instance")
+            .field("prototype", &self.prototype)
+            .field("to_world", &self.to_world)
+            .field("material", &self.material.is_some())
+            .finish()
+    }
+}
+
+impl Instance {
+    pub fn new(
+        prototype: usize,
+        to_world: na::Similarity3<f64>,
+        material: Option<Arc<dyn Material>>,
+    ) -> Instance {
+        Instance {
+            prototype,
+            to_world,
+            to_object: to_world.inverse(),
+            material,
+        }
+    }
+
+    /// `ray` in the coordinates of the model
+    ///
+    /// The direction is scaled with the model rather than normalized, so
+    /// that points keep their parameter along the ray: the closest hit in
+    /// the model is the closest one in the scene. Distances along the ray
+    /// are divided by `scale`.
+    pub fn ray_to_object(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.to_object * ray.position,
+            self.to_object * ray.direction,
+        )
+    }
+
+    /// Scale of the instance, by which distances grow from the model to the
+    /// scene
+    pub fn scale(&self) -> f64 {
+        self.to_world.scaling()
+    }
+
+    /// Normal of the model in the scene coordinates, the scale being uniform
+    pub fn normal_to_world(&self, normal: &Direction) -> Direction {
+        self.to_world.isometry.rotation * normal
+    }
+
+    /// Box of the instance in the scene, around the box of its model
+    fn bounding_box(&self, model: &AxisAlignedBoundingBox) -> AxisAlignedBoundingBox {
+        let corners: Vec<Position> = (0..8)
+            .map(|i| {
+                let corner = |axis: usize| model.bounds[(i >> axis) & 1][axis];
+                self.to_world * Position::new(corner(0), corner(1), corner(2))
+            })
+            .collect();
+        AxisAlignedBoundingBox::new(&corners)
+    }
+}
+
+/// Models placed many times in a scene without copying them: a two level
+/// acceleration structure
+///
+/// Every model and its kd-tree are stored once. The top level tree, a
+/// bounding volume hierarchy of the boxes of the instances, leads the rays to
+/// the instances they may hit, whose model tree is then traversed with the
+/// ray in the coordinates of the model.
+#[derive(Debug)]
+pub struct Instances {
+    pub prototypes: Vec<Prototype>,
+    pub instances: Vec<Instance>,
+    /// Tree of the boxes of the instances in the scene, listing the indices
+    /// of `instances` in the `triangle_index` of its leaves
+    pub tree: Box<KdTree>,
+}
+
+impl Instances {
+    /// Build the tree of every model as `build` and `preset` say, reordered
+    /// when `optimize_layout` is set, see `Prototype::new`, and the top level
+    /// tree of the instances
+    ///
+    /// Panics when an instance refers to a model out of `meshes`.
+    pub fn new(
+        meshes: Vec<Arc<Mesh>>,
+        instances: Vec<Instance>,
+        build: TreeBuild,
+        preset: TreePreset,
+        optimize_layout: bool,
+    ) -> Instances {
+        let _span = tracing::info_span!(
+            "build_instances",
+            models = meshes.len(),
+            instances = instances.len()
+        )
+        .entered();
+        let prototypes: Vec<Prototype> = meshes
+            .into_iter()
+            .map(|mesh| Prototype::new(mesh, build, preset, optimize_layout))
+            .collect();
+        let boxes: Vec<AxisAlignedBoundingBox> = instances
+            .iter()
+            .map(|instance| {
+                let model = &prototypes[instance.prototype].kdtree.bounding_box;
+                instance.bounding_box(model)
+            })
+            .collect();
+        let tree = KdTree::from_boxes_sah_with(&boxes, &preset.parameters());
+        Instances {
+            prototypes,
+            instances,
+            tree,
+        }
+    }
+
+    /// Model of instance `index`
+    pub fn prototype(&self, index: usize) -> &Prototype {
+        &self.prototypes[self.instances[index].prototype]
+    }
+
+    /// Box around all the instances
+    pub fn bounding_box(&self) -> AxisAlignedBoundingBox {
+        AxisAlignedBoundingBox::from_bounds(self.tree.bounding_box.bounds)
+    }
+
+    /// Triangles of the scene, as many as in the models copied at every
+    /// instance
+    pub fn triangle_count(&self) -> usize {
+        self.instances
+            .iter()
+            .map(|instance| self.prototypes[instance.prototype].mesh.triangles.len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prototypes_reorder_a_copy_of_shared_models() {
+        // Triangles at x = 0, 10 then 5, out of order along the row
+        let vertices: Vec<Position> = [0.0, 10.0, 5.0]
+            .iter()
+            .flat_map(|&x| {
+                vec![
+                    Position::new(x, 0.0, 0.0),
+                    Position::new(x + 1.0, 0.0, 0.0),
+                    Position::new(x, 1.0, 0.0),
+                ]
+            })
+            .collect();
+        let shared = Arc::new(Mesh::from_vertices_and_triangles(
+            vertices,
+            vec![[0, 1, 2], [3, 4, 5], [6, 7, 8]],
+        ));
+        let prototype = Prototype::new(
+            Arc::clone(&shared),
+            TreeBuild::KdTree,
+            TreePreset::Balanced,
+            true,
+        );
+        let first_x = |mesh: &Mesh| -> Vec<f64> {
+            mesh.triangles
+                .iter()
+                .map(|t| mesh.vertices[t[0]].x)
+                .collect()
+        };
+        assert_eq!(first_x(&prototype.mesh), vec![0.0, 5.0, 10.0]);
+        assert_eq!(first_x(&shared), vec![0.0, 10.0, 5.0]);
+        assert!(!Arc::ptr_eq(&prototype.mesh, &shared));
+
+        let kept = Prototype::new(shared, TreeBuild::KdTree, TreePreset::Balanced, false);
+        assert_eq!(first_x(&kept.mesh), vec![0.0, 10.0, 5.0]);
+    }
+
+    #[test]
+    fn rays_reach_the_instances_in_their_model() {
+        let triangle = Arc::new(Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(0.0, 0.0, 0.0),
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+        ));
+        let placements: Vec<Instance> = (0..10)
+            .map(|i| {
+                let to_world = na::Similarity3::new(
+                    Direction::new(3.0 * i as f64, 0.0, 5.0),
+                    Direction::y() * std::f64::consts::PI,
+                    2.0,
+                );
+                Instance::new(0, to_world, None)
+            })
+            .collect();
+        let instances = Instances::new(
+            vec![triangle],
+            placements,
+            TreeBuild::KdTree,
+            TreePreset::Balanced,
+            false,
+        );
+        assert_eq!(instances.prototypes.len(), 1);
+        assert_eq!(instances.triangle_count(), 10);
+        let stats = instances.tree.stats();
+        assert_eq!(stats.triangle_references, 10);
+        // Turned around y and scaled: the last one covers x from 25 to 27
+        let bounds = instances.bounding_box().bounds;
+        assert!((bounds[0] - Position::new(-2.0, 0.0, 5.0)).norm() < 1e-6);
+        assert!((bounds[1] - Position::new(27.0, 2.0, 5.0)).norm() < 1e-6);
+
+        let instance = &instances.instances[9];
+        let ray = Ray::new(Position::new(26.5, 0.5, 0.0), Direction::z());
+        let local = instance.ray_to_object(&ray);
+        assert!((local.position - Position::new(0.25, 0.25, 2.5)).norm() < 1e-12);
+        assert!((local.direction - Direction::new(0.0, 0.0, -0.5)).norm() < 1e-12);
+        assert_eq!(instance.scale(), 2.0);
+        // The hit is 5 along the ray, in the model as in the scene
+        let mesh = &instances.prototype(9).mesh;
+        let [a, b, c] = mesh.triangles[0].map(|v| mesh.vertices[v]);
+        let (point, _) = local.intersect_triangle(&a, &b, &c).unwrap();
+        let t = (point - local.position).norm() / local.direction.norm();
+        assert!((t - 5.0).abs() < 1e-12);
+        let world = instance.to_world * point;
+        assert!((world - Position::new(26.5, 0.5, 5.0)).norm() < 1e-12);
+        assert!((instance.normal_to_world(&Direction::z()) + Direction::z()).norm() < 1e-12);
+    }
+}
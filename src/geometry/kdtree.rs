@@ -1,13 +1,17 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::collections::VecDeque;
+use std::mem;
+use std::sync::Mutex;
 
 use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::bvh::TreeParameters;
 use crate::geometry::mesh::Mesh;
 use crate::geometry::ray::Ray;
 use crate::geometry::types::Triangle;
 use crate::geometry::types::{Direction, Position};
 
+#[derive(Debug)]
 pub struct KdTree {
     pub bounding_box: AxisAlignedBoundingBox,
     left: Option<Box<KdTree>>,
@@ -19,7 +23,7 @@ pub struct KdTree {
 }
 
 impl KdTree {
-    fn new_node(
+    pub(crate) fn new_node(
         bb: AxisAlignedBoundingBox,
         left: Option<Box<KdTree>>,
         right: Option<Box<KdTree>>,
@@ -33,7 +37,7 @@ impl KdTree {
         }
     }
 
-    fn new_leaf(
+    pub(crate) fn new_leaf(
         bb: AxisAlignedBoundingBox,
         vertices_index: Vec<usize>,
         triangle_index: Vec<usize>,
@@ -54,14 +58,21 @@ impl KdTree {
     ///    1. The box are defined based on the vertex density
     ///    2. The triangles are put in the leaves they intersect
     pub fn from_mesh(mesh: &Mesh) -> Box<KdTree> {
+        KdTree::from_mesh_with(mesh, &TreeParameters::default())
+    }
+
+    /// Same as `from_mesh`, nodes of fewer than
+    /// `parameters.kd_tree_leaf_vertices` vertices being leaves
+    pub fn from_mesh_with(mesh: &Mesh, parameters: &TreeParameters) -> Box<KdTree> {
         fn recursion_internal(
             mesh: &Mesh,
+            leaf_vertices: usize,
             bb: AxisAlignedBoundingBox,
             index_vertices_pairs: Vec<(usize, &Position)>,
             index_triangle_pairs: Vec<(usize, &Triangle)>,
         ) -> KdTree {
             // Terminal condition
-            if index_vertices_pairs.len() < 10 {
+            if index_vertices_pairs.len() < leaf_vertices {
                 return KdTree::new_leaf(
                     bb,
                     index_vertices_pairs
@@ -131,12 +142,14 @@ impl KdTree {
                 bb,
                 Some(Box::from(recursion_internal(
                     mesh,
+                    leaf_vertices,
                     left_bb,
                     left_vertices,
                     left_triangles,
                 ))),
                 Some(Box::from(recursion_internal(
                     mesh,
+                    leaf_vertices,
                     right_bb,
                     right_vertices,
                     right_triangles,
@@ -144,6 +157,7 @@ impl KdTree {
             )
         }
 
+        let _span = tracing::info_span!("build_kdtree", triangles = mesh.triangles.len()).entered();
         // Initialize the recursion
         let bb = AxisAlignedBoundingBox::new(&mesh.vertices);
         let index_vertices_pairs: Vec<(usize, &Position)> =
@@ -153,6 +167,7 @@ impl KdTree {
 
         Box::from(recursion_internal(
             mesh,
+            parameters.kd_tree_leaf_vertices.max(2),
             bb,
             index_vertices_pairs,
             index_triangles_pairs,
@@ -162,13 +177,59 @@ impl KdTree {
     pub fn is_leaf(&self) -> bool {
         self.vertices_index.is_some()
     }
+
+    /// Both children of an inner node, `None` for leaves
+    pub fn children(&self) -> Option<(&KdTree, &KdTree)> {
+        Some((self.left.as_deref()?, self.right.as_deref()?))
+    }
+
+    /// Shape and size of the tree under this node
+    pub fn stats(&self) -> KdTreeStats {
+        fn visit(node: &KdTree, depth: usize, stats: &mut KdTreeStats) {
+            stats.nodes += 1;
+            stats.depth = stats.depth.max(depth);
+            stats.bytes += mem::size_of::<KdTree>();
+            if let Some(triangles) = &node.triangle_index {
+                let vertices = node.vertices_index.as_ref().map_or(0, Vec::len);
+                stats.leaves += 1;
+                stats.triangle_references += triangles.len();
+                stats.max_leaf_triangles = stats.max_leaf_triangles.max(triangles.len());
+                stats.bytes += (triangles.len() + vertices) * mem::size_of::<usize>();
+            }
+            for child in node.left.iter().chain(node.right.iter()) {
+                visit(child, depth + 1, stats);
+            }
+        }
+        let mut stats = KdTreeStats::default();
+        visit(self, 0, &mut stats);
+        stats
+    }
 }
 
-pub fn iter_intersect_ray<'a>(
+/// Summary of a kd-tree, see `KdTree::stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KdTreeStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Depth of the deepest leaf, 0 when the root is a leaf
+    pub depth: usize,
+    /// Triangles in the largest leaf, which every ray through it tests
+    pub max_leaf_triangles: usize,
+    /// Sum of the triangles of the leaves, triangles crossing several
+    /// leaves being counted in each
+    pub triangle_references: usize,
+    /// Memory used by the nodes and their indices
+    pub bytes: usize,
+}
+
+pub fn iter_intersect_ray<'a, 'r>(
     kdtree: &'a Box<KdTree>,
-    ray: &'a Ray,
-) -> BoxIntersectIter<'a, RayIntersector<'a>> {
-    let ray_box_intersector = RayIntersector { ray: ray };
+    ray: &'r Ray,
+) -> BoxIntersectIter<'a, RayIntersector<'r>> {
+    let ray_box_intersector = RayIntersector {
+        ray: ray,
+        max_distance: f64::INFINITY,
+    };
     BoxIntersectIter::<'a, RayIntersector>::new(ray_box_intersector, kdtree)
 }
 
@@ -231,20 +292,26 @@ pub trait BoxIntersector<'a> {
     fn intersect_box(&self, kdt_node: &'a Box<KdTree>) -> Option<BoxIntersect<'a>>;
 }
 
-pub struct RayIntersector<'a> {
-    ray: &'a Ray,
+pub struct RayIntersector<'r> {
+    ray: &'r Ray,
+    /// Distance along the ray, in the units of the scene, beyond which the
+    /// boxes are ignored
+    max_distance: f64,
 }
 
-impl<'a> BoxIntersector<'a> for RayIntersector<'a> {
+impl<'a, 'r> BoxIntersector<'a> for RayIntersector<'r> {
     fn intersect_box(&self, kdt_node: &'a Box<KdTree>) -> Option<BoxIntersect<'a>> {
-        let hit = self.ray.intersect_box(&(*kdt_node).bounding_box.bounds);
-        match hit {
-            Some(distance) => Some(BoxIntersect {
-                distance: distance,
-                node: kdt_node,
-            }),
-            None => None,
+        let (t_near, _) = self
+            .ray
+            .intersect_box_range(&(*kdt_node).bounding_box.bounds)?;
+        if t_near * self.ray.direction.norm() > self.max_distance {
+            return None;
         }
+        // Boxes around the origin are entered right away
+        Some(BoxIntersect {
+            distance: t_near.max(0.0),
+            node: kdt_node,
+        })
     }
 }
 
@@ -281,16 +348,33 @@ where
     A: BoxIntersector<'a>,
 {
     pub fn new(box_intersector: A, first_node: &'a Box<KdTree>) -> BoxIntersectIter<'a, A> {
-        let mut heap = BinaryHeap::new();
+        Self::with_stack(box_intersector, first_node, BinaryHeap::new())
+    }
+
+    /// Same as `new`, reusing the allocation of `stack`, e.g. the one of a
+    /// previous query given back by `into_stack`
+    pub fn with_stack(
+        box_intersector: A,
+        first_node: &'a Box<KdTree>,
+        mut stack: BinaryHeap<BoxIntersect<'a>>,
+    ) -> BoxIntersectIter<'a, A> {
+        stack.clear();
         let intersect = box_intersector.intersect_box(first_node);
         if intersect.is_some() {
-            heap.push(intersect.unwrap())
+            stack.push(intersect.unwrap())
         }
         BoxIntersectIter {
-            next_nodes: heap,
+            next_nodes: stack,
             box_intersector: box_intersector,
         }
     }
+
+    /// Nodes still pending, emptied, to reuse their allocation
+    pub fn into_stack(self) -> BinaryHeap<BoxIntersect<'a>> {
+        let mut stack = self.next_nodes;
+        stack.clear();
+        stack
+    }
     pub fn closest_branch(self) -> impl Iterator<Item = BoxIntersect<'a>> {
         self.scan(0, |predecessor_is_leaf, intersect: BoxIntersect<'_>| {
             if *predecessor_is_leaf == 1 {
@@ -310,6 +394,19 @@ where
     }
 }
 
+impl<'a, 'r> BoxIntersectIter<'a, RayIntersector<'r>> {
+    /// Skip the nodes entered farther than `max_distance` from the origin of
+    /// the ray, in the units of the scene, e.g. behind the light of a shadow
+    /// ray
+    pub fn within(mut self, max_distance: f64) -> Self {
+        self.box_intersector.max_distance = max_distance;
+        let box_intersector = &self.box_intersector;
+        self.next_nodes
+            .retain(|intersect| box_intersector.intersect_box(intersect.node).is_some());
+        self
+    }
+}
+
 impl<'a, A: BoxIntersector<'a>> Iterator for BoxIntersectIter<'a, A> {
     type Item = BoxIntersect<'a>;
 
@@ -333,25 +430,136 @@ impl<'a, A: BoxIntersector<'a>> Iterator for BoxIntersectIter<'a, A> {
         let intersect_left = self.box_intersector.intersect_box(left_child);
         let intersect_right = self.box_intersector.intersect_box(right_child);
 
-        match (intersect_left, intersect_right) {
-            (None, None) => {
-                println!("Problem with parent box spliting");
-                return None;
+        // Children can be missed by a ray bounded in distance
+        for intersect in intersect_left.into_iter().chain(intersect_right) {
+            self.next_nodes.push(intersect);
+        }
+
+        return Some(cur_node);
+    }
+}
+
+/// Traversal stacks of the threads querying a kd-tree, so that every ray
+/// reuses the allocation of the previous one instead of growing a new heap
+/// of nodes
+///
+/// Threads of the global rayon pool get a stack of their own, other threads
+/// share the last one, and queries finding their stack in use (e.g. nested
+/// in another query) fall back to a new one.
+pub struct TraversalStacks<'a> {
+    stacks: Vec<Mutex<BinaryHeap<BoxIntersect<'a>>>>,
+}
+
+impl<'a> Default for TraversalStacks<'a> {
+    fn default() -> Self {
+        TraversalStacks {
+            stacks: (0..=rayon::current_num_threads())
+                .map(|_| Mutex::new(BinaryHeap::new()))
+                .collect(),
+        }
+    }
+}
+
+impl<'a> TraversalStacks<'a> {
+    fn slot(&self) -> &Mutex<BinaryHeap<BoxIntersect<'a>>> {
+        let shared = self.stacks.len() - 1;
+        let index = rayon::current_thread_index().map_or(shared, |i| i % shared);
+        &self.stacks[index]
+    }
+
+    /// Run `f` on the nodes intersecting `ray` up to `max_distance`, see
+    /// `iter_intersect_ray` and `BoxIntersectIter::within`
+    pub fn traverse<'r, R, F>(
+        &self,
+        kdtree: &'a Box<KdTree>,
+        ray: &'r Ray,
+        max_distance: f64,
+        f: F,
+    ) -> R
+    where
+        F: FnOnce(&mut BoxIntersectIter<'a, RayIntersector<'r>>) -> R,
+    {
+        let slot = self.slot();
+        let stack = match slot.try_lock() {
+            Ok(mut stack) => mem::take(&mut *stack),
+            Err(_) => BinaryHeap::new(),
+        };
+        let ray_box_intersector = RayIntersector { ray, max_distance };
+        let mut nodes = BoxIntersectIter::with_stack(ray_box_intersector, kdtree, stack);
+        let result = f(&mut nodes);
+        if let Ok(mut stack) = slot.try_lock() {
+            if stack.capacity() == 0 {
+                *stack = nodes.into_stack();
             }
-            (Some(i_left), None) => {
-                self.next_nodes.push(i_left);
+        }
+        result
+    }
+}
+
+/// Most rays of an `occluded_packet` query, one per bit of its mask
+pub const PACKET_SIZE: usize = 64;
+
+/// Mask of the `rays`, each with the distance in the units of the scene up
+/// to which it is tested, for which `blocked(triangles, ray, distance)` finds
+/// an obstacle among the triangles of one of the leaves it enters: bit `i`
+/// is set when `rays[i]` is blocked
+///
+/// The rays go down the tree together, each node being tested against the
+/// rays still unblocked that entered its parent, so that coherent rays, e.g.
+/// the shadow rays of a point toward every light, share the loads of the
+/// nodes. The traversal stops once every ray is blocked.
+///
+/// Panics with more than `PACKET_SIZE` rays.
+pub fn occluded_packet<F>(kdtree: &KdTree, rays: &[(Ray, f64)], mut blocked: F) -> u64
+where
+    F: FnMut(&[usize], &Ray, f64) -> bool,
+{
+    assert!(rays.len() <= PACKET_SIZE, "{} rays in a packet", rays.len());
+    let all = match rays.len() {
+        PACKET_SIZE => u64::MAX,
+        n => (1 << n) - 1,
+    };
+    let mut done = 0;
+    let mut stack = vec![(kdtree, all)];
+    while let Some((node, mask)) = stack.pop() {
+        let mut entering = 0;
+        let mut pending = mask & !done;
+        while pending != 0 {
+            let i = pending.trailing_zeros() as usize;
+            pending &= pending - 1;
+            let (ray, distance) = &rays[i];
+            let entered = ray
+                .intersect_box_range(&node.bounding_box.bounds)
+                .is_some_and(|(t_near, _)| t_near * ray.direction.norm() <= *distance);
+            if entered {
+                entering |= 1 << i;
             }
-            (None, Some(i_right)) => {
-                self.next_nodes.push(i_right);
+        }
+        if entering == 0 {
+            continue;
+        }
+        match node.children() {
+            Some((left, right)) => {
+                stack.push((right, entering));
+                stack.push((left, entering));
             }
-            (Some(i_left), Some(i_right)) => {
-                self.next_nodes.push(i_left);
-                self.next_nodes.push(i_right);
+            None => {
+                let triangles = node.triangle_index.as_deref().unwrap_or(&[]);
+                while entering != 0 {
+                    let i = entering.trailing_zeros() as usize;
+                    entering &= entering - 1;
+                    let (ray, distance) = &rays[i];
+                    if blocked(triangles, ray, *distance) {
+                        done |= 1 << i;
+                    }
+                }
+                if done == all {
+                    break;
+                }
             }
         }
-
-        return Some(cur_node);
     }
+    done
 }
 
 /// Return all the leafs under a given KDTree Node
@@ -391,3 +599,136 @@ impl<'a> KdTreeLeafIter<'a> {
         KdTreeLeafIter { pending: pending }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_rays_skip_the_nodes_beyond() {
+        // Row of small triangles along x
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for i in 0..100 {
+            let x = i as f64;
+            let first = vertices.len();
+            vertices.push(Position::new(x, -0.5, -0.5));
+            vertices.push(Position::new(x, 0.5, -0.5));
+            vertices.push(Position::new(x, 0.0, 0.5));
+            triangles.push([first, first + 1, first + 2]);
+        }
+        let mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        let kdtree = KdTree::from_mesh(&mesh);
+        let ray = Ray::new(Position::new(-1.0, 0.0, 0.0), Direction::new(2.0, 0.0, 0.0));
+
+        let all: Vec<BoxIntersect> = iter_intersect_ray(&kdtree, &ray).leaves().collect();
+        let near: Vec<BoxIntersect> = iter_intersect_ray(&kdtree, &ray)
+            .within(10.5)
+            .leaves()
+            .collect();
+        assert!(near.len() < all.len() / 2);
+        // The nearest leaves are still visited, in the same order
+        for (a, b) in near.iter().zip(&all) {
+            assert!(std::ptr::eq(a.node, b.node));
+        }
+        assert!(near
+            .iter()
+            .all(|leaf| leaf.node.bounding_box.bounds[0].x <= 9.5));
+    }
+
+    #[test]
+    fn packets_find_the_same_occlusions() {
+        // Wall of triangles on the plane x = 0, with a hole in the middle
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for i in 0..10 {
+            for j in 0..10 {
+                if (4..6).contains(&i) && (4..6).contains(&j) {
+                    continue;
+                }
+                let (y, z) = (i as f64 - 5.0, j as f64 - 5.0);
+                let first = vertices.len();
+                vertices.push(Position::new(0.0, y, z));
+                vertices.push(Position::new(0.0, y + 1.0, z));
+                vertices.push(Position::new(0.0, y, z + 1.0));
+                vertices.push(Position::new(0.0, y + 1.0, z + 1.0));
+                triangles.push([first, first + 1, first + 2]);
+                triangles.push([first + 1, first + 3, first + 2]);
+            }
+        }
+        let mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        let kdtree = KdTree::from_mesh(&mesh);
+        let blocked = |triangles: &[usize], ray: &Ray, distance: f64| {
+            triangles.iter().any(|&t| {
+                let [a, b, c] = mesh.triangles[t].map(|v| mesh.vertices[v]);
+                ray.intersect_triangle(&a, &b, &c)
+                    .or_else(|| ray.intersect_triangle(&a, &c, &b))
+                    .is_some_and(|(point, _)| (point - ray.position).norm() < distance)
+            })
+        };
+        // Shadow rays of a point in front of the wall toward lights behind
+        // it, through the hole or not, and short of the wall
+        let origin = Position::new(-2.0, 0.0, 0.0);
+        let rays: Vec<(Ray, f64)> = (0..PACKET_SIZE)
+            .map(|i| {
+                let target = Position::new(2.0, (i % 8) as f64 - 3.5, (i / 8) as f64 - 3.5);
+                let distance = if i % 5 == 0 { 1.5 } else { 4.5 };
+                (Ray::new(origin, target - origin), distance)
+            })
+            .collect();
+        let expected = rays
+            .iter()
+            .enumerate()
+            .filter(|(_, (ray, distance))| {
+                iter_intersect_ray(&kdtree, ray)
+                    .within(*distance)
+                    .leaves()
+                    .any(|leaf| {
+                        let triangles = leaf.node.triangle_index.as_ref().unwrap();
+                        blocked(triangles, ray, *distance)
+                    })
+            })
+            .fold(0, |mask, (i, _)| mask | 1u64 << i);
+        assert_ne!(expected, 0);
+        assert_ne!(expected, u64::MAX);
+        assert_eq!(occluded_packet(&kdtree, &rays, blocked), expected);
+        assert_eq!(
+            occluded_packet(&kdtree, &rays[..10], blocked),
+            expected & 0x3ff
+        );
+        assert_eq!(occluded_packet(&kdtree, &[], blocked), 0);
+    }
+
+    #[test]
+    fn traversal_stacks_give_the_same_nodes() {
+        let vertices: Vec<Position> = (0..60)
+            .map(|i| Position::new((i % 4) as f64, (i / 4 % 3) as f64, (i / 12) as f64))
+            .collect();
+        let triangles = (0..58).map(|i| [i, i + 1, i + 2]).collect();
+        let mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        let kdtree = KdTree::from_mesh(&mesh);
+        let stacks = TraversalStacks::default();
+        let ray = Ray::new(
+            Position::new(-1.0, 1.0, -1.0),
+            Direction::new(1.0, 0.1, 1.0),
+        );
+
+        let expected: Vec<*const KdTree> = iter_intersect_ray(&kdtree, &ray)
+            .map(|intersect| &**intersect.node as *const KdTree)
+            .collect();
+        for _ in 0..2 {
+            let nodes: Vec<*const KdTree> =
+                stacks.traverse(&kdtree, &ray, f64::INFINITY, |nodes| {
+                    nodes
+                        .map(|intersect| &**intersect.node as *const KdTree)
+                        .collect()
+                });
+            assert_eq!(nodes, expected);
+        }
+        // The stack of the calling thread was kept for the next ray
+        assert!(stacks
+            .stacks
+            .iter()
+            .any(|s| s.lock().unwrap().capacity() > 0));
+    }
+}
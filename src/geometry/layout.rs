@@ -0,0 +1,178 @@
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::mesh::Mesh;
+use crate::geometry::types::{Direction, Position};
+
+/// Bits of every coordinate in a Morton code
+const MORTON_BITS: u32 = 21;
+
+/// Position of `point` along the Z-order curve filling `bounds`: points
+/// close on the curve are close in space
+///
+/// The coordinates are quantized on 21 bits, whose bits are interleaved as
+/// zyxzyx...
+pub fn morton_code(point: &Position, bounds: &AxisAlignedBoundingBox) -> u64 {
+    let scale = ((1u64 << MORTON_BITS) - 1) as f64;
+    let mut code = 0;
+    for axis in 0..3 {
+        let size = bounds.get_dimension(axis);
+        let t = if size > 0.0 {
+            ((point[axis] - bounds.bounds[0][axis]) / size).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        code |= spread_bits((t * scale) as u64) << axis;
+    }
+    code
+}
+
+/// Insert two zeros after each of the 21 low bits of `value`
+fn spread_bits(value: u64) -> u64 {
+    let mut x = value & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+impl Mesh {
+    /// Reorder the triangles along a Morton curve, and the vertices in the
+    /// order the triangles use them, so that what is close in space is close
+    /// in memory for the kd-tree traversal and the shading
+    ///
+    /// Only the order changes: the triangles keep their vertices, normals,
+    /// materials and attributes, and the triangles of a polygon stay
+    /// together. Vertices used by no triangle are moved to the end.
+    pub fn optimize_layout(&mut self) {
+        // Runs of triangles moved together, one per face
+        let mut faces: Vec<(usize, usize)> = Vec::with_capacity(self.triangles.len());
+        let mut first = 0;
+        for face in self.faces() {
+            let count = face.len().saturating_sub(2);
+            faces.push((first, count));
+            first += count;
+        }
+        if first != self.triangles.len() {
+            faces = (0..self.triangles.len()).map(|t| (t, 1)).collect();
+        }
+        let centers: Vec<Position> = faces
+            .iter()
+            .map(|&(first, count)| {
+                let corners = self.triangles[first..first + count].iter().flatten();
+                let sum = corners
+                    .clone()
+                    .fold(Direction::zeros(), |sum, &v| sum + self.vertices[v].coords);
+                Position::from(sum / corners.count().max(1) as f64)
+            })
+            .collect();
+        let bounds = AxisAlignedBoundingBox::new(&centers);
+        let mut face_order: Vec<usize> = (0..faces.len()).collect();
+        face_order.sort_by_cached_key(|&face| morton_code(&centers[face], &bounds));
+        if let Some(polygons) = &mut self.polygons {
+            *polygons = face_order.iter().map(|&f| polygons[f].clone()).collect();
+        }
+        let triangle_order: Vec<usize> = face_order
+            .iter()
+            .flat_map(|&face| {
+                let (first, count) = faces[face];
+                first..first + count
+            })
+            .collect();
+
+        // Vertices in the order of their first use
+        let mut new_index = vec![usize::MAX; self.vertices.len()];
+        let mut vertex_order = Vec::with_capacity(self.vertices.len());
+        for &triangle in &triangle_order {
+            for &vertex in &self.triangles[triangle] {
+                if new_index[vertex] == usize::MAX {
+                    new_index[vertex] = vertex_order.len();
+                    vertex_order.push(vertex);
+                }
+            }
+        }
+        for (vertex, index) in new_index.iter_mut().enumerate() {
+            if *index == usize::MAX {
+                *index = vertex_order.len();
+                vertex_order.push(vertex);
+            }
+        }
+
+        fn reorder<T: Copy>(values: &mut Vec<T>, order: &[usize]) {
+            *values = order.iter().map(|&i| values[i]).collect();
+        }
+        reorder(&mut self.triangles, &triangle_order);
+        reorder(&mut self.triangle_normals, &triangle_order);
+        if let Some(materials) = &mut self.triangle_materials {
+            reorder(materials, &triangle_order);
+        }
+        for triangle in &mut self.triangles {
+            *triangle = triangle.map(|vertex| new_index[vertex]);
+        }
+        for polygon in self.polygons.iter_mut().flatten() {
+            for vertex in polygon.iter_mut() {
+                *vertex = new_index[*vertex];
+            }
+        }
+        reorder(&mut self.vertices, &vertex_order);
+        reorder(&mut self.vertex_normals, &vertex_order);
+        if let Some(colors) = &mut self.vertex_colors {
+            reorder(colors, &vertex_order);
+        }
+        for uvs in self.uvs.iter_mut().chain(self.extra_uvs.iter_mut()) {
+            reorder(uvs, &vertex_order);
+        }
+        if let Some(tangents) = &mut self.vertex_tangents {
+            reorder(tangents, &vertex_order);
+        }
+        self.attributes.reorder(&vertex_order, &triangle_order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::attributes::{AttributeValues, Domain};
+
+    #[test]
+    fn triangles_close_in_space_end_up_close_in_memory() {
+        // A row of 8 quads, their triangles shuffled
+        let mut vertices = Vec::new();
+        for x in 0..9 {
+            vertices.push(Position::new(x as f64, 0.0, 0.0));
+            vertices.push(Position::new(x as f64, 1.0, 0.0));
+        }
+        let quads = [5, 1, 7, 3, 0, 6, 2, 4];
+        let polygons: Vec<Vec<usize>> = quads
+            .iter()
+            .map(|&q| vec![2 * q, 2 * q + 2, 2 * q + 3, 2 * q + 1])
+            .collect();
+        let mut mesh = Mesh::from_vertices_and_polygons(vertices, polygons);
+        let labels: Vec<f32> = quads.iter().flat_map(|&q| [q as f32; 2]).collect();
+        mesh.set_attribute("quad", Domain::Triangle, AttributeValues::Scalar(labels));
+        mesh.vertices.push(Position::new(-1.0, 0.0, 0.0));
+        mesh.vertex_normals.push(Direction::z());
+        let xs: Vec<f32> = mesh.vertices.iter().map(|v| v.x as f32).collect();
+        mesh.set_attribute("x", Domain::Vertex, AttributeValues::Scalar(xs));
+        mesh.optimize_layout();
+
+        // Along the row, the triangles of a quad still following each other
+        let labels = mesh.attributes.scalar("quad", Domain::Triangle).unwrap();
+        assert_eq!(
+            labels,
+            &[0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0, 4.0, 4.0, 5.0, 5.0, 6.0, 6.0, 7.0, 7.0][..]
+        );
+        for (triangle, &label) in mesh.triangles.iter().zip(labels) {
+            assert!(triangle.iter().all(|&v| {
+                let x = mesh.vertices[v].x as f32;
+                x == label || x == label + 1.0
+            }));
+        }
+        assert_eq!(mesh.polygons.as_ref().unwrap()[0], vec![0, 1, 2, 3]);
+        // Vertices in the order of their first use, the unused one last
+        assert_eq!(mesh.vertices[0], Position::new(0.0, 0.0, 0.0));
+        assert_eq!(mesh.vertices[18], Position::new(-1.0, 0.0, 0.0));
+        let xs = mesh.attributes.scalar("x", Domain::Vertex).unwrap();
+        assert!(mesh.vertices.iter().zip(xs).all(|(v, &x)| v.x as f32 == x));
+    }
+}
@@ -1,34 +1,151 @@
 extern crate nalgebra as na;
-extern crate regex;
 
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::BufRead;
 use std::num;
 use std::path::Path;
+use std::sync::Arc;
 
-use crate::geometry::types::{Direction, Position, Triangle};
+use crate::geometry::attributes::{Attribute, AttributeValue, AttributeValues, Attributes, Domain};
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::mtl;
+use crate::geometry::ply;
+use crate::geometry::stl;
+use crate::geometry::types::{orthonormal_basis, Direction, Position, Triangle};
+use crate::render::material::{Material, VertexColor};
 
 /// This class is responsible for holding the geometry of the objects, and provide
 /// easy look-ups of things like normals for both triangles and vertices
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Mesh {
     pub vertices: Vec<Position>,
     pub vertex_normals: Vec<Direction>,
     pub triangles: Vec<Triangle>,
     pub triangle_normals: Vec<Direction>,
+    /// Faces as authored, when some are not triangles: `triangles` is their
+    /// triangulation, a polygon of n vertices giving the n - 2 triangles
+    /// following those of the previous polygon
+    pub polygons: Option<Vec<Vec<usize>>>,
+    /// Per vertex RGB colors in [0, 1], when provided by the file
+    pub vertex_colors: Option<Vec<[f32; 3]>>,
+    /// Per vertex texture coordinates, when provided by the file: the first
+    /// UV set, the one of the tangents
+    pub uvs: Option<Vec<[f64; 2]>>,
+    /// Per vertex texture coordinates of the UV sets after `uvs`, e.g. the
+    /// non overlapping layout of a lightmap: set 1 is `extra_uvs[0]`
+    pub extra_uvs: Vec<Vec<[f64; 2]>>,
+    /// Per vertex tangent frames, see `compute_tangents`
+    pub vertex_tangents: Option<Vec<Tangent>>,
+    /// Other per vertex or per triangle data kept from the file
+    pub attributes: Attributes,
+    /// Materials of the triangles, `VertexColor` when empty
+    pub materials: Vec<Arc<dyn Material>>,
+    /// Index in `materials` of every triangle, all the triangles use the
+    /// first material when `None`
+    pub triangle_materials: Option<Vec<usize>>,
+}
+
+/// Direction of increasing u on the surface at a vertex, completing the
+/// normal into the frame of tangent space normal maps, as in glTF
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tangent {
+    /// Orthogonal to the vertex normal, normalized
+    pub direction: Direction,
+    /// 1 or -1, the direction of increasing v being
+    /// `handedness * normal × direction`; -1 where the texture is mirrored
+    pub handedness: f64,
 }
 
-/// This defines the errors that can occure when parsing an OFF file
+/// This defines the errors that can occure when parsing a mesh file
 #[derive(Debug)]
-pub enum OFFError {
+pub enum LoadError {
     Io(io::Error),
-    Re(regex::Error),
     String(&'static str),
     ParseFloat(num::ParseFloatError),
     ParseInt(num::ParseIntError),
+    /// The file, or the number of elements it declares, is over a limit:
+    /// what, how many and the limit
+    Limit(&'static str, u64, u64),
+}
+
+/// Sanity limits enforced while loading models
+///
+/// Counts declared by a header are checked before anything is allocated, so
+/// that a corrupted count line cannot exhaust the memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadLimits {
+    /// Size of the file, in bytes
+    pub max_file_size: u64,
+    pub max_vertices: usize,
+    /// Triangles after the triangulation of the polygons
+    pub max_triangles: usize,
+}
+
+impl Default for LoadLimits {
+    /// 4 GiB, 100 million vertices and 200 million triangles
+    fn default() -> Self {
+        LoadLimits {
+            max_file_size: 4 << 30,
+            max_vertices: 100_000_000,
+            max_triangles: 200_000_000,
+        }
+    }
+}
+
+/// Elements reserved up front from a declared count, the vectors grow past
+/// it as the data is actually read
+const MAX_PREALLOCATED: usize = 1 << 16;
+
+impl LoadLimits {
+    /// Open `path`, checking its size
+    pub(crate) fn open(&self, path: &Path) -> Result<File, LoadError> {
+        let file = File::open(path).map_err(LoadError::Io)?;
+        let size = file.metadata().map_err(LoadError::Io)?.len();
+        if size > self.max_file_size {
+            return Err(LoadError::Limit("file size", size, self.max_file_size));
+        }
+        Ok(file)
+    }
+
+    pub(crate) fn check_vertices(&self, count: usize) -> Result<(), LoadError> {
+        check_limit("vertex count", count, self.max_vertices)
+    }
+
+    pub(crate) fn check_triangles(&self, count: usize) -> Result<(), LoadError> {
+        check_limit("triangle count", count, self.max_triangles)
+    }
+}
+
+fn check_limit(what: &'static str, count: usize, max: usize) -> Result<(), LoadError> {
+    if count > max {
+        return Err(LoadError::Limit(what, count as u64, max as u64));
+    }
+    Ok(())
+}
+
+/// Kept for code written when OFF was the only supported format
+pub type OFFError = LoadError;
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::String(message) => write!(f, "{}", message),
+            LoadError::ParseFloat(e) => write!(f, "invalid number: {}", e),
+            LoadError::ParseInt(e) => write!(f, "invalid index: {}", e),
+            LoadError::Limit(what, count, max) => {
+                write!(f, "{} {} is over the limit of {}", what, count, max)
+            }
+        }
+    }
 }
 
+impl error::Error for LoadError {}
+
 impl Mesh {
     pub fn from_vertices_and_triangles(vertices: Vec<Position>, triangles: Vec<Triangle>) -> Mesh {
         // Calculate normals
@@ -40,98 +157,813 @@ impl Mesh {
             vertex_normals: vertex_normals,
             triangles: triangles,
             triangle_normals: triangle_normals,
+            polygons: None,
+            vertex_colors: None,
+            uvs: None,
+            extra_uvs: Vec::new(),
+            vertex_tangents: None,
+            attributes: Attributes::default(),
+            materials: Vec::new(),
+            triangle_materials: None,
+        }
+    }
+
+    /// Mesh of polygons of at least 3 vertices, triangulated as fans and kept
+    /// in `polygons` when some are not triangles
+    pub fn from_vertices_and_polygons(vertices: Vec<Position>, polygons: Vec<Vec<usize>>) -> Mesh {
+        let triangles = fan_triangulation(&polygons);
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        if polygons.iter().any(|p| p.len() > 3) {
+            mesh.polygons = Some(polygons);
         }
+        mesh
     }
-    pub fn load_off_file(path: &Path) -> Result<Mesh, OFFError> {
-        let off_file_result = File::open(path).map_err(OFFError::Io)?;
-
-        let mut line = String::new();
-        let mut reader = io::BufReader::new(off_file_result);
-
-        // Check Magic Line
-        reader.read_line(&mut line).map_err(OFFError::Io)?;
-        if line != "OFF\n" {
-            return Err(OFFError::String("Magic number OFF not present"));
-        }
-        line.clear();
-
-        // Parse Number of vertices and triangles
-        reader.read_line(&mut line).map_err(OFFError::Io)?;
-
-        let re_size = (regex::Regex::new(
-            r"^(?P<nb_vertices>\d+)\s+(?P<nb_triangles>\d+)\s+(?P<nb_x>\d+)\s+$",
-        )
-        .map_err(OFFError::Re))?;
-        let captures = (re_size
-            .captures(&line)
-            .ok_or("Could not decode vertices and triangle count")
-            .map_err(OFFError::String))?;
-        let nb_vertices = captures
-            .name("nb_vertices")
-            .unwrap()
-            .as_str()
-            .parse::<usize>()
-            .unwrap();
-        let nb_triangles = captures
-            .name("nb_triangles")
-            .unwrap()
-            .as_str()
-            .parse::<usize>()
-            .unwrap();
-
-        let mut counter_vertices = nb_vertices;
-        let mut count_triangles = nb_triangles;
-        let mut vertices: Vec<Position> = Vec::with_capacity(counter_vertices);
-        let mut triangles: Vec<Triangle> = Vec::with_capacity(count_triangles);
-
-        let mut point: [f64; 3] = [0.0, 0.0, 0.0];
-        let mut index: Triangle = [0, 0, 0];
-
-        for line in reader.lines() {
-            if counter_vertices > 0 {
-                for (i, split) in line.unwrap().split_whitespace().take(3).enumerate() {
-                    point[i] = split.parse::<f64>().map_err(OFFError::ParseFloat)?;
+
+    /// The six sides of `bounds` as quads, facing out, or in with `inward`,
+    /// e.g. for the walls of a room
+    ///
+    /// Every side has its own vertices, for flat shading, and texture
+    /// coordinates covering it. The boxes of scene files are tessellated so,
+    /// their objects all being meshes of the kd-tree of the scene;
+    /// `render::hittable::Cuboid` is the same box traced without triangles.
+    pub fn cuboid(bounds: &AxisAlignedBoundingBox, inward: bool) -> Mesh {
+        let mut vertices = Vec::with_capacity(24);
+        let mut uvs = Vec::with_capacity(24);
+        let mut polygons = Vec::with_capacity(6);
+        for axis in 0..3 {
+            // Counterclockwise around +axis
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for side in 0..2 {
+                let start = vertices.len();
+                for &[i, j] in &[[0, 0], [1, 0], [1, 1], [0, 1]] {
+                    let mut corner = bounds.bounds[side];
+                    corner[u] = bounds.bounds[i][u];
+                    corner[v] = bounds.bounds[j][v];
+                    vertices.push(corner);
+                    uvs.push([i as f64, j as f64]);
                 }
-                vertices.push(Position::from_slice(&point));
-                counter_vertices -= 1;
-            } else if count_triangles > 0 {
-                for (i, split) in line.unwrap().split_whitespace().skip(1).take(3).enumerate() {
-                    index[i] = split.parse::<usize>().map_err(OFFError::ParseInt)?;
+                let mut polygon: Vec<usize> = (start..start + 4).collect();
+                if (side == 0) != inward {
+                    polygon.reverse();
                 }
-                triangles.push(index);
-                count_triangles -= 1;
+                polygons.push(polygon);
+            }
+        }
+        let mut mesh = Mesh::from_vertices_and_polygons(vertices, polygons);
+        mesh.uvs = Some(uvs);
+        mesh
+    }
+
+    /// Faces as authored: the polygons, or the triangles
+    pub fn faces(&self) -> Box<dyn Iterator<Item = &[usize]> + '_> {
+        match &self.polygons {
+            Some(polygons) => Box::new(polygons.iter().map(|p| p.as_slice())),
+            None => Box::new(self.triangles.iter().map(|t| &t[..])),
+        }
+    }
+
+    /// Edges of the faces, without the diagonals of the triangulation, e.g.
+    /// for wireframes; each edge once, smallest vertex first
+    pub fn edges(&self) -> Vec<[usize; 2]> {
+        let mut edges: Vec<[usize; 2]> = self
+            .faces()
+            .flat_map(|face| {
+                face.iter()
+                    .zip(face.iter().cycle().skip(1))
+                    .map(|(&a, &b)| [a.min(b), a.max(b)])
+            })
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        edges
+    }
+
+    /// Compute the triangle and vertex normals again from the positions,
+    /// e.g. after moving the vertices or to replace those of the file
+    pub fn recompute_normals(&mut self) {
+        self.triangle_normals = compute_triangle_normals(&self.triangles, &self.vertices);
+        self.vertex_normals =
+            compute_vertex_normals(&self.triangles, &self.vertices, &self.triangle_normals);
+    }
+
+    /// Move the mesh by `transform`, e.g. `na::convert(similarity)` or a
+    /// matrix of `na::Affine3::from_matrix_unchecked`
+    ///
+    /// The normals are transformed by the inverse transpose of the linear
+    /// part, so that they stay orthogonal to the surface under non uniform
+    /// scales, and the tangents follow the surface. Mirroring transforms
+    /// reverse the triangles and polygons so that they still face out.
+    pub fn transform(&mut self, transform: &na::Affine3<f64>) {
+        let linear: na::Matrix3<f64> = transform
+            .matrix()
+            .fixed_slice::<na::U3, na::U3>(0, 0)
+            .into_owned();
+        let normal_matrix = transform
+            .inverse()
+            .matrix()
+            .fixed_slice::<na::U3, na::U3>(0, 0)
+            .transpose();
+        let mirrored = linear.determinant() < 0.0;
+
+        for vertex in &mut self.vertices {
+            *vertex = transform * *vertex;
+        }
+        for normal in &mut self.vertex_normals {
+            *normal = normalize_or_zero(normal_matrix * *normal);
+        }
+        for tangents in &mut self.vertex_tangents {
+            for (tangent, normal) in tangents.iter_mut().zip(&self.vertex_normals) {
+                let direction = linear * tangent.direction;
+                tangent.direction = (direction - normal * normal.dot(&direction)).normalize();
+                if mirrored {
+                    tangent.handedness = -tangent.handedness;
+                }
+            }
+        }
+        if mirrored {
+            for triangle in &mut self.triangles {
+                triangle.swap(1, 2);
+            }
+            for polygon in self.polygons.iter_mut().flatten() {
+                polygon[1..].reverse();
+            }
+        }
+        self.triangle_normals = compute_triangle_normals(&self.triangles, &self.vertices);
+    }
+
+    /// Move the mesh by `offset`
+    pub fn translate(&mut self, offset: Direction) {
+        self.transform(&na::Affine3::from_matrix_unchecked(
+            na::Matrix4::new_translation(&offset),
+        ));
+    }
+
+    /// Turn the mesh around the origin
+    pub fn rotate(&mut self, rotation: &na::Rotation3<f64>) {
+        self.transform(&na::Affine3::from_matrix_unchecked(
+            rotation.to_homogeneous(),
+        ));
+    }
+
+    /// Scale the mesh from the origin, by `factor` along every axis
+    pub fn scale(&mut self, factor: &Direction) {
+        self.transform(&na::Affine3::from_matrix_unchecked(
+            na::Matrix4::new_nonuniform_scaling(factor),
+        ));
+    }
+
+    /// The mesh centered on the origin and scaled to the unit cube, from
+    /// -0.5 to 0.5, e.g. for models of unknown units to be seen whole from
+    /// the default camera
+    pub fn normalized(self) -> Mesh {
+        self.normalized_to(0.5)
+    }
+
+    /// The mesh centered on the origin and scaled uniformly so that its
+    /// bounding box fits in the cube from -`half_size` to `half_size`,
+    /// touching it along its longest side
+    ///
+    /// A mesh without extent, e.g. a single point, is only moved.
+    pub fn normalized_to(mut self, half_size: f64) -> Mesh {
+        let bounding_box = self.bounding_box();
+        let extent = bounding_box.extent.max();
+        let scale = if extent > 0.0 {
+            half_size / extent
+        } else {
+            1.0
+        };
+        self.transform(&na::Affine3::from_matrix_unchecked(
+            na::Matrix4::new_scaling(scale).prepend_translation(&-bounding_box.center.coords),
+        ));
+        self
+    }
+
+    /// Merge the vertices closer than `tolerance` to each other, returning
+    /// how many were removed
+    ///
+    /// The first vertex of a group keeps its colors, texture coordinates and
+    /// attributes, and the normals are recomputed so that the shading is
+    /// smooth across the merged seams. Faces are kept even when some of their
+    /// vertices end up merged; those collapsed get a zero normal and take no
+    /// part in the vertex normals.
+    pub fn weld(&mut self, tolerance: f64) -> usize {
+        let (remap, kept) = weld_map(&self.vertices, tolerance);
+        let removed = self.vertices.len() - kept.len();
+        if removed == 0 {
+            return 0;
+        }
+        fn select<T: Copy>(values: &[T], kept: &[usize]) -> Vec<T> {
+            kept.iter().map(|&i| values[i]).collect()
+        }
+        self.vertices = select(&self.vertices, &kept);
+        self.vertex_colors = self.vertex_colors.as_ref().map(|c| select(c, &kept));
+        self.uvs = self.uvs.as_ref().map(|uvs| select(uvs, &kept));
+        for uvs in &mut self.extra_uvs {
+            *uvs = select(uvs, &kept);
+        }
+        self.vertex_tangents = self.vertex_tangents.as_ref().map(|t| select(t, &kept));
+        self.attributes.select_vertices(&kept);
+        for triangle in &mut self.triangles {
+            *triangle = triangle.map(|vertex| remap[vertex]);
+        }
+        for polygon in self.polygons.iter_mut().flatten() {
+            for vertex in polygon.iter_mut() {
+                *vertex = remap[*vertex];
+            }
+        }
+        self.recompute_normals();
+        removed
+    }
+
+    /// Texture coordinates of UV set `set`, 0 being `uvs`
+    pub fn uv_set(&self, set: usize) -> Option<&[[f64; 2]]> {
+        match set {
+            0 => self.uvs.as_deref(),
+            _ => self.extra_uvs.get(set - 1).map(Vec::as_slice),
+        }
+    }
+
+    /// Number of UV sets, those of `extra_uvs` counting only after `uvs`
+    pub fn uv_set_count(&self) -> usize {
+        match self.uvs {
+            Some(_) => 1 + self.extra_uvs.len(),
+            None => 0,
+        }
+    }
+
+    /// Add a UV set after the others, one coordinate per vertex, returning
+    /// its index; the first one goes to `uvs`
+    pub fn add_uv_set(&mut self, uvs: Vec<[f64; 2]>) -> usize {
+        assert_eq!(uvs.len(), self.vertices.len(), "texture coordinates");
+        match self.uvs {
+            None => {
+                self.uvs = Some(uvs);
+                0
+            }
+            Some(_) => {
+                self.extra_uvs.push(uvs);
+                self.extra_uvs.len()
+            }
+        }
+    }
+
+    /// Compute `vertex_tangents` from the first UV set, does nothing
+    /// without them
+    ///
+    /// The tangents of the triangles are averaged at their vertices, weighted
+    /// by area, then made orthogonal to the vertex normals. Vertices whose
+    /// texture coordinates are degenerate get an arbitrary tangent.
+    pub fn compute_tangents(&mut self) {
+        let uvs = match &self.uvs {
+            Some(uvs) => uvs,
+            None => return,
+        };
+        let zero = Direction::new(0.0, 0.0, 0.0);
+        let mut tangents = vec![zero; self.vertices.len()];
+        let mut bitangents = vec![zero; self.vertices.len()];
+        for t in &self.triangles {
+            let e1 = self.vertices[t[1]] - self.vertices[t[0]];
+            let e2 = self.vertices[t[2]] - self.vertices[t[0]];
+            let (du1, dv1) = (uvs[t[1]][0] - uvs[t[0]][0], uvs[t[1]][1] - uvs[t[0]][1]);
+            let (du2, dv2) = (uvs[t[2]][0] - uvs[t[0]][0], uvs[t[2]][1] - uvs[t[0]][1]);
+            let determinant = du1 * dv2 - du2 * dv1;
+            if determinant.abs() < 1e-12 {
+                continue;
+            }
+            // Not divided by the determinant, only its sign matters for the
+            // weighting by area
+            let sign = determinant.signum();
+            let tangent = (e1 * dv2 - e2 * dv1) * sign;
+            let bitangent = (e2 * du1 - e1 * du2) * sign;
+            for &vertex in t {
+                tangents[vertex] += tangent;
+                bitangents[vertex] += bitangent;
+            }
+        }
+        let frames = tangents
+            .iter()
+            .zip(&bitangents)
+            .zip(&self.vertex_normals)
+            .map(|((tangent, bitangent), normal)| {
+                let orthogonal = tangent - normal * normal.dot(tangent);
+                let direction = if orthogonal.norm() > 1e-12 {
+                    orthogonal.normalize()
+                } else {
+                    orthonormal_basis(normal).0
+                };
+                let handedness = if normal.cross(&direction).dot(bitangent) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                Tangent {
+                    direction,
+                    handedness,
+                }
+            })
+            .collect();
+        self.vertex_tangents = Some(frames);
+    }
+
+    /// Tangent frame interpolated at the barycentric coordinates of a
+    /// triangle, `None` without `vertex_tangents`
+    pub fn tangent_at(&self, triangle: usize, barycentric: &[f64; 2]) -> Option<Tangent> {
+        let tangents = self.vertex_tangents.as_ref()?;
+        let [a, b, c] = self.triangles[triangle].map(|vertex| tangents[vertex]);
+        let [u, v] = *barycentric;
+        let direction = a.direction * (1.0 - u - v) + b.direction * u + c.direction * v;
+        Some(Tangent {
+            direction: direction.try_normalize(1e-12).unwrap_or(a.direction),
+            handedness: a.handedness,
+        })
+    }
+
+    /// Add or replace an attribute
+    ///
+    /// Panics unless there is one value per element of `domain`.
+    pub fn set_attribute(&mut self, name: &str, domain: Domain, values: AttributeValues) {
+        let count = match domain {
+            Domain::Vertex => self.vertices.len(),
+            Domain::Triangle => self.triangles.len(),
+        };
+        assert_eq!(values.len(), count, "values of attribute {}", name);
+        self.attributes.insert(name, Attribute { domain, values });
+    }
+
+    /// Value of an attribute at the barycentric coordinates of a triangle:
+    /// interpolated between the vertices, or the value of the triangle
+    pub fn attribute_at(
+        &self,
+        name: &str,
+        triangle: usize,
+        barycentric: &[f64; 2],
+    ) -> Option<AttributeValue> {
+        let attribute = self.attributes.get(name)?;
+        if attribute.domain == Domain::Triangle {
+            return Some(attribute.values.get(triangle));
+        }
+        let [u, v] = barycentric.map(|c| c as f32);
+        let weights = [1.0 - u - v, u, v];
+        let corners = self.triangles[triangle];
+        let mut sum = [0.0f32; 3];
+        for (&vertex, &weight) in corners.iter().zip(&weights) {
+            let value = match attribute.values.get(vertex) {
+                AttributeValue::Scalar(x) => [x, 0.0, 0.0],
+                AttributeValue::Vec2([x, y]) => [x, y, 0.0],
+                AttributeValue::Vec3(xyz) => xyz,
+            };
+            for (s, x) in sum.iter_mut().zip(&value) {
+                *s += weight * x;
+            }
+        }
+        Some(match attribute.values {
+            AttributeValues::Scalar(_) => AttributeValue::Scalar(sum[0]),
+            AttributeValues::Vec2(_) => AttributeValue::Vec2([sum[0], sum[1]]),
+            AttributeValues::Vec3(_) => AttributeValue::Vec3(sum),
+        })
+    }
+
+    /// Material of a triangle
+    pub fn material(&self, triangle: usize) -> &dyn Material {
+        let index = self
+            .triangle_materials
+            .as_ref()
+            .map_or(0, |indices| indices[triangle]);
+        match self.materials.get(index) {
+            Some(material) => material.as_ref(),
+            None => &VertexColor,
+        }
+    }
+    /// Load a model, picking the format from the file extension: OBJ, PLY,
+    /// STL and OFF for anything else
+    pub fn load_file(path: &Path) -> Result<Mesh, LoadError> {
+        Mesh::load_file_with_limits(path, &LoadLimits::default())
+    }
+
+    /// `load_file`, refusing files over `limits`
+    pub fn load_file_with_limits(path: &Path, limits: &LoadLimits) -> Result<Mesh, LoadError> {
+        let _span = tracing::info_span!("load_mesh", path = %path.display()).entered();
+        let file = io::BufReader::new(limits.open(path)?);
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("obj") => load_obj(path, file, limits),
+            Some("ply") => ply::read_ply(file, limits),
+            Some("stl") => stl::read_stl(file, limits),
+            _ => read_off(file, limits),
+        }
+    }
+
+    /// Load an Object File Format file
+    ///
+    /// The `OFF`, `COFF` (vertex colors), `NOFF` (vertex normals) and `CNOFF`
+    /// variants are supported, as well as the `ST` prefix for texture
+    /// coordinates which are skipped. Faces with more than 3 vertices are
+    /// kept in `polygons` and triangulated as fans, face colors are ignored.
+    pub fn load_off_file(path: &Path) -> Result<Mesh, LoadError> {
+        let limits = LoadLimits::default();
+        read_off(io::BufReader::new(limits.open(path)?), &limits)
+    }
+
+    /// Load a Wavefront OBJ file
+    ///
+    /// Faces with more than 3 vertices are kept in `polygons` and triangulated
+    /// as fans, and the normals are recomputed from the triangles. Texture
+    /// coordinates are kept per vertex: vertices used with several texture
+    /// coordinates are split, and vertices used by no face are dropped.
+    /// Normals referenced by the faces are validated but not stored. The
+    /// materials come from the MTL files next to the model, see
+    /// `mtl::apply_materials`.
+    pub fn load_obj_file(path: &Path) -> Result<Mesh, LoadError> {
+        let limits = LoadLimits::default();
+        load_obj(path, io::BufReader::new(limits.open(path)?), &limits)
+    }
+}
+
+/// Read an OBJ file and the materials of its MTL files, relative to `path`
+fn load_obj<R: BufRead>(path: &Path, reader: R, limits: &LoadLimits) -> Result<Mesh, LoadError> {
+    let (mut mesh, materials) = read_obj(reader, limits)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    mtl::apply_materials(&mut mesh, &materials, directory);
+    Ok(mesh)
+}
+
+fn read_off<R: BufRead>(reader: R, limits: &LoadLimits) -> Result<Mesh, LoadError> {
+    /// Content lines split in tokens, without comments and blank lines
+    fn content_lines<R: BufRead>(
+        reader: R,
+    ) -> impl Iterator<Item = Result<Vec<String>, LoadError>> {
+        reader
+            .lines()
+            .map(|line| {
+                let line = line.map_err(LoadError::Io)?;
+                let content = match line.find('#') {
+                    Some(i) => &line[..i],
+                    None => &line,
+                };
+                Ok(content.split_whitespace().map(String::from).collect())
+            })
+            .filter(|tokens: &Result<Vec<String>, LoadError>| {
+                tokens.as_ref().map_or(true, |t| !t.is_empty())
+            })
+    }
+
+    fn parse_floats(tokens: &[String]) -> Result<Vec<f64>, LoadError> {
+        tokens
+            .iter()
+            .map(|t| t.parse::<f64>().map_err(LoadError::ParseFloat))
+            .collect()
+    }
+
+    let mut lines = content_lines(reader);
+    let mut header = lines
+        .next()
+        .ok_or(LoadError::String("Magic number OFF not present"))??;
+
+    // [ST][C][N]OFF, the counts may follow on the same line
+    let keyword = header.remove(0);
+    let mut prefix = keyword
+        .strip_suffix("OFF")
+        .ok_or(LoadError::String("Magic number OFF not present"))?;
+    let has_uvs = prefix.starts_with("ST");
+    if has_uvs {
+        prefix = &prefix[2..];
+    }
+    let has_colors = prefix.starts_with('C');
+    if has_colors {
+        prefix = &prefix[1..];
+    }
+    let has_normals = prefix == "N";
+    if !(has_normals || prefix.is_empty()) {
+        return Err(LoadError::String("Unsupported OFF variant"));
+    }
+
+    if header.is_empty() {
+        header = lines.next().ok_or(LoadError::String(
+            "Could not decode vertices and triangle count",
+        ))??;
+    }
+    if header.len() < 2 {
+        return Err(LoadError::String(
+            "Could not decode vertices and triangle count",
+        ));
+    }
+    let nb_vertices = header[0].parse::<usize>().map_err(LoadError::ParseInt)?;
+    let nb_faces = header[1].parse::<usize>().map_err(LoadError::ParseInt)?;
+    limits.check_vertices(nb_vertices)?;
+    limits.check_triangles(nb_faces)?;
+
+    let corrupted =
+        "OFF file corrupted: vertice / triangle count declared doesn't match available data";
+    let mut vertices: Vec<Position> = Vec::with_capacity(nb_vertices.min(MAX_PREALLOCATED));
+    let mut normals: Vec<Direction> = Vec::new();
+    let mut colors: Vec<[f32; 3]> = Vec::new();
+    let mut polygons: Vec<Vec<usize>> = Vec::with_capacity(nb_faces.min(MAX_PREALLOCATED));
+    let mut triangle_count = 0;
+
+    let mut position_count = 3;
+    if has_normals {
+        position_count += 3;
+    }
+    for _ in 0..nb_vertices {
+        let tokens = lines.next().ok_or(LoadError::String(corrupted))??;
+        if tokens.len() < position_count {
+            return Err(LoadError::String("OFF vertex with missing coordinates"));
+        }
+        let values = parse_floats(&tokens[..position_count])?;
+        vertices.push(Position::new(values[0], values[1], values[2]));
+        if has_normals {
+            normals.push(Direction::new(values[3], values[4], values[5]).normalize());
+        }
+        if has_colors {
+            // RGB or RGBA, as integers in [0, 255] or floats in [0, 1]
+            let color_tokens = &tokens[position_count..];
+            let color_count = if has_uvs {
+                color_tokens.len().saturating_sub(2)
             } else {
-                break;
+                color_tokens.len()
+            };
+            if color_count < 3 {
+                return Err(LoadError::String("COFF vertex without color"));
             }
+            let color = parse_floats(&color_tokens[..3])?;
+            let scale = if color_tokens[..3].iter().all(|t| t.parse::<u8>().is_ok())
+                && color.iter().any(|&c| c > 1.0)
+            {
+                1.0 / 255.0
+            } else {
+                1.0
+            };
+            colors.push([
+                (color[0] * scale) as f32,
+                (color[1] * scale) as f32,
+                (color[2] * scale) as f32,
+            ]);
         }
+    }
 
-        if counter_vertices > 0 || count_triangles > 0 {
-            return Err(OFFError::String("OFF file corrupted: vertice / triangle count declared doesn't match available data"));
+    for _ in 0..nb_faces {
+        let tokens = lines.next().ok_or(LoadError::String(corrupted))??;
+        let count = tokens[0].parse::<usize>().map_err(LoadError::ParseInt)?;
+        if count < 3 {
+            return Err(LoadError::String("OFF face with less than 3 vertices"));
+        }
+        if tokens.len() < count + 1 {
+            return Err(LoadError::String(corrupted));
         }
+        let face = tokens[1..=count]
+            .iter()
+            .map(|t| {
+                let index = t.parse::<usize>().map_err(LoadError::ParseInt)?;
+                if index >= nb_vertices {
+                    return Err(LoadError::String("OFF face references a missing vertex"));
+                }
+                Ok(index)
+            })
+            .collect::<Result<Vec<usize>, LoadError>>()?;
+        triangle_count += count - 2;
+        limits.check_triangles(triangle_count)?;
+        polygons.push(face);
+    }
+
+    let mut mesh = Mesh::from_vertices_and_polygons(vertices, polygons);
+    if has_normals {
+        mesh.vertex_normals = normals;
+    }
+    if has_colors {
+        mesh.vertex_colors = Some(colors);
+    }
+    Ok(mesh)
+}
+
+/// Material references of an OBJ file, resolved by `mtl::apply_materials`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ObjMaterials {
+    /// Files given to `mtllib`, relative to the OBJ file
+    pub libraries: Vec<String>,
+    /// Materials given to `usemtl`, in the order of their first use
+    pub names: Vec<String>,
+    /// Index in `names` of every triangle, `None` before the first `usemtl`;
+    /// empty without `usemtl`
+    pub triangle_names: Vec<Option<usize>>,
+}
+
+fn read_obj<R: BufRead>(reader: R, limits: &LoadLimits) -> Result<(Mesh, ObjMaterials), LoadError> {
+    /// Resolve a 1-based (or negative, relative to the end) OBJ index
+    fn resolve_index(token: &str, count: usize) -> Result<usize, LoadError> {
+        let index = token.parse::<i64>().map_err(LoadError::ParseInt)?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(LoadError::String("OBJ face references a missing element"));
+        }
+        Ok(resolved as usize)
+    }
+
+    let mut vertices: Vec<Position> = Vec::new();
+    let mut nb_normals = 0;
+    let mut uvs: Vec<[f64; 2]> = Vec::new();
+    // Vertex and texture coordinates of the corners of every face
+    let mut faces: Vec<Vec<(usize, Option<usize>)>> = Vec::new();
+    let mut triangle_count = 0;
+    let mut materials = ObjMaterials::default();
+    let mut current_material = None;
+
+    for line in reader.lines() {
+        let line = line.map_err(LoadError::Io)?;
+        let content = match line.find('#') {
+            Some(i) => &line[..i],
+            None => &line,
+        };
+        let mut tokens = content.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut point: [f64; 3] = [0.0, 0.0, 0.0];
+                for coordinate in point.iter_mut() {
+                    *coordinate = tokens
+                        .next()
+                        .ok_or(LoadError::String("OBJ vertex with less than 3 coordinates"))?
+                        .parse::<f64>()
+                        .map_err(LoadError::ParseFloat)?;
+                }
+                limits.check_vertices(vertices.len() + 1)?;
+                vertices.push(Position::from_slice(&point));
+            }
+            Some("vn") => nb_normals += 1,
+            Some("vt") => {
+                // The optional third coordinate is for 3D textures
+                let mut uv: [f64; 2] = [0.0, 0.0];
+                for coordinate in uv.iter_mut() {
+                    *coordinate = match tokens.next() {
+                        Some(token) => token.parse::<f64>().map_err(LoadError::ParseFloat)?,
+                        None => 0.0,
+                    };
+                }
+                limits.check_vertices(uvs.len() + 1)?;
+                uvs.push(uv);
+            }
+            Some("f") => {
+                let mut face = Vec::new();
+                for corner in tokens {
+                    let mut indices = corner.split('/');
+                    let vertex = indices.next().unwrap_or("");
+                    let vertex = resolve_index(vertex, vertices.len())?;
+                    let uv = match indices.next().filter(|uv| !uv.is_empty()) {
+                        Some(uv) => Some(resolve_index(uv, uvs.len())?),
+                        None => None,
+                    };
+                    face.push((vertex, uv));
+                    if let Some(normal) = indices.next().filter(|n| !n.is_empty()) {
+                        resolve_index(normal, nb_normals)?;
+                    }
+                }
+                if face.len() < 3 {
+                    return Err(LoadError::String("OBJ face with less than 3 vertices"));
+                }
+                triangle_count += face.len() - 2;
+                limits.check_triangles(triangle_count)?;
+                if current_material.is_some() {
+                    materials
+                        .triangle_names
+                        .extend((2..face.len()).map(|_| current_material));
+                }
+                faces.push(face);
+            }
+            // File names may contain spaces, several files are rare
+            Some("mtllib") => materials
+                .libraries
+                .push(tokens.collect::<Vec<_>>().join(" ")),
+            Some("usemtl") => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                current_material = Some(match materials.names.iter().position(|n| *n == name) {
+                    Some(index) => index,
+                    None => {
+                        // The triangles before the first material
+                        materials.triangle_names.resize(triangle_count, None);
+                        materials.names.push(name);
+                        materials.names.len() - 1
+                    }
+                });
+            }
+            // Groups, objects, smoothing groups, lines...
+            _ => {}
+        }
+    }
+
+    if uvs.is_empty() {
+        let polygons = faces
+            .iter()
+            .map(|face| face.iter().map(|&(vertex, _)| vertex).collect())
+            .collect();
+        return Ok((
+            Mesh::from_vertices_and_polygons(vertices, polygons),
+            materials,
+        ));
+    }
+    // One vertex per pair of position and texture coordinates, corners
+    // without texture coordinates get (0, 0)
+    let mut corners: HashMap<(usize, Option<usize>), usize> = HashMap::new();
+    let mut split_vertices: Vec<Position> = Vec::new();
+    let mut vertex_uvs: Vec<[f64; 2]> = Vec::new();
+    let polygons = faces
+        .iter()
+        .map(|face| {
+            face.iter()
+                .map(|&(vertex, uv)| {
+                    *corners.entry((vertex, uv)).or_insert_with(|| {
+                        split_vertices.push(vertices[vertex]);
+                        vertex_uvs.push(uv.map_or([0.0, 0.0], |uv| uvs[uv]));
+                        split_vertices.len() - 1
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    let mut mesh = Mesh::from_vertices_and_polygons(split_vertices, polygons);
+    mesh.uvs = Some(vertex_uvs);
+    mesh.compute_tangents();
+    Ok((mesh, materials))
+}
 
-        let mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+/// Triangulate polygons of at least 3 vertices as fans around their first
+/// vertex, which suits the convex faces of most models
+pub fn fan_triangulation(polygons: &[Vec<usize>]) -> Vec<Triangle> {
+    polygons
+        .iter()
+        .flat_map(|p| (1..p.len() - 1).map(move |i| [p[0], p[i], p[i + 1]]))
+        .collect()
+}
 
-        return Ok(mesh);
+/// Merge the positions closer than `tolerance`, or equal when it is 0
+///
+/// Returns the new index of every position and the positions kept, the first
+/// of every group, in their order. Positions are bucketed in a grid of cells
+/// the size of the tolerance, so that only neighboring cells are compared.
+pub(crate) fn weld_map(positions: &[Position], tolerance: f64) -> (Vec<usize>, Vec<usize>) {
+    let cell = |p: &Position| {
+        if tolerance > 0.0 {
+            p.coords.map(|x| (x / tolerance).floor() as i64)
+        } else {
+            // Adding 0 turns -0 into 0
+            p.coords.map(|x| (x + 0.0).to_bits() as i64)
+        }
+    };
+    let reach = if tolerance > 0.0 { 1 } else { 0 };
+    let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    let mut remap = Vec::with_capacity(positions.len());
+    let mut kept: Vec<usize> = Vec::new();
+    for p in positions {
+        let c = cell(p);
+        let mut found = None;
+        'search: for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    let neighbor = [c.x + dx, c.y + dy, c.z + dz];
+                    for &k in grid.get(&neighbor).into_iter().flatten() {
+                        if (positions[kept[k]] - p).norm() <= tolerance {
+                            found = Some(k);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        let index = found.unwrap_or_else(|| {
+            grid.entry([c.x, c.y, c.z]).or_default().push(kept.len());
+            kept.push(remap.len());
+            kept.len() - 1
+        });
+        remap.push(index);
     }
+    (remap, kept)
 }
 
 /// Compute the normals of the triangles.
 /// This defines the orientation of the triangles
-/// calculated normals are normalized vectors (length 1.0)
+/// calculated normals are normalized vectors (length 1.0), or zero for
+/// degenerate triangles, e.g. with merged corners
 fn compute_triangle_normals(triangles: &[Triangle], vertices: &[Position]) -> Vec<Direction> {
     triangles
         .iter()
         .map(|t| {
             let u = vertices[t[1]] - vertices[t[0]];
             let v = vertices[t[2]] - vertices[t[0]];
-            u.cross(&v).normalize()
+            normalize_or_zero(u.cross(&v))
         })
         .collect()
 }
 
+/// `v` normalized, or zero when it has no direction, rather than NaN
+fn normalize_or_zero(v: Direction) -> Direction {
+    v.try_normalize(0.0).unwrap_or_else(Direction::zeros)
+}
+
 /// Compute the normals of vertices
 /// by averaging the normals of neighbouring triangles
-/// calculated normals are normalized vectors (length 1.0)
+/// calculated normals are normalized vectors (length 1.0), or zero for the
+/// vertices of degenerate triangles alone
 fn compute_vertex_normals(
     triangles: &[Triangle],
     vertices: &[Position],
@@ -146,5 +978,250 @@ fn compute_vertex_normals(
         }
     }
 
-    return vertex_normals.iter().map(|n| n.normalize()).collect();
+    return vertex_normals.into_iter().map(normalize_or_zero).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuboid_sides_face_out_or_in() {
+        let bounds = AxisAlignedBoundingBox::from_bounds([
+            Position::new(-1.0, 0.0, 2.0),
+            Position::new(1.0, 3.0, 4.0),
+        ]);
+        for &inward in &[false, true] {
+            let mesh = Mesh::cuboid(&bounds, inward);
+            assert_eq!(mesh.vertices.len(), 24);
+            assert_eq!(mesh.triangles.len(), 12);
+            assert_eq!(mesh.faces().count(), 6);
+            let sign = if inward { -1.0 } else { 1.0 };
+            for (t, triangle) in mesh.triangles.iter().enumerate() {
+                let center = triangle
+                    .iter()
+                    .fold(Direction::zeros(), |sum, &v| sum + mesh.vertices[v].coords)
+                    / 3.0;
+                let outward = bounds
+                    .face_normal_at(&Position::from(center), 1e-9)
+                    .unwrap();
+                assert_eq!(mesh.triangle_normals[t], outward * sign);
+            }
+        }
+        let mesh = Mesh::cuboid(&bounds, false);
+        assert_eq!(mesh.bounding_box().bounds, bounds.bounds);
+        assert_eq!(mesh.uvs.as_ref().unwrap()[2], [1.0, 1.0]);
+    }
+
+    #[test]
+    fn off_polygons_comments_and_crlf() {
+        let off = "OFF\r\n# a unit quad\r\n4 1 0\r\n0 0 0\r\n1 0 0\r\n\r\n1 1 0\r\n0 1 0 # last\r\n4 0 1 2 3\r\n";
+        let mesh = read_off(off.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.polygons, Some(vec![vec![0, 1, 2, 3]]));
+        assert_eq!(mesh.edges(), vec![[0, 1], [0, 3], [1, 2], [2, 3]]);
+        assert!(mesh.vertex_colors.is_none());
+    }
+
+    #[test]
+    fn off_colors_and_normals() {
+        let off = "CNOFF 3 1 0\n0 0 0 0 0 2 255 0 0 255\n1 0 0 0 0 1 0 255 0 255\n0 1 0 0 0 1 0 0 255 255\n3 0 1 2 1 1 1\n";
+        let mesh = read_off(off.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.vertex_normals[0], Direction::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.vertex_colors.unwrap()[1], [0.0, 1.0, 0.0]);
+
+        let off = "COFF\n3 1 0\n0 0 0 0.5 0.5 0.5 1\n1 0 0 1 1 1 1\n0 1 0 0 0 0 1\n3 0 1 2\n";
+        let mesh = read_off(off.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.vertex_colors.unwrap()[0], [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn off_count_mismatch_is_an_error() {
+        let off = "OFF\n3 2 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        assert!(read_off(off.as_bytes(), &LoadLimits::default()).is_err());
+    }
+
+    #[test]
+    fn declared_counts_over_the_limits_are_refused() {
+        let off = "OFF\n1000000000000 1\n0 0 0\n";
+        match read_off(off.as_bytes(), &LoadLimits::default()) {
+            Err(LoadError::Limit("vertex count", 1_000_000_000_000, _)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let limits = LoadLimits {
+            max_triangles: 2,
+            ..Default::default()
+        };
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\nf 1 2 3\n";
+        assert!(read_obj(obj.as_bytes(), &LoadLimits::default()).is_ok());
+        assert!(matches!(
+            read_obj(obj.as_bytes(), &limits),
+            Err(LoadError::Limit("triangle count", 3, 2))
+        ));
+    }
+
+    #[test]
+    fn obj_polygons_are_triangulated() {
+        let obj = "# a unit quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1 4//1\n";
+        let (mesh, _) = read_obj(obj.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn obj_texture_coordinates_split_the_seams() {
+        // Two triangles sharing an edge whose vertices have different
+        // texture coordinates on each side
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvt 0.5 0.5\n\
+                   f 1/1 2/2 3/3\nf 2/4 4/4 3/3\n";
+        let (mesh, _) = read_obj(obj.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [3, 4, 2]]);
+        assert_eq!(mesh.vertices[3], mesh.vertices[1]);
+        let uvs = mesh.uvs.unwrap();
+        assert_eq!(uvs[1], [1.0, 0.0]);
+        assert_eq!(uvs[3], [0.5, 0.5]);
+    }
+
+    #[test]
+    fn tangents_follow_the_texture_coordinates() {
+        // Unit quad in the xz plane facing up, u along -z and v along x
+        let obj = "v 0 0 0\nv 0 0 -1\nv 1 0 -1\nv 1 0 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                   f 1/1 4/4 3/3 2/2\n";
+        let (mesh, _) = read_obj(obj.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.vertex_normals[0], Direction::new(0.0, 1.0, 0.0));
+        for tangent in mesh.vertex_tangents.as_ref().unwrap() {
+            assert!((tangent.direction - Direction::new(0.0, 0.0, -1.0)).norm() < 1e-12);
+            // normal × tangent = (0, 1, 0) × (0, 0, -1) = (-1, 0, 0), so v
+            // going along x is mirrored
+            assert_eq!(tangent.handedness, -1.0);
+        }
+        let tangent = mesh.tangent_at(1, &[0.2, 0.3]).unwrap();
+        assert!((tangent.direction - Direction::new(0.0, 0.0, -1.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn weld_merges_close_vertices_and_smooths_the_seam() {
+        // Two triangles folded along a split edge, one side slightly off
+        let vertices = vec![
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 0.0, 0.0),
+            Position::new(0.0, 1.0, 0.0),
+            Position::new(1.0, 0.0, 1e-7),
+            Position::new(0.0, 1.0, 0.0),
+            Position::new(1.0, 1.0, 1.0),
+        ];
+        let triangles = vec![[0, 1, 2], [3, 5, 4]];
+        let mut exact = Mesh::from_vertices_and_triangles(vertices.clone(), triangles.clone());
+        assert_eq!(exact.weld(0.0), 1);
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        mesh.vertex_colors = Some((0..6).map(|i| [i as f32; 3]).collect());
+        assert_eq!(mesh.weld(1e-6), 2);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [1, 3, 2]]);
+        assert_eq!(mesh.vertex_colors.as_ref().unwrap()[3], [5.0; 3]);
+        // The shared vertices average the normals of both sides
+        let seam = mesh.vertex_normals[1];
+        assert!(seam.dot(&mesh.triangle_normals[0]) < 1.0 - 1e-6);
+        assert!(seam.dot(&mesh.triangle_normals[1]) < 1.0 - 1e-6);
+    }
+
+    #[test]
+    fn collapsed_triangles_leave_the_normals_finite() {
+        // A sliver whose two close corners merge, next to a regular triangle
+        let vertices = vec![
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 0.0, 0.0),
+            Position::new(0.0, 1.0, 0.0),
+            Position::new(1.0, 1e-7, 0.0),
+        ];
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, vec![[0, 1, 2], [0, 3, 1]]);
+        assert_eq!(mesh.weld(1e-6), 1);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 1, 1]]);
+        assert_eq!(mesh.triangle_normals[1], Direction::zeros());
+        for normal in &mesh.vertex_normals {
+            assert_eq!(*normal, Direction::new(0.0, 0.0, 1.0));
+        }
+        mesh.scale(&Direction::new(2.0, 1.0, 1.0));
+        let finite = |n: &Direction| n.iter().all(|c| c.is_finite());
+        assert!(mesh.vertex_normals.iter().all(finite));
+    }
+
+    #[test]
+    fn transforms_keep_the_normals_on_the_surface() {
+        // Triangle of the plane x + y = 1, facing +x +y
+        let mut mesh = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+                Position::new(1.0, 0.0, 1.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        mesh.uvs = Some(vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]);
+        mesh.compute_tangents();
+        let handedness = mesh.vertex_tangents.as_ref().unwrap()[0].handedness;
+
+        // Stretched along x, the plane is x / 2 + y = 1
+        mesh.scale(&Direction::new(2.0, 1.0, 1.0));
+        let normal = Direction::new(0.5, 1.0, 0.0).normalize();
+        assert!((mesh.triangle_normals[0] - normal).norm() < 1e-12);
+        assert!((mesh.vertex_normals[0] - normal).norm() < 1e-12);
+        let tangent = mesh.vertex_tangents.as_ref().unwrap()[0];
+        assert!((tangent.direction - Direction::new(-2.0, 1.0, 0.0).normalize()).norm() < 1e-12);
+
+        // Mirrored along x, still facing away from the origin
+        mesh.scale(&Direction::new(-1.0, 1.0, 1.0));
+        assert_eq!(mesh.triangles, vec![[0, 2, 1]]);
+        let normal = Direction::new(-0.5, 1.0, 0.0).normalize();
+        assert!((mesh.triangle_normals[0] - normal).norm() < 1e-12);
+        assert!((mesh.vertex_normals[0] - normal).norm() < 1e-12);
+        let tangent = mesh.vertex_tangents.as_ref().unwrap()[0];
+        assert_eq!(tangent.handedness, -handedness);
+        assert!(tangent.direction.dot(&normal).abs() < 1e-12);
+
+        mesh.rotate(&na::Rotation3::from_axis_angle(
+            &Direction::z_axis(),
+            std::f64::consts::FRAC_PI_2,
+        ));
+        mesh.translate(Direction::new(0.0, 0.0, 3.0));
+        assert!((mesh.vertices[2] - Position::new(0.0, -2.0, 4.0)).norm() < 1e-12);
+        let normal = Direction::new(-1.0, -0.5, 0.0).normalize();
+        assert!((mesh.vertex_normals[0] - normal).norm() < 1e-12);
+    }
+
+    #[test]
+    fn normalized_meshes_fit_the_unit_cube() {
+        let mesh = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(10.0, 20.0, 30.0),
+                Position::new(50.0, 20.0, 30.0),
+                Position::new(10.0, 40.0, 35.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let normal = mesh.triangle_normals[0];
+        let mesh = mesh.normalized();
+        let bounds = mesh.bounding_box().bounds;
+        assert!((bounds[0] - Position::new(-0.5, -0.25, -0.0625)).norm() < 1e-12);
+        assert!((bounds[1] - Position::new(0.5, 0.25, 0.0625)).norm() < 1e-12);
+        assert!((mesh.triangle_normals[0] - normal).norm() < 1e-12);
+
+        let point =
+            Mesh::from_vertices_and_triangles(vec![Position::new(1.0, 2.0, 3.0); 3], vec![]);
+        assert_eq!(point.normalized_to(2.0).vertices[0], Position::origin());
+    }
+
+    #[test]
+    fn obj_negative_indices_are_relative() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 0 0 1\nf -4 -3 -1\n";
+        let (mesh, _) = read_obj(obj.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 1, 3]]);
+    }
+
+    #[test]
+    fn obj_out_of_range_index_is_an_error() {
+        let obj = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        assert!(read_obj(obj.as_bytes(), &LoadLimits::default()).is_err());
+    }
 }
@@ -1,5 +1,18 @@
+pub mod attributes;
 pub mod bounding_box;
+pub mod bvh;
+pub mod displacement;
+pub mod export;
+pub mod half_edge;
+pub mod import;
+pub mod instance;
 pub mod kdtree;
+pub mod layout;
 pub mod mesh;
+pub mod mtl;
+pub mod ply;
+pub mod qbvh;
 pub mod ray;
+pub mod stl;
+pub mod terrain;
 pub mod types;
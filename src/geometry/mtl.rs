@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use crate::geometry::mesh::{LoadError, LoadLimits, Mesh, ObjMaterials};
+use crate::render::color::Color;
+use crate::render::config::{MaterialConfig, ShadingModel};
+
+/// Read the materials of a Wavefront MTL file, in their order
+///
+/// `Kd` gives the color, `Ks` and `Ns` the highlights of Blinn-Phong
+/// materials, those with a `Ks` other than black being glossy, and `map_Kd`
+/// the texture, the last token of the line. The other statements are
+/// ignored; textures are not loaded.
+pub fn read_mtl<R: BufRead>(reader: R) -> Result<Vec<(String, MaterialConfig)>, LoadError> {
+    fn parse_color<'a, I: Iterator<Item = &'a str>>(tokens: I) -> Result<Color, LoadError> {
+        let values = tokens
+            .map(|t| t.parse::<f64>().map_err(LoadError::ParseFloat))
+            .collect::<Result<Vec<f64>, LoadError>>()?;
+        match values[..] {
+            // A single value is a gray
+            [gray] => Ok(Color::gray(gray)),
+            [r, g, b, ..] => Ok(Color::new(r, g, b)),
+            _ => Err(LoadError::String("MTL color with less than 3 channels")),
+        }
+    }
+
+    let mut materials: Vec<(String, MaterialConfig)> = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(LoadError::Io)?;
+        let content = match line.find('#') {
+            Some(i) => &line[..i],
+            None => &line,
+        };
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            materials.push((name, MaterialConfig::default()));
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some((_, material)) => material,
+            None => continue,
+        };
+        match keyword {
+            "Kd" => material.color = parse_color(tokens)?,
+            "Ks" => {
+                material.specular = parse_color(tokens)?;
+                material.model = match material.specular == Color::BLACK {
+                    true => ShadingModel::Lambert,
+                    false => ShadingModel::BlinnPhong,
+                };
+            }
+            "Ns" => {
+                material.shininess = tokens
+                    .next()
+                    .ok_or(LoadError::String("MTL Ns without exponent"))?
+                    .parse::<f32>()
+                    .map_err(LoadError::ParseFloat)?;
+            }
+            // Options such as -s or -o come before the file name; files
+            // written on Windows may use backslashes
+            "map_Kd" => {
+                material.texture = tokens.last().map(|t| PathBuf::from(t.replace('\\', "/")));
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// Read an MTL file and load the textures of its materials, relative to it
+///
+/// Textures which cannot be loaded are left out with a warning, so that the
+/// model still renders.
+pub fn load_mtl_file(path: &Path) -> Result<Vec<(String, MaterialConfig)>, LoadError> {
+    let limits = LoadLimits::default();
+    let mut materials = read_mtl(io::BufReader::new(limits.open(path)?))?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    for (name, material) in &mut materials {
+        if let Err(e) = material.load_textures(directory) {
+            tracing::warn!("texture of material {} left out: {}", name, e);
+            material.texture = None;
+        }
+    }
+    Ok(materials)
+}
+
+/// Give `mesh` the materials its OBJ file uses, from the MTL files in
+/// `directory`
+///
+/// Missing files and materials are reported as warnings: the triangles
+/// they shade, as those before the first `usemtl`, get the default material.
+pub(crate) fn apply_materials(mesh: &mut Mesh, materials: &ObjMaterials, directory: &Path) {
+    if materials.names.is_empty() {
+        return;
+    }
+    let mut library: HashMap<String, MaterialConfig> = HashMap::new();
+    for file in &materials.libraries {
+        let path = directory.join(file.replace('\\', "/"));
+        match load_mtl_file(&path) {
+            Ok(loaded) => {
+                for (name, material) in loaded {
+                    library.entry(name).or_insert(material);
+                }
+            }
+            Err(e) => tracing::warn!("materials of {} left out: {}", path.display(), e),
+        }
+    }
+    let mut configs: Vec<MaterialConfig> = materials
+        .names
+        .iter()
+        .map(|name| {
+            library.remove(name).unwrap_or_else(|| {
+                tracing::warn!("material {} not found in the MTL files", name);
+                MaterialConfig::default()
+            })
+        })
+        .collect();
+    let default = configs.len();
+    if materials.triangle_names.iter().any(Option::is_none) {
+        configs.push(MaterialConfig::default());
+    }
+    mesh.materials = configs.iter().map(MaterialConfig::build).collect();
+    mesh.triangle_materials = Some(
+        materials
+            .triangle_names
+            .iter()
+            .map(|name| name.unwrap_or(default))
+            .collect(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mtl_statements_map_on_the_materials() {
+        let mtl = "# two materials\nnewmtl matte\nKd 0.8 0.2 0.1\nKs 0 0 0\nNs 10\n\n\
+                   newmtl shiny wood\nKd 1 1 1\nKs 0.5 0.5 0.5\nNs 200\nmap_Kd -s 2 2 1 textures\\wood.png\n";
+        let materials = read_mtl(mtl.as_bytes()).unwrap();
+        assert_eq!(materials.len(), 2);
+        let (name, matte) = &materials[0];
+        assert_eq!(name, "matte");
+        assert_eq!(matte.model, ShadingModel::Lambert);
+        assert_eq!(matte.color, Color::new(0.8, 0.2, 0.1));
+        assert_eq!(matte.texture, None);
+        let (name, shiny) = &materials[1];
+        assert_eq!(name, "shiny wood");
+        assert_eq!(shiny.model, ShadingModel::BlinnPhong);
+        assert_eq!(shiny.specular, Color::gray(0.5));
+        assert_eq!(shiny.shininess, 200.0);
+        assert_eq!(shiny.texture, Some(PathBuf::from("textures/wood.png")));
+    }
+
+    #[test]
+    fn obj_files_load_their_materials_and_textures() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("textures")).unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0]))
+            .save(dir.path().join("textures/red.png"))
+            .unwrap();
+        std::fs::write(
+            dir.path().join("quad.mtl"),
+            "newmtl red\nKd 1 1 1\nmap_Kd textures/red.png\nnewmtl missing\nmap_Kd nowhere.png\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("quad.obj"),
+            "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\n\
+             f 1 2 3\nusemtl red\nf 1/1 2/2 3/3 4/3\nusemtl unknown\nf 1 3 4\nusemtl red\nf 1 2 4\n",
+        )
+        .unwrap();
+
+        let materials = load_mtl_file(&dir.path().join("quad.mtl")).unwrap();
+        assert!(materials[0].1.texture_image.is_some());
+        assert_eq!(
+            materials[0].1.texture,
+            Some(dir.path().join("textures/red.png"))
+        );
+        assert_eq!(materials[1].1.texture, None);
+
+        let mesh = Mesh::load_file(&dir.path().join("quad.obj")).unwrap();
+        // red, unknown, then the default of the first triangle
+        assert_eq!(mesh.materials.len(), 3);
+        assert_eq!(mesh.triangle_materials, Some(vec![2, 0, 0, 1, 0]));
+    }
+}
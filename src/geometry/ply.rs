@@ -0,0 +1,540 @@
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+
+use crate::geometry::attributes::{AttributeValues, Domain};
+use crate::geometry::mesh::{LoadError, LoadLimits, Mesh};
+use crate::geometry::types::{Direction, Position};
+use crate::render::material::MAX_UV_SETS;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, LoadError> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::Int8),
+            "uchar" | "uint8" => Ok(ScalarType::UInt8),
+            "short" | "int16" => Ok(ScalarType::Int16),
+            "ushort" | "uint16" => Ok(ScalarType::UInt16),
+            "int" | "int32" => Ok(ScalarType::Int32),
+            "uint" | "uint32" => Ok(ScalarType::UInt32),
+            "float" | "float32" => Ok(ScalarType::Float32),
+            "double" | "float64" => Ok(ScalarType::Float64),
+            _ => Err(LoadError::String("PLY property with an unknown type")),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    /// Type of the item count, type of the items
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+/// Source of property values, whatever the encoding of the body
+struct ValueReader<R: BufRead> {
+    reader: R,
+    encoding: Encoding,
+    /// Remaining tokens of the current line in ASCII files
+    tokens: Vec<String>,
+}
+
+impl<R: BufRead> ValueReader<R> {
+    fn read(&mut self, kind: ScalarType) -> Result<f64, LoadError> {
+        match self.encoding {
+            Encoding::Ascii => {
+                while self.tokens.is_empty() {
+                    let mut line = String::new();
+                    if self.reader.read_line(&mut line).map_err(LoadError::Io)? == 0 {
+                        return Err(LoadError::String("PLY file ends before its declared data"));
+                    }
+                    self.tokens = line.split_whitespace().rev().map(String::from).collect();
+                }
+                let token = self.tokens.pop().unwrap();
+                token.parse::<f64>().map_err(LoadError::ParseFloat)
+            }
+            Encoding::BinaryLittleEndian | Encoding::BinaryBigEndian => {
+                let mut buffer = [0u8; 8];
+                let bytes = &mut buffer[..kind.size()];
+                self.reader.read_exact(bytes).map_err(LoadError::Io)?;
+                if self.encoding == Encoding::BinaryBigEndian {
+                    bytes.reverse();
+                }
+                Ok(match kind {
+                    ScalarType::Int8 => bytes[0] as i8 as f64,
+                    ScalarType::UInt8 => bytes[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::Int32 => {
+                        i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::UInt32 => {
+                        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::Float32 => {
+                        f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+                    }
+                    ScalarType::Float64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+
+    /// Read all the values of a property, a list yields its items
+    fn read_property(
+        &mut self,
+        kind: &PropertyType,
+        values: &mut Vec<f64>,
+    ) -> Result<(), LoadError> {
+        values.clear();
+        match kind {
+            PropertyType::Scalar(t) => values.push(self.read(*t)?),
+            PropertyType::List(count_type, item_type) => {
+                let count = self.read(*count_type)?;
+                if count < 0.0 {
+                    return Err(LoadError::String("PLY list with a negative size"));
+                }
+                for _ in 0..count as usize {
+                    values.push(self.read(*item_type)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Mesh {
+    /// Load a PLY file, in ASCII or binary (little or big endian) encoding
+    ///
+    /// Vertex positions and faces are required, vertex normals
+    /// (`nx`, `ny`, `nz`), colors (`red`, `green`, `blue`) and texture
+    /// coordinates (`u`, `v` or `s`, `t`, then `u1`, `v1`... for the next
+    /// UV sets) are used when present, up to `MAX_UV_SETS` sets. Other scalar properties of the vertices and faces are kept
+    /// as scalar attributes named after them. Faces with more than 3 vertices
+    /// are kept in `polygons` and triangulated as fans, and any other element
+    /// is skipped.
+    pub fn load_ply_file(path: &Path) -> Result<Mesh, LoadError> {
+        let limits = LoadLimits::default();
+        read_ply(io::BufReader::new(limits.open(path)?), &limits)
+    }
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(Encoding, Vec<Element>), LoadError> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(LoadError::Io)?;
+    if line.trim_end() != "ply" {
+        return Err(LoadError::String("Magic number ply not present"));
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(LoadError::Io)? == 0 {
+            return Err(LoadError::String("PLY header is not terminated"));
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["end_header"] => break,
+            ["format", format, _version] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(LoadError::String("Unknown PLY format")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse::<usize>().map_err(LoadError::ParseInt)?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => elements
+                .last_mut()
+                .ok_or(LoadError::String(
+                    "PLY property declared outside an element",
+                ))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    kind: PropertyType::List(
+                        ScalarType::parse(count_type)?,
+                        ScalarType::parse(item_type)?,
+                    ),
+                }),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or(LoadError::String(
+                    "PLY property declared outside an element",
+                ))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    kind: PropertyType::Scalar(ScalarType::parse(kind)?),
+                }),
+            // comments, obj_info and blank lines
+            _ => {}
+        }
+    }
+
+    let encoding = encoding.ok_or(LoadError::String("PLY format is not declared"))?;
+    Ok((encoding, elements))
+}
+
+pub(crate) fn read_ply<R: BufRead>(mut reader: R, limits: &LoadLimits) -> Result<Mesh, LoadError> {
+    let (encoding, elements) = read_header(&mut reader)?;
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => limits.check_vertices(element.count)?,
+            "face" => limits.check_triangles(element.count)?,
+            _ => {}
+        }
+    }
+    let mut values = ValueReader {
+        reader,
+        encoding,
+        tokens: Vec::new(),
+    };
+
+    let mut vertices: Vec<Position> = Vec::new();
+    let mut normals: Vec<Direction> = Vec::new();
+    let mut colors: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<Vec<[f64; 2]>> = Vec::new();
+    let mut polygons: Vec<Vec<usize>> = Vec::new();
+    let mut triangle_count = 0;
+    let mut property_values: Vec<Vec<f64>> = Vec::new();
+    // Other scalar properties of the vertices and faces: name, index and
+    // values
+    let mut vertex_attributes: Vec<(String, usize, Vec<f32>)> = Vec::new();
+    let mut face_attributes: Vec<(String, usize, Vec<f32>)> = Vec::new();
+
+    for element in elements.iter() {
+        property_values.resize(element.properties.len(), Vec::new());
+        match element.name.as_str() {
+            "vertex" => {
+                let position = [
+                    element.property(&["x"]),
+                    element.property(&["y"]),
+                    element.property(&["z"]),
+                ];
+                if position.iter().any(|p| p.is_none()) {
+                    return Err(LoadError::String("PLY vertices without x, y, z"));
+                }
+                let normal = [
+                    element.property(&["nx"]),
+                    element.property(&["ny"]),
+                    element.property(&["nz"]),
+                ];
+                let color = [
+                    element.property(&["red", "r", "diffuse_red"]),
+                    element.property(&["green", "g", "diffuse_green"]),
+                    element.property(&["blue", "b", "diffuse_blue"]),
+                ];
+                // Integer colors are stored in [0, 255]
+                let color_scale = match color[0].map(|i| &element.properties[i].kind) {
+                    Some(PropertyType::Scalar(ScalarType::Float32))
+                    | Some(PropertyType::Scalar(ScalarType::Float64)) => 1.0,
+                    _ => 1.0 / 255.0,
+                };
+                let uv_sets = uv_sets(element);
+                uvs = vec![Vec::new(); uv_sets.len()];
+                let known = position.iter().chain(&normal).chain(&color).flatten();
+                let known = known.chain(uv_sets.iter().flatten());
+                vertex_attributes = other_scalars(element, &known.cloned().collect::<Vec<_>>());
+
+                for _ in 0..element.count {
+                    for (property, v) in element.properties.iter().zip(&mut property_values) {
+                        values.read_property(&property.kind, v)?;
+                    }
+                    let value = |i: Option<usize>| property_values[i.unwrap()][0];
+                    vertices.push(Position::new(
+                        value(position[0]),
+                        value(position[1]),
+                        value(position[2]),
+                    ));
+                    if normal.iter().all(|n| n.is_some()) {
+                        normals.push(Direction::new(
+                            value(normal[0]),
+                            value(normal[1]),
+                            value(normal[2]),
+                        ));
+                    }
+                    if color.iter().all(|c| c.is_some()) {
+                        colors.push([
+                            (value(color[0]) * color_scale) as f32,
+                            (value(color[1]) * color_scale) as f32,
+                            (value(color[2]) * color_scale) as f32,
+                        ]);
+                    }
+                    for (set, [u, v]) in uvs.iter_mut().zip(&uv_sets) {
+                        set.push([property_values[*u][0], property_values[*v][0]]);
+                    }
+                    for (_, i, attribute) in &mut vertex_attributes {
+                        attribute.push(property_values[*i][0] as f32);
+                    }
+                }
+            }
+            "face" => {
+                let indices = element
+                    .property(&["vertex_indices", "vertex_index"])
+                    .ok_or(LoadError::String("PLY faces without vertex indices"))?;
+                face_attributes = other_scalars(element, &[indices]);
+                for _ in 0..element.count {
+                    for (property, v) in element.properties.iter().zip(&mut property_values) {
+                        values.read_property(&property.kind, v)?;
+                    }
+                    let face = &property_values[indices];
+                    if face.len() < 3 {
+                        return Err(LoadError::String("PLY face with less than 3 vertices"));
+                    }
+                    triangle_count += face.len() - 2;
+                    limits.check_triangles(triangle_count)?;
+                    if face.iter().any(|&i| i < 0.0 || i.fract() != 0.0) {
+                        return Err(LoadError::String("PLY face with an invalid vertex index"));
+                    }
+                    polygons.push(face.iter().map(|&i| i as usize).collect());
+                    // Every triangle of the fan gets the values of the face
+                    for (_, i, attribute) in &mut face_attributes {
+                        let value = property_values[*i][0] as f32;
+                        attribute.resize(triangle_count, value);
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for (property, v) in element.properties.iter().zip(&mut property_values) {
+                        values.read_property(&property.kind, v)?;
+                    }
+                }
+            }
+        }
+    }
+
+    if polygons.iter().flatten().any(|&i| i >= vertices.len()) {
+        return Err(LoadError::String("PLY face references a missing vertex"));
+    }
+
+    let mut mesh = Mesh::from_vertices_and_polygons(vertices, polygons);
+    if normals.len() == mesh.vertices.len() {
+        mesh.vertex_normals = normals.iter().map(|n| n.normalize()).collect();
+    }
+    if colors.len() == mesh.vertices.len() {
+        mesh.vertex_colors = Some(colors);
+    }
+    if uvs.len() > MAX_UV_SETS {
+        tracing::warn!(
+            "{} UV sets of the PLY file left out, textures use the first {}",
+            uvs.len() - MAX_UV_SETS,
+            MAX_UV_SETS
+        );
+        uvs.truncate(MAX_UV_SETS);
+    }
+    for set in uvs
+        .into_iter()
+        .filter(|set| set.len() == mesh.vertices.len())
+    {
+        mesh.add_uv_set(set);
+    }
+    for (name, _, values) in vertex_attributes {
+        mesh.set_attribute(&name, Domain::Vertex, AttributeValues::Scalar(values));
+    }
+    for (name, _, values) in face_attributes {
+        mesh.set_attribute(&name, Domain::Triangle, AttributeValues::Scalar(values));
+    }
+    Ok(mesh)
+}
+
+/// Properties of the UV sets of the vertices of `element`, in their order
+///
+/// The first set is `u`, `v`, `s`, `t`, `texture_u`, `texture_v` or
+/// `texture_s`, `texture_t`, the next ones the same names followed by their
+/// number, e.g. `s1`, `t1`.
+fn uv_sets(element: &Element) -> Vec<[usize; 2]> {
+    const NAMES: [(&str, &str); 4] = [
+        ("u", "v"),
+        ("s", "t"),
+        ("texture_u", "texture_v"),
+        ("texture_s", "texture_t"),
+    ];
+    let mut sets = Vec::new();
+    for set in 0.. {
+        let suffix = match set {
+            0 => String::new(),
+            _ => set.to_string(),
+        };
+        let found = NAMES.iter().find_map(|(u, v)| {
+            Some([
+                element.property(&[format!("{}{}", u, suffix).as_str()])?,
+                element.property(&[format!("{}{}", v, suffix).as_str()])?,
+            ])
+        });
+        match found {
+            Some(properties) => sets.push(properties),
+            // Files may number their sets from 1
+            None if set == 0 => {}
+            None => break,
+        }
+    }
+    sets
+}
+
+/// Scalar properties of `element` other than the `known` ones, with their
+/// index and no values yet
+fn other_scalars(element: &Element, known: &[usize]) -> Vec<(String, usize, Vec<f32>)> {
+    element
+        .properties
+        .iter()
+        .enumerate()
+        .filter(|(i, p)| !known.contains(i) && matches!(p.kind, PropertyType::Scalar(_)))
+        .map(|(i, p)| (p.name.clone(), i, Vec::new()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::attributes::AttributeValue;
+
+    #[test]
+    fn ascii_ply_with_colors() {
+        let ply = "ply\nformat ascii 1.0\ncomment a quad\nelement vertex 4\n\
+                   property float x\nproperty float y\nproperty float z\n\
+                   property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                   element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                   0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 0 0 0\n4 0 1 2 3\n";
+        let mesh = read_ply(ply.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.vertex_colors.unwrap()[1], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn other_properties_are_kept_as_attributes() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 4\n\
+                   property float x\nproperty float y\nproperty float z\nproperty float quality\n\
+                   element face 2\nproperty list uchar int vertex_indices\nproperty uchar label\n\
+                   end_header\n0 0 0 0.5\n1 0 0 1\n1 1 0 0\n0 1 0 0.25\n4 0 1 2 3 7\n3 0 2 3 9\n";
+        let mesh = read_ply(ply.as_bytes(), &LoadLimits::default()).unwrap();
+        let quality = mesh.attributes.scalar("quality", Domain::Vertex).unwrap();
+        assert_eq!(quality, &[0.5, 1.0, 0.0, 0.25]);
+        let label = mesh.attributes.scalar("label", Domain::Triangle).unwrap();
+        assert_eq!(label, &[7.0, 7.0, 9.0]);
+        assert!(mesh.attributes.get("x").is_none());
+        assert_eq!(
+            mesh.attribute_at("quality", 0, &[0.5, 0.0]),
+            Some(AttributeValue::Scalar(0.75))
+        );
+    }
+
+    #[test]
+    fn uv_sets_are_read_up_to_the_supported_count() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 3\n\
+                   property float x\nproperty float y\nproperty float z\n\
+                   property float s\nproperty float t\nproperty float s1\nproperty float t1\n\
+                   property float u2\nproperty float v2\nproperty float u3\nproperty float v3\n\
+                   property float u4\nproperty float v4\nproperty float u6\nproperty float v6\n\
+                   element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                   0 0 0 0 0 1 1 2 2 3 3 4 4 6 6\n1 0 0 1 0 1 1 2 2 3 3 4 4 6 6\n\
+                   0 1 0 0 1 1 1 2 2 3 3 4 4 6 6\n3 0 1 2\n";
+        let mesh = read_ply(ply.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.uv_set_count(), MAX_UV_SETS);
+        assert_eq!(mesh.uv_set(0).unwrap()[1], [1.0, 0.0]);
+        assert_eq!(mesh.uv_set(1).unwrap()[0], [1.0, 1.0]);
+        assert_eq!(mesh.uv_set(3).unwrap()[2], [3.0, 3.0]);
+        // The set after the last supported one is dropped, not kept as
+        // attributes, and the sets stop at the first missing number
+        assert!(mesh.attributes.get("u4").is_none());
+        assert!(mesh.attributes.get("s").is_none());
+        assert!(mesh.attributes.get("u6").is_some());
+    }
+
+    #[test]
+    fn negative_or_fractional_indices_are_errors() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\n\
+                      property float x\nproperty float y\nproperty float z\n\
+                      element face 1\nproperty list uchar float vertex_indices\nend_header\n\
+                      0 0 0\n1 0 0\n0 1 0\n";
+        for face in &["3 0 1 -2\n", "3 0 1 1.5\n"] {
+            let ply = format!("{}{}", header, face);
+            assert!(matches!(
+                read_ply(ply.as_bytes(), &LoadLimits::default()),
+                Err(LoadError::String("PLY face with an invalid vertex index"))
+            ));
+        }
+        let ply = format!("{}3 0 1 2\n", header);
+        assert!(read_ply(ply.as_bytes(), &LoadLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn binary_big_endian_ply_with_normals() {
+        let mut ply = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+                        property double x\nproperty double y\nproperty double z\n\
+                        property float nx\nproperty float ny\nproperty float nz\n\
+                        element face 1\nproperty list uchar uint vertex_indices\nend_header\n"
+            .to_vec();
+        for p in &[[0.0f64, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for c in p {
+                ply.extend_from_slice(&c.to_be_bytes());
+            }
+            for c in &[0.0f32, 0.0, 2.0] {
+                ply.extend_from_slice(&c.to_be_bytes());
+            }
+        }
+        ply.push(3);
+        for i in 0..3u32 {
+            ply.extend_from_slice(&i.to_be_bytes());
+        }
+        let mesh = read_ply(&ply[..], &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.vertices[1], Position::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        assert_eq!(mesh.vertex_normals[0], Direction::new(0.0, 0.0, 1.0));
+        assert!(mesh.vertex_colors.is_none());
+    }
+}
@@ -0,0 +1,365 @@
+use std::mem;
+
+use crate::geometry::kdtree::KdTree;
+use crate::geometry::ray::Ray;
+use crate::geometry::types::Position;
+
+/// Count of the children that are inner nodes rather than leaves
+const INNER: u32 = u32::MAX;
+
+/// Integer type of the quantized bounds, `u8` or `u16`
+pub trait Quantum: Copy + Default + Send + Sync + 'static {
+    /// Steps across the box of a node
+    const STEPS: u32;
+
+    fn from_steps(steps: u32) -> Self;
+    fn steps(self) -> u32;
+}
+
+impl Quantum for u8 {
+    const STEPS: u32 = u8::MAX as u32;
+
+    fn from_steps(steps: u32) -> Self {
+        steps as u8
+    }
+
+    fn steps(self) -> u32 {
+        self as u32
+    }
+}
+
+impl Quantum for u16 {
+    const STEPS: u32 = u16::MAX as u32;
+
+    fn from_steps(steps: u32) -> Self {
+        steps as u16
+    }
+
+    fn steps(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Inner node of a `QuantizedBvh`: 32 bytes with 8-bit bounds, half a cache
+/// line, 64 bytes with 16-bit ones
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(32))]
+pub struct QuantizedNode<Q> {
+    /// Box of each child, minimum then maximum corner, in steps of the box
+    /// of this node rounded outward
+    bounds: [[[Q; 3]; 2]; 2],
+    /// Node of each inner child, first triangle in `triangles` of each leaf
+    children: [u32; 2],
+    /// Triangles of each leaf, `INNER` for inner nodes
+    counts: [u32; 2],
+}
+
+/// Compact copy of a tree of boxes for faster traversals: the nodes are
+/// stored depth first in a single array, the left child right after its
+/// parent, and the boxes of the children are quantized in the box of their
+/// parent on 8 or 16 bits, so that a node fits in a cache line
+///
+/// Only the root box is stored in full, the others are decoded on the way
+/// down. Quantized boxes are a little larger than the boxes of the tree,
+/// which costs a few more intersection tests but never misses a triangle.
+#[derive(Clone, Debug)]
+pub struct QuantizedBvh<Q> {
+    bounds: [Position; 2],
+    nodes: Vec<QuantizedNode<Q>>,
+    /// Triangles of the leaves, one after the other
+    triangles: Vec<u32>,
+}
+
+impl<Q: Quantum> QuantizedBvh<Q> {
+    /// Quantize `tree`, built by any `TreeBuild`
+    pub fn from_tree(tree: &KdTree) -> QuantizedBvh<Q> {
+        let _span = tracing::info_span!("quantize_tree").entered();
+        let mut bvh = QuantizedBvh {
+            bounds: tree.bounding_box.bounds,
+            nodes: Vec::new(),
+            triangles: Vec::new(),
+        };
+        match tree.children() {
+            Some((left, right)) => {
+                bvh.add_node(left, right, tree.bounding_box.bounds);
+            }
+            // A single leaf becomes the only child of the root
+            None => {
+                let mut node = QuantizedNode {
+                    bounds: [[[Q::default(); 3], [Q::from_steps(Q::STEPS); 3]]; 2],
+                    children: [0; 2],
+                    counts: [0; 2],
+                };
+                (node.children[0], node.counts[0]) = bvh.add_leaf(tree);
+                bvh.nodes.push(node);
+            }
+        }
+        bvh
+    }
+
+    /// Node over `left` and `right`, whose parent has the decoded `bounds`
+    fn add_node(&mut self, left: &KdTree, right: &KdTree, bounds: [Position; 2]) -> u32 {
+        let index = self.nodes.len();
+        self.nodes.push(QuantizedNode {
+            bounds: [[[Q::default(); 3]; 2]; 2],
+            children: [0; 2],
+            counts: [0; 2],
+        });
+        for (c, child) in [left, right].iter().enumerate() {
+            let quantized = quantize(&child.bounding_box.bounds, &bounds);
+            let (first, count) = match child.children() {
+                Some((left, right)) => {
+                    let decoded = dequantize(&quantized, &bounds);
+                    (self.add_node(left, right, decoded), INNER)
+                }
+                None => self.add_leaf(child),
+            };
+            let node = &mut self.nodes[index];
+            node.bounds[c] = quantized;
+            node.children[c] = first;
+            node.counts[c] = count;
+        }
+        index as u32
+    }
+
+    fn add_leaf(&mut self, leaf: &KdTree) -> (u32, u32) {
+        let first = self.triangles.len() as u32;
+        let triangles = leaf.triangle_index.as_deref().unwrap_or(&[]);
+        self.triangles.extend(triangles.iter().map(|&t| t as u32));
+        (first, triangles.len() as u32)
+    }
+
+    /// Closest of the hits found by `hit` in the leaves along `ray`, at a
+    /// distance in units of the ray direction as the distances of the boxes
+    ///
+    /// The leaves are visited front to back and skipped once they are
+    /// entered beyond the closest hit so far.
+    pub fn closest_hit<T, F>(&self, ray: &Ray, mut hit: F) -> Option<(f64, T)>
+    where
+        F: FnMut(&[u32]) -> Option<(f64, T)>,
+    {
+        let mut closest = None;
+        self.traverse(ray, f64::INFINITY, |triangles, limit| {
+            match hit(triangles) {
+                Some((t, value)) if t < limit => {
+                    closest = Some((t, value));
+                    t
+                }
+                _ => limit,
+            }
+        });
+        closest
+    }
+
+    /// Whether `hit` finds something in one of the leaves entered by `ray`
+    /// before `max_distance`, in the units of the scene, e.g. a shadow ray
+    pub fn any_hit<F>(&self, ray: &Ray, max_distance: f64, mut hit: F) -> bool
+    where
+        F: FnMut(&[u32]) -> bool,
+    {
+        let mut found = false;
+        let limit = max_distance / ray.direction.norm();
+        self.traverse(ray, limit, |triangles, limit| {
+            if hit(triangles) {
+                found = true;
+                f64::NEG_INFINITY
+            } else {
+                limit
+            }
+        });
+        found
+    }
+
+    /// Hand the triangles of the leaves entered by `ray` before `limit` to
+    /// `leaf`, nearest first, which returns the new limit
+    fn traverse<F>(&self, ray: &Ray, mut limit: f64, mut leaf: F)
+    where
+        F: FnMut(&[u32], f64) -> f64,
+    {
+        let entry = match ray.intersect_box_range(&self.bounds) {
+            Some((near, _)) if near <= limit => near.max(0.0),
+            _ => return,
+        };
+        // Entry distance, node or first triangle, count and decoded box
+        let mut stack: Vec<(f64, u32, u32, [Position; 2])> = Vec::with_capacity(64);
+        stack.push((entry, 0, INNER, self.bounds));
+        while let Some((entry, first, count, bounds)) = stack.pop() {
+            if entry > limit {
+                continue;
+            }
+            if count != INNER {
+                let first = first as usize;
+                limit = leaf(&self.triangles[first..first + count as usize], limit);
+                continue;
+            }
+            let node = &self.nodes[first as usize];
+            let mut entered = [None, None];
+            for c in 0..2 {
+                if node.counts[c] == 0 {
+                    continue;
+                }
+                let child_bounds = dequantize(&node.bounds[c], &bounds);
+                if let Some((near, _)) = ray.intersect_box_range(&child_bounds) {
+                    let near = near.max(0.0);
+                    if near <= limit {
+                        entered[c] = Some((near, node.children[c], node.counts[c], child_bounds));
+                    }
+                }
+            }
+            // The nearest child is popped first
+            if let [Some(left), Some(right)] = &entered {
+                if left.0 < right.0 {
+                    entered.swap(0, 1);
+                }
+            }
+            stack.extend(entered.iter().flatten());
+        }
+    }
+
+    /// Size of a node in bytes
+    pub fn node_size() -> usize {
+        mem::size_of::<QuantizedNode<Q>>()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Memory used by the nodes and the triangle indices
+    pub fn bytes(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.nodes.len() * Self::node_size()
+            + self.triangles.len() * mem::size_of::<u32>()
+    }
+}
+
+/// Coordinate of `steps` along an axis starting at `low` and `size` long
+fn decode(low: f64, size: f64, steps: u32, total: u32) -> f64 {
+    low + size * steps as f64 / total as f64
+}
+
+/// Steps of `parent` covering `child` on every axis, rounded outward
+fn quantize<Q: Quantum>(child: &[Position; 2], parent: &[Position; 2]) -> [[Q; 3]; 2] {
+    let mut quantized = [[Q::default(); 3]; 2];
+    for axis in 0..3 {
+        let (low, size) = (parent[0][axis], parent[1][axis] - parent[0][axis]);
+        let (mut min, mut max) = (0, Q::STEPS);
+        if size > 0.0 {
+            let scale = Q::STEPS as f64 / size;
+            let steps = |x: f64, round: fn(f64) -> f64| {
+                round((x - low) * scale).clamp(0.0, Q::STEPS as f64) as u32
+            };
+            min = steps(child[0][axis], f64::floor);
+            max = steps(child[1][axis], f64::ceil);
+            // The division can round the decoded bounds inward by an ulp
+            while min > 0 && decode(low, size, min, Q::STEPS) > child[0][axis] {
+                min -= 1;
+            }
+            while max < Q::STEPS && decode(low, size, max, Q::STEPS) < child[1][axis] {
+                max += 1;
+            }
+        }
+        quantized[0][axis] = Q::from_steps(min);
+        quantized[1][axis] = Q::from_steps(max);
+    }
+    quantized
+}
+
+/// Box of the steps `quantized` of `parent`
+fn dequantize<Q: Quantum>(quantized: &[[Q; 3]; 2], parent: &[Position; 2]) -> [Position; 2] {
+    let corner = |i: usize| {
+        let coordinate = |axis: usize| {
+            let size = parent[1][axis] - parent[0][axis];
+            decode(parent[0][axis], size, quantized[i][axis].steps(), Q::STEPS)
+        };
+        Position::new(coordinate(0), coordinate(1), coordinate(2))
+    };
+    [corner(0), corner(1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::bvh::TreeBuild;
+    use crate::geometry::mesh::Mesh;
+    use crate::geometry::terrain::HeightMap;
+    use crate::geometry::types::Direction;
+    use crate::render::config::CameraConfig;
+    use crate::render::image::primary_ray;
+
+    /// Closest hit of `ray` among `triangles`, in units of its direction
+    fn closest(mesh: &Mesh, ray: &Ray, triangles: &[u32]) -> Option<(f64, u32)> {
+        triangles
+            .iter()
+            .filter_map(|&t| {
+                let [a, b, c] = mesh.triangles[t as usize].map(|v| mesh.vertices[v]);
+                let (point, _) = ray.intersect_triangle(&a, &b, &c)?;
+                Some(((point - ray.position).norm() / ray.direction.norm(), t))
+            })
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+    }
+
+    fn check_hits<Q: Quantum>(mesh: &Mesh, tree: &KdTree, camera_config: &CameraConfig) {
+        let bvh = QuantizedBvh::<Q>::from_tree(tree);
+        let all: Vec<u32> = (0..mesh.triangles.len() as u32).collect();
+        for j in 0..camera_config.height {
+            for i in 0..camera_config.width {
+                let ray = primary_ray(i as f64, j as f64, camera_config);
+                let expected = closest(mesh, &ray, &all);
+                let hit = bvh.closest_hit(&ray, |triangles| closest(mesh, &ray, triangles));
+                // The same distance, rays through an edge may find either
+                // triangle
+                match (hit, expected) {
+                    (Some((t, _)), Some((expected, _))) => assert!((t - expected).abs() < 1e-9),
+                    (hit, expected) => assert_eq!(hit.is_some(), expected.is_some()),
+                }
+                if let Some((t, _)) = expected {
+                    let length = ray.direction.norm();
+                    assert!(bvh.any_hit(&ray, t * length * 1.01, |triangles| {
+                        closest(mesh, &ray, triangles).is_some()
+                    }));
+                    assert!(!bvh.any_hit(&ray, t * length * 0.99, |triangles| {
+                        closest(mesh, &ray, triangles).is_some_and(|(hit, _)| hit < t * 0.99)
+                    }));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quantized_trees_find_the_same_hits() {
+        assert_eq!(QuantizedBvh::<u8>::node_size(), 32);
+        assert_eq!(QuantizedBvh::<u16>::node_size(), 64);
+
+        let heights = HeightMap::from_fn(24, 24, |x, y| {
+            ::image::Luma([((x * 7 + y * 13) % 17 * 3000) as u16])
+        });
+        let mesh = Mesh::terrain(&heights, 1.0, 4.0).unwrap();
+        let camera_config = CameraConfig::look_at(
+            Position::new(-20.0, 25.0, 30.0),
+            Position::new(0.0, 1.0, 0.0),
+            Direction::y(),
+            50.0,
+            40,
+            30,
+        );
+        for &build in TreeBuild::ALL.iter() {
+            let tree = KdTree::build(&mesh, build, Default::default());
+            check_hits::<u8>(&mesh, &tree, &camera_config);
+            check_hits::<u16>(&mesh, &tree, &camera_config);
+        }
+
+        // A tree of a single leaf
+        let triangle = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-1.0, 0.0, 0.0),
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let tree = KdTree::from_mesh(&triangle);
+        assert!(tree.is_leaf());
+        check_hits::<u8>(&triangle, &tree, &CameraConfig::default());
+    }
+}
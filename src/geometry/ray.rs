@@ -81,6 +81,20 @@ impl Ray {
     /// Return the number of direction to the intersection point
     /// or none if no intersection can be found
     pub fn intersect_box(&self, bounds: &[Position; 2]) -> Option<f64> {
+        let (t_near, t_far) = self.intersect_box_range(bounds)?;
+
+        // We are only considering the forward intersection with this
+        if t_near >= 0.0 {
+            return Some(t_near);
+        };
+        Some(t_far)
+    }
+
+    /// Number of directions to the points where the line of the ray enters
+    /// and exits the box, or none if the ray misses it or the box is behind
+    ///
+    /// The entry is negative when the ray starts inside the box.
+    pub fn intersect_box_range(&self, bounds: &[Position; 2]) -> Option<(f64, f64)> {
         let (mut tmin, mut tmax) = self.min_max_intersection(bounds, 0);
         let (tymin, tymax) = self.min_max_intersection(bounds, 1);
 
@@ -105,14 +119,31 @@ impl Ray {
             tmax = tzmax
         };
 
-        // We are only considering the forward intersection with this
-        if tmin >= 0.0 {
-            return Some(tmin);
-        };
         if tmax < 0.0 {
             return None;
         };
+        Some((tmin, tmax))
+    }
+}
 
-        Some(tmax)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_range_from_outside_inside_and_behind() {
+        let bounds = [Position::new(0.0, 0.0, 0.0), Position::new(2.0, 2.0, 2.0)];
+        let ray = Ray::new(Position::new(-1.0, 1.0, 1.0), Direction::new(1.0, 0.0, 0.0));
+        assert_eq!(ray.intersect_box_range(&bounds), Some((1.0, 3.0)));
+        assert_eq!(ray.intersect_box(&bounds), Some(1.0));
+
+        let inside = Ray::new(Position::new(0.5, 1.0, 1.0), Direction::new(1.0, 0.0, 0.0));
+        assert_eq!(inside.intersect_box_range(&bounds), Some((-0.5, 1.5)));
+        assert_eq!(inside.intersect_box(&bounds), Some(1.5));
+
+        let behind = Ray::new(Position::new(3.0, 1.0, 1.0), Direction::new(1.0, 0.0, 0.0));
+        assert_eq!(behind.intersect_box_range(&bounds), None);
+        let aside = Ray::new(Position::new(-1.0, 3.0, 1.0), Direction::new(1.0, 0.0, 0.0));
+        assert_eq!(aside.intersect_box_range(&bounds), None);
     }
 }
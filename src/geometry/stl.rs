@@ -0,0 +1,172 @@
+use std::convert::TryInto;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use crate::geometry::mesh::{weld_map, LoadError, LoadLimits, Mesh};
+use crate::geometry::types::Position;
+
+/// Size of the header of binary files, followed by the triangle count
+const HEADER_SIZE: usize = 80;
+/// Normal, 3 vertices and attribute byte count of a binary triangle
+const TRIANGLE_SIZE: usize = 50;
+
+impl Mesh {
+    /// Load a STL file, binary or ASCII
+    ///
+    /// STL stores every triangle with its own copy of its vertices: vertices
+    /// at exactly the same position are merged so that the mesh is connected,
+    /// see `weld` to merge close ones. The normals of the file are ignored and
+    /// recomputed from the triangles.
+    pub fn load_stl_file(path: &Path) -> Result<Mesh, LoadError> {
+        let limits = LoadLimits::default();
+        read_stl(io::BufReader::new(limits.open(path)?), &limits)
+    }
+}
+
+pub(crate) fn read_stl<R: Read>(mut reader: R, limits: &LoadLimits) -> Result<Mesh, LoadError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(LoadError::Io)?;
+    // ASCII files start with "solid", but so do many binary ones: the size
+    // of a binary file is given by its triangle count
+    let soup = match binary_triangle_count(&bytes) {
+        Some(count) => read_binary(&bytes, count, limits)?,
+        None if bytes.starts_with(b"solid") => read_ascii(&bytes, limits)?,
+        None => return Err(LoadError::String("Neither an ASCII nor a binary STL file")),
+    };
+    let (remap, kept) = weld_map(&soup, 0.0);
+    let vertices = kept.iter().map(|&i| soup[i]).collect();
+    let triangles = remap
+        .chunks(3)
+        .map(|corners| [corners[0], corners[1], corners[2]])
+        .collect();
+    Ok(Mesh::from_vertices_and_triangles(vertices, triangles))
+}
+
+fn binary_triangle_count(bytes: &[u8]) -> Option<usize> {
+    let count = u32::from_le_bytes(bytes.get(HEADER_SIZE..HEADER_SIZE + 4)?.try_into().ok()?);
+    let size = (count as u64) * TRIANGLE_SIZE as u64 + HEADER_SIZE as u64 + 4;
+    if size == bytes.len() as u64 {
+        Some(count as usize)
+    } else {
+        None
+    }
+}
+
+/// Corners of the triangles, 3 per triangle
+fn read_binary(
+    bytes: &[u8],
+    count: usize,
+    limits: &LoadLimits,
+) -> Result<Vec<Position>, LoadError> {
+    limits.check_triangles(count)?;
+    limits.check_vertices(count * 3)?;
+    let float =
+        |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as f64;
+    let mut corners = Vec::with_capacity(count * 3);
+    for triangle in 0..count {
+        // Skip the normal
+        let start = HEADER_SIZE + 4 + triangle * TRIANGLE_SIZE + 12;
+        for corner in 0..3 {
+            let offset = start + corner * 12;
+            corners.push(Position::new(
+                float(offset),
+                float(offset + 4),
+                float(offset + 8),
+            ));
+        }
+    }
+    Ok(corners)
+}
+
+fn read_ascii(bytes: &[u8], limits: &LoadLimits) -> Result<Vec<Position>, LoadError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| LoadError::String("STL file is not text"))?;
+    let mut tokens = text.split_whitespace();
+    let mut corners = Vec::new();
+    let mut facet_corners = 0;
+    while let Some(token) = tokens.next() {
+        match token {
+            "vertex" => {
+                let mut coordinate = || -> Result<f64, LoadError> {
+                    tokens
+                        .next()
+                        .ok_or(LoadError::String("STL vertex with less than 3 coordinates"))?
+                        .parse::<f64>()
+                        .map_err(LoadError::ParseFloat)
+                };
+                corners.push(Position::new(coordinate()?, coordinate()?, coordinate()?));
+                facet_corners += 1;
+            }
+            "endfacet" => {
+                if facet_corners != 3 {
+                    return Err(LoadError::String("STL facet without 3 vertices"));
+                }
+                facet_corners = 0;
+                limits.check_triangles(corners.len() / 3)?;
+                limits.check_vertices(corners.len())?;
+            }
+            _ => {}
+        }
+    }
+    if facet_corners != 0 {
+        return Err(LoadError::String("STL facet is not terminated"));
+    }
+    Ok(corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::Direction;
+
+    #[test]
+    fn ascii_and_binary_stl_are_welded() {
+        let stl = "solid quad\n\
+                   facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 1 1 0\nendloop\nendfacet\n\
+                   facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 1 0\nvertex 0 1 0\nendloop\nendfacet\n\
+                   endsolid quad\n";
+        let mesh = read_stl(stl.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+
+        // The same quad written in binary, read back identically
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quad.stl");
+        mesh.save_stl(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + 4 + 2 * TRIANGLE_SIZE);
+        let binary = Mesh::load_stl_file(&path).unwrap();
+        assert_eq!(binary.vertices, mesh.vertices);
+        assert_eq!(binary.triangles, mesh.triangles);
+
+        let truncated =
+            "solid quad\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nendloop\nendfacet\n";
+        assert!(read_stl(truncated.as_bytes(), &LoadLimits::default()).is_err());
+    }
+
+    #[test]
+    fn degenerate_facets_keep_the_normals_finite() {
+        let stl = "solid sliver
+                   facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endloop
+endfacet
+                   facet normal 0 0 0
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 1 0 0
+endloop
+endfacet
+                   endsolid sliver
+";
+        let mesh = read_stl(stl.as_bytes(), &LoadLimits::default()).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        for normal in &mesh.vertex_normals {
+            assert_eq!(*normal, Direction::new(0.0, 0.0, 1.0));
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use image::{ImageBuffer, Luma};
+
+use crate::geometry::mesh::{LoadError, LoadLimits, Mesh};
+use crate::geometry::types::Position;
+
+/// Grayscale elevation data, 16 bits to keep the precision of terrain files
+pub type HeightMap = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+impl Mesh {
+    /// Grid with a vertex per pixel of `heights`, lying on the xz plane and
+    /// centered on the origin, raised along y from 0 for black to `height`
+    /// for white
+    ///
+    /// Pixels are `spacing` apart, the top of the image toward -z. The
+    /// texture coordinates put the texel centers on the vertices so that an
+    /// image of the same size, e.g. a satellite view, matches the relief.
+    pub fn terrain(heights: &HeightMap, spacing: f64, height: f64) -> Result<Mesh, LoadError> {
+        let (width, depth) = (heights.width() as usize, heights.height() as usize);
+        if width < 2 || depth < 2 {
+            return Err(LoadError::String(
+                "a height map needs at least 2 by 2 pixels",
+            ));
+        }
+        let limits = LoadLimits::default();
+        limits.check_vertices(width * depth)?;
+        limits.check_triangles(2 * (width - 1) * (depth - 1))?;
+
+        let x0 = -spacing * (width - 1) as f64 / 2.0;
+        let z0 = -spacing * (depth - 1) as f64 / 2.0;
+        let mut vertices = Vec::with_capacity(width * depth);
+        let mut uvs = Vec::with_capacity(width * depth);
+        for (column, row, pixel) in heights.enumerate_pixels() {
+            vertices.push(Position::new(
+                x0 + spacing * column as f64,
+                height * pixel[0] as f64 / u16::MAX as f64,
+                z0 + spacing * row as f64,
+            ));
+            uvs.push([
+                (column as f64 + 0.5) / width as f64,
+                1.0 - (row as f64 + 0.5) / depth as f64,
+            ]);
+        }
+        let mut triangles = Vec::with_capacity(2 * (width - 1) * (depth - 1));
+        for row in 0..depth - 1 {
+            for column in 0..width - 1 {
+                let top_left = row * width + column;
+                let bottom_left = top_left + width;
+                // Counterclockwise seen from above
+                triangles.push([top_left, bottom_left, bottom_left + 1]);
+                triangles.push([top_left, bottom_left + 1, top_left + 1]);
+            }
+        }
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        mesh.uvs = Some(uvs);
+        Ok(mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height_maps_become_grids_facing_up() {
+        let heights = HeightMap::from_fn(3, 2, |x, _| Luma([(x as u16) * (u16::MAX / 2)]));
+        let mesh = Mesh::terrain(&heights, 2.0, 10.0).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.triangles.len(), 4);
+        assert_eq!(mesh.vertices[0], Position::new(-2.0, 0.0, -1.0));
+        assert_eq!(mesh.vertices[5].x, 2.0);
+        assert_eq!(mesh.vertices[5].z, 1.0);
+        assert!((mesh.vertices[5].y - 10.0).abs() < 1e-3);
+        assert_eq!(mesh.uvs.as_ref().unwrap()[0], [0.5 / 3.0, 0.75]);
+        // Raised toward +x, so tilted toward -x
+        for normal in &mesh.triangle_normals {
+            assert!(normal.y > 0.0 && normal.x < 0.0);
+        }
+
+        assert!(Mesh::terrain(&HeightMap::new(1, 5), 1.0, 1.0).is_err());
+    }
+}
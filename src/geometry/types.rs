@@ -9,3 +9,14 @@ pub type Position = Point3<f64>;
 pub type Direction = Vector3<f64>;
 /// Triangle as indices of a vertex array
 pub type Triangle = [usize; 3];
+
+/// Two directions orthogonal to `n` and to each other
+pub(crate) fn orthonormal_basis(n: &Direction) -> (Direction, Direction) {
+    let helper = if n.x.abs() < 0.9 {
+        Direction::new(1.0, 0.0, 0.0)
+    } else {
+        Direction::new(0.0, 1.0, 0.0)
+    };
+    let u = n.cross(&helper).normalize();
+    (u, n.cross(&u))
+}
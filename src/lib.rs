@@ -1,2 +1,5 @@
+pub mod cli;
+pub mod error;
 pub mod geometry;
 pub mod render;
+pub mod viewer;
@@ -0,0 +1,167 @@
+extern crate image;
+extern crate nalgebra as na;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use self::image::ImageResult;
+use crate::geometry::import::Unit;
+use crate::geometry::instance::Prototype;
+use crate::geometry::mesh::Mesh;
+use crate::geometry::types::{Direction, Position};
+use crate::render::config::{CameraConfig, RenderingConfig};
+use crate::render::image::{render_image, RgbImage};
+use crate::render::ray_tracer::make_kdt_ray_tracer;
+
+/// Path of the frame `frame` of a sequence, `<directory>/<prefix>_0042.png`,
+/// the numbers padded so that the files sort in order
+pub fn frame_path(directory: &Path, prefix: &str, frame: u32) -> PathBuf {
+    directory.join(format!("{}_{:04}.png", prefix, frame))
+}
+
+/// Render `frames` frames with `render` and save them to the numbered files
+/// of `frame_path`, returning their paths
+pub fn save_sequence<F>(
+    directory: &Path,
+    prefix: &str,
+    frames: u32,
+    mut render: F,
+) -> ImageResult<Vec<PathBuf>>
+where
+    F: FnMut(u32) -> RgbImage,
+{
+    (0..frames)
+        .map(|frame| {
+            let path = frame_path(directory, prefix, frame);
+            render(frame).save(&path)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Camera turned by `angle` radians around the line along `axis` through
+/// `pivot`, still looking at the same point of what it orbits
+pub fn orbit_camera(
+    camera_config: &CameraConfig,
+    pivot: &Position,
+    axis: &Direction,
+    angle: f64,
+) -> CameraConfig {
+    let rotation = na::Rotation3::from_axis_angle(&na::Unit::new_normalize(*axis), angle);
+    CameraConfig {
+        camera_position: *pivot + rotation * (camera_config.camera_position - *pivot),
+        x: rotation * camera_config.x,
+        y: rotation * camera_config.y,
+        z: rotation * camera_config.z,
+        ..camera_config.clone()
+    }
+}
+
+/// Render a turntable of `mesh`: `frames` frames of a full turn of the
+/// camera of `camera_config` around `axis` through the center of the bounding
+/// box of the mesh, the first frame being seen from `camera_config` itself,
+/// saved to the numbered files `<directory>/turntable_0000.png`, ...
+///
+/// The lights of `rendering_config` stay in place, as the mesh does. The tree
+/// of the mesh is built once for all the frames, on a copy reordered unless
+/// `rendering_config.optimize_layout` is off.
+pub fn render_turntable(
+    mesh: &Mesh,
+    axis: Direction,
+    frames: u32,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    directory: &Path,
+) -> ImageResult<Vec<PathBuf>> {
+    let Prototype { mesh, kdtree } = Prototype::new(
+        Arc::new(mesh.clone()),
+        rendering_config.tree_build,
+        rendering_config.tree_preset,
+        rendering_config.optimize_layout,
+    );
+    let pivot = mesh.bounding_box().center;
+    save_sequence(directory, "turntable", frames, |frame| {
+        let angle = 2.0 * std::f64::consts::PI * frame as f64 / frames as f64;
+        let camera = orbit_camera(camera_config, &pivot, &axis, angle);
+        let tracer = make_kdt_ray_tracer(&mesh, &kdtree, &camera, rendering_config, Unit::Meters);
+        render_image(tracer, &camera, rendering_config)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orbits_keep_the_distance_and_the_view() {
+        let pivot = Position::new(1.0, 0.0, 0.0);
+        let camera_config = CameraConfig::look_at(
+            Position::new(1.0, 2.0, -4.0),
+            pivot,
+            Direction::y(),
+            45.0,
+            40,
+            30,
+        );
+        let quarter = orbit_camera(
+            &camera_config,
+            &pivot,
+            &Direction::new(0.0, 3.0, 0.0),
+            std::f64::consts::FRAC_PI_2,
+        );
+        // From -z to -x around +y
+        assert!((quarter.camera_position - Position::new(-3.0, 2.0, 0.0)).norm() < 1e-12);
+        let to_pivot = (pivot - quarter.camera_position).normalize();
+        assert!((quarter.z - to_pivot).norm() < 1e-12);
+        assert!(quarter.y.dot(&quarter.z).abs() < 1e-12);
+        assert_eq!(quarter.width, 40);
+
+        let turn = orbit_camera(&camera_config, &pivot, &Direction::y(), 0.0);
+        assert_eq!(turn, camera_config);
+    }
+
+    #[test]
+    fn turntable_frames_are_numbered_files() {
+        let mesh = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-1.0, 0.0, -1.0),
+                Position::new(-1.0, 0.0, 1.0),
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 2, 1], [0, 1, 3], [1, 2, 3], [2, 0, 3]],
+        );
+        let camera_config = CameraConfig::framing(
+            &mesh.bounding_box(),
+            Direction::new(0.0, -0.5, 1.0),
+            Direction::y(),
+            45.0,
+            8,
+            6,
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let paths = render_turntable(
+            &mesh,
+            Direction::y(),
+            3,
+            &camera_config,
+            &Default::default(),
+            dir.path(),
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                dir.path().join("turntable_0000.png"),
+                dir.path().join("turntable_0001.png"),
+                dir.path().join("turntable_0002.png"),
+            ]
+        );
+        for path in &paths {
+            let frame = image::open(path).unwrap().to_rgb8();
+            assert_eq!(frame.dimensions(), (8, 6));
+            // The mesh is in the middle of every frame, on black
+            assert_ne!(frame.get_pixel(4, 3), &image::Rgb([0, 0, 0]));
+        }
+    }
+}
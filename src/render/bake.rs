@@ -0,0 +1,421 @@
+extern crate image;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use self::image::Rgb;
+use crate::geometry::mesh::Mesh;
+use crate::geometry::types::{orthonormal_basis, Direction};
+use crate::render::image::RgbImage;
+
+/// Quantity baked into a texture, see `bake`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BakeTarget {
+    /// Interpolated vertex normals, in world coordinates
+    WorldNormal,
+    /// Normals of the flat triangles in the tangent frame of the interpolated
+    /// normals, a map giving a faceted look to the smooth shading, in the
+    /// convention of `NormalMapped`
+    TangentNormal,
+    /// Mean curvature, mid gray where the surface is flat, lighter where it
+    /// is convex and darker where it is concave
+    Curvature,
+}
+
+impl BakeTarget {
+    pub const ALL: [BakeTarget; 3] = [
+        BakeTarget::WorldNormal,
+        BakeTarget::TangentNormal,
+        BakeTarget::Curvature,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BakeTarget::WorldNormal => "world_normal",
+            BakeTarget::TangentNormal => "tangent_normal",
+            BakeTarget::Curvature => "curvature",
+        }
+    }
+}
+
+impl fmt::Display for BakeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BakeTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BakeTarget::ALL
+            .iter()
+            .cloned()
+            .find(|target| target.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = BakeTarget::ALL.iter().map(|t| t.name()).collect();
+                format!(
+                    "unknown bake target {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Size and filling of a baked texture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BakeConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Texels the charts are grown by, so that filtering at their edges does
+    /// not blend in the empty background
+    pub padding: u32,
+    /// Curvature mapped to white, and its opposite to black; `None` picks
+    /// the 95th percentile of the curvatures of the vertices
+    pub curvature_range: Option<f64>,
+    /// UV set of the mesh the texture is laid out over, 0 for the first
+    pub uv_set: usize,
+}
+
+impl Default for BakeConfig {
+    fn default() -> Self {
+        BakeConfig {
+            width: 1024,
+            height: 1024,
+            padding: 2,
+            curvature_range: None,
+            uv_set: 0,
+        }
+    }
+}
+
+/// Bake `target` into a texture over the texture coordinates of UV set
+/// `config.uv_set` of the mesh
+///
+/// Every texel covered by a triangle in texture space gets the value at its
+/// center, texels of overlapping triangles the value of the last one.
+/// Normals are stored mapped from [-1, 1] to [0, 255], without color space
+/// encoding, to be read back with `Texture::data`. Fails without this UV
+/// set, or without `vertex_tangents` for tangent space normals, which follow
+/// the first set whichever one is baked over.
+pub fn bake(mesh: &Mesh, target: BakeTarget, config: &BakeConfig) -> Result<RgbImage, String> {
+    let _span = tracing::info_span!("bake", target = target.name()).entered();
+    let uvs = mesh
+        .uv_set(config.uv_set)
+        .ok_or_else(|| match config.uv_set {
+            0 => "the mesh has no texture coordinates to bake into".to_string(),
+            set => format!("the mesh has no UV set {} to bake into", set),
+        })?;
+    if target == BakeTarget::TangentNormal && mesh.vertex_tangents.is_none() {
+        return Err("tangent space normals need the vertex tangents".to_string());
+    }
+    let curvature = match target {
+        BakeTarget::Curvature => mean_curvature(mesh),
+        _ => Vec::new(),
+    };
+    let range = config
+        .curvature_range
+        .unwrap_or_else(|| percentile_magnitude(&curvature, 0.95));
+
+    let (width, height) = (config.width, config.height);
+    let mut img = RgbImage::new(width, height);
+    let mut filled = vec![false; (width * height) as usize];
+    for (t, triangle) in mesh.triangles.iter().enumerate() {
+        // Texel centers are at integer coordinates, as `Texture::sample`
+        // reads them
+        let corners = triangle.map(|vertex| {
+            let [u, v] = uvs[vertex];
+            [u * width as f64 - 0.5, (1.0 - v) * height as f64 - 0.5]
+        });
+        let [a, b, c] = corners;
+        let area = (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1]);
+        // Nothing to bake from triangles flat in texture space or in space
+        if area.abs() < 1e-12 || !mesh.triangle_normals[t].iter().all(|c| c.is_finite()) {
+            continue;
+        }
+        let min = |i: usize| corners.iter().map(|p| p[i]).fold(f64::INFINITY, f64::min);
+        let max = |i: usize| {
+            corners
+                .iter()
+                .map(|p| p[i])
+                .fold(f64::NEG_INFINITY, f64::max)
+        };
+        let x_range = min(0).ceil().max(0.0) as u32..=max(0).floor().min(width as f64 - 1.0) as u32;
+        for y in min(1).ceil().max(0.0) as u32..=max(1).floor().min(height as f64 - 1.0) as u32 {
+            for x in x_range.clone() {
+                let (px, py) = (x as f64, y as f64);
+                let edge = |p: [f64; 2], q: [f64; 2]| {
+                    ((q[0] - p[0]) * (py - p[1]) - (px - p[0]) * (q[1] - p[1])) / area
+                };
+                // Barycentric coordinates of the corners 1 and 2
+                let (u, v) = (edge(c, a), edge(a, b));
+                if u < -1e-9 || v < -1e-9 || u + v > 1.0 + 1e-9 {
+                    continue;
+                }
+                let value = match target {
+                    BakeTarget::WorldNormal => encode_normal(&smooth_normal(mesh, t, u, v)),
+                    BakeTarget::TangentNormal => tangent_normal(mesh, t, u, v),
+                    BakeTarget::Curvature => {
+                        let [i, j, k] = *triangle;
+                        let h = curvature[i] * (1.0 - u - v) + curvature[j] * u + curvature[k] * v;
+                        let gray = 0.5 + 0.5 * (h / range).clamp(-1.0, 1.0);
+                        [(gray * 255.0).round() as u8; 3]
+                    }
+                };
+                img.put_pixel(x, y, Rgb(value));
+                filled[(y * width + x) as usize] = true;
+            }
+        }
+    }
+    dilate(&mut img, &mut filled, config.padding);
+    Ok(img)
+}
+
+fn smooth_normal(mesh: &Mesh, triangle: usize, u: f64, v: f64) -> Direction {
+    let [a, b, c] = mesh.triangles[triangle].map(|vertex| mesh.vertex_normals[vertex]);
+    let normal = a * (1.0 - u - v) + b * u + c * v;
+    normal
+        .try_normalize(1e-12)
+        .unwrap_or(mesh.triangle_normals[triangle])
+}
+
+/// Normal of the triangle in the tangent frame of the smooth normal, built
+/// as `NormalMapped` does
+fn tangent_normal(mesh: &Mesh, triangle: usize, u: f64, v: f64) -> [u8; 3] {
+    let normal = smooth_normal(mesh, triangle, u, v);
+    let tangent = mesh
+        .tangent_at(triangle, &[u, v])
+        .expect("the tangents were checked");
+    let t = (tangent.direction - normal * normal.dot(&tangent.direction))
+        .try_normalize(1e-12)
+        .unwrap_or_else(|| orthonormal_basis(&normal).0);
+    let b = normal.cross(&t) * tangent.handedness;
+    let flat = mesh.triangle_normals[triangle];
+    encode_normal(&Direction::new(
+        flat.dot(&t),
+        flat.dot(&b),
+        flat.dot(&normal),
+    ))
+}
+
+fn encode_normal(normal: &Direction) -> [u8; 3] {
+    [normal.x, normal.y, normal.z].map(|c| ((c * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Grow the filled texels over their empty neighbors, one ring per
+/// iteration
+fn dilate(img: &mut RgbImage, filled: &mut [bool], iterations: u32) {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    for _ in 0..iterations {
+        let mut grown = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if filled[(y * width + x) as usize] {
+                    continue;
+                }
+                let neighbor = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+                    .find(|&(nx, ny)| {
+                        nx >= 0
+                            && ny >= 0
+                            && nx < width
+                            && ny < height
+                            && filled[(ny * width + nx) as usize]
+                    });
+                if let Some((nx, ny)) = neighbor {
+                    grown.push((x, y, *img.get_pixel(nx as u32, ny as u32)));
+                }
+            }
+        }
+        if grown.is_empty() {
+            return;
+        }
+        for (x, y, pixel) in grown {
+            img.put_pixel(x as u32, y as u32, pixel);
+            filled[(y * width + x) as usize] = true;
+        }
+    }
+}
+
+/// Mean curvature at the vertices, positive where the surface is convex
+/// along its normals, 0 on the boundaries
+///
+/// The curvature is half the length of the cotangent Laplacian of the
+/// positions, divided by the mixed Voronoi area around the vertex, signed by
+/// the vertex normal.
+///
+/// # Reference
+/// * Meyer et al., Discrete Differential-Geometry Operators for Triangulated
+///   2-Manifolds, VisMath (2002)
+pub fn mean_curvature(mesh: &Mesh) -> Vec<f64> {
+    let zero = Direction::zeros();
+    let mut laplacian = vec![zero; mesh.vertices.len()];
+    let mut area = vec![0.0; mesh.vertices.len()];
+    let mut edge_faces: HashMap<[usize; 2], u32> = HashMap::new();
+    for triangle in &mesh.triangles {
+        let p = triangle.map(|vertex| mesh.vertices[vertex]);
+        let triangle_area = (p[1] - p[0]).cross(&(p[2] - p[0])).norm() / 2.0;
+        if triangle_area <= 0.0 {
+            continue;
+        }
+        // Cotangent of the angle at every corner
+        let cotangents = [0, 1, 2].map(|k| {
+            let (ei, ej) = (p[(k + 1) % 3] - p[k], p[(k + 2) % 3] - p[k]);
+            ei.dot(&ej) / ei.cross(&ej).norm()
+        });
+        let obtuse = cotangents.iter().position(|&c| c < 0.0);
+        for k in 0..3 {
+            let (i, j) = ((k + 1) % 3, (k + 2) % 3);
+            let (vi, vj) = (triangle[i], triangle[j]);
+            let edge = p[j] - p[i];
+            laplacian[vi] += edge * cotangents[k];
+            laplacian[vj] -= edge * cotangents[k];
+            *edge_faces.entry([vi.min(vj), vi.max(vj)]).or_insert(0) += 1;
+            area[triangle[k]] += match obtuse {
+                // Voronoi area of the corner
+                None => {
+                    let (ek, ej) = (p[k] - p[j], p[k] - p[i]);
+                    (ek.norm_squared() * cotangents[i] + ej.norm_squared() * cotangents[j]) / 8.0
+                }
+                Some(o) if o == k => triangle_area / 2.0,
+                Some(_) => triangle_area / 4.0,
+            };
+        }
+    }
+    let mut curvature: Vec<f64> = laplacian
+        .iter()
+        .zip(&area)
+        .zip(&mesh.vertex_normals)
+        .map(
+            |((l, &a), n)| {
+                if a > 0.0 {
+                    -l.dot(n) / (4.0 * a)
+                } else {
+                    0.0
+                }
+            },
+        )
+        .collect();
+    for (edge, &faces) in &edge_faces {
+        if faces == 1 {
+            curvature[edge[0]] = 0.0;
+            curvature[edge[1]] = 0.0;
+        }
+    }
+    curvature
+}
+
+/// Magnitude below which `fraction` of the values are, 1 when they are all 0
+fn percentile_magnitude(values: &[f64], fraction: f64) -> f64 {
+    let mut magnitudes: Vec<f64> = values.iter().map(|v| v.abs()).collect();
+    magnitudes.sort_by(|a, b| a.total_cmp(b));
+    match magnitudes.get((magnitudes.len() as f64 * fraction) as usize) {
+        Some(&m) if m > 0.0 => m,
+        _ => magnitudes
+            .last()
+            .cloned()
+            .filter(|&m| m > 0.0)
+            .unwrap_or(1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::Position;
+    use std::f64::consts::PI;
+
+    /// Sphere of radius `radius` with latitude and longitude as texture
+    /// coordinates, seams split
+    fn uv_sphere(radius: f64, rings: usize, segments: usize) -> Mesh {
+        let mut vertices = Vec::new();
+        let mut uvs = Vec::new();
+        for ring in 0..=rings {
+            let theta = PI * ring as f64 / rings as f64;
+            for segment in 0..=segments {
+                let phi = 2.0 * PI * segment as f64 / segments as f64;
+                vertices.push(Position::new(
+                    radius * theta.sin() * phi.cos(),
+                    radius * theta.cos(),
+                    -radius * theta.sin() * phi.sin(),
+                ));
+                uvs.push([
+                    segment as f64 / segments as f64,
+                    1.0 - ring as f64 / rings as f64,
+                ]);
+            }
+        }
+        let index = |ring: usize, segment: usize| ring * (segments + 1) + segment;
+        let mut triangles = Vec::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let (a, b) = (index(ring, segment), index(ring, segment + 1));
+                let (c, d) = (index(ring + 1, segment), index(ring + 1, segment + 1));
+                // Without the flat triangles at the poles
+                if ring + 1 < rings {
+                    triangles.push([a, c, d]);
+                }
+                if ring > 0 {
+                    triangles.push([a, d, b]);
+                }
+            }
+        }
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        mesh.uvs = Some(uvs);
+        mesh.compute_tangents();
+        mesh
+    }
+
+    #[test]
+    fn sphere_bakes_outward_normals_and_its_curvature() {
+        // The seams split the vertices, the curvature needs them welded
+        let mut welded = uv_sphere(2.0, 24, 48);
+        welded.weld(1e-9);
+        for (vertex, h) in mean_curvature(&welded).iter().enumerate() {
+            assert!((h - 0.5).abs() < 0.02, "vertex {}: {}", vertex, h);
+        }
+
+        let config = BakeConfig {
+            width: 64,
+            height: 32,
+            ..Default::default()
+        };
+        let mut sphere = uv_sphere(2.0, 24, 48);
+        let normals = bake(&sphere, BakeTarget::WorldNormal, &config).unwrap();
+        // The middle row is the equator, u = 0 along +x
+        let equator = normals.get_pixel(0, 16);
+        assert!(equator[0] > 240 && (equator[1] as i32 - 128).abs() < 16);
+        // Flat triangles are close to the smooth normal, tilted both ways
+        let tangent = bake(&sphere, BakeTarget::TangentNormal, &config).unwrap();
+        assert!(tangent.pixels().all(|p| p[2] > 240));
+        let curvature = bake(&sphere, BakeTarget::Curvature, &config).unwrap();
+        assert!(curvature.get_pixel(20, 10)[0] > 250);
+
+        // A lightmap set squeezing the layout into the left half
+        let squeezed = sphere
+            .uvs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|&[u, v]| [u / 2.0, v]);
+        assert_eq!(sphere.add_uv_set(squeezed.collect()), 1);
+        let lightmap = BakeConfig {
+            uv_set: 1,
+            ..config
+        };
+        let squeezed = bake(&sphere, BakeTarget::WorldNormal, &lightmap).unwrap();
+        assert!(squeezed.get_pixel(0, 16)[0] > 240);
+        assert_eq!(squeezed.get_pixel(48, 16).0, [0, 0, 0]);
+
+        sphere.uvs = None;
+        assert!(bake(&sphere, BakeTarget::Curvature, &config).is_err());
+        let missing = BakeConfig {
+            uv_set: 2,
+            ..config
+        };
+        assert!(bake(&sphere, BakeTarget::WorldNormal, &missing).is_err());
+        assert_eq!("tangent_normal".parse(), Ok(BakeTarget::TangentNormal));
+    }
+}
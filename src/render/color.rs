@@ -0,0 +1,248 @@
+extern crate nalgebra as na;
+
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign};
+
+use serde::{Deserialize, Serialize};
+
+/// Linear RGB color with the Rec. 709 primaries of sRGB, the working space
+/// of the renderer
+///
+/// Channels are not bounded: radiance and illuminance go above 1. Stored in
+/// files as `[r, g, b]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[f64; 3]", into = "[f64; 3]")]
+pub struct Color {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::gray(0.0);
+    pub const WHITE: Color = Color::gray(1.0);
+
+    pub const fn new(r: f64, g: f64, b: f64) -> Color {
+        Color { r, g, b }
+    }
+
+    pub const fn gray(value: f64) -> Color {
+        Color::new(value, value, value)
+    }
+
+    /// Apply `f` to every channel
+    pub fn map<F: Fn(f64) -> f64>(self, f: F) -> Color {
+        Color::new(f(self.r), f(self.g), f(self.b))
+    }
+
+    /// Relative luminance (Rec. 709)
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Decode an 8-bit sRGB color, e.g. a texture pixel
+    pub fn from_srgb8(rgb: [u8; 3]) -> Color {
+        Color::from(rgb.map(|c| srgb_to_linear(c as f64 / 255.0)))
+    }
+
+    /// 8-bit sRGB encoding, channels outside of [0, 1] are clipped
+    pub fn to_srgb8(self) -> [u8; 3] {
+        self.map(|c| linear_to_srgb(c.clamp(0.0, 1.0))).to_u8()
+    }
+
+    /// 8-bit value of an already encoded color, channels outside of [0, 1]
+    /// are clipped
+    pub fn to_u8(self) -> [u8; 3] {
+        <[f64; 3]>::from(self).map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+}
+
+/// sRGB transfer function, from an encoded value in [0, 1] to linear
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of `srgb_to_linear`
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl From<[f64; 3]> for Color {
+    fn from(rgb: [f64; 3]) -> Color {
+        Color::new(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+impl From<Color> for [f64; 3] {
+    fn from(color: Color) -> [f64; 3] {
+        [color.r, color.g, color.b]
+    }
+}
+
+impl Add for Color {
+    type Output = Color;
+
+    fn add(self, other: Color) -> Color {
+        Color::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl AddAssign for Color {
+    fn add_assign(&mut self, other: Color) {
+        *self = *self + other;
+    }
+}
+
+/// Channel by channel product, e.g. a light filtered by an albedo
+impl Mul for Color {
+    type Output = Color;
+
+    fn mul(self, other: Color) -> Color {
+        Color::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+impl Mul<f64> for Color {
+    type Output = Color;
+
+    fn mul(self, factor: f64) -> Color {
+        self.map(|c| c * factor)
+    }
+}
+
+impl MulAssign<f64> for Color {
+    fn mul_assign(&mut self, factor: f64) {
+        *self = *self * factor;
+    }
+}
+
+impl Div<f64> for Color {
+    type Output = Color;
+
+    fn div(self, divisor: f64) -> Color {
+        self.map(|c| c / divisor)
+    }
+}
+
+impl Sum for Color {
+    fn sum<I: Iterator<Item = Color>>(iter: I) -> Color {
+        iter.fold(Color::BLACK, Add::add)
+    }
+}
+
+/// Primaries of the color space of the output images
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Primaries {
+    /// Those of sRGB and of the renderer, no conversion
+    #[default]
+    Rec709,
+    /// Wide gamut displays, e.g. recent phones and laptops
+    DisplayP3,
+    /// Ultra HD television
+    Rec2020,
+}
+
+impl Primaries {
+    /// Conversion of linear Rec. 709 colors to these primaries, D65 white
+    fn matrix(self) -> na::Matrix3<f64> {
+        match self {
+            Primaries::Rec709 => na::Matrix3::identity(),
+            Primaries::DisplayP3 => na::Matrix3::new(
+                0.822_462, 0.177_538, 0.0, //
+                0.033_194, 0.966_806, 0.0, //
+                0.017_083, 0.072_397, 0.910_520,
+            ),
+            Primaries::Rec2020 => na::Matrix3::new(
+                0.627_404, 0.329_283, 0.043_313, //
+                0.069_097, 0.919_540, 0.011_362, //
+                0.016_391, 0.088_013, 0.895_595,
+            ),
+        }
+    }
+}
+
+/// Encoding of the output values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transfer {
+    /// Values are written as computed
+    #[default]
+    Linear,
+    /// Piecewise sRGB curve, what most image viewers expect
+    Srgb,
+    /// Pure power law, e.g. 2.2 or 2.4
+    Gamma(f64),
+}
+
+impl Transfer {
+    fn encode(self, value: f64) -> f64 {
+        let value = value.max(0.0);
+        match self {
+            Transfer::Linear => value,
+            Transfer::Srgb => linear_to_srgb(value.min(1.0)),
+            Transfer::Gamma(gamma) => value.powf(1.0 / gamma),
+        }
+    }
+}
+
+/// Conversion of the rendered colors to the color space of the output, in
+/// the spirit of a display transform of ICC or OpenColorIO pipelines
+///
+/// Colors are converted to the output primaries, then encoded. The default
+/// writes the working space values unchanged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputTransform {
+    pub primaries: Primaries,
+    pub transfer: Transfer,
+}
+
+impl OutputTransform {
+    pub fn apply(&self, color: Color) -> Color {
+        let rgb = na::Vector3::new(color.r, color.g, color.b);
+        let converted = match self.primaries {
+            Primaries::Rec709 => rgb,
+            primaries => primaries.matrix() * rgb,
+        };
+        Color::new(converted.x, converted.y, converted.z).map(|c| self.transfer.encode(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips_and_conversions_keep_white() {
+        for value in 0..=255u8 {
+            let color = Color::from_srgb8([value; 3]);
+            assert_eq!(color.to_srgb8(), [value; 3]);
+        }
+        assert!((Color::from_srgb8([188; 3]).r - 0.5).abs() < 0.01);
+
+        for &primaries in &[Primaries::DisplayP3, Primaries::Rec2020] {
+            let transform = OutputTransform {
+                primaries,
+                transfer: Transfer::Srgb,
+            };
+            let white = transform.apply(Color::WHITE);
+            assert!((white.r - 1.0).abs() < 1e-5 && (white.b - 1.0).abs() < 1e-5);
+            // Saturated red is inside the wider gamuts
+            let red = transform.apply(Color::new(1.0, 0.0, 0.0));
+            assert!(red.r < 1.0 && red.g > 0.0);
+        }
+        assert_eq!(
+            serde_json::to_string(&Color::new(0.5, 0.25, 1.0)).unwrap(),
+            "[0.5,0.25,1.0]"
+        );
+    }
+}
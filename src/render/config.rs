@@ -1,21 +1,1599 @@
-use crate::geometry::types::{Direction, Position};
+extern crate image;
+extern crate nalgebra as na;
 
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use self::image::ImageError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::bvh::{TreeBuild, TreePreset};
+use crate::geometry::mesh::{LoadError, LoadLimits, Mesh};
+use crate::geometry::types::{orthonormal_basis, Direction, Position};
+use crate::render::color::{Color, OutputTransform};
+use crate::render::environment::{Background, Environment, HdrImage};
+use crate::render::framebuffer::{Dither, Exposure, TileRect, ToneMapping};
+use crate::render::image::RgbImage;
+use crate::render::light::{
+    cone_solid_angle, DirectionalLight, IesProfile, Light, LightUnits, PointLight, SpotLight,
+};
+use crate::render::material::{
+    BlinnPhong, Glass, Lambertian, Material, MetallicRoughness, Mirror, NormalMapped, Texture,
+};
+use crate::render::sampler::SamplerKind;
+
+/// Version of the configuration file schema written by this build
+///
+/// Version 1 cameras took `fov` as the tangent of the horizontal extent, in
+/// radians, with an `aspect_ratio`; they are converted when loaded, see
+/// `upgrade_camera_v1`. Files without a `version` predate it and are
+/// version 1.
+pub const CONFIG_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CameraConfig {
     pub camera_position: Position,
     pub x: Direction,
     pub y: Direction,
     pub z: Direction,
+    /// Angle between the top and the bottom of the image of a perspective
+    /// camera, in degrees; the width follows from the aspect of the image
     pub fov: f64,
-    pub aspect_ratio: f64,
     pub width: u32,
     pub height: u32,
+    /// Diameter of the lens, 0 for a pinhole camera keeping everything
+    /// sharp; wider lenses blur what is out of focus
+    pub aperture: f64,
+    /// Distance along `z` of the plane in focus through the lens
+    pub focus_distance: f64,
+    /// How the directions around the camera are laid out in the image
+    pub projection: Projection,
+}
+
+impl Default for CameraConfig {
+    /// 400x300 pinhole camera 10 units behind the origin, looking at it
+    /// along +z
+    fn default() -> Self {
+        CameraConfig {
+            camera_position: Position::new(0.0, 0.0, -10.0),
+            x: Direction::new(1.0, 0.0, 0.0),
+            y: Direction::new(0.0, 1.0, 0.0),
+            z: Direction::new(0.0, 0.0, 1.0),
+            fov: 60.0,
+            width: 400,
+            height: 300,
+            aperture: 0.0,
+            focus_distance: 10.0,
+            projection: Projection::Perspective,
+        }
+    }
+}
+
+impl CameraConfig {
+    /// Camera at `eye` looking at `target`, the top of the image toward `up`
+    ///
+    /// The basis is orthonormal whatever the arguments: an `up` along the
+    /// view is replaced by another direction, and a `target` at the eye is
+    /// looked at along +z.
+    pub fn look_at(
+        eye: Position,
+        target: Position,
+        up: Direction,
+        fov: f64,
+        width: u32,
+        height: u32,
+    ) -> CameraConfig {
+        let z = (target - eye)
+            .try_normalize(1e-12)
+            .unwrap_or_else(Direction::z);
+        let x = up
+            .cross(&z)
+            .try_normalize(1e-12)
+            .unwrap_or_else(|| orthonormal_basis(&z).0);
+        CameraConfig {
+            camera_position: eye,
+            x,
+            y: z.cross(&x),
+            z,
+            fov,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    /// Camera looking along `direction` at the center of `bounds`, e.g. of
+    /// a mesh or a scene, the top of the image toward `up`, as close as it
+    /// can be with the whole box in the image, and focused on its center
+    ///
+    /// The corners of the box touch the edges of the image on the side it
+    /// fills the most.
+    pub fn framing(
+        bounds: &AxisAlignedBoundingBox,
+        direction: Direction,
+        up: Direction,
+        fov: f64,
+        width: u32,
+        height: u32,
+    ) -> CameraConfig {
+        let center = bounds.center;
+        let camera = CameraConfig::look_at(center - direction, center, up, fov, width, height);
+        let tan_y = (fov.to_radians() / 2.0).tan();
+        let tan_x = tan_y * camera.aspect_ratio();
+        // Distance from the center at which each corner enters the image
+        let distance = (0..8)
+            .map(|corner| {
+                let point = Position::new(
+                    bounds.bounds[corner & 1].x,
+                    bounds.bounds[(corner >> 1) & 1].y,
+                    bounds.bounds[corner >> 2].z,
+                );
+                let offset = point - center;
+                let (x, y) = (offset.dot(&camera.x), offset.dot(&camera.y));
+                (x.abs() / tan_x).max(y.abs() / tan_y) - offset.dot(&camera.z)
+            })
+            .fold(f64::EPSILON, f64::max);
+        CameraConfig {
+            camera_position: center - camera.z * distance,
+            focus_distance: distance,
+            ..camera
+        }
+    }
+
+    /// Camera of a view matrix exported by another tool, taking points from
+    /// the scene coordinates to those of the camera, with the axes of the
+    /// camera in `convention`
+    ///
+    /// Scales in the matrix are dropped. `None` if it cannot be inverted.
+    /// Tools giving the pose of the camera instead, as the world matrix of
+    /// Blender objects, need it inverted first.
+    pub fn from_matrix(
+        view: &na::Matrix4<f64>,
+        convention: CameraConvention,
+        fov: f64,
+        width: u32,
+        height: u32,
+    ) -> Option<CameraConfig> {
+        let pose = view.try_inverse()?;
+        let column = |c: usize| Direction::new(pose[(0, c)], pose[(1, c)], pose[(2, c)]);
+        let (x, y, z) = match convention {
+            CameraConvention::OpenGl => (column(0), column(1), -column(2)),
+            CameraConvention::OpenCv => (column(0), -column(1), column(2)),
+        };
+        Some(CameraConfig {
+            camera_position: Position::from(column(3)),
+            x: x.try_normalize(1e-12)?,
+            y: y.try_normalize(1e-12)?,
+            z: z.try_normalize(1e-12)?,
+            fov,
+            width,
+            height,
+            ..Default::default()
+        })
+    }
+
+    /// Builder starting from the default camera
+    pub fn builder() -> CameraConfigBuilder {
+        CameraConfigBuilder {
+            camera: Default::default(),
+            target: None,
+            focus_distance: None,
+        }
+    }
+
+    /// Width of the image over its height, its pixels being square
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height.max(1) as f64
+    }
+}
+
+/// Axes of the cameras in the view matrices of other tools
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraConvention {
+    /// Looking along -z, the top of the image toward +y: OpenGL, Blender
+    OpenGl,
+    /// Looking along +z, the top of the image toward -y: OpenCV, COLMAP
+    OpenCv,
 }
 
+/// Camera set up piece by piece, the rest as `CameraConfig::default()`
+///
+/// ```no_run
+/// # use ray_ruster::render::config::CameraConfig;
+/// # use ray_ruster::geometry::types::{Direction, Position};
+/// let camera = CameraConfig::builder()
+///     .position(Position::new(0.0, 2.0, -5.0))
+///     .look_at(Position::origin(), Direction::y())
+///     .size(640, 480)
+///     .aperture(0.1)
+///     .build();
+/// ```
+pub struct CameraConfigBuilder {
+    camera: CameraConfig,
+    target: Option<(Position, Direction)>,
+    focus_distance: Option<f64>,
+}
+
+impl CameraConfigBuilder {
+    pub fn position(mut self, position: Position) -> Self {
+        self.camera.camera_position = position;
+        self
+    }
+
+    /// Turn the camera toward `target`, the top of the image toward `up`,
+    /// focused on it unless `focus_distance` says otherwise
+    pub fn look_at(mut self, target: Position, up: Direction) -> Self {
+        self.target = Some((target, up));
+        self
+    }
+
+    /// Vertical angle of view, in degrees
+    pub fn fov(mut self, fov: f64) -> Self {
+        self.camera.fov = fov;
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.camera.width = width;
+        self.camera.height = height;
+        self
+    }
+
+    pub fn aperture(mut self, aperture: f64) -> Self {
+        self.camera.aperture = aperture;
+        self
+    }
+
+    pub fn focus_distance(mut self, focus_distance: f64) -> Self {
+        self.focus_distance = Some(focus_distance);
+        self
+    }
+
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.camera.projection = projection;
+        self
+    }
+
+    pub fn build(self) -> CameraConfig {
+        let mut camera = self.camera;
+        if let Some((target, up)) = self.target {
+            let eye = camera.camera_position;
+            let oriented =
+                CameraConfig::look_at(eye, target, up, camera.fov, camera.width, camera.height);
+            camera.x = oriented.x;
+            camera.y = oriented.y;
+            camera.z = oriented.z;
+            camera.focus_distance = (target - eye).norm();
+        }
+        if let Some(focus_distance) = self.focus_distance {
+            camera.focus_distance = focus_distance;
+        }
+        camera
+    }
+}
+
+/// How a camera maps the directions around it to the image
+///
+/// Spelled `{ "kind": "fisheye", "fov": 180 }` in configuration files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Projection {
+    /// Pinhole camera seeing `fov` degrees, straight lines staying straight
+    #[default]
+    Perspective,
+    /// Every direction around the camera, the longitude along the width
+    /// from -180 degrees on the left to 180 on the right, `z` in the middle,
+    /// and the latitude along the height, `y` at the top: the layout of
+    /// `EnvironmentConfig` images, to bake them from a scene
+    Equirectangular,
+    /// Equidistant fisheye seeing `fov` degrees, up to 360, across the circle
+    /// inscribed in the image, the angle to `z` growing with the distance to
+    /// the center
+    Fisheye { fov: f64 },
+}
+
+impl Projection {
+    pub const ALL: [Projection; 3] = [
+        Projection::Perspective,
+        Projection::Equirectangular,
+        Projection::Fisheye { fov: 180.0 },
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Projection::Perspective => "perspective",
+            Projection::Equirectangular => "equirectangular",
+            Projection::Fisheye { .. } => "fisheye",
+        }
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Projection::ALL
+            .iter()
+            .cloned()
+            .find(|projection| projection.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Projection::ALL.iter().map(|p| p.name()).collect();
+                format!(
+                    "unknown projection {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How the shading normal is computed at a hit point
+///
+/// Spelled in lowercase on the command line and in configuration files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum NormalMode {
+    /// Vertex normals interpolated with the barycentric coordinates
     Phong,
+    /// Flat shading with the normal of the triangle
     Triangle,
 }
 
+impl NormalMode {
+    pub const ALL: [NormalMode; 2] = [NormalMode::Phong, NormalMode::Triangle];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NormalMode::Phong => "phong",
+            NormalMode::Triangle => "triangle",
+        }
+    }
+}
+
+impl fmt::Display for NormalMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for NormalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NormalMode::ALL
+            .iter()
+            .cloned()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = NormalMode::ALL.iter().map(|m| m.name()).collect();
+                format!(
+                    "unknown normal mode {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How the paths of secondary rays are terminated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PathTermination {
+    /// Always follow paths up to `max_depth` bounces
+    Fixed,
+    /// Randomly stop paths after `min_depth` bounces, with a probability based
+    /// on their throughput, and re-weight the surviving ones
+    RussianRoulette { min_depth: u32 },
+}
+
+/// How the color seen by a camera ray is computed from its hit
+///
+/// Spelled `{ "kind": "path_tracing" }` in configuration files and
+/// `--integrator path_tracing` on the command line, where ambient occlusion
+/// takes 16 samples without distance limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Integrator {
+    /// Gray level of the cosine between the normal and the ray, to look at
+    /// the geometry alone
+    FacingRatio,
+    /// Fraction of `samples` rays over the hemisphere of the normal that
+    /// nothing blocks within `distance` (no limit when absent)
+    AmbientOcclusion {
+        samples: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        distance: Option<f64>,
+    },
+    /// Light of the light sources, with the mirror reflections and
+    /// refractions traced up to `max_depth` bounces
+    #[default]
+    Whitted,
+    /// Whitted, plus the light bounced by matte surfaces and the environment
+    /// they see, following one random direction per bounce up to `max_depth`
+    /// bounces or the `termination` of the path
+    PathTracing,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 4] = [
+        Integrator::FacingRatio,
+        Integrator::AmbientOcclusion {
+            samples: 16,
+            distance: None,
+        },
+        Integrator::Whitted,
+        Integrator::PathTracing,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Integrator::FacingRatio => "facing_ratio",
+            Integrator::AmbientOcclusion { .. } => "ambient_occlusion",
+            Integrator::Whitted => "whitted",
+            Integrator::PathTracing => "path_tracing",
+        }
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Integrator::ALL
+            .iter()
+            .cloned()
+            .find(|integrator| integrator.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Integrator::ALL.iter().map(|i| i.name()).collect();
+                format!(
+                    "unknown integrator {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// False color image of what the camera rays find, rendered instead of the
+/// shaded image to look into the geometry and the kd-tree
+///
+/// Spelled `{ "kind": "kd_tree_nodes", "max": 100 }` in configuration files
+/// and `--debug-view kd_tree_nodes` on the command line, where the node
+/// count is mapped up to 100.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DebugView {
+    /// Shading normal, its coordinates mapped from [-1, 1] to RGB
+    Normals,
+    /// Distance to the hit, from red at the front of the bounding box of the
+    /// scene to blue at its back
+    Depth,
+    /// Barycentric coordinates of the hit in its triangle, as RGB
+    Barycentrics,
+    /// Color drawn at random from the index of the triangle hit
+    TriangleIndex,
+    /// Nodes of the kd-tree visited to find the hit, from blue for none to
+    /// red for `max` or more; none are counted without kd-tree
+    KdTreeNodes { max: u32 },
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 5] = [
+        DebugView::Normals,
+        DebugView::Depth,
+        DebugView::Barycentrics,
+        DebugView::TriangleIndex,
+        DebugView::KdTreeNodes { max: 100 },
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::Normals => "normals",
+            DebugView::Depth => "depth",
+            DebugView::Barycentrics => "barycentrics",
+            DebugView::TriangleIndex => "triangle_index",
+            DebugView::KdTreeNodes { .. } => "kd_tree_nodes",
+        }
+    }
+}
+
+impl fmt::Display for DebugView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DebugView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DebugView::ALL
+            .iter()
+            .cloned()
+            .find(|view| view.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = DebugView::ALL.iter().map(|v| v.name()).collect();
+                format!(
+                    "unknown debug view {}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RenderingConfig {
+    pub integrator: Integrator,
+    /// Renders a false color image instead of shading the hits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_view: Option<DebugView>,
     pub normal_mode: NormalMode,
+    /// Maximum number of bounces of secondary rays, e.g. reflections and
+    /// refractions of mirrors and glass
+    pub max_depth: u32,
+    pub termination: PathTermination,
+    /// Reorder the triangles of scenes, and of the models of their
+    /// instances, for memory locality before building their kd-tree, see
+    /// `Mesh::optimize_layout`
+    pub optimize_layout: bool,
+    /// How the tree of the boxes around the triangles of scenes is built
+    pub tree_build: TreeBuild,
+    /// Parameters of the tree builds, trading their time for the speed of
+    /// the renders
+    pub tree_preset: TreePreset,
+    /// Number of rendering threads, 0 uses one thread per core
+    pub threads: usize,
+    /// Side of the square tiles distributed to the threads, in pixels
+    pub tile_size: u32,
+    /// Number of rays averaged per pixel, spread over the pixel to smooth the
+    /// edges
+    pub samples_per_pixel: u32,
+    /// Placement of the rays in the pixels
+    pub sampler: SamplerKind,
+    /// Seed of the random numbers, renders with the same seed are identical
+    pub seed: u64,
+    /// Region of the image to render, in pixels from the top left corner:
+    /// the render is that part of the image alone, seen through the same
+    /// camera, e.g. to iterate on a detail of a large frame
+    pub crop: Option<TileRect>,
+    /// Brightness adjustment of high dynamic range renders before tone
+    /// mapping
+    pub exposure: Exposure,
+    /// Compression of the high dynamic range renders into the 8-bit range,
+    /// after the exposure
+    pub tone_mapping: ToneMapping,
+    /// Noise added when quantizing high dynamic range renders to 8 bits
+    pub dither: Dither,
+    /// Color space of the output images
+    pub output_transform: OutputTransform,
+    /// Material of the meshes that do not have their own, e.g. models
+    /// rendered without a scene
+    pub material: MaterialConfig,
+    /// Light sources of the render, see `LightConfig::build`
+    #[serde(skip)]
+    pub lights: Vec<Arc<dyn Light>>,
+    /// Light the renders without lights from the camera, rather than leaving
+    /// them to the background and the environment alone
+    pub headlight: bool,
+    /// Seen by the rays missing the scene when it has no environment
+    pub background: Background,
+    /// Seen by the rays missing the scene, see `EnvironmentConfig::build`
+    #[serde(skip)]
+    pub environment: Option<Arc<Environment>>,
+}
+
+impl Default for RenderingConfig {
+    fn default() -> Self {
+        RenderingConfig {
+            integrator: Integrator::Whitted,
+            debug_view: None,
+            normal_mode: NormalMode::Phong,
+            max_depth: 8,
+            termination: PathTermination::RussianRoulette { min_depth: 3 },
+            optimize_layout: true,
+            tree_build: TreeBuild::KdTree,
+            tree_preset: TreePreset::Balanced,
+            threads: 0,
+            tile_size: 32,
+            samples_per_pixel: 1,
+            sampler: SamplerKind::Regular,
+            seed: 0,
+            crop: None,
+            exposure: Exposure::Manual { ev: 0.0 },
+            tone_mapping: ToneMapping::Clamp,
+            dither: Dither::None,
+            output_transform: OutputTransform::default(),
+            material: MaterialConfig::default(),
+            background: Background::Black,
+            lights: Vec::new(),
+            headlight: true,
+            environment: None,
+        }
+    }
+}
+
+impl PartialEq for RenderingConfig {
+    /// Lights and environments are the same when they are shared, they
+    /// cannot be compared
+    fn eq(&self, other: &Self) -> bool {
+        let RenderingConfig {
+            integrator,
+            debug_view,
+            normal_mode,
+            max_depth,
+            termination,
+            optimize_layout,
+            tree_build,
+            tree_preset,
+            threads,
+            tile_size,
+            samples_per_pixel,
+            sampler,
+            seed,
+            crop,
+            exposure,
+            tone_mapping,
+            dither,
+            output_transform,
+            material,
+            background,
+            lights,
+            headlight,
+            environment,
+        } = self;
+        *integrator == other.integrator
+            && *debug_view == other.debug_view
+            && *normal_mode == other.normal_mode
+            && *max_depth == other.max_depth
+            && *termination == other.termination
+            && *optimize_layout == other.optimize_layout
+            && *tree_build == other.tree_build
+            && *tree_preset == other.tree_preset
+            && *threads == other.threads
+            && *tile_size == other.tile_size
+            && *samples_per_pixel == other.samples_per_pixel
+            && *sampler == other.sampler
+            && *seed == other.seed
+            && *crop == other.crop
+            && *exposure == other.exposure
+            && *tone_mapping == other.tone_mapping
+            && *dither == other.dither
+            && *output_transform == other.output_transform
+            && *material == other.material
+            && *background == other.background
+            && lights.len() == other.lights.len()
+            && lights
+                .iter()
+                .zip(&other.lights)
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+            && *headlight == other.headlight
+            && match (environment, &other.environment) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+    }
+}
+
+impl RenderingConfig {
+    /// Pixels of the image that are rendered: the crop window, cut to the
+    /// image, or the whole image
+    pub fn region(&self, camera_config: &CameraConfig) -> TileRect {
+        let (width, height) = (camera_config.width, camera_config.height);
+        match self.crop {
+            Some(crop) => {
+                let x = crop.x.min(width);
+                let y = crop.y.min(height);
+                TileRect {
+                    x,
+                    y,
+                    width: crop.width.min(width - x),
+                    height: crop.height.min(height - y),
+                }
+            }
+            None => TileRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+        }
+    }
+
+    /// Configuration for bit-stable images, e.g. for regression tests
+    ///
+    /// Paths always have `max_depth` bounces and are never terminated
+    /// stochastically, so the image only depends on the scene and the
+    /// sample positions.
+    pub fn deterministic(max_depth: u32) -> Self {
+        RenderingConfig {
+            max_depth,
+            termination: PathTermination::Fixed,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        match self.termination {
+            PathTermination::Fixed => true,
+            PathTermination::RussianRoulette { .. } => false,
+        }
+    }
+}
+
+/// Reflection model of a material
+///
+/// Spelled in snake case on the command line and in configuration files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadingModel {
+    /// Matte, see `material::Lambertian`
+    #[default]
+    Lambert,
+    /// Glossy, see `material::BlinnPhong`
+    BlinnPhong,
+    /// Physically based metallic-roughness, see
+    /// `material::MetallicRoughness`
+    Pbr,
+    /// Perfect reflection, see `material::Mirror`
+    Mirror,
+    /// Transparent with refraction, see `material::Glass`
+    Glass,
+}
+
+impl ShadingModel {
+    pub const ALL: [ShadingModel; 5] = [
+        ShadingModel::Lambert,
+        ShadingModel::BlinnPhong,
+        ShadingModel::Pbr,
+        ShadingModel::Mirror,
+        ShadingModel::Glass,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShadingModel::Lambert => "lambert",
+            ShadingModel::BlinnPhong => "blinn_phong",
+            ShadingModel::Pbr => "pbr",
+            ShadingModel::Mirror => "mirror",
+            ShadingModel::Glass => "glass",
+        }
+    }
+}
+
+impl fmt::Display for ShadingModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ShadingModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ShadingModel::ALL
+            .iter()
+            .cloned()
+            .find(|model| model.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown shading model {}, expected lambert, blinn_phong, pbr, mirror \
+                     or glass",
+                    s
+                )
+            })
+    }
+}
+
+/// Appearance of an object of the scene
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialConfig {
+    pub model: ShadingModel,
+    /// RGB albedo in [0, 1], multiplied with the vertex colors of the mesh
+    /// and the texture
+    pub color: Color,
+    /// Image multiplied with the albedo through the texture coordinates of
+    /// the mesh, relative to the file once saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<PathBuf>,
+    /// UV set of the mesh the texture is mapped through, 0 for the first
+    pub texture_uv_set: usize,
+    /// The loaded `texture`, see `load_textures`
+    #[serde(skip)]
+    pub texture_image: Option<Arc<RgbImage>>,
+    /// Tangent space normal map perturbing the shading normals, see
+    /// `material::NormalMapped`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<PathBuf>,
+    /// UV set of the mesh the normal map is mapped through
+    pub normal_map_uv_set: usize,
+    /// The loaded `normal_map`
+    #[serde(skip)]
+    pub normal_map_image: Option<Arc<RgbImage>>,
+    /// Scale of the tilt of the mapped normals, 1 as stored in the map
+    pub normal_strength: f32,
+    /// RGB color of the highlights, Blinn-Phong only
+    pub specular: Color,
+    /// Sharpness of the highlights, Blinn-Phong only
+    pub shininess: f32,
+    /// 0 for dielectrics, 1 for metals, PBR only
+    pub metallic: f32,
+    /// 0 for mirrors, 1 for fully rough surfaces, PBR only
+    pub roughness: f32,
+    /// Index of refraction, glass only
+    pub ior: f32,
+}
+
+impl Default for MaterialConfig {
+    fn default() -> Self {
+        MaterialConfig {
+            model: ShadingModel::Lambert,
+            color: Color::WHITE,
+            texture: None,
+            texture_uv_set: 0,
+            texture_image: None,
+            normal_map: None,
+            normal_map_uv_set: 0,
+            normal_map_image: None,
+            normal_strength: 1.0,
+            specular: Color::gray(0.5),
+            shininess: 32.0,
+            metallic: 0.0,
+            roughness: 0.5,
+            ior: 1.5,
+        }
+    }
+}
+
+impl MaterialConfig {
+    /// Load the `texture` and `normal_map` images, relative to `directory`
+    pub fn load_textures(&mut self, directory: &Path) -> Result<(), ConfigError> {
+        fn load(
+            path: &mut Option<PathBuf>,
+            directory: &Path,
+        ) -> Result<Option<Arc<RgbImage>>, ConfigError> {
+            let resolved = match path {
+                Some(path) => directory.join(path),
+                None => return Ok(None),
+            };
+            let image =
+                image::open(&resolved).map_err(|e| ConfigError::Image(resolved.clone(), e))?;
+            *path = Some(resolved);
+            Ok(Some(Arc::new(image.to_rgb8())))
+        }
+        self.texture_image = load(&mut self.texture, directory)?;
+        self.normal_map_image = load(&mut self.normal_map, directory)?;
+        Ok(())
+    }
+
+    /// Store the image paths relative to `directory` when possible, e.g.
+    /// before saving a file there
+    pub(crate) fn relative_paths(&mut self, directory: &Path) {
+        for path in self.texture.iter_mut().chain(self.normal_map.iter_mut()) {
+            *path = relative_to(path, directory);
+        }
+    }
+
+    /// Material shading the renders, without textures until `load_textures`
+    pub fn build(&self) -> Arc<dyn Material> {
+        let base = self.build_base();
+        match &self.normal_map_image {
+            Some(image) => Arc::new(NormalMapped {
+                base,
+                normal_map: Texture::data(image.clone()).with_uv_set(self.normal_map_uv_set),
+                strength: self.normal_strength as f64,
+            }),
+            None => base,
+        }
+    }
+
+    fn build_base(&self) -> Arc<dyn Material> {
+        let texture = self
+            .texture_image
+            .clone()
+            .map(|image| Texture::new(image).with_uv_set(self.texture_uv_set));
+        match self.model {
+            ShadingModel::Lambert => Arc::new(Lambertian {
+                albedo: self.color,
+                texture,
+            }),
+            ShadingModel::BlinnPhong => Arc::new(BlinnPhong {
+                albedo: self.color,
+                texture,
+                specular: self.specular,
+                shininess: self.shininess as f64,
+            }),
+            ShadingModel::Pbr => Arc::new(MetallicRoughness {
+                base_color: self.color,
+                texture,
+                metallic: self.metallic.clamp(0.0, 1.0) as f64,
+                roughness: self.roughness.clamp(0.0, 1.0) as f64,
+            }),
+            ShadingModel::Mirror => Arc::new(Mirror {
+                color: self.color,
+                texture,
+            }),
+            ShadingModel::Glass => Arc::new(Glass {
+                color: self.color,
+                ior: self.ior as f64,
+            }),
+        }
+    }
+}
+
+/// Light source of the scene, as stored on disk
+///
+/// The tracers shade the lights built from these with `build`, with shadows;
+/// renders without lights are lit from the camera, see
+/// `RenderingConfig::headlight`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LightConfig {
+    /// Light emitted uniformly in all directions from a point
+    Point {
+        position: Position,
+        intensity: f64,
+        #[serde(default)]
+        units: LightUnits,
+        #[serde(default = "white", skip_serializing_if = "is_white")]
+        color: Color,
+    },
+    /// Parallel light from infinitely far away, e.g. the sun
+    Directional {
+        /// Direction in which the light travels
+        direction: Direction,
+        /// Illuminance on a surface facing the light, in lux
+        illuminance: f64,
+        #[serde(default = "white", skip_serializing_if = "is_white")]
+        color: Color,
+    },
+    /// Light emitted in a cone, see `light::SpotLight`
+    Spot {
+        position: Position,
+        direction: Direction,
+        intensity: f64,
+        /// Ignored with an IES profile, which is scaled by `intensity`
+        #[serde(default)]
+        units: LightUnits,
+        /// Half angle of the cone, in degrees
+        cone_angle: f64,
+        /// Width of the transition outside of the cone, in degrees
+        #[serde(default)]
+        falloff_angle: f64,
+        /// IES photometric profile, relative to the scene file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ies: Option<PathBuf>,
+        #[serde(default = "white", skip_serializing_if = "is_white")]
+        color: Color,
+    },
+}
+
+/// Equirectangular image surrounding the scene, see `environment::Environment`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentConfig {
+    /// Radiance `.hdr` file or sRGB image, relative to the scene file
+    pub path: PathBuf,
+    /// Factor applied to the pixel values
+    #[serde(default = "one")]
+    pub intensity: f64,
+    /// Rotation around the vertical axis, in degrees
+    #[serde(default)]
+    pub rotation: f64,
+    /// Number of directional lights lighting the scene with it, shared
+    /// among the portals of the scene if it has any
+    #[serde(default = "default_environment_lights")]
+    pub lights: u32,
+    /// The loaded image, see `load`
+    #[serde(skip)]
+    pub image: Option<Arc<HdrImage>>,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn default_environment_lights() -> u32 {
+    64
+}
+
+impl EnvironmentConfig {
+    /// Load the image, relative to `directory`
+    pub fn load(&mut self, directory: &Path) -> Result<(), ConfigError> {
+        let path = directory.join(&self.path);
+        let image = HdrImage::load(&path).map_err(|e| ConfigError::Image(path.clone(), e))?;
+        self.image = Some(Arc::new(image));
+        self.path = path;
+        Ok(())
+    }
+
+    /// Environment of the renders, `None` until the image is loaded
+    pub fn build(&self) -> Option<Environment> {
+        let image = self.image.clone()?;
+        Some(Environment::new(image, self.intensity, self.rotation))
+    }
+}
+
+/// Relief added to the model of an object from a height map when the scene
+/// is loaded, see `Mesh::displace`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DisplacementConfig {
+    /// Grayscale image read through the first UV set of the model, relative
+    /// to the scene file
+    pub path: PathBuf,
+    /// Distance between black and white, in the units of the model
+    #[serde(default = "one")]
+    pub scale: f64,
+    /// Height that stays in place, black by default
+    #[serde(default)]
+    pub midlevel: f64,
+    /// Times the triangles are split in 4 first, for the vertices to follow
+    /// the details of the image
+    #[serde(default)]
+    pub subdivisions: u32,
+    /// The loaded image, see `load`
+    #[serde(skip)]
+    pub image: Option<Arc<RgbImage>>,
+}
+
+impl DisplacementConfig {
+    /// Load the image, relative to `directory`
+    pub fn load(&mut self, directory: &Path) -> Result<(), ConfigError> {
+        let path = directory.join(&self.path);
+        let image = image::open(&path).map_err(|e| ConfigError::Image(path.clone(), e))?;
+        self.image = Some(Arc::new(image.to_rgb8()));
+        self.path = path;
+        Ok(())
+    }
+
+    /// Subdivide and displace `mesh`, failing when it has no texture
+    /// coordinates or would have too many triangles; does nothing until the
+    /// image is loaded
+    pub fn apply(&self, mesh: &mut Mesh) -> Result<(), String> {
+        let image = match &self.image {
+            Some(image) => image,
+            None => return Ok(()),
+        };
+        let triangles = 4usize
+            .checked_pow(self.subdivisions)
+            .and_then(|factor| mesh.triangles.len().checked_mul(factor))
+            .unwrap_or(usize::MAX);
+        LoadLimits::default()
+            .check_triangles(triangles)
+            .map_err(|e| format!("{} subdivisions: {}", self.subdivisions, e))?;
+        for _ in 0..self.subdivisions {
+            mesh.subdivide();
+        }
+        mesh.displace(&Texture::data(image.clone()), self.scale, self.midlevel)
+    }
+}
+
+/// Landscape built from a grayscale height map instead of a model file, see
+/// `Mesh::terrain`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainConfig {
+    /// Distance between the pixels of the height map
+    #[serde(default = "one")]
+    pub spacing: f64,
+    /// Elevation of white, black being at 0
+    #[serde(default = "one")]
+    pub height: f64,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig {
+            spacing: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+impl TerrainConfig {
+    /// Build the grid of the height map at `path`
+    pub fn load(&self, path: &Path) -> Result<Mesh, ConfigError> {
+        let image = image::open(path).map_err(|e| ConfigError::Image(path.to_path_buf(), e))?;
+        Mesh::terrain(&image.to_luma16(), self.spacing, self.height)
+            .map_err(|e| ConfigError::Mesh(path.to_path_buf(), e))
+    }
+}
+
+/// Model of an object built from a few numbers rather than read from a file
+///
+/// Spelled `{ "kind": "box", "min": [-1, 0, -1], "max": [1, 2, 1] }` in scene
+/// files, the transform of the object turning and moving it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ShapeConfig {
+    /// Box between the corners `min` and `max`, its sides facing out, or in
+    /// with `inside`, e.g. for the walls of a room
+    Box {
+        min: Position,
+        max: Position,
+        #[serde(default, skip_serializing_if = "is_false")]
+        inside: bool,
+    },
+}
+
+impl ShapeConfig {
+    pub fn build(&self) -> Mesh {
+        match *self {
+            ShapeConfig::Box { min, max, inside } => Mesh::cuboid(
+                &AxisAlignedBoundingBox::from_bounds([min.inf(&max), min.sup(&max)]),
+                inside,
+            ),
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Lights are white unless they give a color
+fn white() -> Color {
+    Color::WHITE
+}
+
+fn is_white(color: &Color) -> bool {
+    *color == Color::WHITE
+}
+
+impl LightConfig {
+    /// Light source shading the renders
+    ///
+    /// Intensities are converted to candela, fluxes (lumens, watts) being
+    /// spread over the directions lit by the light: the whole sphere for
+    /// points, the cone up to the middle of the falloff for spots. IES
+    /// profiles are loaded from their (already resolved) path.
+    pub fn build(&self) -> Result<Arc<dyn Light>, ConfigError> {
+        Ok(match self {
+            LightConfig::Point {
+                position,
+                intensity,
+                units,
+                color,
+            } => Arc::new(PointLight {
+                position: *position,
+                intensity: units.to_candela(*intensity, cone_solid_angle(180.0)),
+                color: *color,
+            }),
+            LightConfig::Directional {
+                direction,
+                illuminance,
+                color,
+            } => Arc::new(DirectionalLight {
+                direction: direction.normalize(),
+                illuminance: *illuminance,
+                color: *color,
+            }),
+            LightConfig::Spot {
+                position,
+                direction,
+                intensity,
+                units,
+                cone_angle,
+                falloff_angle,
+                ies,
+                color,
+            } => {
+                let profile = match ies {
+                    Some(path) => Some(
+                        IesProfile::load(path).map_err(|e| ConfigError::Mesh(path.clone(), e))?,
+                    ),
+                    None => None,
+                };
+                let intensity = match profile {
+                    Some(_) => *intensity,
+                    None => units.to_candela(
+                        *intensity,
+                        cone_solid_angle(cone_angle + falloff_angle / 2.0),
+                    ),
+                };
+                Arc::new(SpotLight {
+                    position: *position,
+                    direction: direction.normalize(),
+                    intensity,
+                    color: *color,
+                    cone_angle: *cone_angle,
+                    falloff_angle: *falloff_angle,
+                    profile,
+                })
+            }
+        })
+    }
+}
+
+/// Everything needed to reproduce a render, as stored on disk
+///
+/// Missing fields take their default value, so a file only needs to list
+/// what differs from the defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfigFile {
+    pub version: u32,
+    pub camera: CameraConfig,
+    pub rendering: RenderingConfig,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile {
+            version: CONFIG_VERSION,
+            camera: Default::default(),
+            rendering: Default::default(),
+        }
+    }
+}
+
+/// This defines the errors that can occure when reading a configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    /// The file was written by a more recent version
+    UnsupportedVersion(u32),
+    /// A model or light profile referenced by a scene could not be loaded
+    Mesh(PathBuf, LoadError),
+    /// A texture referenced by a material could not be loaded
+    Image(PathBuf, ImageError),
+    /// The content is well formed but does not make sense
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Json(e) => write!(f, "invalid configuration: {}", e),
+            ConfigError::Toml(e) => write!(f, "invalid configuration: {}", e),
+            ConfigError::UnsupportedVersion(version) => write!(
+                f,
+                "configuration version {} is not supported, expected at most {}",
+                version, CONFIG_VERSION
+            ),
+            ConfigError::Mesh(path, e) => write!(f, "could not load {}: {}", path.display(), e),
+            ConfigError::Image(path, e) => write!(f, "could not load {}: {}", path.display(), e),
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// Parse a file of the versioned schema, e.g. a configuration or a scene
+pub(crate) fn from_versioned_json<T: DeserializeOwned>(json: &str) -> Result<T, ConfigError> {
+    from_versioned_value(serde_json::from_str(json).map_err(ConfigError::Json)?)
+}
+
+/// Parse a file of the versioned schema written in TOML, the same fields as
+/// in JSON
+pub(crate) fn from_versioned_toml<T: DeserializeOwned>(toml: &str) -> Result<T, ConfigError> {
+    from_versioned_value(toml::from_str(toml).map_err(ConfigError::Toml)?)
+}
+
+/// Read a file of the versioned schema, in TOML when its extension is
+/// `.toml` and in JSON otherwise
+pub(crate) fn load_versioned<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("toml") => from_versioned_toml(&text),
+        _ => from_versioned_json(&text),
+    }
+}
+
+fn from_versioned_value<T: DeserializeOwned>(
+    mut value: serde_json::Value,
+) -> Result<T, ConfigError> {
+    // Check the version before the layout, which may have changed. Only
+    // version 1 files were written without one.
+    let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(1);
+    if version > CONFIG_VERSION as u64 {
+        return Err(ConfigError::UnsupportedVersion(version as u32));
+    }
+    if version < 2 {
+        if let Some(camera) = value.get_mut("camera").and_then(|c| c.as_object_mut()) {
+            upgrade_camera_v1(camera)?;
+        }
+        value["version"] = CONFIG_VERSION.into();
+    }
+    serde_json::from_value(value).map_err(ConfigError::Json)
+}
+
+/// Convert a camera of a version 1 file to the current `fov`, in place
+///
+/// Version 1 spread the image plane over `tan(fov)` horizontally, `fov` being
+/// in radians (60 by default), and over that divided by `aspect_ratio` (4/3
+/// by default) vertically. The vertical extent is kept; pixels are now
+/// square, so the horizontal one changes when `aspect_ratio` was not the
+/// aspect of the image.
+fn upgrade_camera_v1(
+    camera: &mut serde_json::Map<String, serde_json::Value>,
+) -> Result<(), ConfigError> {
+    let number = |name: &str, default: f64| match camera.get(name) {
+        Some(value) => value
+            .as_f64()
+            .ok_or_else(|| ConfigError::Invalid(format!("camera {} is not a number", name))),
+        None => Ok(default),
+    };
+    let fov = number("fov", 60.0)?;
+    let aspect_ratio = number("aspect_ratio", 4.0 / 3.0)?;
+    let default = CameraConfig::default();
+    let width = number("width", default.width as f64)?;
+    let height = number("height", default.height as f64)?;
+    if (aspect_ratio - width / height.max(1.0)).abs() > 1e-3 {
+        tracing::warn!(
+            "version 1 camera aspect ratio {} differs from the {}x{} image, its pixels are now square",
+            aspect_ratio,
+            width,
+            height
+        );
+    }
+    let vertical = 2.0 * (fov.tan().abs() / aspect_ratio / 2.0).atan().to_degrees();
+    camera.insert("fov".to_string(), vertical.into());
+    camera.remove("aspect_ratio");
+    Ok(())
+}
+
+pub(crate) fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("configurations are always serializable")
+}
+
+impl ConfigFile {
+    pub fn from_json(json: &str) -> Result<ConfigFile, ConfigError> {
+        from_versioned_json(json)
+    }
+
+    pub fn to_json(&self) -> String {
+        to_json(self)
+    }
+
+    /// Load a configuration file, JSON or TOML, and the textures of its
+    /// material
+    pub fn load(path: &Path) -> Result<ConfigFile, ConfigError> {
+        let mut config_file: ConfigFile = load_versioned(path)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        config_file.rendering.material.load_textures(directory)?;
+        Ok(config_file)
+    }
+
+    /// Save the configuration file, with the image paths relative to it when
+    /// possible
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut config_file = self.clone();
+        config_file.rendering.material.relative_paths(directory);
+        fs::write(path, config_file.to_json() + "\n").map_err(ConfigError::Io)
+    }
+}
+
+/// `path` relative to `directory` when it is inside, absolute otherwise
+pub(crate) fn relative_to(path: &Path, directory: &Path) -> PathBuf {
+    let directory = if directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        directory
+    };
+    match (path.canonicalize(), directory.canonicalize()) {
+        (Ok(path), Ok(directory)) => match path.strip_prefix(&directory) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        },
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_mode_names_round_trip() {
+        for &mode in NormalMode::ALL.iter() {
+            assert_eq!(mode.to_string().parse::<NormalMode>(), Ok(mode));
+            let json = serde_json::to_string(&mode).unwrap();
+            assert_eq!(json, format!("\"{}\"", mode));
+            assert_eq!(serde_json::from_str::<NormalMode>(&json).unwrap(), mode);
+        }
+        assert!("Phong".parse::<NormalMode>().is_err());
+    }
+
+    #[test]
+    fn framing_fits_the_box_in_the_image() {
+        let bounds = AxisAlignedBoundingBox::from_bounds([
+            Position::new(1.0, -2.0, 0.0),
+            Position::new(3.0, 2.0, 1.0),
+        ]);
+        let camera = CameraConfig::framing(
+            &bounds,
+            Direction::new(1.0, 1.0, -1.0),
+            Direction::z(),
+            40.0,
+            160,
+            90,
+        );
+        assert!((camera.z - Direction::new(1.0, 1.0, -1.0).normalize()).norm() < 1e-12);
+        let mut extent: f64 = 0.0;
+        for corner in 0..8 {
+            let point = Position::new(
+                bounds.bounds[corner & 1].x,
+                bounds.bounds[(corner >> 1) & 1].y,
+                bounds.bounds[corner >> 2].z,
+            );
+            let (i, j) = crate::render::image::project(&point, &camera).unwrap();
+            // Within the image, from -0.5 to the size - 0.5
+            assert!(i > -0.5 - 1e-9 && i < 159.5 + 1e-9, "{}", i);
+            assert!(j > -0.5 - 1e-9 && j < 89.5 + 1e-9, "{}", j);
+            extent = extent
+                .max((i - 79.5).abs() / 80.0)
+                .max((j - 44.5).abs() / 45.0);
+        }
+        // Touching an edge
+        assert!((extent - 1.0).abs() < 1e-9, "{}", extent);
+        let center = camera.camera_position + camera.z * camera.focus_distance;
+        assert!((center - bounds.center).norm() < 1e-9);
+    }
+
+    #[test]
+    fn look_at_builds_an_orthonormal_basis() {
+        let eye = Position::new(1.0, 2.0, 3.0);
+        let camera = CameraConfig::look_at(eye, Position::origin(), Direction::y(), 45.0, 200, 100);
+        assert_eq!(camera.camera_position, eye);
+        assert!((camera.z + eye.coords.normalize()).norm() < 1e-12);
+        assert_eq!(camera.aspect_ratio(), 2.0);
+        // The same as the rotation the binaries used to build by hand
+        let rot = nalgebra::Rotation3::face_towards(&-eye.coords, &Direction::y());
+        assert!((camera.x - rot * Direction::x()).norm() < 1e-12);
+        assert!((camera.y - rot * Direction::y()).norm() < 1e-12);
+
+        // Looking straight up, or at itself
+        for target in [Position::new(1.0, 5.0, 3.0), eye] {
+            let camera = CameraConfig::look_at(eye, target, Direction::y(), 45.0, 200, 100);
+            let basis = nalgebra::Matrix3::from_columns(&[camera.x, camera.y, camera.z]);
+            assert!((basis.transpose() * basis - nalgebra::Matrix3::identity()).norm() < 1e-12);
+            assert!(basis.determinant() > 0.0);
+        }
+    }
+
+    #[test]
+    fn from_matrix_matches_look_at() {
+        let eye = Position::new(1.0, 2.0, 3.0);
+        let target = Position::new(0.0, 0.5, -1.0);
+        let expected = CameraConfig::look_at(eye, target, Direction::y(), 45.0, 200, 100);
+        let close = |camera: &CameraConfig| {
+            (camera.camera_position - expected.camera_position).norm() < 1e-12
+                && (camera.x - expected.x).norm() < 1e-12
+                && (camera.y - expected.y).norm() < 1e-12
+                && (camera.z - expected.z).norm() < 1e-12
+        };
+
+        let view = na::Isometry3::look_at_rh(&eye, &target, &Direction::y()).to_homogeneous();
+        let camera =
+            CameraConfig::from_matrix(&view, CameraConvention::OpenGl, 45.0, 200, 100).unwrap();
+        assert!(close(&camera), "{:?}", camera);
+        // Scales are dropped
+        let scaled = na::Matrix4::from_diagonal(&na::Vector4::new(3.0, 3.0, 3.0, 1.0)) * view;
+        let camera =
+            CameraConfig::from_matrix(&scaled, CameraConvention::OpenGl, 45.0, 200, 100).unwrap();
+        assert!(close(&camera), "{:?}", camera);
+        // OpenCV flips y and z
+        let flip = na::Matrix4::from_diagonal(&na::Vector4::new(1.0, -1.0, -1.0, 1.0));
+        let camera =
+            CameraConfig::from_matrix(&(flip * view), CameraConvention::OpenCv, 45.0, 200, 100)
+                .unwrap();
+        assert!(close(&camera), "{:?}", camera);
+
+        assert!(CameraConfig::from_matrix(
+            &na::Matrix4::zeros(),
+            CameraConvention::OpenGl,
+            45.0,
+            200,
+            100
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn builder_defaults_and_look_at() {
+        assert_eq!(CameraConfig::builder().build(), CameraConfig::default());
+
+        let eye = Position::new(1.0, 2.0, 3.0);
+        let camera = CameraConfig::builder()
+            .look_at(Position::origin(), Direction::y())
+            .position(eye)
+            .fov(45.0)
+            .size(200, 100)
+            .aperture(0.5)
+            .build();
+        let expected =
+            CameraConfig::look_at(eye, Position::origin(), Direction::y(), 45.0, 200, 100);
+        assert_eq!(camera.camera_position, expected.camera_position);
+        assert_eq!(camera.z, expected.z);
+        assert_eq!((camera.width, camera.height), (200, 100));
+        assert_eq!(camera.aperture, 0.5);
+        assert!((camera.focus_distance - eye.coords.norm()).abs() < 1e-12);
+
+        let camera = CameraConfig::builder().focus_distance(2.0).build();
+        assert_eq!(camera.focus_distance, 2.0);
+    }
+
+    #[test]
+    fn config_file_defaults_and_round_trip() {
+        let config = ConfigFile::from_json(
+            r#"{"version": 2, "camera": {"camera_position": [0.0, 0.5, -10.0], "fov": 45.0}, "rendering": {"termination": {"kind": "fixed"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.camera.fov, 45.0);
+        assert_eq!(
+            config.camera.camera_position,
+            Position::new(0.0, 0.5, -10.0)
+        );
+        assert_eq!(config.camera.width, CameraConfig::default().width);
+        assert!(config.rendering.is_deterministic());
+
+        assert_eq!(ConfigFile::from_json(&config.to_json()).unwrap(), config);
+        match ConfigFile::from_json(r#"{"version": 99}"#) {
+            Err(ConfigError::UnsupportedVersion(99)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn version_1_cameras_keep_their_framing() {
+        let config = ConfigFile::from_json(
+            r#"{"version": 1, "camera": {"fov": 0.5, "aspect_ratio": 2.0, "width": 200, "height": 100}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        // The image plane was tan(0.5) wide and half of it high
+        let height = 2.0 * (config.camera.fov.to_radians() / 2.0).tan();
+        assert!((height - 0.5f64.tan() / 2.0).abs() < 1e-12);
+        let width = height * config.camera.aspect_ratio();
+        assert!((width - 0.5f64.tan()).abs() < 1e-12);
+
+        // The defaults of version 1, 400x300 pixels over tan(60)
+        let config = ConfigFile::from_json(r#"{"version": 1, "camera": {}}"#).unwrap();
+        let height = 2.0 * (config.camera.fov.to_radians() / 2.0).tan();
+        assert!((height - 60f64.tan() * 0.75).abs() < 1e-12);
+        assert!(matches!(
+            ConfigFile::from_json(r#"{"version": 1, "camera": {"fov": "wide"}}"#),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn files_without_version_are_version_1() {
+        let config = ConfigFile::from_json(r#"{"camera": {"fov": 0.5}}"#).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        // 0.5 radians over the default aspect ratio of version 1, not degrees
+        let height = 2.0 * (config.camera.fov.to_radians() / 2.0).tan();
+        assert!((height - 0.5f64.tan() * 0.75).abs() < 1e-12);
+        assert_eq!(
+            ConfigFile::from_json(r#"{"version": 1, "camera": {"fov": 0.5}}"#).unwrap(),
+            config
+        );
+    }
 }
@@ -0,0 +1,258 @@
+extern crate image;
+extern crate rayon;
+
+use std::fmt;
+use std::str::FromStr;
+
+use self::image::Rgb;
+use self::rayon::prelude::*;
+use crate::render::image::{Aovs, HdrRgbImage};
+
+/// Filter removing the noise of renders with few samples per pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Denoiser {
+    /// Edge-avoiding à-trous wavelet filter guided by the normal and depth
+    /// passes, see `atrous`
+    Atrous,
+    /// Intel Open Image Denoise, only available with the `oidn` feature
+    Oidn,
+}
+
+impl Denoiser {
+    pub const ALL: [Denoiser; 2] = [Denoiser::Atrous, Denoiser::Oidn];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Denoiser::Atrous => "atrous",
+            Denoiser::Oidn => "oidn",
+        }
+    }
+}
+
+impl fmt::Display for Denoiser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Denoiser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Denoiser::ALL
+            .iter()
+            .cloned()
+            .find(|denoiser| denoiser.name() == s)
+            .ok_or_else(|| format!("unknown denoiser {}, expected atrous or oidn", s))
+    }
+}
+
+/// Settings of the à-trous filter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtrousConfig {
+    /// Passes of the filter, each one twice as wide as the previous one: 5
+    /// passes blur over 61 pixels
+    pub iterations: u32,
+    /// Difference of color, after dividing out the albedo, that halves the
+    /// weight of a neighbor; halved at every pass
+    pub sigma_color: f32,
+    /// Distance between unit normals that halves the weight of a neighbor
+    pub sigma_normal: f32,
+    /// Relative difference of depth, per pixel of distance, that halves the
+    /// weight of a neighbor
+    pub sigma_depth: f32,
+}
+
+impl Default for AtrousConfig {
+    fn default() -> Self {
+        AtrousConfig {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_normal: 0.2,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+/// Denoise the color of a render with its auxiliary passes
+///
+/// Fails when the denoiser is not available in this build, or fails itself.
+pub fn denoise(aovs: &Aovs, denoiser: Denoiser) -> Result<HdrRgbImage, String> {
+    let _span = tracing::info_span!("denoise", denoiser = denoiser.name()).entered();
+    match denoiser {
+        Denoiser::Atrous => Ok(atrous(aovs, &AtrousConfig::default())),
+        Denoiser::Oidn => open_image_denoise(aovs),
+    }
+}
+
+/// Edge-avoiding à-trous wavelet filter: repeated 5x5 B-spline blurs whose
+/// taps spread further apart at every pass, weighted down across edges of
+/// the color, normal and depth passes
+///
+/// The texture detail is kept by filtering the color divided by the albedo,
+/// multiplied back at the end.
+///
+/// # Reference
+/// * Dammertz et al., Edge-Avoiding À-Trous Wavelet Transform for fast Global
+///   Illumination Filtering, High Performance Graphics (2010)
+pub fn atrous(aovs: &Aovs, config: &AtrousConfig) -> HdrRgbImage {
+    const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+    let width = aovs.color.width() as usize;
+    let height = aovs.color.height() as usize;
+    let albedo: Vec<[f32; 3]> = aovs.albedo.pixels().map(|p| demodulation(p.0)).collect();
+    let normal: Vec<[f32; 3]> = aovs.normal.pixels().map(|p| p.0).collect();
+    let depth = &aovs.depth;
+    let mut color: Vec<[f32; 3]> = aovs
+        .color
+        .pixels()
+        .zip(&albedo)
+        .map(|(c, a)| [c[0] / a[0], c[1] / a[1], c[2] / a[2]])
+        .collect();
+
+    for iteration in 0..config.iterations {
+        let step = 1 << iteration;
+        let sigma_color = config.sigma_color / step as f32;
+        let filtered: Vec<Vec<[f32; 3]>> = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        let p = y * width + x;
+                        let mut sum = [0.0; 3];
+                        let mut total_weight = 0.0;
+                        for (j, kj) in KERNEL.iter().enumerate() {
+                            let qy = y as isize + (j as isize - 2) * step;
+                            if qy < 0 || qy >= height as isize {
+                                continue;
+                            }
+                            for (i, ki) in KERNEL.iter().enumerate() {
+                                let qx = x as isize + (i as isize - 2) * step;
+                                if qx < 0 || qx >= width as isize {
+                                    continue;
+                                }
+                                let q = qy as usize * width + qx as usize;
+                                let weight = ki
+                                    * kj
+                                    * gaussian(distance_squared(color[p], color[q]), sigma_color)
+                                    * gaussian(
+                                        distance_squared(normal[p], normal[q]),
+                                        config.sigma_normal,
+                                    )
+                                    * depth_weight(
+                                        depth[p],
+                                        depth[q],
+                                        config.sigma_depth * step as f32,
+                                    );
+                                for c in 0..3 {
+                                    sum[c] += weight * color[q][c];
+                                }
+                                total_weight += weight;
+                            }
+                        }
+                        // The center tap always has a weight of at least 9/64
+                        sum.map(|s| s / total_weight)
+                    })
+                    .collect()
+            })
+            .collect();
+        color = filtered.into_iter().flatten().collect();
+    }
+
+    HdrRgbImage::from_fn(width as u32, height as u32, |x, y| {
+        let p = y as usize * width + x as usize;
+        Rgb([
+            color[p][0] * albedo[p][0],
+            color[p][1] * albedo[p][1],
+            color[p][2] * albedo[p][2],
+        ])
+    })
+}
+
+/// Albedo the color is divided by before filtering, 1 where it is too dark
+/// to tell the lighting apart, e.g. without hit
+fn demodulation(albedo: [f32; 3]) -> [f32; 3] {
+    albedo.map(|a| if a > 1e-3 { a } else { 1.0 })
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+/// Weight of a neighbor at `distance_squared`, 1/2 at `sigma`
+fn gaussian(distance_squared: f32, sigma: f32) -> f32 {
+    (-std::f32::consts::LN_2 * distance_squared / (sigma * sigma)).exp()
+}
+
+/// Weight of a neighbor by the relative difference of depth; pixels without
+/// hit only mix with each other
+fn depth_weight(p: f64, q: f64, sigma: f32) -> f32 {
+    match (p.is_finite(), q.is_finite()) {
+        (true, true) => gaussian(((p - q) / p.max(1e-9)).powi(2) as f32, sigma),
+        (false, false) => 1.0,
+        _ => 0.0,
+    }
+}
+
+#[cfg(feature = "oidn")]
+fn open_image_denoise(aovs: &Aovs) -> Result<HdrRgbImage, String> {
+    let width = aovs.color.width();
+    let height = aovs.color.height();
+    let device = oidn::Device::new().map_err(|e| e.to_string())?;
+    let mut output = vec![0.0; aovs.color.as_raw().len()];
+    oidn::RayTracing::try_new(&device)
+        .map_err(|e| e.to_string())?
+        .hdr(true)
+        .image_dimensions(width as usize, height as usize)
+        .albedo_normal(aovs.albedo.as_raw(), aovs.normal.as_raw())
+        .filter(aovs.color.as_raw(), &mut output)
+        .map_err(|e| e.to_string())?;
+    Ok(HdrRgbImage::from_raw(width, height, output).expect("the output has the size of the input"))
+}
+
+#[cfg(not(feature = "oidn"))]
+fn open_image_denoise(_aovs: &Aovs) -> Result<HdrRgbImage, String> {
+    Err("Open Image Denoise is not available, build with `--features oidn`".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::rng::SampleRng;
+    use rand::Rng;
+
+    #[test]
+    fn atrous_removes_the_noise_and_keeps_the_edges() {
+        // Two walls meeting in the middle, lit differently, with noise
+        let (width, height) = (32, 16);
+        let expected = |x: u32| if x < width / 2 { 0.2 } else { 0.8 };
+        let color = HdrRgbImage::from_fn(width, height, |x, y| {
+            let noise = SampleRng::for_pixel(7, x, y, 0).gen::<f32>() - 0.5;
+            Rgb([expected(x) + noise * 0.2; 3])
+        });
+        let normal = HdrRgbImage::from_fn(width, height, |x, _| {
+            Rgb(if x < width / 2 {
+                [1.0, 0.0, 0.0]
+            } else {
+                [0.0, 0.0, 1.0]
+            })
+        });
+        let aovs = Aovs {
+            color,
+            depth: vec![5.0; (width * height) as usize],
+            normal,
+            albedo: HdrRgbImage::from_pixel(width, height, Rgb([1.0; 3])),
+        };
+
+        let error = |img: &HdrRgbImage| {
+            img.enumerate_pixels()
+                .map(|(x, _, p)| (p[0] - expected(x)).abs())
+                .fold(0.0f32, f32::max)
+        };
+        let denoised = atrous(&aovs, &AtrousConfig::default());
+        assert!(error(&aovs.color) > 0.08);
+        assert!(error(&denoised) < 0.03, "{}", error(&denoised));
+        assert!("oidn".parse::<Denoiser>() == Ok(Denoiser::Oidn));
+        assert!("nlm".parse::<Denoiser>().is_err());
+    }
+}
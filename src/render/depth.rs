@@ -0,0 +1,76 @@
+extern crate image;
+
+use self::image::{GrayImage, Luma};
+
+/// Darkest gray level used for hits, so that the background (black) remains
+/// distinguishable from far geometry
+const FAR_LEVEL: f64 = 32.0;
+
+/// How hit distances are mapped to gray levels, the closest being white
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthMapping {
+    /// Linear between the given distances, clamped outside
+    Linear { near: f64, far: f64 },
+    /// Linear between the closest and the farthest hit of the image
+    Auto,
+    /// Histogram equalization: every gray level is used by roughly the same
+    /// number of pixels, which keeps details visible when most of the depths
+    /// are concentrated in a small range
+    HistogramEqualized,
+}
+
+/// Turn a depth buffer (row major, top row first) into an 8-bit preview
+///
+/// Pixels without hit (infinite or NaN depth) are black.
+pub fn depth_to_image(depth: &[f64], width: u32, height: u32, mapping: DepthMapping) -> GrayImage {
+    assert_eq!(depth.len(), (width * height) as usize);
+    let hits = || depth.iter().cloned().filter(|d| d.is_finite());
+    let min = hits().fold(f64::INFINITY, f64::min);
+    let max = hits().fold(f64::NEG_INFINITY, f64::max);
+
+    // Maps a finite depth to [0, 1], 0 being the closest
+    let normalized: Box<dyn Fn(f64) -> f64> = match mapping {
+        DepthMapping::Linear { near, far } => {
+            let range = (far - near).max(f64::MIN_POSITIVE);
+            Box::new(move |d| ((d - near) / range).clamp(0.0, 1.0))
+        }
+        DepthMapping::Auto => {
+            let range = (max - min).max(f64::MIN_POSITIVE);
+            Box::new(move |d| (d - min) / range)
+        }
+        DepthMapping::HistogramEqualized => {
+            // Exact cumulative distribution: the rank of a depth among the hits
+            let mut sorted: Vec<f64> = hits().collect();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let span = (sorted.len().max(2) - 1) as f64;
+            Box::new(move |d| sorted.partition_point(|&s| s < d) as f64 / span)
+        }
+    };
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let d = depth[(y * width + x) as usize];
+        if !d.is_finite() {
+            return Luma([0]);
+        }
+        Luma([(255.0 - normalized(d) * (255.0 - FAR_LEVEL)).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equalization_spreads_clustered_depths() {
+        // Most of the pixels are packed in a thin slab, one is far away
+        let depth = [1.0, 1.001, 1.002, 1.003, 100.0, f64::INFINITY];
+        let auto = depth_to_image(&depth, 6, 1, DepthMapping::Auto);
+        let equalized = depth_to_image(&depth, 6, 1, DepthMapping::HistogramEqualized);
+
+        // Linear mapping crushes the slab in a couple of gray levels
+        assert!(auto.get_pixel(0, 0)[0] - auto.get_pixel(3, 0)[0] <= 1);
+        assert!(equalized.get_pixel(0, 0)[0] - equalized.get_pixel(3, 0)[0] > 100);
+        assert_eq!(equalized.get_pixel(4, 0)[0], FAR_LEVEL as u8);
+        assert_eq!(equalized.get_pixel(5, 0)[0], 0);
+    }
+}
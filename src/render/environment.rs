@@ -0,0 +1,577 @@
+extern crate image;
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use self::image::codecs::hdr::HdrDecoder;
+use self::image::{ImageError, ImageResult};
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::import::Unit;
+use crate::geometry::types::{Direction, Position};
+use crate::render::color::Color;
+use crate::render::light::{DirectionalLight, Light, LightPortal, LightSample};
+
+/// Image of linear colors, rows top first
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    /// Load a Radiance `.hdr` file, or decode any other image as sRGB
+    pub fn load(path: &Path) -> ImageResult<HdrImage> {
+        let is_hdr = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            let file = File::open(path).map_err(ImageError::IoError)?;
+            let decoder = HdrDecoder::new(BufReader::new(file))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .iter()
+                .map(|p| Color::from(p.0.map(f64::from)))
+                .collect();
+            return Ok(HdrImage {
+                width: metadata.width,
+                height: metadata.height,
+                pixels,
+            });
+        }
+        let image = image::open(path)?.to_rgb8();
+        Ok(HdrImage {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.pixels().map(|p| Color::from_srgb8(p.0)).collect(),
+        })
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+/// Direction drawn by `Environment::sample`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnvironmentSample {
+    /// Toward the environment, normalized
+    pub direction: Direction,
+    pub radiance: Color,
+    /// Probability density of `direction`, per steradian
+    pub pdf: f64,
+}
+
+/// Light coming from infinitely far away in every direction, given by an
+/// equirectangular (latitude-longitude) image
+///
+/// The top row of the image is straight up (+y), the bottom row straight
+/// down, and its center is seen looking along +z, before `rotation`. Rays
+/// missing the scene see the environment, and hits are lit by it through
+/// `lights`. Directions are drawn in proportion to their brightness, so that
+/// small bright areas such as the sun get most of the samples.
+#[derive(Clone, Debug)]
+pub struct Environment {
+    image: Arc<HdrImage>,
+    /// Factor applied to the pixel values, radiance in candela per square
+    /// meter once multiplied
+    pub intensity: f64,
+    /// Rotation around the vertical axis, in degrees
+    pub rotation: f64,
+    /// Cumulative distribution of the rows, normalized, one more than rows
+    rows: Vec<f64>,
+    /// Cumulative distribution of the pixels in every row, normalized
+    columns: Vec<Vec<f64>>,
+}
+
+/// Normalized running sum of `weights`, starting at 0; uniform when they are
+/// all zero
+fn cumulative(weights: impl Iterator<Item = f64>) -> Vec<f64> {
+    let mut sums = vec![0.0];
+    for weight in weights {
+        sums.push(sums[sums.len() - 1] + weight);
+    }
+    let total = sums[sums.len() - 1];
+    let count = (sums.len() - 1) as f64;
+    for (i, sum) in sums.iter_mut().enumerate() {
+        *sum = if total > 0.0 {
+            *sum / total
+        } else {
+            i as f64 / count
+        };
+    }
+    sums
+}
+
+/// Bin of `cdf` where `u` falls, and where in the bin, in [0, 1)
+fn invert(cdf: &[f64], u: f64) -> (usize, f64) {
+    let bin = (cdf.partition_point(|&c| c <= u).max(1) - 1).min(cdf.len() - 2);
+    let width = cdf[bin + 1] - cdf[bin];
+    let offset = if width > 0.0 {
+        (u - cdf[bin]) / width
+    } else {
+        0.5
+    };
+    (bin, offset.clamp(0.0, 1.0 - 1e-9))
+}
+
+impl Environment {
+    pub fn new(image: Arc<HdrImage>, intensity: f64, rotation: f64) -> Environment {
+        let (width, height) = (image.width, image.height.max(1));
+        // Pixels near the poles cover a smaller solid angle
+        let sin = |y: u32| (PI * (y as f64 + 0.5) / height as f64).sin();
+        let columns: Vec<Vec<f64>> = (0..image.height)
+            .map(|y| cumulative((0..width).map(|x| image.get(x, y).luminance().max(0.0))))
+            .collect();
+        let rows = cumulative((0..image.height).map(|y| {
+            let row_sum: f64 = (0..width)
+                .map(|x| image.get(x, y).luminance().max(0.0))
+                .sum();
+            row_sum * sin(y)
+        }));
+        Environment {
+            image,
+            intensity,
+            rotation,
+            rows,
+            columns,
+        }
+    }
+
+    /// Position in the image, in [0, 1], of a normalized direction
+    fn uv(&self, direction: &Direction) -> [f64; 2] {
+        let phi = direction.x.atan2(direction.z) - self.rotation.to_radians();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+        [u, v]
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Direction {
+        let phi = 2.0 * PI * (u - 0.5) + self.rotation.to_radians();
+        let theta = PI * v;
+        Direction::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        )
+    }
+
+    fn pixel(&self, u: f64, v: f64) -> (u32, u32) {
+        let x = ((u * self.image.width as f64) as u32).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as u32).min(self.image.height - 1);
+        (x, y)
+    }
+
+    /// Radiance coming from `direction`, normalized
+    pub fn radiance(&self, direction: &Direction) -> Color {
+        if self.image.pixels.is_empty() {
+            return Color::BLACK;
+        }
+        let [u, v] = self.uv(direction);
+        let (x, y) = self.pixel(u, v);
+        self.image.get(x, y) * self.intensity
+    }
+
+    /// Draw a direction from two uniform random numbers in [0, 1)
+    pub fn sample(&self, u: f64, v: f64) -> Option<EnvironmentSample> {
+        if self.image.pixels.is_empty() {
+            return None;
+        }
+        let (y, dy) = invert(&self.rows, v);
+        let (x, dx) = invert(&self.columns[y], u);
+        let u = (x as f64 + dx) / self.image.width as f64;
+        let v = (y as f64 + dy) / self.image.height as f64;
+        let direction = self.direction(u, v);
+        let pdf = self.pdf(&direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.radiance(&direction),
+            pdf,
+        })
+    }
+
+    /// Probability density of `sample` drawing `direction`, per steradian
+    pub fn pdf(&self, direction: &Direction) -> f64 {
+        if self.image.pixels.is_empty() {
+            return 0.0;
+        }
+        let [u, v] = self.uv(direction);
+        let (x, y) = self.pixel(u, v);
+        let (x, y) = (x as usize, y as usize);
+        let row = self.rows[y + 1] - self.rows[y];
+        let column = self.columns[y][x + 1] - self.columns[y][x];
+        // Density over the image, then over the sphere
+        let density = row * column * (self.image.width * self.image.height) as f64;
+        let sin = (PI * v).sin();
+        if sin <= 0.0 {
+            return 0.0;
+        }
+        density / (2.0 * PI * PI * sin)
+    }
+
+    /// Directional lights standing for the environment, e.g. for tracers
+    /// which only shade hits with light sources
+    ///
+    /// Directions are drawn by `sample` on a regular grid of about `count`
+    /// points, each light carrying the radiance of its direction divided by
+    /// its density and the number of lights: together they give on average
+    /// the illuminance of the whole environment, without noise from one
+    /// pixel to the next.
+    pub fn lights(&self, count: u32) -> Vec<DirectionalLight> {
+        let side = (count.max(1) as f64).sqrt().ceil() as u32;
+        let total = (side * side) as f64;
+        let mut lights = Vec::new();
+        for i in 0..side {
+            for j in 0..side {
+                let u = (i as f64 + 0.5) / side as f64;
+                let v = (j as f64 + 0.5) / side as f64;
+                if let Some(sample) = self.sample(u, v) {
+                    lights.push(DirectionalLight {
+                        direction: -sample.direction,
+                        illuminance: 1.0,
+                        color: sample.radiance / (sample.pdf * total),
+                    });
+                }
+            }
+        }
+        lights
+    }
+
+    /// Lights standing for the environment seen through `portals`, for
+    /// interiors it only reaches through their windows
+    ///
+    /// The `count` lights are shared among the portals, each one passing
+    /// through a cell of a regular grid over its portal, so that all the
+    /// shadow rays go where light can come from.
+    pub fn portal_lights(
+        self: &Arc<Self>,
+        portals: &[LightPortal],
+        count: u32,
+    ) -> Vec<PortalLight> {
+        let per_portal = count / portals.len().max(1) as u32;
+        let side = (per_portal.max(1) as f64).sqrt().ceil() as u32;
+        let mut lights = Vec::new();
+        for portal in portals {
+            for i in 0..side {
+                for j in 0..side {
+                    lights.push(PortalLight {
+                        environment: Arc::clone(self),
+                        portal: *portal,
+                        u: (i as f64 + 0.5) / side as f64,
+                        v: (j as f64 + 0.5) / side as f64,
+                        cells: (side * side) as f64,
+                    });
+                }
+            }
+        }
+        lights
+    }
+}
+
+/// Environment seen through one cell of a portal, see
+/// `Environment::portal_lights`
+#[derive(Clone, Debug)]
+pub struct PortalLight {
+    environment: Arc<Environment>,
+    portal: LightPortal,
+    /// Center of the cell on the portal, in [0, 1]²
+    u: f64,
+    v: f64,
+    /// Number of cells of the portal
+    cells: f64,
+}
+
+impl Light for PortalLight {
+    fn illuminate(&self, point: &Position, _units: Unit) -> Option<LightSample> {
+        let sample = self.portal.sample(point, self.u, self.v)?;
+        let radiance = self.environment.radiance(&sample.direction);
+        Some(LightSample {
+            direction: sample.direction,
+            // The shadow rays go on through the portal, out of the scene
+            distance: f64::INFINITY,
+            illuminance: radiance / (sample.pdf * self.cells),
+        })
+    }
+}
+
+/// What the rays missing the scene see when it has no `Environment`
+///
+/// Spelled `{ "kind": "black" }`, `{ "kind": "gradient", ... }` or
+/// `{ "kind": "sky", ... }` in configuration files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Background {
+    #[default]
+    Black,
+    /// Blend from `horizon` to `zenith` with the elevation of the rays, and
+    /// `ground` below the horizon
+    Gradient {
+        zenith: Color,
+        horizon: Color,
+        ground: Color,
+    },
+    /// Clear daylight sky of the Preetham model, in candela per square meter
+    /// (a few thousands: an exposure around -13 EV keeps it in range)
+    ///
+    /// # Reference
+    /// * A. J. Preetham, P. Shirley, B. Smits, A Practical Analytic Model for
+    ///   Daylight (SIGGRAPH 1999)
+    Sky {
+        /// Toward the sun, above the horizon
+        sun_direction: Direction,
+        /// Haziness of the air, from 2 (clear) to about 10 (hazy)
+        turbidity: f64,
+    },
+}
+
+/// Distribution of the sky brightness of Perez et al., of the angle between
+/// the zenith and the view `theta`, and the one between the view and the sun
+/// `gamma`
+fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+/// Luminance in kcd/m² (Y) or chromaticity (x, y) of the Preetham sky
+fn preetham(
+    zenith: f64,
+    coefficients: &[f64; 5],
+    cos_theta: f64,
+    gamma: f64,
+    theta_sun: f64,
+) -> f64 {
+    zenith * perez(coefficients, cos_theta, gamma) / perez(coefficients, 1.0, theta_sun)
+}
+
+impl Background {
+    /// Radiance coming from `direction`, normalized
+    pub fn radiance(&self, direction: &Direction) -> Color {
+        match *self {
+            Background::Black => Color::BLACK,
+            Background::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                if direction.y < 0.0 {
+                    return ground;
+                }
+                horizon * (1.0 - direction.y) + zenith * direction.y
+            }
+            Background::Sky {
+                sun_direction,
+                turbidity,
+            } => {
+                let t = turbidity;
+                let sun = sun_direction.normalize();
+                let theta_sun = sun.y.clamp(0.0, 1.0).acos();
+                // The ground reflects the sky just above the horizon
+                let cos_theta = direction.y.max(0.01);
+                let gamma = direction.dot(&sun).clamp(-1.0, 1.0).acos();
+
+                let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+                let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+                let (s1, s2, s3) = (theta_sun, theta_sun.powi(2), theta_sun.powi(3));
+                let zenith_x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+                    + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+                    + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+                let zenith_y_chromaticity = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+                    + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+                    + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+
+                let luminance = [
+                    0.1787 * t - 1.4630,
+                    -0.3554 * t + 0.4275,
+                    -0.0227 * t + 5.3251,
+                    0.1206 * t - 2.5771,
+                    -0.0670 * t + 0.3703,
+                ];
+                let x_coefficients = [
+                    -0.0193 * t - 0.2592,
+                    -0.0665 * t + 0.0008,
+                    -0.0004 * t + 0.2125,
+                    -0.0641 * t - 0.8989,
+                    -0.0033 * t + 0.0452,
+                ];
+                let y_coefficients = [
+                    -0.0167 * t - 0.2608,
+                    -0.0950 * t + 0.0092,
+                    -0.0079 * t + 0.2102,
+                    -0.0441 * t - 1.6537,
+                    -0.0109 * t + 0.0529,
+                ];
+                let big_y = preetham(zenith_y, &luminance, cos_theta, gamma, theta_sun) * 1000.0;
+                let x = preetham(zenith_x, &x_coefficients, cos_theta, gamma, theta_sun);
+                let y = preetham(
+                    zenith_y_chromaticity,
+                    &y_coefficients,
+                    cos_theta,
+                    gamma,
+                    theta_sun,
+                );
+
+                // CIE XYZ to linear sRGB
+                let big_x = x / y * big_y;
+                let big_z = (1.0 - x - y) / y * big_y;
+                Color::new(
+                    3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z,
+                    -0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z,
+                    0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z,
+                )
+                .map(|c| c.max(0.0))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn portal_lights_give_the_illuminance_through_the_window() {
+        let image = Arc::new(HdrImage {
+            width: 4,
+            height: 2,
+            pixels: vec![Color::WHITE; 8],
+        });
+        let environment = Arc::new(Environment::new(image, 1.0, 0.0));
+        // 2 x 2 window one unit above the origin, lighting downward
+        let window = LightPortal {
+            corner: Position::new(-1.0, 1.0, -1.0),
+            edge_u: Direction::x() * 2.0,
+            edge_v: Direction::z() * 2.0,
+            double_sided: false,
+        };
+        let lights = environment.portal_lights(&[window], 64);
+        assert_eq!(lights.len(), 64);
+        let illuminance: f64 = lights
+            .iter()
+            .filter_map(|light| light.illuminate(&Position::origin(), Unit::Meters))
+            .map(|sample| sample.illuminance.r * sample.direction.y)
+            .sum();
+        // Irradiance of a uniform rectangle of unit radiance
+        let expected = 4.0 * std::f64::consts::FRAC_1_SQRT_2 * (0.5f64).sqrt().atan();
+        assert!(
+            (illuminance - expected).abs() < 0.01 * expected,
+            "{}",
+            illuminance
+        );
+
+        // Most of the lights of the whole environment would hit the walls
+        let through = environment
+            .lights(64)
+            .iter()
+            .filter(|light| window.pdf(&Position::origin(), &-light.direction) > 0.0)
+            .count();
+        assert!(through < 16, "{}", through);
+
+        // Nothing comes through the back of the window
+        let above = Position::new(0.0, 2.0, 0.0);
+        assert!(lights
+            .iter()
+            .all(|light| light.illuminate(&above, Unit::Meters).is_none()));
+    }
+
+    #[test]
+    fn samples_follow_the_brightness_and_match_their_density() {
+        // Dark sky with a small bright sun, up and toward +z
+        let (width, height) = (32, 16);
+        let mut pixels = vec![Color::gray(0.1); (width * height) as usize];
+        pixels[(4 * width + 16) as usize] = Color::gray(1000.0);
+        let image = Arc::new(HdrImage {
+            width,
+            height,
+            pixels,
+        });
+        let environment = Environment::new(image, 1.0, 0.0);
+
+        let sun = environment.direction(16.5 / 32.0, 4.5 / 16.0);
+        assert!(sun.y > 0.5 && sun.z > 0.5);
+        assert_eq!(environment.radiance(&sun), Color::gray(1000.0));
+
+        // Most samples go to the sun, and agree with the density
+        let mut toward_sun = 0;
+        let n = 64;
+        for i in 0..n {
+            for j in 0..n {
+                let u = (i as f64 + 0.5) / n as f64;
+                let v = (j as f64 + 0.5) / n as f64;
+                let sample = environment.sample(u, v).unwrap();
+                assert!((sample.pdf - environment.pdf(&sample.direction)).abs() < 1e-9);
+                if environment.radiance(&sample.direction).r > 1.0 {
+                    toward_sun += 1;
+                }
+            }
+        }
+        assert!(toward_sun > n * n / 2);
+
+        // The density integrates to 1 over the sphere, pixel by pixel
+        let mut integral = 0.0;
+        for y in 0..height {
+            let top = (PI * y as f64 / height as f64).cos();
+            let bottom = (PI * (y + 1) as f64 / height as f64).cos();
+            for x in 0..width {
+                let center = environment.direction(
+                    (x as f64 + 0.5) / width as f64,
+                    (y as f64 + 0.5) / height as f64,
+                );
+                let solid_angle = 2.0 * PI / width as f64 * (top - bottom);
+                integral += environment.pdf(&center) * solid_angle;
+            }
+        }
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+
+        // A uniform environment gives an illuminance of pi facing up
+        let uniform = Arc::new(HdrImage {
+            width,
+            height,
+            pixels: vec![Color::WHITE; (width * height) as usize],
+        });
+        let illuminance: f64 = Environment::new(uniform, 1.0, 90.0)
+            .lights(1024)
+            .iter()
+            .map(|light| light.color.r * (-light.direction.y).max(0.0))
+            .sum();
+        assert!((illuminance - PI).abs() < 0.05, "{}", illuminance);
+    }
+
+    #[test]
+    fn gradient_and_sky_backgrounds() {
+        let gradient = Background::Gradient {
+            zenith: Color::new(0.0, 0.0, 1.0),
+            horizon: Color::WHITE,
+            ground: Color::gray(0.2),
+        };
+        assert_eq!(
+            gradient.radiance(&Direction::y()),
+            Color::new(0.0, 0.0, 1.0)
+        );
+        assert_eq!(gradient.radiance(&Direction::z()), Color::WHITE);
+        assert_eq!(gradient.radiance(&-Direction::y()), Color::gray(0.2));
+
+        let sky = Background::Sky {
+            sun_direction: Direction::new(0.0, 1.0, 1.0),
+            turbidity: 3.0,
+        };
+        let zenith = sky.radiance(&Direction::y());
+        // Blue sky of a few thousand cd/m², brighter toward the sun
+        assert!(zenith.b > zenith.r);
+        assert!(zenith.luminance() > 1000.0 && zenith.luminance() < 20000.0);
+        let toward_sun = sky.radiance(&Direction::new(0.0, 1.0, 1.2).normalize());
+        let away = sky.radiance(&Direction::new(0.0, 1.0, -1.2).normalize());
+        assert!(toward_sun.luminance() > away.luminance());
+        assert_eq!(
+            Background::default().radiance(&Direction::y()),
+            Color::BLACK
+        );
+    }
+}
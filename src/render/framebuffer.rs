@@ -0,0 +1,398 @@
+extern crate image;
+
+use std::str::FromStr;
+
+use self::image::Rgb;
+use serde::{Deserialize, Serialize};
+
+use crate::render::color::{Color, OutputTransform};
+use crate::render::image::RgbImage;
+
+/// Operator mapping accumulated colors to the displayable [0, 1] range
+///
+/// Spelled in lowercase in configuration files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapping {
+    /// Values above 1 are clipped
+    #[default]
+    Clamp,
+    /// c / (1 + c), compresses highlights instead of clipping them
+    Reinhard,
+    /// Filmic curve of the ACES reference rendering, with a toe in the
+    /// shadows and a soft shoulder in the highlights (fit of Narkowicz, 2015)
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn apply(&self, color: Color) -> Color {
+        color.map(|c| match self {
+            ToneMapping::Clamp => c.clamp(0.0, 1.0),
+            ToneMapping::Reinhard => c.max(0.0) / (1.0 + c.max(0.0)),
+            ToneMapping::Aces => {
+                let c = c.max(0.0);
+                (c * (2.51 * c + 0.03) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }
+        })
+    }
+}
+
+/// Reflectance of the gray that auto exposure brings the average scene to
+const MIDDLE_GRAY: f32 = 0.18;
+
+/// Brightness adjustment applied before tone mapping, in stops
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Exposure {
+    /// Colors are multiplied by 2^ev
+    Manual { ev: f32 },
+    /// The log-average luminance of the image becomes middle gray (18%), so
+    /// that renders keep a usable brightness whatever the light intensities
+    Auto,
+}
+
+/// Exposure value, in stops, bringing the log-average of `luminances` to
+/// middle gray; 0 without any
+pub fn auto_exposure<I: IntoIterator<Item = f64>>(luminances: I) -> f32 {
+    // Keeps black pixels from dragging the average down to 0
+    const DELTA: f64 = 1e-4;
+    let mut log_sum = 0.0;
+    let mut count = 0;
+    for luminance in luminances {
+        log_sum += (DELTA + luminance.max(0.0)).ln();
+        count += 1;
+    }
+    if count == 0 {
+        return 0.0;
+    }
+    let average = (log_sum / count as f64).exp();
+    (MIDDLE_GRAY / average as f32).log2()
+}
+
+impl Exposure {
+    /// Multiplier to apply to the colors of `buffer`
+    pub fn scale(&self, buffer: &AccumulationBuffer) -> f32 {
+        match self {
+            Exposure::Manual { ev } => ev.exp2(),
+            Exposure::Auto => buffer.auto_exposure().exp2(),
+        }
+    }
+}
+
+/// Noise added when quantizing colors to 8 bits, so that smooth gradients
+/// (vignettes, skies) turn into fine grain instead of visible bands
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dither {
+    /// Plain rounding
+    #[default]
+    None,
+    /// 8x8 Bayer matrix, a regular cross-hatch pattern
+    Ordered,
+    /// Interleaved gradient noise, without the visible structure of the Bayer
+    /// matrix nor the clumps of white noise
+    BlueNoise,
+}
+
+/// 8x8 Bayer threshold matrix, values in [0, 64)
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+impl Dither {
+    /// Threshold offset of pixel (x, y), in [-0.5, 0.5) 8-bit steps
+    fn offset(self, x: u32, y: u32) -> f32 {
+        match self {
+            Dither::None => 0.0,
+            Dither::Ordered => {
+                (f32::from(BAYER_8X8[(y % 8) as usize][(x % 8) as usize]) + 0.5) / 64.0 - 0.5
+            }
+            Dither::BlueNoise => {
+                // Jimenez, Next Generation Post Processing in Call of Duty:
+                // Advanced Warfare, 2014
+                let gradient = (0.067_110_56 * x as f32 + 0.005_837_15 * y as f32).fract();
+                (52.982_918 * gradient).fract() - 0.5
+            }
+        }
+    }
+
+    /// 8-bit value of an encoded color in [0, 1] at pixel (x, y)
+    pub fn quantize(self, color: Color, x: u32, y: u32) -> [u8; 3] {
+        let offset = self.offset(x, y) as f64;
+        let convert = |c: f64| {
+            (c.clamp(0.0, 1.0) * 255.0 + offset)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        };
+        [convert(color.r), convert(color.g), convert(color.b)]
+    }
+}
+
+/// Rectangle of pixels, in image coordinates
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for TileRect {
+    type Err = String;
+
+    /// `x,y,width,height`, e.g. `100,50,64,64`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|e| format!("invalid rectangle {}: {}", s, e))?;
+        match values.as_slice() {
+            &[x, y, width, height] => Ok(TileRect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(format!(
+                "invalid rectangle {}, expected x,y,width,height",
+                s
+            )),
+        }
+    }
+}
+
+/// Progressive accumulation of linear samples
+///
+/// Samples are summed per pixel so that any number of passes can be averaged
+/// without loss. The buffer tracks which tiles received samples since the last
+/// snapshot, so that a preview only converts the regions that changed to
+/// 8 bits instead of the whole frame on every UI update.
+pub struct AccumulationBuffer {
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    sum: Vec<Color>,
+    samples: Vec<u32>,
+    dirty: Vec<bool>,
+    exposure: f32,
+    dither: Dither,
+    output_transform: OutputTransform,
+}
+
+impl AccumulationBuffer {
+    pub fn new(width: u32, height: u32, tile_size: u32) -> AccumulationBuffer {
+        let tile_size = tile_size.max(1);
+        let nb_pixels = (width * height) as usize;
+        let nb_tiles = (tiles(width, tile_size) * tiles(height, tile_size)) as usize;
+        AccumulationBuffer {
+            width,
+            height,
+            tile_size,
+            sum: vec![Color::BLACK; nb_pixels],
+            samples: vec![0; nb_pixels],
+            dirty: vec![true; nb_tiles],
+            exposure: 1.0,
+            dither: Dither::None,
+            output_transform: OutputTransform::default(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Color) {
+        let index = (y * self.width + x) as usize;
+        self.sum[index] += color;
+        self.samples[index] += 1;
+        let tile = self.tile_index(x, y);
+        self.dirty[tile] = true;
+    }
+
+    /// Number of samples accumulated in a pixel
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.samples[(y * self.width + x) as usize]
+    }
+
+    /// Average of the samples of a pixel, black if it has none
+    pub fn mean(&self, x: u32, y: u32) -> Color {
+        let index = (y * self.width + x) as usize;
+        self.sum[index] / self.samples[index].max(1) as f64
+    }
+
+    /// Drop all the samples, e.g. when the camera moved
+    pub fn clear(&mut self) {
+        for s in self.sum.iter_mut() {
+            *s = Color::BLACK;
+        }
+        for n in self.samples.iter_mut() {
+            *n = 0;
+        }
+        for d in self.dirty.iter_mut() {
+            *d = true;
+        }
+    }
+
+    /// Multiplier applied to the colors before tone mapping, see
+    /// `Exposure::scale`
+    ///
+    /// A new exposure changes every pixel, the next snapshot converts the
+    /// whole frame.
+    pub fn set_exposure(&mut self, scale: f32) {
+        if scale != self.exposure {
+            self.exposure = scale;
+            for d in self.dirty.iter_mut() {
+                *d = true;
+            }
+        }
+    }
+
+    /// Dithering of the 8-bit snapshots
+    pub fn set_dither(&mut self, dither: Dither) {
+        if dither != self.dither {
+            self.dither = dither;
+            for d in self.dirty.iter_mut() {
+                *d = true;
+            }
+        }
+    }
+
+    /// Conversion of the tone mapped colors to the color space of the
+    /// snapshots
+    pub fn set_output_transform(&mut self, output_transform: OutputTransform) {
+        if output_transform != self.output_transform {
+            self.output_transform = output_transform;
+            for d in self.dirty.iter_mut() {
+                *d = true;
+            }
+        }
+    }
+
+    /// Exposure value, in stops, bringing the log-average luminance of the
+    /// pixels with samples to middle gray; 0 for an empty buffer
+    pub fn auto_exposure(&self) -> f32 {
+        let pixels = (0..self.height).flat_map(|y| (0..self.width).map(move |x| (x, y)));
+        auto_exposure(
+            pixels
+                .filter(|&(x, y)| self.sample_count(x, y) > 0)
+                .map(|(x, y)| self.mean(x, y).luminance()),
+        )
+    }
+
+    /// Convert the tiles that changed since the previous call into `target`
+    ///
+    /// `target` must have the dimensions of the buffer. Returns the updated
+    /// regions so that the caller can only redraw those.
+    pub fn update_snapshot(
+        &mut self,
+        target: &mut RgbImage,
+        tone_mapping: ToneMapping,
+    ) -> Vec<TileRect> {
+        assert_eq!(target.dimensions(), (self.width, self.height));
+        let tiles_x = tiles(self.width, self.tile_size);
+        let mut updated = Vec::new();
+        for tile in 0..self.dirty.len() {
+            if !self.dirty[tile] {
+                continue;
+            }
+            self.dirty[tile] = false;
+            let rect = self.tile_rect(tile as u32 % tiles_x, tile as u32 / tiles_x);
+            for y in rect.y..rect.y + rect.height {
+                for x in rect.x..rect.x + rect.width {
+                    target.put_pixel(x, y, Rgb(self.display(x, y, tone_mapping)));
+                }
+            }
+            updated.push(rect);
+        }
+        updated
+    }
+
+    /// Full tonemapped 8-bit copy of the buffer
+    pub fn to_image(&self, tone_mapping: ToneMapping) -> RgbImage {
+        let mut img = RgbImage::new(self.width, self.height);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = Rgb(self.display(x, y, tone_mapping));
+        }
+        img
+    }
+
+    /// 8-bit color of a pixel: exposed, tone mapped, encoded and dithered
+    fn display(&self, x: u32, y: u32, tone_mapping: ToneMapping) -> [u8; 3] {
+        let exposed = self.mean(x, y) * self.exposure as f64;
+        let encoded = self.output_transform.apply(tone_mapping.apply(exposed));
+        self.dither.quantize(encoded, x, y)
+    }
+
+    fn tile_index(&self, x: u32, y: u32) -> usize {
+        let tiles_x = tiles(self.width, self.tile_size);
+        ((y / self.tile_size) * tiles_x + x / self.tile_size) as usize
+    }
+
+    fn tile_rect(&self, tile_x: u32, tile_y: u32) -> TileRect {
+        let x = tile_x * self.tile_size;
+        let y = tile_y * self.tile_size;
+        TileRect {
+            x,
+            y,
+            width: self.tile_size.min(self.width - x),
+            height: self.tile_size.min(self.height - y),
+        }
+    }
+}
+
+/// Split an image in tiles, row by row from the top left corner; tiles on the
+/// right and bottom edges are cropped to the image
+pub fn tile_grid(width: u32, height: u32, tile_size: u32) -> Vec<TileRect> {
+    let mut rects = Vec::new();
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            rects.push(TileRect {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    rects
+}
+
+/// Number of tiles needed to cover `length` pixels
+fn tiles(length: u32, tile_size: u32) -> u32 {
+    length.div_ceil(tile_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dithering_preserves_the_average_level() {
+        // Between two 8-bit levels, rounding alone would give 100 everywhere
+        let level = 100.3;
+        let color = Color::gray(level / 255.0);
+        for &dither in &[Dither::Ordered, Dither::BlueNoise] {
+            let mut sum = 0.0;
+            for y in 0..64 {
+                for x in 0..64 {
+                    sum += f64::from(dither.quantize(color, x, y)[0]);
+                }
+            }
+            let average = sum / (64.0 * 64.0);
+            assert!((average - level).abs() < 0.05, "{:?}: {}", dither, average);
+        }
+        assert_eq!(Dither::None.quantize(color, 3, 5), [100; 3]);
+    }
+}
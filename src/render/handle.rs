@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::geometry::ray::Ray;
+use crate::render::color::Color;
+use crate::render::config::{CameraConfig, RenderingConfig};
+use crate::render::framebuffer::tile_grid;
+use crate::render::image::{render_hdr_tiles_until, HdrRgbImage};
+use crate::render::ray_tracer::SampleKey;
+
+/// How far a render went
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    pub finished_tiles: usize,
+    pub total_tiles: usize,
+    /// Camera rays traced so far, the rays they spawn aside
+    pub rays: u64,
+    /// Time since the render started
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Percentage of the tiles done, 100 for an empty image
+    pub fn percent(&self) -> f64 {
+        if self.total_tiles == 0 {
+            return 100.0;
+        }
+        self.finished_tiles as f64 * 100.0 / self.total_tiles as f64
+    }
+}
+
+/// Control over a render from other threads, e.g. a viewer showing its
+/// progress with a button to stop it
+///
+/// Clones control the same render. A handle is used for one render, see
+/// `render_with_handle`.
+#[derive(Clone, Debug, Default)]
+pub struct RenderHandle {
+    state: Arc<HandleState>,
+}
+
+#[derive(Debug, Default)]
+struct HandleState {
+    cancelled: AtomicBool,
+    finished_tiles: AtomicUsize,
+    total_tiles: AtomicUsize,
+    rays: AtomicU64,
+    start: Mutex<Option<Instant>>,
+}
+
+impl RenderHandle {
+    pub fn new() -> RenderHandle {
+        Default::default()
+    }
+
+    /// Stop the render: the tiles in progress are finished, the others
+    /// skipped, and the render returns no image
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Progress of the render, nothing done before it starts
+    pub fn progress(&self) -> Progress {
+        let start = *self.state.start.lock().unwrap();
+        Progress {
+            finished_tiles: self.state.finished_tiles.load(Ordering::Relaxed),
+            total_tiles: self.state.total_tiles.load(Ordering::Relaxed),
+            rays: self.state.rays.load(Ordering::Relaxed),
+            elapsed: start.map_or(Duration::ZERO, |start| start.elapsed()),
+        }
+    }
+}
+
+/// Render the linear colors of the image as `render_hdr_tiles` does, until
+/// `handle` cancels it, calling `on_progress` from the rendering threads
+/// after every tile
+///
+/// Returns `None` when the render was cancelled.
+pub fn render_with_handle<F, P>(
+    ray_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    handle: &RenderHandle,
+    on_progress: P,
+) -> Option<HdrRgbImage>
+where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+    P: Fn(&Progress) + Sync,
+{
+    let state = &handle.state;
+    let region = rendering_config.region(camera_config);
+    let tile_count = tile_grid(
+        region.width,
+        region.height,
+        rendering_config.tile_size.max(1),
+    )
+    .len();
+    let samples_per_pixel = u64::from(rendering_config.samples_per_pixel.max(1));
+    state.total_tiles.store(tile_count, Ordering::Relaxed);
+    *state.start.lock().unwrap() = Some(Instant::now());
+
+    render_hdr_tiles_until(
+        ray_tracer,
+        camera_config,
+        rendering_config,
+        || handle.is_cancelled(),
+        |tile| {
+            let pixels = u64::from(tile.rect.width * tile.rect.height);
+            state
+                .rays
+                .fetch_add(pixels * samples_per_pixel, Ordering::Relaxed);
+            state.finished_tiles.fetch_add(1, Ordering::Relaxed);
+            on_progress(&handle.progress());
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn progress_is_reported_and_cancel_stops_the_render() {
+        let camera_config = CameraConfig {
+            width: 16,
+            height: 8,
+            ..Default::default()
+        };
+        let rendering_config = RenderingConfig {
+            tile_size: 4,
+            threads: 1,
+            samples_per_pixel: 2,
+            ..Default::default()
+        };
+        let tracer = |_: Ray, _: SampleKey| Color::WHITE;
+
+        let handle = RenderHandle::new();
+        let reports = AtomicU32::new(0);
+        let img = render_with_handle(tracer, &camera_config, &rendering_config, &handle, |_| {
+            reports.fetch_add(1, Ordering::Relaxed);
+        });
+        assert!(img.is_some());
+        assert_eq!(reports.into_inner(), 8);
+        let progress = handle.progress();
+        assert_eq!(progress.percent(), 100.0);
+        assert_eq!(progress.rays, 16 * 8 * 2);
+
+        // Cancelled from the progress callback, as another thread would
+        let handle = RenderHandle::new();
+        let img = render_with_handle(tracer, &camera_config, &rendering_config, &handle, |p| {
+            if p.finished_tiles == 3 {
+                handle.cancel();
+            }
+        });
+        assert!(img.is_none());
+        assert_eq!(handle.progress().finished_tiles, 3);
+    }
+}
@@ -0,0 +1,504 @@
+use std::cell::Cell;
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::instance::Prototype;
+use crate::geometry::kdtree::{occluded_packet, TraversalStacks};
+use crate::geometry::ray::Ray;
+use crate::geometry::types::{orthonormal_basis, Direction, Position};
+use crate::render::color::Color;
+use crate::render::config::NormalMode;
+use crate::render::material::{Material, MAX_UV_SETS};
+use crate::render::ray_tracer::{
+    self, kdtree_closest_intersection, mesh_surface, TriangleIntersect,
+};
+
+/// Surface at the hit of a `Hittable`, before its material bends the normal
+pub struct HitSurface<'a> {
+    /// Normal shaded, normalized
+    pub normal: Direction,
+    /// Normal of the surface itself, to offset the secondary rays
+    pub face_normal: Direction,
+    pub vertex_color: Color,
+    pub uvs: [Option<[f64; 2]>; MAX_UV_SETS],
+    /// `None` for the default material of the rendering
+    pub material: Option<&'a dyn Material>,
+}
+
+/// Anything the tracers can hit: meshes through their kd-tree, or analytic
+/// surfaces that need no triangles
+///
+/// The hits are `TriangleIntersect`s: analytic surfaces hit triangle 0 and
+/// give the texture coordinates of the hit as its barycentric coordinates.
+pub trait Hittable: fmt::Debug + Send + Sync {
+    /// Closest hit of `ray` up to `far`, in the units of the scene, on the
+    /// front of the surface only unless `two_sided`
+    ///
+    /// `stacks` are the traversal stacks of the tracer, for the hittables
+    /// traversing a kd-tree.
+    fn closest_hit<'a>(
+        &'a self,
+        stacks: &TraversalStacks<'a>,
+        ray: &Ray,
+        far: f64,
+        two_sided: bool,
+    ) -> Option<TriangleIntersect>;
+
+    /// Whether the surface blocks `ray` before `distance`, from either side
+    fn occluded(&self, ray: &Ray, distance: f64) -> bool;
+
+    /// Box around the surface, `None` when it is unbounded
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox>;
+
+    /// Surface at a hit found by `closest_hit`
+    fn surface(&self, intersect: &TriangleIntersect, normal_mode: NormalMode) -> HitSurface<'_>;
+}
+
+/// A hit of an analytic surface at `t` along `ray`
+fn analytic_hit(ray: &Ray, t: f64, uv: [f64; 2]) -> TriangleIntersect {
+    TriangleIntersect {
+        triangle_index: 0,
+        intersection: ray.position + ray.direction * t,
+        barycentric_coordinate: uv,
+        tangent: None,
+        instance: None,
+        hittable: None,
+    }
+}
+
+/// Surface of an analytic hit, the same texture coordinates in every set
+fn analytic_surface<'a>(
+    normal: Direction,
+    uv: [f64; 2],
+    material: &'a Option<Arc<dyn Material>>,
+) -> HitSurface<'a> {
+    HitSurface {
+        normal,
+        face_normal: normal,
+        vertex_color: Color::WHITE,
+        uvs: [Some(uv); MAX_UV_SETS],
+        material: material.as_deref(),
+    }
+}
+
+/// A model with its kd-tree, hit through it
+impl Hittable for Prototype {
+    fn closest_hit<'a>(
+        &'a self,
+        stacks: &TraversalStacks<'a>,
+        ray: &Ray,
+        far: f64,
+        two_sided: bool,
+    ) -> Option<TriangleIntersect> {
+        kdtree_closest_intersection(
+            stacks,
+            &self.kdtree,
+            &self.mesh,
+            ray,
+            far,
+            two_sided,
+            &Cell::new(0),
+        )
+    }
+
+    fn occluded(&self, ray: &Ray, distance: f64) -> bool {
+        let rays = [(Ray::new(ray.position, ray.direction), distance)];
+        occluded_packet(&self.kdtree, &rays, |triangles, ray, distance| {
+            ray_tracer::occluded(triangles.iter().copied(), ray, distance, &self.mesh)
+        }) != 0
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(AxisAlignedBoundingBox::from_bounds(
+            self.kdtree.bounding_box.bounds,
+        ))
+    }
+
+    fn surface(&self, intersect: &TriangleIntersect, normal_mode: NormalMode) -> HitSurface<'_> {
+        mesh_surface(&self.mesh, intersect, normal_mode)
+    }
+}
+
+/// Sphere, seen from the outside
+///
+/// Its texture coordinates are the longitude around +y from +z, from 0 to 1,
+/// and the latitude from 0 at the bottom to 1 at the top.
+#[derive(Clone, Debug)]
+pub struct Sphere {
+    pub center: Position,
+    pub radius: f64,
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Sphere {
+    pub fn new(center: Position, radius: f64) -> Sphere {
+        Sphere {
+            center,
+            radius,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: Arc<dyn Material>) -> Sphere {
+        Sphere {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    /// Outward normal at `position`, on the sphere
+    fn normal(&self, position: &Position) -> Direction {
+        (position - self.center) / self.radius
+    }
+
+    fn uv(normal: &Direction) -> [f64; 2] {
+        [
+            0.5 + normal.x.atan2(normal.z) / (2.0 * PI),
+            0.5 + normal.y.max(-1.0).min(1.0).asin() / PI,
+        ]
+    }
+
+    /// Parameters along `ray` where it crosses the sphere, nearest first
+    fn crossings(&self, ray: &Ray) -> Option<[f64; 2]> {
+        let offset = ray.position - self.center;
+        let a = ray.direction.norm_squared();
+        let b = ray.direction.dot(&offset);
+        let c = offset.norm_squared() - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 || a == 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        Some([(-b - root) / a, (-b + root) / a])
+    }
+}
+
+impl Hittable for Sphere {
+    fn closest_hit<'a>(
+        &'a self,
+        _stacks: &TraversalStacks<'a>,
+        ray: &Ray,
+        far: f64,
+        two_sided: bool,
+    ) -> Option<TriangleIntersect> {
+        let far = far / ray.direction.norm();
+        let crossings = self.crossings(ray)?;
+        crossings
+            .iter()
+            .filter(|&&t| t > 0.0 && t <= far)
+            .map(|&t| (t, self.normal(&(ray.position + ray.direction * t))))
+            .find(|(_, normal)| two_sided || ray.direction.dot(normal) < 0.0)
+            .map(|(t, normal)| analytic_hit(ray, t, Sphere::uv(&normal)))
+    }
+
+    fn occluded(&self, ray: &Ray, distance: f64) -> bool {
+        let far = distance / ray.direction.norm();
+        self.crossings(ray)
+            .is_some_and(|crossings| crossings.iter().any(|&t| t > 0.0 && t < far))
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        let extent = Direction::repeat(self.radius);
+        Some(AxisAlignedBoundingBox::from_bounds([
+            self.center - extent,
+            self.center + extent,
+        ]))
+    }
+
+    fn surface(&self, intersect: &TriangleIntersect, _normal_mode: NormalMode) -> HitSurface<'_> {
+        let normal = self.normal(&intersect.intersection).normalize();
+        analytic_surface(normal, intersect.barycentric_coordinate, &self.material)
+    }
+}
+
+/// Infinite plane through `point`, seen from the side `normal` points to
+///
+/// Its texture coordinates are the coordinates on the plane in the units of
+/// the scene, from `point`, so that textures repeat every unit.
+#[derive(Clone, Debug)]
+pub struct Plane {
+    pub point: Position,
+    /// Normalized
+    pub normal: Direction,
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Plane {
+    /// Plane through `point` facing `normal`, normalized here
+    pub fn new(point: Position, normal: Direction) -> Plane {
+        Plane {
+            point,
+            normal: normal.normalize(),
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: Arc<dyn Material>) -> Plane {
+        Plane {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    /// Parameter along `ray` where it crosses the plane, and the cosine of
+    /// the ray with the normal, unnormalized
+    fn crossing(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let cos = ray.direction.dot(&self.normal);
+        if cos == 0.0 {
+            return None;
+        }
+        Some(((self.point - ray.position).dot(&self.normal) / cos, cos))
+    }
+}
+
+impl Hittable for Plane {
+    fn closest_hit<'a>(
+        &'a self,
+        _stacks: &TraversalStacks<'a>,
+        ray: &Ray,
+        far: f64,
+        two_sided: bool,
+    ) -> Option<TriangleIntersect> {
+        let far = far / ray.direction.norm();
+        let (t, cos) = self.crossing(ray)?;
+        if t <= 0.0 || t > far || !(two_sided || cos < 0.0) {
+            return None;
+        }
+        let intersection = ray.position + ray.direction * t;
+        let (u, v) = orthonormal_basis(&self.normal);
+        let offset = intersection - self.point;
+        Some(analytic_hit(ray, t, [offset.dot(&u), offset.dot(&v)]))
+    }
+
+    fn occluded(&self, ray: &Ray, distance: f64) -> bool {
+        let far = distance / ray.direction.norm();
+        self.crossing(ray).is_some_and(|(t, _)| t > 0.0 && t < far)
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        None
+    }
+
+    fn surface(&self, intersect: &TriangleIntersect, _normal_mode: NormalMode) -> HitSurface<'_> {
+        analytic_surface(
+            self.normal,
+            intersect.barycentric_coordinate,
+            &self.material,
+        )
+    }
+}
+
+/// Axis-aligned box, seen from the outside, or from the inside with
+/// `inward`, e.g. for the walls of a room
+///
+/// Its texture coordinates cover every side, as those of `Mesh::cuboid`.
+#[derive(Clone, Debug)]
+pub struct Cuboid {
+    pub bounds: AxisAlignedBoundingBox,
+    pub inward: bool,
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Cuboid {
+    pub fn new(bounds: AxisAlignedBoundingBox, inward: bool) -> Cuboid {
+        Cuboid {
+            bounds,
+            inward,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: Arc<dyn Material>) -> Cuboid {
+        Cuboid {
+            material: Some(material),
+            ..self
+        }
+    }
+
+    /// Normal of the side at `position`, facing out or in with `inward`
+    fn normal(&self, position: &Position) -> Direction {
+        // The hits are on the box up to rounding, far less than its epsilon
+        let tolerance = self.bounds.ray_epsilon().unwrap_or(0.0);
+        let outward = self
+            .bounds
+            .face_normal_at(position, tolerance)
+            .unwrap_or_else(Direction::zeros);
+        if self.inward {
+            -outward
+        } else {
+            outward
+        }
+    }
+
+    /// Coordinates of `position` across the side facing `normal`, from 0 to
+    /// 1 counterclockwise around its axis
+    fn uv(&self, position: &Position, normal: &Direction) -> [f64; 2] {
+        let axis = normal.iamax();
+        let [min, max] = self.bounds.bounds;
+        let along = |i: usize| {
+            let size = max[i] - min[i];
+            if size > 0.0 {
+                (position[i] - min[i]) / size
+            } else {
+                0.0
+            }
+        };
+        [along((axis + 1) % 3), along((axis + 2) % 3)]
+    }
+}
+
+impl Hittable for Cuboid {
+    fn closest_hit<'a>(
+        &'a self,
+        _stacks: &TraversalStacks<'a>,
+        ray: &Ray,
+        far: f64,
+        two_sided: bool,
+    ) -> Option<TriangleIntersect> {
+        let far = far / ray.direction.norm();
+        let (entry, exit) = ray.intersect_box_range(&self.bounds.bounds)?;
+        [entry, exit]
+            .iter()
+            .filter(|&&t| t > 0.0 && t <= far)
+            .map(|&t| (t, ray.position + ray.direction * t))
+            .map(|(t, position)| (t, position, self.normal(&position)))
+            .find(|(_, _, normal)| two_sided || ray.direction.dot(normal) < 0.0)
+            .map(|(t, position, normal)| analytic_hit(ray, t, self.uv(&position, &normal)))
+    }
+
+    fn occluded(&self, ray: &Ray, distance: f64) -> bool {
+        let far = distance / ray.direction.norm();
+        ray.intersect_box_range(&self.bounds.bounds)
+            .is_some_and(|(entry, exit)| [entry, exit].iter().any(|&t| t > 0.0 && t < far))
+    }
+
+    fn bounding_box(&self) -> Option<AxisAlignedBoundingBox> {
+        Some(self.bounds.clone())
+    }
+
+    fn surface(&self, intersect: &TriangleIntersect, _normal_mode: NormalMode) -> HitSurface<'_> {
+        analytic_surface(
+            self.normal(&intersect.intersection),
+            intersect.barycentric_coordinate,
+            &self.material,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::kdtree::KdTree;
+    use crate::geometry::mesh::Mesh;
+
+    fn hit(hittable: &dyn Hittable, ray: &Ray, two_sided: bool) -> Option<TriangleIntersect> {
+        hittable.closest_hit(&TraversalStacks::default(), ray, f64::INFINITY, two_sided)
+    }
+
+    #[test]
+    fn spheres_are_hit_on_the_outside() {
+        let sphere = Sphere::new(Position::new(0.0, 0.0, 5.0), 2.0);
+        let ray = Ray::new(Position::origin(), Direction::new(0.0, 0.0, 2.0));
+        let front = hit(&sphere, &ray, false).unwrap();
+        assert!((front.intersection - Position::new(0.0, 0.0, 3.0)).norm() < 1e-12);
+        let surface = sphere.surface(&front, NormalMode::Phong);
+        assert!((surface.normal + Direction::z()).norm() < 1e-12);
+        assert!(surface.material.is_none());
+        // Seen from its center, only from inside
+        let inside = Ray::new(sphere.center, Direction::x());
+        assert!(hit(&sphere, &inside, false).is_none());
+        let back = hit(&sphere, &inside, true).unwrap();
+        assert!((back.intersection - Position::new(2.0, 0.0, 5.0)).norm() < 1e-12);
+        assert!(sphere.occluded(&inside, 2.5));
+        assert!(!sphere.occluded(&inside, 1.5));
+        // Beyond the distance searched
+        let near = sphere.closest_hit(&TraversalStacks::default(), &ray, 2.5, false);
+        assert!(near.is_none());
+    }
+
+    #[test]
+    fn planes_are_unbounded_and_hit_from_their_front() {
+        let plane = Plane::new(Position::new(0.0, -1.0, 0.0), Direction::new(0.0, 2.0, 0.0));
+        assert!(plane.bounding_box().is_none());
+        let down = Ray::new(
+            Position::new(100.0, 0.0, -50.0),
+            Direction::new(1.0, -1.0, 0.0),
+        );
+        let hit_down = hit(&plane, &down, false).unwrap();
+        assert!((hit_down.intersection - Position::new(101.0, -1.0, -50.0)).norm() < 1e-9);
+        assert_eq!(
+            plane.surface(&hit_down, NormalMode::Phong).normal,
+            Direction::y()
+        );
+        let up = Ray::new(Position::new(0.0, -2.0, 0.0), Direction::y());
+        assert!(hit(&plane, &up, false).is_none());
+        assert!(hit(&plane, &up, true).is_some());
+        assert!(plane.occluded(&up, 1.5));
+        let level = Ray::new(Position::origin(), Direction::x());
+        assert!(hit(&plane, &level, true).is_none());
+    }
+
+    #[test]
+    fn cuboids_are_hit_on_their_sides() {
+        let bounds = AxisAlignedBoundingBox::from_bounds([
+            Position::new(-1.0, -1.0, 4.0),
+            Position::new(1.0, 1.0, 6.0),
+        ]);
+        let cuboid = Cuboid::new(bounds.clone(), false);
+        let ray = Ray::new(Position::new(0.5, -0.5, 0.0), Direction::new(0.0, 0.0, 2.0));
+        let front = hit(&cuboid, &ray, false).unwrap();
+        assert!((front.intersection - Position::new(0.5, -0.5, 4.0)).norm() < 1e-12);
+        assert_eq!(front.barycentric_coordinate, [0.75, 0.25]);
+        let surface = cuboid.surface(&front, NormalMode::Phong);
+        assert_eq!(surface.normal, -Direction::z());
+        assert!(cuboid.occluded(&ray, 5.0));
+        assert!(!cuboid.occluded(&ray, 3.0));
+        assert_eq!(cuboid.bounding_box().unwrap().bounds, bounds.bounds);
+
+        // From its center, the walls of a room face in
+        let inside = Ray::new(Position::new(0.0, 0.0, 5.0), Direction::x());
+        assert!(hit(&cuboid, &inside, false).is_none());
+        let room = Cuboid::new(bounds, true);
+        let wall = hit(&room, &inside, false).unwrap();
+        assert!((wall.intersection - Position::new(1.0, 0.0, 5.0)).norm() < 1e-12);
+        assert_eq!(
+            room.surface(&wall, NormalMode::Phong).normal,
+            -Direction::x()
+        );
+        // The far wall, through the back of the near one
+        let far_wall = hit(&room, &ray, false).unwrap();
+        assert!((far_wall.intersection - Position::new(0.5, -0.5, 6.0)).norm() < 1e-12);
+        let near_wall = hit(&room, &ray, true).unwrap();
+        assert_eq!(near_wall.intersection, front.intersection);
+    }
+
+    #[test]
+    fn meshes_are_hit_through_their_kdtree() {
+        let mesh = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-1.0, -1.0, 2.0),
+                Position::new(1.0, -1.0, 2.0),
+                Position::new(0.0, 1.0, 2.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let prototype = Prototype {
+            kdtree: KdTree::from_mesh(&mesh),
+            mesh: Arc::new(mesh),
+        };
+        let ray = Ray::new(Position::origin(), Direction::z());
+        let stacks = TraversalStacks::default();
+        // Wound counterclockwise seen from +z, the ray sees its back
+        assert!(prototype.closest_hit(&stacks, &ray, 10.0, false).is_none());
+        let back = prototype.closest_hit(&stacks, &ray, 10.0, true).unwrap();
+        assert!((back.intersection - Position::new(0.0, 0.0, 2.0)).norm() < 1e-12);
+        assert!(prototype.occluded(&ray, 3.0));
+        assert!(!prototype.occluded(&ray, 1.0));
+        assert_eq!(
+            prototype.bounding_box().unwrap().bounds[1],
+            Position::new(1.0, 1.0, 2.0)
+        );
+    }
+}
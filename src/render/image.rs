@@ -1,33 +1,1236 @@
 extern crate image;
+extern crate rayon;
 
-use self::image::{Rgb, RgbImage};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+pub use self::image::RgbImage;
+use self::image::{ImageBuffer, Rgb};
+use self::rayon::prelude::*;
+use self::rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
 use crate::geometry::ray::Ray;
-use crate::render::config::CameraConfig;
+use crate::geometry::types::{Direction, Position};
+use crate::render::color::Color;
+use crate::render::config::{CameraConfig, Projection, RenderingConfig};
+use crate::render::framebuffer::{auto_exposure, tile_grid, Exposure, TileRect};
+use crate::render::ray_tracer::{Sample, SampleKey};
+use crate::render::sampler::{lens_sample, pixel_samples};
+
+/// Linear colors of a render, before tone mapping, rows top first
+pub type HdrRgbImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+/// Render the image on `rendering_config.threads` threads, averaging
+/// `rendering_config.samples_per_pixel` rays per pixel placed by
+/// `rendering_config.sampler`, then converting the average for display with
+/// `to_display`
+pub fn render_image<F>(
+    ray_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+) -> RgbImage
+where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+{
+    render_tiles(ray_tracer, camera_config, rendering_config, |_| {})
+}
 
-pub fn render_image<F: Fn(Ray) -> [u8; 3]>(
+/// Render the linear colors of the image, without converting them for
+/// display, e.g. to save them or to tone map them several ways
+pub fn render_hdr_image<F>(
     ray_tracer: F,
     camera_config: &CameraConfig,
-) -> RgbImage {
-    let mut img = RgbImage::new(camera_config.width, camera_config.height);
+    rendering_config: &RenderingConfig,
+) -> HdrRgbImage
+where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+{
+    render_hdr_tiles(ray_tracer, camera_config, rendering_config, |_| {})
+}
 
-    let step_x = camera_config.fov.tan() / (camera_config.width as f64);
-    let step_y =
-        camera_config.fov.tan() / camera_config.aspect_ratio / (camera_config.height as f64);
-    let camera_position = camera_config.camera_position;
-    let width = camera_config.width;
+/// Pixels of a finished tile
+pub struct Tile {
+    pub rect: TileRect,
+    /// RGB rows of the tile, top first, encoded for the output; at 0 EV with
+    /// the automatic exposure, which is measured on the whole image
+    pub pixels: Vec<u8>,
+    /// Linear RGB rows of the tile, top first, before tone mapping
+    pub colors: Vec<f32>,
+}
+
+/// Render the image in square tiles of `rendering_config.tile_size` pixels
+/// shared between the threads, handing every tile to `on_tile` as soon as it
+/// is done
+///
+/// `on_tile` is called from the rendering threads, in no particular order,
+/// e.g. to report progress or update a preview.
+///
+/// The image does not depend on the number of threads nor on the scheduling:
+/// every tile is rendered into its own buffer, with the samples of a pixel
+/// always drawn and summed in the same order, and the tiles are merged into
+/// the image in grid order once they are all done.
+///
+/// With `rendering_config.crop`, the image is the crop window alone, with the
+/// pixels of the full image, and the tiles are given in its coordinates.
+pub fn render_tiles<F, C>(
+    ray_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    on_tile: C,
+) -> RgbImage
+where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+    C: Fn(&Tile) + Sync,
+{
+    let hdr = render_hdr_tiles(ray_tracer, camera_config, rendering_config, on_tile);
+    to_display(&hdr, rendering_config)
+}
+
+/// Same as `render_tiles`, keeping the linear colors of the image
+pub fn render_hdr_tiles<F, C>(
+    ray_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    on_tile: C,
+) -> HdrRgbImage
+where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+    C: Fn(&Tile) + Sync,
+{
+    render_hdr_tiles_until(
+        ray_tracer,
+        camera_config,
+        rendering_config,
+        || false,
+        on_tile,
+    )
+    .expect("renders without cancellation complete")
+}
+
+/// Same as `render_hdr_tiles`, skipping the tiles left once `cancelled`
+/// returns true, in which case there is no image
+pub(crate) fn render_hdr_tiles_until<F, S, C>(
+    ray_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    cancelled: S,
+    on_tile: C,
+) -> Option<HdrRgbImage>
+where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+    S: Fn() -> bool + Sync,
+    C: Fn(&Tile) + Sync,
+{
+    let region = rendering_config.region(camera_config);
+    let width = region.width;
+    let rects = tile_grid(width, region.height, rendering_config.tile_size.max(1));
+    let samples_per_pixel = rendering_config.samples_per_pixel.max(1);
+    let span = tracing::info_span!("render", width, height = region.height, samples_per_pixel);
+    let _entered = span.enter();
+
+    let tiles: Option<Vec<Tile>> = with_threads(rendering_config.threads, || {
+        rects
+            .par_iter()
+            .map(|rect| {
+                if cancelled() {
+                    return None;
+                }
+                let tile = render_tile(&ray_tracer, camera_config, rendering_config, *rect, &span);
+                on_tile(&tile);
+                Some(tile)
+            })
+            .collect()
+    });
+
+    // `collect` keeps the order of `rects`, whichever thread finished first
+    let mut img = HdrRgbImage::new(width, region.height);
+    let buffer: &mut [f32] = &mut img;
+    for tile in tiles? {
+        let rect = tile.rect;
+        for (row, y) in (rect.y..rect.y + rect.height).enumerate() {
+            let start = ((y * width + rect.x) * 3) as usize;
+            let length = (rect.width * 3) as usize;
+            buffer[start..start + length]
+                .copy_from_slice(&tile.colors[row * length..(row + 1) * length]);
+        }
+    }
+    Some(img)
+}
+
+/// Render the pixels of `rect`, in the coordinates of the region of
+/// `rendering_config`
+fn render_tile<F>(
+    ray_tracer: &F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    rect: TileRect,
+    span: &tracing::Span,
+) -> Tile
+where
+    F: Fn(Ray, SampleKey) -> Color,
+{
+    let _tile_span = tracing::debug_span!(parent: span, "tile", x = rect.x, y = rect.y).entered();
     let height = camera_config.height;
+    let region = rendering_config.region(camera_config);
+    let samples_per_pixel = rendering_config.samples_per_pixel.max(1);
+    let size = (rect.width * rect.height * 3) as usize;
+    let mut pixels = Vec::with_capacity(size);
+    let mut colors = Vec::with_capacity(size);
+    // In the full image, the samples of a pixel do not depend on the crop
+    for y in region.y + rect.y..region.y + rect.y + rect.height {
+        // Rows are stored top first, the camera y axis goes up
+        let j = height - 1 - y;
+        for i in region.x + rect.x..region.x + rect.x + rect.width {
+            let offsets = pixel_samples(
+                rendering_config.sampler,
+                samples_per_pixel,
+                rendering_config.seed,
+                i,
+                y,
+            );
+            let sum: Color = offsets
+                .iter()
+                .zip(0..)
+                .map(|(&(dx, dy), s)| {
+                    let lens = lens_sample(rendering_config.seed, i, y, s);
+                    let ray = lens_ray(i as f64 + dx, j as f64 + dy, lens, camera_config);
+                    ray_tracer(ray, SampleKey { x: i, y, sample: s })
+                })
+                .sum();
+            let mean = <[f64; 3]>::from(sum / offsets.len() as f64).map(|c| c as f32);
+            colors.extend(&mean);
+            pixels.extend(&display(Rgb(mean), 1.0, i, y, rendering_config));
+        }
+    }
+    Tile {
+        rect,
+        pixels,
+        colors,
+    }
+}
 
-    for i in 0..width {
-        for j in 0..height {
-            let dir = ((i as f64 - (width as f64) / 2.0) * step_x * camera_config.x
-                + (j as f64 - (height as f64) / 2.0) * step_y * camera_config.y
+/// Convert the linear colors of a render to 8 bits with
+/// `rendering_config.tone_mapping`, `output_transform` and `dither`
+///
+/// The automatic exposure is measured on `hdr` and applied here, the manual
+/// one being applied by the tracers.
+pub fn to_display(hdr: &HdrRgbImage, rendering_config: &RenderingConfig) -> RgbImage {
+    let _span = tracing::info_span!("tone_map").entered();
+    let exposure = match rendering_config.exposure {
+        Exposure::Auto => f64::from(image_exposure(hdr).exp2()),
+        Exposure::Manual { .. } => 1.0,
+    };
+    RgbImage::from_fn(hdr.width(), hdr.height(), |x, y| {
+        Rgb(display(
+            *hdr.get_pixel(x, y),
+            exposure,
+            x,
+            y,
+            rendering_config,
+        ))
+    })
+}
+
+/// Exposure value, in stops, bringing the log-average luminance of `hdr` to
+/// middle gray, see `Exposure::Auto`
+pub fn image_exposure(hdr: &HdrRgbImage) -> f32 {
+    auto_exposure(
+        hdr.pixels()
+            .map(|p| Color::from(p.0.map(f64::from)).luminance()),
+    )
+}
+
+/// 8-bit color of pixel (x, y): exposed, tone mapped, encoded and dithered
+fn display(
+    color: Rgb<f32>,
+    exposure: f64,
+    x: u32,
+    y: u32,
+    rendering_config: &RenderingConfig,
+) -> [u8; 3] {
+    let color = Color::from(color.0.map(f64::from)) * exposure;
+    let encoded = rendering_config
+        .output_transform
+        .apply(rendering_config.tone_mapping.apply(color));
+    rendering_config.dither.quantize(encoded, x, y)
+}
+
+/// Pool of `threads` rendering threads, `None` for the global pool (one
+/// thread per core) when `threads` is 0
+///
+/// Pools are built once per thread count and kept for the next renders,
+/// e.g. the frames of a preview.
+pub fn thread_pool(threads: usize) -> Result<Option<Arc<ThreadPool>>, ThreadPoolBuildError> {
+    static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+    if threads == 0 {
+        return Ok(None);
+    }
+    let mut pools = POOLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(pool) = pools.get(&threads) {
+        return Ok(Some(pool.clone()));
+    }
+    let pool = Arc::new(ThreadPoolBuilder::new().num_threads(threads).build()?);
+    pools.insert(threads, pool.clone());
+    Ok(Some(pool))
+}
+
+/// Run `f` on the `thread_pool` of `threads` threads, or on the global pool
+/// when it cannot be started
+pub(crate) fn with_threads<R, F>(threads: usize, f: F) -> R
+where
+    R: Send,
+    F: FnOnce() -> R + Send,
+{
+    match thread_pool(threads) {
+        Ok(Some(pool)) => pool.install(f),
+        Ok(None) => f(),
+        Err(e) => {
+            tracing::warn!(
+                "could not start {} rendering threads, using one per core: {}",
+                threads,
+                e
+            );
+            f()
+        }
+    }
+}
+
+/// Side of a pixel of a perspective camera on the image plane, at distance 1
+/// along `z`: the height of the image spans `fov` degrees
+fn pixel_size(camera_config: &CameraConfig) -> f64 {
+    2.0 * (camera_config.fov.to_radians() / 2.0).tan() / camera_config.height.max(1) as f64
+}
+
+/// Ray going through the point (i, j) of the camera plane, in pixels, (0, 0)
+/// being the center of the bottom left pixel, in the `projection` of the
+/// camera
+///
+/// Perspective rays go through the image plane at distance 1 along `z`,
+/// centered on the camera axis, whose square pixels are `pixel_size` wide.
+pub fn primary_ray(i: f64, j: f64, camera_config: &CameraConfig) -> Ray {
+    let (width, height) = (camera_config.width as f64, camera_config.height as f64);
+    let dir = match camera_config.projection {
+        Projection::Perspective => {
+            let size = pixel_size(camera_config);
+            ((i + 0.5 - width / 2.0) * size * camera_config.x
+                + (j + 0.5 - height / 2.0) * size * camera_config.y
                 + camera_config.z)
-                .normalize();
-            let ray = Ray::new(camera_position, dir);
-            let color = ray_tracer(ray);
-            img.put_pixel(i, height - 1 - j, Rgb([color[0], color[1], color[2]]));
+                .normalize()
+        }
+        Projection::Equirectangular => {
+            let longitude = 2.0 * PI * ((i + 0.5) / width - 0.5);
+            let colatitude = PI * (1.0 - (j + 0.5) / height);
+            colatitude.sin()
+                * (longitude.sin() * camera_config.x + longitude.cos() * camera_config.z)
+                + colatitude.cos() * camera_config.y
+        }
+        Projection::Fisheye { fov } => {
+            let (x, y) = (i + 0.5 - width / 2.0, j + 0.5 - height / 2.0);
+            let radius = x.hypot(y) / (width.min(height) / 2.0);
+            let angle = (radius * fov.to_radians() / 2.0).min(PI);
+            let around = y.atan2(x);
+            angle.sin() * (around.cos() * camera_config.x + around.sin() * camera_config.y)
+                + angle.cos() * camera_config.z
+        }
+    };
+    Ray::new(camera_config.camera_position, dir)
+}
+
+/// `primary_ray` through a thin lens of diameter `camera_config.aperture`:
+/// leaving the lens at `lens`, a point in [0, 1)² mapped onto its disk, to
+/// the point of the pinhole ray at `focus_distance` along the view axis,
+/// which is sharp whatever the point of the lens
+///
+/// Only perspective cameras have a lens, the others stay sharp.
+pub(crate) fn lens_ray(i: f64, j: f64, lens: (f64, f64), camera_config: &CameraConfig) -> Ray {
+    let ray = primary_ray(i, j, camera_config);
+    if camera_config.aperture <= 0.0 || camera_config.projection != Projection::Perspective {
+        return ray;
+    }
+    let focus = ray.position
+        + ray.direction * (camera_config.focus_distance / ray.direction.dot(&camera_config.z));
+    // Concentric mapping of the square onto the disk, keeping the areas
+    let (a, b) = (2.0 * lens.0 - 1.0, 2.0 * lens.1 - 1.0);
+    let (radius, angle) = if a == 0.0 && b == 0.0 {
+        (0.0, 0.0)
+    } else if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+    let radius = radius * camera_config.aperture / 2.0;
+    let origin = camera_config.camera_position
+        + camera_config.x * (radius * angle.cos())
+        + camera_config.y * (radius * angle.sin());
+    Ray::new(origin, (focus - origin).normalize())
+}
+
+/// Point (i, j) of the camera plane whose `primary_ray` goes through
+/// `point`, `None` behind a perspective camera or at the camera; the camera
+/// axes are orthonormal
+pub(crate) fn project(point: &Position, camera_config: &CameraConfig) -> Option<(f64, f64)> {
+    let (width, height) = (camera_config.width as f64, camera_config.height as f64);
+    let offset = point - camera_config.camera_position;
+    let (x, y, z) = (
+        offset.dot(&camera_config.x),
+        offset.dot(&camera_config.y),
+        offset.dot(&camera_config.z),
+    );
+    match camera_config.projection {
+        Projection::Perspective => {
+            if z <= 0.0 {
+                return None;
+            }
+            let size = pixel_size(camera_config);
+            Some((
+                x / z / size + width / 2.0 - 0.5,
+                y / z / size + height / 2.0 - 0.5,
+            ))
+        }
+        Projection::Equirectangular => {
+            let distance = offset.norm();
+            if distance == 0.0 {
+                return None;
+            }
+            let longitude = x.atan2(z);
+            let colatitude = (y / distance).clamp(-1.0, 1.0).acos();
+            Some((
+                (longitude / (2.0 * PI) + 0.5) * width - 0.5,
+                (1.0 - colatitude / PI) * height - 0.5,
+            ))
+        }
+        Projection::Fisheye { fov } => {
+            let distance = offset.norm();
+            if distance == 0.0 {
+                return None;
+            }
+            let angle = (z / distance).clamp(-1.0, 1.0).acos();
+            let radius = angle / (fov.to_radians() / 2.0) * width.min(height) / 2.0;
+            let around = y.atan2(x);
+            Some((
+                radius * around.cos() + width / 2.0 - 0.5,
+                radius * around.sin() + height / 2.0 - 0.5,
+            ))
+        }
+    }
+}
+
+/// Render the image as `render_hdr_tiles` does without storing it, handing
+/// the pixels of every finished tile to `on_pixel`
+///
+/// This lets streaming consumers (previews, encoders, ...) avoid a full frame
+/// copy. `on_pixel` is called from the rendering threads with the pixels in
+/// the coordinates of the region, (0, 0) being its top left corner, and
+/// their linear colors.
+pub fn render_pixels<F, C>(
+    ray_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    on_pixel: C,
+) where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+    C: Fn(u32, u32, Color) + Sync,
+{
+    let region = rendering_config.region(camera_config);
+    let rects = tile_grid(
+        region.width,
+        region.height,
+        rendering_config.tile_size.max(1),
+    );
+    let span = tracing::info_span!("render", width = region.width, height = region.height);
+    let _entered = span.enter();
+
+    with_threads(rendering_config.threads, || {
+        rects.par_iter().for_each(|rect| {
+            let tile = render_tile(&ray_tracer, camera_config, rendering_config, *rect, &span);
+            for (index, color) in tile.colors.chunks(3).enumerate() {
+                let index = index as u32;
+                let color = Color::new(color[0] as f64, color[1] as f64, color[2] as f64);
+                on_pixel(
+                    rect.x + index % rect.width,
+                    rect.y + index / rect.width,
+                    color,
+                );
+            }
+        })
+    })
+}
+
+/// Summary of the difference between two renders of the same size
+pub struct ImageDifference {
+    /// Root mean square error over all channels, in 8-bit units
+    pub rmse: f64,
+    /// Fraction of the pixels whose color differs
+    pub changed_fraction: f64,
+}
+
+/// Compare two renders pixel by pixel
+///
+/// Returns None if the images do not have the same dimensions
+pub fn compare_images(a: &RgbImage, b: &RgbImage) -> Option<ImageDifference> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    let mut squared_error = 0.0;
+    let mut changed = 0;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        if pa != pb {
+            changed += 1;
+        }
+        for c in 0..3 {
+            let d = pa[c] as f64 - pb[c] as f64;
+            squared_error += d * d;
         }
     }
+    let nb_pixels = (a.width() * a.height()).max(1) as f64;
+    Some(ImageDifference {
+        rmse: (squared_error / (3.0 * nb_pixels)).sqrt(),
+        changed_fraction: changed as f64 / nb_pixels,
+    })
+}
 
-    return img;
+/// False color image of the per-pixel difference between two renders
+///
+/// The largest difference maps to white, going through red and yellow, so
+/// that small changes remain visible. Returns None if the images do not have
+/// the same dimensions.
+pub fn difference_heatmap(a: &RgbImage, b: &RgbImage) -> Option<RgbImage> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    let difference = |x: u32, y: u32| {
+        let (pa, pb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        (0..3)
+            .map(|c| (pa[c] as f32 - pb[c] as f32).abs())
+            .fold(0.0, f32::max)
+    };
+    let mut max_difference: f32 = 0.0;
+    for (x, y, _) in a.enumerate_pixels() {
+        max_difference = max_difference.max(difference(x, y));
+    }
+    let scale = if max_difference > 0.0 {
+        1.0 / max_difference
+    } else {
+        0.0
+    };
+    Some(RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let t = (difference(x, y) * scale) as f64;
+        Rgb(Color::new(3.0 * t, 3.0 * t - 1.0, 3.0 * t - 2.0).to_u8())
+    }))
+}
+
+/// Auxiliary outputs of a render, pixel by pixel, rows top first
+pub struct Aovs {
+    /// Same as `render_hdr_image`
+    pub color: HdrRgbImage,
+    /// Distance to the closest hit of the pixel, infinite without hit, for
+    /// `depth::depth_to_image`
+    pub depth: Vec<f64>,
+    /// Average shading normal, in world coordinates
+    pub normal: HdrRgbImage,
+    /// Average base color of the materials
+    pub albedo: HdrRgbImage,
+}
+
+impl Aovs {
+    /// Save all the passes as the layers of an OpenEXR image: `R`, `G`, `B`
+    /// and `Z` as usual, then `albedo.*` and `normal.*`
+    pub fn save_exr(&self, path: &Path) -> io::Result<()> {
+        let _span = tracing::info_span!("save_exr", path = %path.display()).entered();
+        let channel =
+            |img: &HdrRgbImage, c: usize| -> Vec<f32> { img.pixels().map(|p| p[c]).collect() };
+        let depth: Vec<f32> = self.depth.iter().map(|&d| d as f32).collect();
+        let planes = [
+            ("R", channel(&self.color, 0)),
+            ("G", channel(&self.color, 1)),
+            ("B", channel(&self.color, 2)),
+            ("Z", depth),
+            ("albedo.R", channel(&self.albedo, 0)),
+            ("albedo.G", channel(&self.albedo, 1)),
+            ("albedo.B", channel(&self.albedo, 2)),
+            ("normal.X", channel(&self.normal, 0)),
+            ("normal.Y", channel(&self.normal, 1)),
+            ("normal.Z", channel(&self.normal, 2)),
+        ];
+        let channels: Vec<(&str, &[f32])> = planes
+            .iter()
+            .map(|(name, values)| (*name, &values[..]))
+            .collect();
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_exr(
+            &mut writer,
+            self.color.width(),
+            self.color.height(),
+            &channels,
+        )?;
+        writer.flush()
+    }
+}
+
+/// Render the color of the image with its depth, normal and albedo passes,
+/// with the same samples and crop as `render_hdr_image`
+pub fn render_aovs<F>(
+    sample_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+) -> Aovs
+where
+    F: Fn(Ray, SampleKey) -> Sample + Sync,
+{
+    let height = camera_config.height;
+    let region = rendering_config.region(camera_config);
+    let samples_per_pixel = rendering_config.samples_per_pixel.max(1);
+    let span = tracing::info_span!(
+        "render_aovs",
+        width = region.width,
+        height = region.height,
+        samples_per_pixel
+    );
+    let _entered = span.enter();
+
+    let rows: Vec<Vec<Sample>> = with_threads(rendering_config.threads, || {
+        (region.y..region.y + region.height)
+            .into_par_iter()
+            .map(|y| {
+                let _row_span = tracing::debug_span!(parent: &span, "row", y).entered();
+                // Rows are stored top first, the camera y axis goes up
+                let j = height - 1 - y;
+                (region.x..region.x + region.width)
+                    .map(|i| {
+                        let offsets = pixel_samples(
+                            rendering_config.sampler,
+                            samples_per_pixel,
+                            rendering_config.seed,
+                            i,
+                            y,
+                        );
+                        let mut pixel = Sample {
+                            color: Color::BLACK,
+                            depth: f64::INFINITY,
+                            normal: Direction::zeros(),
+                            albedo: Color::BLACK,
+                        };
+                        for (&(dx, dy), s) in offsets.iter().zip(0..) {
+                            let lens = lens_sample(rendering_config.seed, i, y, s);
+                            let ray = lens_ray(i as f64 + dx, j as f64 + dy, lens, camera_config);
+                            let sample = sample_tracer(ray, SampleKey { x: i, y, sample: s });
+                            pixel.color += sample.color;
+                            pixel.depth = pixel.depth.min(sample.depth);
+                            pixel.normal += sample.normal;
+                            pixel.albedo += sample.albedo;
+                        }
+                        let count = offsets.len() as f64;
+                        pixel.color = pixel.color / count;
+                        pixel.normal = pixel.normal.try_normalize(1e-12).unwrap_or(pixel.normal);
+                        pixel.albedo = pixel.albedo / count;
+                        pixel
+                    })
+                    .collect()
+            })
+            .collect()
+    });
+
+    let image = |value: &dyn Fn(&Sample) -> [f64; 3]| {
+        HdrRgbImage::from_fn(region.width, region.height, |x, y| {
+            Rgb(value(&rows[y as usize][x as usize]).map(|c| c as f32))
+        })
+    };
+    Aovs {
+        color: image(&|p| p.color.into()),
+        depth: rows.iter().flatten().map(|p| p.depth).collect(),
+        normal: image(&|p| p.normal.into()),
+        albedo: image(&|p| p.albedo.into()),
+    }
+}
+
+/// Write named channels of 32-bit floats as an uncompressed OpenEXR image,
+/// e.g. the color of a render and its AOVs (depth, normals...) for
+/// compositing
+///
+/// Every channel holds `width * height` values, rows top first. Channels are
+/// stored in the order of their names, as the format requires; `R`, `G` and
+/// `B` are shown as the color by the viewers.
+///
+/// # Reference
+/// * https://openexr.com/en/latest/OpenEXRFileLayout.html
+pub fn write_exr<W: Write>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    channels: &[(&str, &[f32])],
+) -> io::Result<()> {
+    let mut channels = channels.to_vec();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, values) in &channels {
+        if values.len() != (width * height) as usize || name.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("channel {:?} does not match the image size", name),
+            ));
+        }
+    }
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        header.extend(name.as_bytes());
+        header.push(0);
+        header.extend(kind.as_bytes());
+        header.push(0);
+        header.extend(&(value.len() as i32).to_le_bytes());
+        header.extend(value);
+    };
+    let mut channel_list = Vec::new();
+    for (name, _) in &channels {
+        channel_list.extend(name.as_bytes());
+        channel_list.push(0);
+        // FLOAT pixels, not perceptually linear, no subsampling
+        channel_list.extend(&2i32.to_le_bytes());
+        channel_list.extend(&[0, 0, 0, 0]);
+        channel_list.extend(&1i32.to_le_bytes());
+        channel_list.extend(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    attribute("channels", "chlist", &channel_list);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    // Magic number and version 2, single part scan lines
+    writer.write_all(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0])?;
+    writer.write_all(&header)?;
+    // Offset of every line from the start of the file
+    let line_size = 8 + 4 * width as u64 * channels.len() as u64;
+    let first_line = 8 + header.len() as u64 + 8 * height as u64;
+    for y in 0..height as u64 {
+        writer.write_all(&(first_line + y * line_size).to_le_bytes())?;
+    }
+    for y in 0..height as usize {
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&((line_size - 8) as i32).to_le_bytes())?;
+        for (_, values) in &channels {
+            let row = &values[y * width as usize..(y + 1) * width as usize];
+            for value in row {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Save the linear colors of a render as an OpenEXR image, see `write_exr`
+pub fn save_exr(img: &HdrRgbImage, path: &Path) -> io::Result<()> {
+    let _span = tracing::info_span!("save_exr", path = %path.display()).entered();
+    let channel = |c: usize| -> Vec<f32> { img.pixels().map(|p| p[c]).collect() };
+    let (r, g, b) = (channel(0), channel(1), channel(2));
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_exr(
+        &mut writer,
+        img.width(),
+        img.height(),
+        &[("R", &r), ("G", &g), ("B", &b)],
+    )?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::framebuffer::ToneMapping;
+    use crate::render::sampler::SamplerKind;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn lenses_blur_what_is_out_of_focus() {
+        // A wall facing the camera, black left of x = 0 and white right of
+        // it, which falls between the pixels 4 and 5
+        let edge = |wall: f64| {
+            move |ray: Ray, _: SampleKey| {
+                let t = (wall - ray.position.z) / ray.direction.z;
+                Color::gray(if ray.position.x + t * ray.direction.x > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                })
+            }
+        };
+        let camera_config = CameraConfig {
+            width: 10,
+            height: 1,
+            fov: 2.0,
+            aperture: 2.0,
+            ..Default::default()
+        };
+        let rendering_config = RenderingConfig {
+            samples_per_pixel: 64,
+            ..Default::default()
+        };
+        let edge_pixels = |wall: f64| {
+            let img = render_hdr_image(edge(wall), &camera_config, &rendering_config);
+            (img.get_pixel(4, 0)[0], img.get_pixel(5, 0)[0])
+        };
+        // The camera is 10 units behind the origin, and focuses 10 units away
+        assert_eq!(edge_pixels(0.0), (0.0, 1.0));
+        let (left, right) = edge_pixels(10.0);
+        assert!(left > 0.05 && right < 0.95, "{} {}", left, right);
+
+        // Every point of the lens sees the point in focus of the pinhole ray
+        let pinhole = primary_ray(2.0, 0.0, &camera_config);
+        let focus = pinhole.position + pinhole.direction * (10.0 / pinhole.direction.z);
+        for &lens in &[(0.0, 0.0), (0.9, 0.2), (0.5, 0.99)] {
+            let ray = lens_ray(2.0, 0.0, lens, &camera_config);
+            assert!((ray.position - camera_config.camera_position).norm() <= 1.0 + 1e-12);
+            let t = (focus.z - ray.position.z) / ray.direction.z;
+            assert!((ray.position + ray.direction * t - focus).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn perspective_spans_the_vertical_field_of_view() {
+        let camera_config = CameraConfig {
+            width: 8,
+            height: 4,
+            fov: 90.0,
+            ..Default::default()
+        };
+        let direction = |i: f64, j: f64| {
+            let d = primary_ray(i, j, &camera_config).direction;
+            d / d.z
+        };
+        // The edges of the image, half a pixel from the outer centers
+        assert!((direction(3.5, 3.5) - Direction::new(0.0, 1.0, 1.0)).norm() < 1e-9);
+        assert!((direction(-0.5, -0.5) - Direction::new(-2.0, -1.0, 1.0)).norm() < 1e-9);
+        assert!((direction(7.5, 1.5) - Direction::new(2.0, 0.0, 1.0)).norm() < 1e-9);
+        let (i, j) = project(&Position::new(1.0, -0.5, -9.0), &camera_config).unwrap();
+        assert!((i - 5.5).abs() < 1e-9 && (j - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn panoramas_see_all_around() {
+        let camera_config = CameraConfig {
+            width: 8,
+            height: 4,
+            projection: Projection::Equirectangular,
+            ..Default::default()
+        };
+        let direction = |i: f64, j: f64, camera_config: &CameraConfig| {
+            primary_ray(i, j, camera_config).direction
+        };
+        let close = |a: Direction, b: Direction| (a - b.normalize()).norm() < 1e-9;
+        // Left edge behind, middle ahead, right of the middle toward +x, top
+        // up, as environment images
+        assert!(close(direction(-0.5, 1.5, &camera_config), -Direction::z()));
+        assert!(close(direction(3.5, 1.5, &camera_config), Direction::z()));
+        assert!(close(direction(5.5, 1.5, &camera_config), Direction::x()));
+        assert!(close(direction(3.5, 3.5, &camera_config), Direction::y()));
+
+        let fisheye = CameraConfig {
+            projection: Projection::Fisheye { fov: 180.0 },
+            ..camera_config.clone()
+        };
+        assert!(close(direction(3.5, 1.5, &fisheye), Direction::z()));
+        // The circle of 180 degrees touches the top and bottom of the image
+        assert!(close(direction(3.5, 3.5, &fisheye), Direction::y()));
+        assert!(close(direction(1.5, 1.5, &fisheye), -Direction::x()));
+
+        for camera_config in [&camera_config, &fisheye] {
+            for &(i, j) in &[(0.0, 0.0), (6.3, 2.1), (4.0, 3.0)] {
+                let ray = primary_ray(i, j, camera_config);
+                let (x, y) = project(&(ray.position + ray.direction * 3.0), camera_config).unwrap();
+                assert!((x - i).abs() < 1e-9 && (y - j).abs() < 1e-9, "{} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn tiled_render_matches_pixel_order() {
+        let camera_config = CameraConfig {
+            width: 7,
+            height: 5,
+            ..Default::default()
+        };
+        let tracer = |ray: Ray, _: SampleKey| {
+            let d = ray.direction;
+            Color::new((d.x + 1.0) / 2.0, (d.y + 1.0) / 2.0, d.z)
+        };
+
+        let mut expected = RgbImage::new(7, 5);
+        for (x, y, pixel) in expected.enumerate_pixels_mut() {
+            let j = 5 - 1 - y;
+            *pixel = Rgb(tracer(
+                primary_ray(x as f64, j as f64, &camera_config),
+                SampleKey::default(),
+            )
+            .to_u8());
+        }
+        let rendering_config = RenderingConfig {
+            threads: 3,
+            tile_size: 3,
+            ..Default::default()
+        };
+        let rendered_pixels = AtomicUsize::new(0);
+        let img = render_tiles(tracer, &camera_config, &rendering_config, |tile| {
+            assert_eq!(
+                tile.pixels.len() as u32,
+                tile.rect.width * tile.rect.height * 3
+            );
+            rendered_pixels.fetch_add(tile.pixels.len() / 3, Ordering::Relaxed);
+        });
+        assert_eq!(img, expected);
+        assert_eq!(rendered_pixels.into_inner(), 7 * 5);
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_image() {
+        let camera_config = CameraConfig {
+            width: 19,
+            height: 11,
+            ..Default::default()
+        };
+        let tracer = |ray: Ray, _: SampleKey| {
+            let d = ray.direction;
+            let wave = ((d.x * 40.0).sin() * (d.y * 30.0).cos() + 1.0) / 2.0;
+            Color::new(wave, wave * wave, d.z)
+        };
+        let render = |threads| {
+            let rendering_config = RenderingConfig {
+                threads,
+                tile_size: 4,
+                samples_per_pixel: 5,
+                sampler: SamplerKind::Jittered,
+                seed: 7,
+                ..Default::default()
+            };
+            render_image(tracer, &camera_config, &rendering_config)
+        };
+        let reference = render(1);
+        for &threads in &[2, 3, 8] {
+            assert!(render(threads) == reference, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn seed_alone_decides_the_random_samples() {
+        let camera_config = CameraConfig {
+            width: 9,
+            height: 7,
+            ..Default::default()
+        };
+        let tracer =
+            |ray: Ray, _: SampleKey| Color::new((ray.direction.x * 50.0).sin().abs(), 0.5, 0.5);
+        let render = |seed| {
+            let rendering_config = RenderingConfig {
+                samples_per_pixel: 3,
+                sampler: SamplerKind::Stratified,
+                seed,
+                ..Default::default()
+            };
+            render_hdr_image(tracer, &camera_config, &rendering_config)
+        };
+        let reference = render(11);
+        // Bit for bit
+        assert!(render(11).as_raw() == reference.as_raw());
+        assert!(render(12).as_raw() != reference.as_raw());
+    }
+
+    #[test]
+    fn thread_pools_are_kept_per_thread_count() {
+        assert!(thread_pool(0).unwrap().is_none());
+        let pool = thread_pool(3).unwrap().unwrap();
+        assert_eq!(pool.current_num_threads(), 3);
+        assert!(Arc::ptr_eq(&pool, &thread_pool(3).unwrap().unwrap()));
+        assert!(!Arc::ptr_eq(&pool, &thread_pool(2).unwrap().unwrap()));
+    }
+
+    #[test]
+    fn crop_renders_a_window_of_the_full_image() {
+        let camera_config = CameraConfig {
+            width: 12,
+            height: 9,
+            ..Default::default()
+        };
+        let tracer = |ray: Ray, _: SampleKey| {
+            Color::new((ray.direction.x * 50.0).sin().abs(), ray.direction.y, 0.5)
+        };
+        let mut rendering_config = RenderingConfig {
+            tile_size: 2,
+            samples_per_pixel: 3,
+            sampler: SamplerKind::Jittered,
+            ..Default::default()
+        };
+        let full = render_hdr_image(tracer, &camera_config, &rendering_config);
+
+        rendering_config.crop = Some("3,2,5,4".parse().unwrap());
+        let crop = render_hdr_image(tracer, &camera_config, &rendering_config);
+        assert_eq!(crop.dimensions(), (5, 4));
+        for (x, y, pixel) in crop.enumerate_pixels() {
+            assert_eq!(pixel, full.get_pixel(x + 3, y + 2));
+        }
+        let sample_tracer = |ray: Ray, key: SampleKey| Sample {
+            color: tracer(ray, key),
+            depth: 1.0,
+            normal: Direction::zeros(),
+            albedo: Color::WHITE,
+        };
+        let aovs = render_aovs(sample_tracer, &camera_config, &rendering_config);
+        assert!(aovs.color == crop);
+        let streamed = Mutex::new(HdrRgbImage::new(5, 4));
+        render_pixels(tracer, &camera_config, &rendering_config, |x, y, color| {
+            let pixel = Rgb(<[f64; 3]>::from(color).map(|c| c as f32));
+            streamed.lock().unwrap().put_pixel(x, y, pixel);
+        });
+        assert!(streamed.into_inner().unwrap() == crop);
+
+        // Cut to the image
+        rendering_config.crop = Some("10,0,5,20".parse().unwrap());
+        let crop = render_hdr_image(tracer, &camera_config, &rendering_config);
+        assert_eq!(crop.dimensions(), (2, 9));
+    }
+
+    #[test]
+    fn supersampling_smooths_edges() {
+        let camera_config = CameraConfig {
+            width: 9,
+            height: 1,
+            ..Default::default()
+        };
+        // Vertical edge through the center of the column 4
+        let tracer = |ray: Ray, _: SampleKey| {
+            if ray.direction.x > 0.0 {
+                Color::WHITE
+            } else {
+                Color::BLACK
+            }
+        };
+        let mut rendering_config = RenderingConfig::default();
+        let aliased = render_image(tracer, &camera_config, &rendering_config);
+        assert_eq!(aliased.get_pixel(4, 0), &Rgb([0, 0, 0]));
+
+        rendering_config.samples_per_pixel = 4;
+        let smooth = render_image(tracer, &camera_config, &rendering_config);
+        assert_eq!(smooth.get_pixel(4, 0), &Rgb([128, 128, 128]));
+        assert_eq!(smooth.get_pixel(5, 0), &Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn samples_integrate_the_pixel_footprint() {
+        let camera_config = CameraConfig {
+            width: 32,
+            height: 8,
+            ..Default::default()
+        };
+        let size = pixel_size(&camera_config);
+        // Linear across the image plane, in red and green: the mean over a
+        // pixel is the value at its center
+        let plane = |ray: Ray, _: SampleKey| {
+            let point = ray.direction / ray.direction.z;
+            Color::new(point.x, point.y, 0.7)
+        };
+        for &sampler in SamplerKind::ALL.iter() {
+            for &samples_per_pixel in [4, 9, 16].iter() {
+                let rendering_config = RenderingConfig {
+                    sampler,
+                    samples_per_pixel,
+                    seed: 5,
+                    ..Default::default()
+                };
+                let img = render_hdr_image(plane, &camera_config, &rendering_config);
+                let (width, height) = img.dimensions();
+                let name = format!("{} x{}", sampler, samples_per_pixel);
+                // The weights of the samples add up to 1
+                for pixel in img.pixels() {
+                    assert!((pixel[2] - 0.7).abs() < 1e-6, "{}: {:?}", name, pixel);
+                }
+                // Centered on the pixels: no shift of the image, which
+                // would brighten or darken it where it has gradients
+                let mut bias = [0.0, 0.0];
+                for (x, y, pixel) in img.enumerate_pixels() {
+                    let j = height - 1 - y;
+                    let center = [
+                        (x as f64 + 0.5 - width as f64 / 2.0) * size,
+                        (j as f64 + 0.5 - height as f64 / 2.0) * size,
+                    ];
+                    for c in 0..2 {
+                        let error = (pixel[c] as f64 - center[c]) / size;
+                        assert!(error.abs() < 0.5, "{}: {} off", name, error);
+                        bias[c] += error / (width * height) as f64;
+                    }
+                }
+                let tolerance = match sampler {
+                    SamplerKind::Regular => 1e-5,
+                    SamplerKind::Jittered | SamplerKind::Stratified => 0.05,
+                };
+                assert!(
+                    bias.iter().all(|b| b.abs() < tolerance),
+                    "{}: {:?}",
+                    name,
+                    bias
+                );
+                // One pixel of the plane per pixel of the image, along
+                // both axes
+                let gradient_x = (0..height)
+                    .map(|y| img.get_pixel(width - 1, y)[0] - img.get_pixel(0, y)[0])
+                    .sum::<f32>() as f64
+                    / (height * (width - 1)) as f64;
+                let gradient_y = (0..width)
+                    .map(|x| img.get_pixel(x, 0)[1] - img.get_pixel(x, height - 1)[1])
+                    .sum::<f32>() as f64
+                    / (width * (height - 1)) as f64;
+                assert!((gradient_x / size - 1.0).abs() < 0.02, "{}", name);
+                assert!((gradient_y / size - 1.0).abs() < 0.1, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn tone_mapping_keeps_the_highlights() {
+        let camera_config = CameraConfig {
+            width: 2,
+            height: 1,
+            ..Default::default()
+        };
+        // Highlight four times brighter than white on the right
+        let tracer = |ray: Ray, _: SampleKey| {
+            if ray.direction.x >= 0.0 {
+                Color::gray(4.0)
+            } else {
+                Color::gray(0.5)
+            }
+        };
+        let mut rendering_config = RenderingConfig::default();
+        let hdr = render_hdr_image(tracer, &camera_config, &rendering_config);
+        assert_eq!(hdr.get_pixel(1, 0), &Rgb([4.0; 3]));
+        assert_eq!(
+            to_display(&hdr, &rendering_config).get_pixel(1, 0),
+            &Rgb([255; 3])
+        );
+
+        rendering_config.tone_mapping = ToneMapping::Reinhard;
+        let reinhard = render_image(tracer, &camera_config, &rendering_config);
+        assert_eq!(reinhard.get_pixel(0, 0), &Rgb([85; 3]));
+        assert_eq!(reinhard.get_pixel(1, 0), &Rgb([204; 3]));
+
+        rendering_config.tone_mapping = ToneMapping::Aces;
+        let aces = to_display(&hdr, &rendering_config);
+        assert!(aces.get_pixel(1, 0)[0] > 204 && aces.get_pixel(1, 0)[0] < 255);
+        assert!(aces.get_pixel(0, 0)[0] > 85);
+    }
+
+    #[test]
+    fn auto_exposure_brings_dim_scenes_to_middle_gray() {
+        let camera_config = CameraConfig {
+            width: 4,
+            height: 4,
+            ..Default::default()
+        };
+        let rendering_config = RenderingConfig {
+            exposure: Exposure::Auto,
+            ..Default::default()
+        };
+        let img = render_image(|_, _| Color::gray(0.01), &camera_config, &rendering_config);
+        let gray = rendering_config
+            .output_transform
+            .apply(Color::gray(0.18))
+            .to_u8();
+        for pixel in img.pixels() {
+            assert!((i32::from(pixel[0]) - i32::from(gray[0])).abs() <= 1);
+        }
+        // The linear colors are left as traced
+        let hdr = render_hdr_image(|_, _| Color::gray(0.01), &camera_config, &rendering_config);
+        assert_eq!(hdr.get_pixel(0, 0), &Rgb([0.01; 3]));
+        assert!((image_exposure(&hdr) - (0.18f32 / 0.0101).log2()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn exr_layout() {
+        use std::convert::TryInto;
+
+        let red = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let green = [0.5; 6];
+        let mut file = Vec::new();
+        write_exr(&mut file, 3, 2, &[("R", &red), ("G", &green)]).unwrap();
+        assert_eq!(&file[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        // Lines of two channels of three floats, after the offset table
+        let line_size = 8 + 2 * 3 * 4;
+        let second_line = file.len() - line_size;
+        let offset_table = second_line - line_size - 16;
+        let offset = |i: usize| {
+            let bytes = &file[offset_table + 8 * i..offset_table + 8 * (i + 1)];
+            u64::from_le_bytes(bytes.try_into().unwrap()) as usize
+        };
+        assert_eq!(offset(0), second_line - line_size);
+        assert_eq!(offset(1), second_line);
+        let float = |at: usize| f32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+        assert_eq!(&file[second_line..second_line + 4], &1i32.to_le_bytes());
+        // G comes before R
+        assert_eq!(float(second_line + 8), 0.5);
+        assert_eq!(float(second_line + 8 + 12), 4.0);
+        assert_eq!(float(second_line + 8 + 20), 6.0);
+
+        assert!(write_exr(&mut Vec::new(), 2, 2, &[("R", &red)]).is_err());
+    }
+
+    #[test]
+    fn aovs_match_the_color_render() {
+        let camera_config = CameraConfig {
+            width: 6,
+            height: 4,
+            ..Default::default()
+        };
+        // Floor below the horizon, sky above
+        let sample_tracer = |ray: Ray, _: SampleKey| {
+            let d = ray.direction;
+            if d.y < 0.0 {
+                Sample {
+                    color: Color::new(0.5, 0.25, d.x.abs()),
+                    depth: 1.0 / -d.y,
+                    normal: Direction::y(),
+                    albedo: Color::gray(0.5),
+                }
+            } else {
+                Sample {
+                    color: Color::WHITE,
+                    depth: f64::INFINITY,
+                    normal: Direction::zeros(),
+                    albedo: Color::BLACK,
+                }
+            }
+        };
+        let rendering_config = RenderingConfig {
+            samples_per_pixel: 4,
+            sampler: SamplerKind::Jittered,
+            ..Default::default()
+        };
+        let aovs = render_aovs(sample_tracer, &camera_config, &rendering_config);
+        let color = render_hdr_image(
+            |ray, key| sample_tracer(ray, key).color,
+            &camera_config,
+            &rendering_config,
+        );
+        assert_eq!(aovs.color, color);
+
+        assert_eq!(aovs.depth.len(), 6 * 4);
+        assert!(aovs.depth[0].is_infinite());
+        assert!(aovs.depth[6 * 4 - 1].is_finite());
+        assert_eq!(aovs.normal.get_pixel(0, 3), &Rgb([0.0, 1.0, 0.0]));
+        assert_eq!(aovs.normal.get_pixel(0, 0), &Rgb([0.0; 3]));
+        assert_eq!(aovs.albedo.get_pixel(5, 3), &Rgb([0.5; 3]));
+    }
 }
@@ -0,0 +1,341 @@
+extern crate nalgebra as na;
+extern crate rayon;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use self::rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::kdtree::KdTree;
+use crate::geometry::mesh::Mesh;
+use crate::geometry::ray::Ray;
+use crate::geometry::types::{Direction, Position};
+use crate::render::config::{self, CameraConfig, ConfigError, CONFIG_VERSION};
+use crate::render::image::primary_ray;
+use crate::render::ray_tracer::make_kdt_hit_finder;
+use crate::render::scene::Transform;
+
+/// Layout of the rays of a range sensor, in its own frame: looking along
+/// +z, +y up, as cameras do
+///
+/// Spelled `{ "kind": "spinning", "channels": 16, "steps": 1800,
+/// "elevation": [-15, 15] }` in sensor files.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScanPattern {
+    /// Lidar spinning around its up axis: `channels` lasers spread evenly
+    /// from `elevation[0]` to `elevation[1]` degrees above the horizontal
+    /// plane, all firing `steps` times per turn, from +z toward +x
+    Spinning {
+        channels: u32,
+        steps: u32,
+        elevation: [f64; 2],
+    },
+    /// Depth camera: a ray through every pixel of a `width` by `height`
+    /// pinhole camera, `fov` degrees from the top to the bottom of the image
+    DepthCamera { width: u32, height: u32, fov: f64 },
+}
+
+impl ScanPattern {
+    /// Directions of the rays in the frame of the sensor, normalized, in the
+    /// order of the scan: every channel at each step of a spinning lidar,
+    /// the rows of pixels from the top of a depth camera
+    pub fn directions(&self) -> Vec<Direction> {
+        match *self {
+            ScanPattern::Spinning {
+                channels,
+                steps,
+                elevation,
+            } => {
+                let elevations: Vec<f64> = (0..channels)
+                    .map(|channel| {
+                        let t = match channels {
+                            1 => 0.5,
+                            _ => channel as f64 / (channels - 1) as f64,
+                        };
+                        (elevation[0] + t * (elevation[1] - elevation[0])).to_radians()
+                    })
+                    .collect();
+                (0..steps)
+                    .flat_map(|step| {
+                        let azimuth = 2.0 * std::f64::consts::PI * step as f64 / steps as f64;
+                        elevations.iter().map(move |elevation| {
+                            Direction::new(
+                                elevation.cos() * azimuth.sin(),
+                                elevation.sin(),
+                                elevation.cos() * azimuth.cos(),
+                            )
+                        })
+                    })
+                    .collect()
+            }
+            ScanPattern::DepthCamera { width, height, fov } => {
+                let camera = CameraConfig {
+                    camera_position: Position::origin(),
+                    x: Direction::x(),
+                    y: Direction::y(),
+                    z: Direction::z(),
+                    fov,
+                    width,
+                    height,
+                    ..Default::default()
+                };
+                (0..height)
+                    .flat_map(|row| (0..width).map(move |column| (column, height - 1 - row)))
+                    .map(|(i, j)| primary_ray(i as f64, j as f64, &camera).direction)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// A range sensor placed in the scene, as stored in sensor files, JSON or
+/// TOML as the scene files
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    pub version: u32,
+    pub position: Position,
+    /// Rotations of the sensor around the x, y and z axes in degrees, applied
+    /// in this order, as those of the objects
+    pub rotation: [f64; 3],
+    pub pattern: ScanPattern,
+    /// Nearest and farthest distances measured, in scene units: the sensor
+    /// is blind to the surfaces outside
+    pub range: [f64; 2],
+}
+
+impl Default for SensorConfig {
+    /// Spinning lidar of 16 channels over 30 degrees, a step every 0.2
+    /// degree, measuring from 0.1 to 100 units, at the origin
+    fn default() -> Self {
+        SensorConfig {
+            version: CONFIG_VERSION,
+            position: Position::origin(),
+            rotation: [0.0, 0.0, 0.0],
+            pattern: ScanPattern::Spinning {
+                channels: 16,
+                steps: 1800,
+                elevation: [-15.0, 15.0],
+            },
+            range: [0.1, 100.0],
+        }
+    }
+}
+
+impl SensorConfig {
+    /// Load a sensor file, in TOML when it ends in `.toml` and in JSON
+    /// otherwise
+    pub fn load(path: &Path) -> Result<SensorConfig, ConfigError> {
+        config::load_versioned(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        fs::write(path, config::to_json(self) + "\n").map_err(ConfigError::Io)
+    }
+
+    /// From the frame of the sensor to the scene
+    pub fn pose(&self) -> na::Isometry3<f64> {
+        let transform = Transform {
+            translation: self.position.coords,
+            rotation: self.rotation,
+            scale: 1.0,
+        };
+        transform.similarity().isometry
+    }
+}
+
+/// Distances measured by a sensor, one per ray of its pattern
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scan {
+    /// Position of the sensor in the scene
+    pub origin: Position,
+    /// Direction of every ray in the scene, normalized
+    pub directions: Vec<Direction>,
+    /// Distance to the surface hit by every ray, infinite when there is none
+    /// within the range of the sensor
+    pub ranges: Vec<f64>,
+}
+
+/// Cast the rays of `sensor` on `mesh` through its kd-tree, in parallel
+pub fn scan(mesh: &Mesh, kdtree: &Box<KdTree>, sensor: &SensorConfig) -> Scan {
+    let _span = tracing::info_span!("scan", triangles = mesh.triangles.len()).entered();
+    let pose = sensor.pose();
+    let origin = Position::from(pose.translation.vector);
+    let directions: Vec<Direction> = sensor
+        .pattern
+        .directions()
+        .iter()
+        .map(|direction| pose.rotation * direction)
+        .collect();
+    let closest = make_kdt_hit_finder(mesh, kdtree);
+    let [near, far] = sensor.range;
+    let ranges = directions
+        .par_iter()
+        .map(|direction| {
+            let range = closest(&Ray::new(origin, *direction), far)
+                .map(|hit| (hit.intersection - origin).norm());
+            match range {
+                Some(range) if range >= near && range <= far => range,
+                _ => f64::INFINITY,
+            }
+        })
+        .collect();
+    Scan {
+        origin,
+        directions,
+        ranges,
+    }
+}
+
+impl Scan {
+    /// Points hit, in the scene, with the index of their ray
+    pub fn points(&self) -> impl Iterator<Item = (usize, Position)> + '_ {
+        self.ranges
+            .iter()
+            .enumerate()
+            .filter(|(_, range)| range.is_finite())
+            .map(move |(i, range)| (i, self.origin + self.directions[i] * *range))
+    }
+
+    /// Save the points hit, as a PLY point cloud when the path ends in
+    /// `.ply` and as CSV otherwise
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("ply") => self.write_ply(&mut writer)?,
+            _ => self.write_csv(&mut writer)?,
+        }
+        writer.flush()
+    }
+
+    /// Write the points hit as CSV: a header line, then the index of the ray,
+    /// the position and the range of every point
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "ray,x,y,z,range")?;
+        for (i, point) in self.points() {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                i, point.x, point.y, point.z, self.ranges[i]
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the points hit as a binary PLY point cloud: vertices without
+    /// faces, with their range and the index of their ray
+    pub fn write_ply<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "ply\nformat binary_little_endian 1.0\nelement vertex {}\n\
+             property double x\nproperty double y\nproperty double z\n\
+             property double range\nproperty uint ray\nend_header\n",
+            self.points().count()
+        )?;
+        for (i, point) in self.points() {
+            for value in &[point.x, point.y, point.z, self.ranges[i]] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.write_all(&(i as u32).to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wall of two triangles on the plane z = 5, from -10 to 10 in x and y
+    fn wall() -> Mesh {
+        Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-10.0, -10.0, 5.0),
+                Position::new(10.0, -10.0, 5.0),
+                Position::new(10.0, 10.0, 5.0),
+                Position::new(-10.0, 10.0, 5.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    #[test]
+    fn lidars_measure_the_distance_to_the_surfaces() {
+        let mesh = wall();
+        let kdtree = KdTree::from_mesh(&mesh);
+        let sensor = SensorConfig {
+            position: Position::new(0.0, 0.0, 1.0),
+            pattern: ScanPattern::Spinning {
+                channels: 3,
+                steps: 8,
+                elevation: [-10.0, 10.0],
+            },
+            ..Default::default()
+        };
+        let scan = scan(&mesh, &kdtree, &sensor);
+        assert_eq!(scan.ranges.len(), 24);
+        // Straight ahead, then at 45 degrees
+        assert!((scan.ranges[1] - 4.0).abs() < 1e-9);
+        assert!((scan.ranges[4] - 4.0 * 2f64.sqrt()).abs() < 1e-9);
+        // Rays facing away from the wall, from the side to the back, miss
+        assert_eq!(scan.points().count(), 9);
+        for (_, point) in scan.points() {
+            assert!((point.z - 5.0).abs() < 1e-9);
+        }
+
+        // Out of range, the wall is not seen
+        let near = SensorConfig {
+            range: [0.1, 3.0],
+            ..sensor.clone()
+        };
+        assert_eq!(super::scan(&mesh, &kdtree, &near).points().count(), 0);
+    }
+
+    #[test]
+    fn depth_cameras_follow_their_pose() {
+        let mesh = wall();
+        let kdtree = KdTree::from_mesh(&mesh);
+        // Turned to look along -x, then moved to look at the wall
+        let sensor = SensorConfig {
+            position: Position::new(0.0, 0.0, 0.0),
+            rotation: [0.0, -90.0, 0.0],
+            pattern: ScanPattern::DepthCamera {
+                width: 4,
+                height: 3,
+                fov: 30.0,
+            },
+            ..Default::default()
+        };
+        let directions = sensor.pattern.directions();
+        assert_eq!(directions.len(), 12);
+        // From the top left
+        assert!(directions[0].x < 0.0 && directions[0].y > 0.0);
+        assert!(directions[11].x > 0.0 && directions[11].y < 0.0);
+        assert_eq!(scan(&mesh, &kdtree, &sensor).points().count(), 0);
+
+        let facing = SensorConfig {
+            rotation: [0.0, 0.0, 0.0],
+            ..sensor
+        };
+        let scan = scan(&mesh, &kdtree, &facing);
+        assert_eq!(scan.points().count(), 12);
+        // The middle row is level with the sensor
+        let (_, point) = scan.points().nth(5).unwrap();
+        assert!(point.y.abs() < 1e-9);
+
+        let mut csv = Vec::new();
+        scan.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 13);
+        assert!(csv.starts_with("ray,x,y,z,range\n0,"));
+        let mut ply = Vec::new();
+        scan.write_ply(&mut ply).unwrap();
+        let header = b"end_header\n";
+        let end = ply.windows(header.len()).position(|w| w == header).unwrap();
+        assert_eq!(ply.len() - end - header.len(), 12 * (4 * 8 + 4));
+    }
+}
@@ -0,0 +1,483 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::import::Unit;
+use crate::geometry::mesh::LoadError;
+use crate::geometry::ray::Ray;
+use crate::geometry::types::{orthonormal_basis, Direction, Position};
+use crate::render::color::Color;
+
+/// Luminous efficacy of radiant watts at the peak of the eye sensitivity
+pub const LUMENS_PER_WATT: f64 = 683.0;
+
+/// Unit of the intensity of a light
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LightUnits {
+    /// Luminous intensity, lumens per steradian
+    #[default]
+    Candela,
+    /// Luminous flux, spread over the directions lit by the light
+    Lumens,
+    /// Radiant flux, as lumens at `LUMENS_PER_WATT`
+    Watts,
+}
+
+impl LightUnits {
+    /// Intensity in candela of a light emitting `value` over `solid_angle`
+    /// steradians
+    pub fn to_candela(self, value: f64, solid_angle: f64) -> f64 {
+        match self {
+            LightUnits::Candela => value,
+            LightUnits::Lumens => value / solid_angle,
+            LightUnits::Watts => value * LUMENS_PER_WATT / solid_angle,
+        }
+    }
+}
+
+/// Illuminance in lux received at `distance`, in scene units, from a light of
+/// `candela`, facing it
+///
+/// The inverse square law holds in meters, lights keep the same brightness
+/// whatever the unit of the scene.
+pub fn illuminance(candela: f64, distance: f64, units: Unit) -> f64 {
+    let meters = distance * units.meters();
+    candela / (meters * meters)
+}
+
+/// Solid angle of a cone of half angle `angle`, in degrees
+pub fn cone_solid_angle(angle: f64) -> f64 {
+    2.0 * std::f64::consts::PI * (1.0 - angle.min(180.0).to_radians().cos())
+}
+
+/// Light reaching a point from a light source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSample {
+    /// Toward the light, normalized
+    pub direction: Direction,
+    /// Distance to the light, infinite for directional lights
+    pub distance: f64,
+    /// Illuminance on a surface facing the light, in lux, per channel
+    pub illuminance: Color,
+}
+
+/// Source of direct light, sampled by the tracers to shade the hits
+pub trait Light: fmt::Debug + Send + Sync {
+    /// Light reaching `point`, in the scene `units`, ignoring occluders
+    ///
+    /// `None` when the light does not reach the point at all.
+    fn illuminate(&self, point: &Position, units: Unit) -> Option<LightSample>;
+}
+
+/// Light emitted uniformly in all directions from a point, e.g. a bulb
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointLight {
+    pub position: Position,
+    /// Luminous intensity, in candela
+    pub intensity: f64,
+    /// Tint multiplied with the intensity, white for neutral lights
+    pub color: Color,
+}
+
+impl Light for PointLight {
+    fn illuminate(&self, point: &Position, units: Unit) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.norm();
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            illuminance: self.color * illuminance(self.intensity, distance, units),
+        })
+    }
+}
+
+/// Parallel light coming from infinitely far away, e.g. the sun
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectionalLight {
+    /// Direction in which the light travels, normalized
+    pub direction: Direction,
+    /// Illuminance on a surface facing the light, in lux
+    pub illuminance: f64,
+    pub color: Color,
+}
+
+impl Light for DirectionalLight {
+    fn illuminate(&self, _point: &Position, _units: Unit) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: f64::INFINITY,
+            illuminance: self.color * self.illuminance,
+        })
+    }
+}
+
+/// Light emitted in a cone, e.g. a ceiling spot
+///
+/// Without a profile, the full `intensity` is emitted inside `cone_angle` and
+/// smoothly fades to nothing over `falloff_angle` more degrees. With an IES
+/// profile, the measured distribution of the fixture replaces the cone and
+/// `intensity` scales it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpotLight {
+    pub position: Position,
+    /// Axis of the cone, normalized
+    pub direction: Direction,
+    pub intensity: f64,
+    pub color: Color,
+    /// Half angle of the cone lit at full intensity, in degrees
+    pub cone_angle: f64,
+    /// Width of the transition outside of the cone, in degrees
+    pub falloff_angle: f64,
+    pub profile: Option<IesProfile>,
+}
+
+impl SpotLight {
+    /// Intensity emitted along `direction` (from the light, normalized)
+    pub fn intensity_toward(&self, direction: &Direction) -> f64 {
+        let angle = self
+            .direction
+            .dot(direction)
+            .clamp(-1.0, 1.0)
+            .acos()
+            .to_degrees();
+        match &self.profile {
+            Some(profile) => {
+                // Photometric horizontal angles turn around the axis, from an
+                // arbitrary but fixed reference
+                let (reference, side) = orthonormal_basis(&self.direction);
+                let horizontal = direction.dot(&side).atan2(direction.dot(&reference));
+                self.intensity * profile.candela(angle, horizontal.to_degrees())
+            }
+            None => {
+                let outside = angle - self.cone_angle;
+                if outside <= 0.0 {
+                    self.intensity
+                } else if outside >= self.falloff_angle {
+                    0.0
+                } else {
+                    // Smoothstep from the edge of the cone
+                    let t = 1.0 - outside / self.falloff_angle;
+                    self.intensity * t * t * (3.0 - 2.0 * t)
+                }
+            }
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn illuminate(&self, point: &Position, units: Unit) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.norm();
+        let direction = to_light / distance;
+        let intensity = self.intensity_toward(&-direction);
+        if intensity <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            illuminance: self.color * illuminance(intensity, distance, units),
+        })
+    }
+}
+
+/// Photometric profile of a light fixture, from an IESNA LM-63 file
+///
+/// Vertical angles go from 0 (the axis of the light) to 180 degrees,
+/// horizontal angles around the axis. Profiles covering less than 360
+/// horizontal degrees are symmetric.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IesProfile {
+    pub vertical_angles: Vec<f64>,
+    pub horizontal_angles: Vec<f64>,
+    /// Candela values, one row of `vertical_angles.len()` values per
+    /// horizontal angle, with the multiplier of the file applied
+    pub candela: Vec<Vec<f64>>,
+}
+
+impl IesProfile {
+    pub fn load(path: &Path) -> Result<IesProfile, LoadError> {
+        IesProfile::parse(&fs::read_to_string(path).map_err(LoadError::Io)?)
+    }
+
+    pub fn parse(content: &str) -> Result<IesProfile, LoadError> {
+        // Keywords come first, the numbers follow the TILT line
+        let mut lines = content.lines();
+        let tilt = lines
+            .by_ref()
+            .map(str::trim)
+            .find(|line| line.starts_with("TILT="))
+            .ok_or(LoadError::String("IES file without TILT line"))?;
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f64>().map_err(LoadError::ParseFloat));
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or(Err(LoadError::String("IES file truncated")))
+        };
+
+        if tilt == "TILT=INCLUDE" {
+            // Lamp to luminaire geometry, then pairs of angles and factors
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        // Photometric type, units, dimensions, ballast factors and watts
+        for _ in 0..8 {
+            next()?;
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(LoadError::String("IES file without angles"));
+        }
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<f64>, LoadError>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<f64>, LoadError>>()?;
+        let mut candela = Vec::with_capacity(horizontal_count);
+        for _ in 0..horizontal_count {
+            candela.push(
+                (0..vertical_count)
+                    .map(|_| next().map(|c| c * multiplier))
+                    .collect::<Result<Vec<f64>, LoadError>>()?,
+            );
+        }
+
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    /// Interpolated intensity at the given angles, in degrees
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let mut horizontal = horizontal.rem_euclid(360.0);
+        let last = *self.horizontal_angles.last().unwrap();
+        // Fold the angle in the range covered by the symmetric profiles
+        if last <= 180.0 && horizontal > 180.0 {
+            horizontal = 360.0 - horizontal;
+        }
+        if last <= 90.0 && horizontal > 90.0 {
+            horizontal = 180.0 - horizontal;
+        }
+
+        let (h0, h1, th) = bracket(&self.horizontal_angles, horizontal);
+        let (v0, v1, tv) = bracket(&self.vertical_angles, vertical);
+        let row = |h: usize| self.candela[h][v0] * (1.0 - tv) + self.candela[h][v1] * tv;
+        row(h0) * (1.0 - th) + row(h1) * th
+    }
+}
+
+/// Indices around `value` in the sorted `angles` and the interpolation
+/// factor between them, clamped at the ends
+fn bracket(angles: &[f64], value: f64) -> (usize, usize, f64) {
+    let upper = angles.partition_point(|&a| a <= value);
+    if upper == 0 {
+        return (0, 0, 0.0);
+    }
+    if upper == angles.len() {
+        return (upper - 1, upper - 1, 0.0);
+    }
+    let (a0, a1) = (angles[upper - 1], angles[upper]);
+    (upper - 1, upper, (value - a0) / (a1 - a0))
+}
+
+/// Rectangular opening (window, door...) through which an environment light
+/// reaches the inside of a scene
+///
+/// Sampling the environment through the portals instead of over the whole
+/// sphere sends the shadow rays of interior points where light can actually
+/// come from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightPortal {
+    pub corner: Position,
+    pub edge_u: Direction,
+    pub edge_v: Direction,
+    /// Let the light through both faces. Otherwise only points on the side of
+    /// `edge_u × edge_v`, the inside, are lit through the portal.
+    #[serde(default = "default_double_sided")]
+    pub double_sided: bool,
+}
+
+fn default_double_sided() -> bool {
+    true
+}
+
+/// Direction toward a point of a portal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PortalSample {
+    pub direction: Direction,
+    pub distance: f64,
+    /// Probability density of the direction, with respect to solid angle
+    pub pdf: f64,
+}
+
+impl LightPortal {
+    pub fn normal(&self) -> Direction {
+        self.edge_u.cross(&self.edge_v).normalize()
+    }
+
+    pub fn area(&self) -> f64 {
+        self.edge_u.cross(&self.edge_v).norm()
+    }
+
+    /// Can `origin` receive light through the portal
+    pub fn faces(&self, origin: &Position) -> bool {
+        let side = (origin - self.corner).dot(&self.normal());
+        side > 0.0 || (self.double_sided && side < 0.0)
+    }
+
+    /// Sample a point of the portal uniformly from (u, v) in [0, 1)²
+    ///
+    /// Returns None when `origin` is behind a single sided portal or in its
+    /// plane.
+    pub fn sample(&self, origin: &Position, u: f64, v: f64) -> Option<PortalSample> {
+        if !self.faces(origin) {
+            return None;
+        }
+        let to_point = self.corner + u * self.edge_u + v * self.edge_v - origin;
+        let distance = to_point.norm();
+        let direction = to_point / distance;
+        let cosine = direction.dot(&self.normal()).abs();
+        Some(PortalSample {
+            direction,
+            distance,
+            pdf: distance * distance / (self.area() * cosine),
+        })
+    }
+
+    /// Density with which `sample` returns `direction` from `origin`, 0 when
+    /// the direction misses the portal
+    ///
+    /// Needed to weight directions sampled by other strategies (e.g. the
+    /// materials) against portal sampling.
+    pub fn pdf(&self, origin: &Position, direction: &Direction) -> f64 {
+        if !self.faces(origin) {
+            return 0.0;
+        }
+        let ray = Ray::new(*origin, *direction);
+        let (p0, p1, p2) = (
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+        );
+        let p3 = self.corner + self.edge_u + self.edge_v;
+        // Both orientations, the intersection test culls back faces
+        let hit = [(p0, p1, p2), (p0, p2, p1), (p3, p2, p1), (p3, p1, p2)]
+            .iter()
+            .filter_map(|(a, b, c)| ray.intersect_triangle(a, b, c))
+            .next();
+        match hit {
+            Some((point, _)) => {
+                let distance = (point - origin).norm();
+                let cosine = direction.normalize().dot(&self.normal()).abs();
+                distance * distance / (self.area() * cosine)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IES: &str = "IESNA:LM-63-2002\r
+[TEST] symmetric downlight\r
+TILT=NONE\r
+1 1000 2 3 1 1 2 0 0 0\r
+1.0 1.0 50\r
+0 45 90\r
+0\r
+100, 50, 0\r
+";
+
+    #[test]
+    fn ies_profile_is_interpolated() {
+        let profile = IesProfile::parse(IES).unwrap();
+        assert_eq!(profile.vertical_angles, vec![0.0, 45.0, 90.0]);
+        // Multiplier applied, rotational symmetry
+        assert_eq!(profile.candela(0.0, 123.0), 200.0);
+        assert_eq!(profile.candela(22.5, 0.0), 150.0);
+        assert_eq!(profile.candela(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn light_units_and_falloff() {
+        let sphere = 4.0 * std::f64::consts::PI;
+        assert!((LightUnits::Lumens.to_candela(sphere * 100.0, sphere) - 100.0).abs() < 1e-9);
+        assert!((cone_solid_angle(180.0) - sphere).abs() < 1e-9);
+        // 100 cd seen from 2 m, whatever the unit of the scene
+        assert!((illuminance(100.0, 2.0, Unit::Meters) - 25.0).abs() < 1e-9);
+        assert!((illuminance(100.0, 200.0, Unit::Centimeters) - 25.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn spot_cone_fades_out() {
+        let spot = SpotLight {
+            position: Position::new(0.0, 0.0, 0.0),
+            direction: Direction::new(0.0, 0.0, 1.0),
+            intensity: 10.0,
+            color: Color::WHITE,
+            cone_angle: 30.0,
+            falloff_angle: 10.0,
+            profile: None,
+        };
+        let toward = |degrees: f64| {
+            let a = degrees.to_radians();
+            spot.intensity_toward(&Direction::new(a.sin(), 0.0, a.cos()))
+        };
+        assert_eq!(toward(20.0), 10.0);
+        assert!((toward(35.0) - 5.0).abs() < 1e-9);
+        assert_eq!(toward(45.0), 0.0);
+    }
+
+    fn window() -> LightPortal {
+        LightPortal {
+            corner: Position::new(-1.0, 0.0, 2.0),
+            edge_u: Direction::new(0.0, 1.0, 0.0),
+            edge_v: Direction::new(2.0, 0.0, 0.0),
+            double_sided: false,
+        }
+    }
+
+    #[test]
+    fn portal_samples_match_their_pdf() {
+        let portal = window();
+        // The inside is toward -z
+        let origin = Position::new(0.3, 0.2, 0.0);
+        for &(u, v) in &[(0.5, 0.5), (0.1, 0.9), (0.8, 0.3)] {
+            let sample = portal.sample(&origin, u, v).unwrap();
+            let pdf = portal.pdf(&origin, &sample.direction);
+            assert!((pdf - sample.pdf).abs() < 1e-9 * pdf);
+        }
+        assert_eq!(portal.pdf(&origin, &Direction::new(0.0, 0.0, -1.0)), 0.0);
+    }
+
+    #[test]
+    fn single_sided_portals_only_light_the_inside() {
+        let outside = Position::new(0.0, 0.5, 4.0);
+        assert!(window().sample(&outside, 0.5, 0.5).is_none());
+        let portal = LightPortal {
+            double_sided: true,
+            ..window()
+        };
+        assert!(portal.sample(&outside, 0.5, 0.5).is_some());
+    }
+}
@@ -0,0 +1,735 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::geometry::mesh::Tangent;
+use crate::geometry::types::{orthonormal_basis, Direction, Position};
+use crate::render::color::Color;
+use crate::render::image::RgbImage;
+
+/// UV sets of a mesh available to the textures, the others are ignored
+pub const MAX_UV_SETS: usize = 4;
+
+/// Surface point being shaded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceHit {
+    pub position: Position,
+    /// Shading normal, normalized
+    pub normal: Direction,
+    /// Vertex colors interpolated at the hit, white when the mesh has none
+    pub vertex_color: Color,
+    /// Texture coordinates of every UV set interpolated at the hit, for the
+    /// sets the mesh has
+    pub uvs: [Option<[f64; 2]>; MAX_UV_SETS],
+    /// Tangent frame interpolated at the hit, when the mesh has texture
+    /// coordinates
+    pub tangent: Option<Tangent>,
+}
+
+impl SurfaceHit {
+    /// Texture coordinates of UV set `set` at the hit
+    pub fn uv(&self, set: usize) -> Option<[f64; 2]> {
+        self.uvs.get(set).copied().flatten()
+    }
+}
+
+/// Direction of incoming light drawn by `Material::sample`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrdfSample {
+    /// Toward the light, normalized
+    pub direction: Direction,
+    /// brdf * cos / pdf, the factor applied to the light coming from
+    /// `direction` in a Monte Carlo estimate
+    pub weight: Color,
+    /// Probability density of `direction`, per steradian
+    pub pdf: f64,
+}
+
+/// Perfectly smooth interface of a material, followed by the secondary rays
+/// of the tracers rather than shaded with the lights
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Specular {
+    /// Mirror reflecting `tint` of the light at all angles
+    Reflection { tint: Color },
+    /// Boundary of a transparent medium of index of refraction `ior`,
+    /// splitting the light between reflection and refraction by the Fresnel
+    /// equations; the refracted light is multiplied by `tint`
+    Dielectric { tint: Color, ior: f64 },
+}
+
+impl Specular {
+    /// Directions the light arriving along `incoming` leaves in, with the
+    /// fraction of it they carry
+    ///
+    /// `normal` is the outward normal of the surface, rays coming from
+    /// behind it leave the medium. Total internal reflection sends all the
+    /// light back inside.
+    pub fn scatter(&self, incoming: &Direction, normal: &Direction) -> Vec<(Direction, Color)> {
+        let reflected = incoming - 2.0 * incoming.dot(normal) * normal;
+        let (tint, ior) = match *self {
+            Specular::Reflection { tint } => return vec![(reflected, tint)],
+            Specular::Dielectric { tint, ior } => (tint, ior),
+        };
+        let entering = incoming.dot(normal) < 0.0;
+        let (normal, eta) = if entering {
+            (*normal, 1.0 / ior)
+        } else {
+            (-normal, ior)
+        };
+        let cos_i = -incoming.dot(&normal);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        if sin2_t >= 1.0 {
+            return vec![(reflected, Color::WHITE)];
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let fresnel = fresnel_dielectric(cos_i, cos_t, eta);
+        let refracted = eta * incoming + (eta * cos_i - cos_t) * normal;
+        vec![
+            (reflected, Color::gray(fresnel)),
+            (refracted.normalize(), tint * (1.0 - fresnel)),
+        ]
+    }
+}
+
+/// Fraction of unpolarized light reflected by a dielectric interface, from
+/// the cosines of the incident and refracted rays with the normal and the
+/// ratio of the indices of refraction, incident over refracted
+fn fresnel_dielectric(cos_i: f64, cos_t: f64, eta: f64) -> f64 {
+    let s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (s * s + p * p) / 2.0
+}
+
+/// How a surface reflects the light, attached to the triangles of a `Mesh`
+pub trait Material: fmt::Debug + Send + Sync {
+    /// Bidirectional reflectance distribution function: radiance reflected
+    /// toward `to_viewer` per unit of irradiance arriving from `to_light`
+    ///
+    /// Both directions start at the hit and are normalized.
+    fn brdf(&self, hit: &SurfaceHit, to_light: &Direction, to_viewer: &Direction) -> Color;
+
+    /// Draw a direction of incoming light for secondary rays, from two
+    /// uniform random numbers in [0, 1)
+    ///
+    /// `None` when the drawn direction goes below the surface. The default
+    /// draws a cosine weighted direction, which suits matte materials.
+    fn sample(
+        &self,
+        hit: &SurfaceHit,
+        to_viewer: &Direction,
+        u: f64,
+        v: f64,
+    ) -> Option<BrdfSample> {
+        let direction = cosine_direction(&hit.normal, u, v);
+        brdf_sample(self, hit, direction, to_viewer)
+    }
+
+    /// Normal used for the lighting, e.g. perturbed by a normal map; the
+    /// tracers replace the normal of the hit with it before shading
+    fn shading_normal(&self, hit: &SurfaceHit) -> Direction {
+        hit.normal
+    }
+
+    /// Probability density of `sample` drawing `to_light`
+    fn pdf(&self, hit: &SurfaceHit, to_light: &Direction, _to_viewer: &Direction) -> f64 {
+        hit.normal.dot(to_light).max(0.0) / PI
+    }
+
+    /// Smooth interface traced recursively by the tracers, none by default
+    fn specular(&self, _hit: &SurfaceHit) -> Option<Specular> {
+        None
+    }
+
+    /// Base color of the surface, e.g. for the albedo pass of denoisers; the
+    /// default is the reflectance toward the normal, which suits matte
+    /// materials
+    fn albedo(&self, hit: &SurfaceHit) -> Color {
+        self.brdf(hit, &hit.normal, &hit.normal) * PI
+    }
+}
+
+/// Direction of the hemisphere around `normal`, with a density proportional
+/// to the cosine with the normal
+pub(crate) fn cosine_direction(normal: &Direction, u: f64, v: f64) -> Direction {
+    let (x, y) = orthonormal_basis(normal);
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+    (r * phi.cos()) * x + (r * phi.sin()) * y + (1.0 - u).max(0.0).sqrt() * normal
+}
+
+/// Complete a sampled `direction` with its weight and density
+fn brdf_sample<M: Material + ?Sized>(
+    material: &M,
+    hit: &SurfaceHit,
+    direction: Direction,
+    to_viewer: &Direction,
+) -> Option<BrdfSample> {
+    let cos = hit.normal.dot(&direction);
+    let pdf = material.pdf(hit, &direction, to_viewer);
+    if cos <= 0.0 || pdf <= 0.0 {
+        return None;
+    }
+    let brdf = material.brdf(hit, &direction, to_viewer);
+    Some(BrdfSample {
+        direction,
+        weight: brdf * (cos / pdf),
+        pdf,
+    })
+}
+
+/// Image mapped on a surface through its texture coordinates
+///
+/// The image is repeated outside of [0, 1], with v = 0 on its bottom row as
+/// in OBJ files, and filtered bilinearly. It is mapped through the first UV
+/// set of the mesh unless told otherwise by `with_uv_set`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Texture {
+    image: Arc<RgbImage>,
+    /// Whether the pixels are sRGB encoded colors, rather than data
+    srgb: bool,
+    uv_set: usize,
+}
+
+impl Texture {
+    /// Texture of sRGB encoded colors, e.g. an albedo
+    pub fn new(image: Arc<RgbImage>) -> Self {
+        Texture {
+            image,
+            srgb: true,
+            uv_set: 0,
+        }
+    }
+
+    /// Texture of data mapped linearly to [0, 1], e.g. a normal map
+    pub fn data(image: Arc<RgbImage>) -> Self {
+        Texture {
+            image,
+            srgb: false,
+            uv_set: 0,
+        }
+    }
+
+    /// The same texture mapped through UV set `set`, e.g. a lightmap
+    pub fn with_uv_set(self, set: usize) -> Self {
+        Texture {
+            uv_set: set,
+            ..self
+        }
+    }
+
+    pub fn uv_set(&self) -> usize {
+        self.uv_set
+    }
+
+    /// Linear value at the hit, `None` when the mesh does not have the UV
+    /// set of the texture
+    pub fn sample_at(&self, hit: &SurfaceHit) -> Option<Color> {
+        hit.uv(self.uv_set).map(|uv| self.sample(uv))
+    }
+
+    /// Linear value at `uv`
+    pub fn sample(&self, uv: [f64; 2]) -> Color {
+        let (width, height) = self.image.dimensions();
+        if width == 0 || height == 0 {
+            return Color::WHITE;
+        }
+        // Pixel centers are at half integers
+        let x = uv[0] * width as f64 - 0.5;
+        let y = (1.0 - uv[1]) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |dx: i64, dy: i64| {
+            let px = (x0 as i64 + dx).rem_euclid(width as i64) as u32;
+            let py = (y0 as i64 + dy).rem_euclid(height as i64) as u32;
+            let pixel = self.image.get_pixel(px, py).0;
+            if self.srgb {
+                Color::from_srgb8(pixel)
+            } else {
+                Color::from(pixel.map(|c| c as f64 / 255.0))
+            }
+        };
+        let top = texel(0, 0) * (1.0 - fx) + texel(1, 0) * fx;
+        let bottom = texel(0, 1) * (1.0 - fx) + texel(1, 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// `color` multiplied with the vertex colors and the texture at the hit;
+/// the texture is ignored on meshes without its texture coordinates
+fn albedo_at(color: Color, texture: &Option<Texture>, hit: &SurfaceHit) -> Color {
+    let texel = texture.as_ref().and_then(|texture| texture.sample_at(hit));
+    color * hit.vertex_color * texel.unwrap_or(Color::WHITE)
+}
+
+/// Material whose shading normal is perturbed by a tangent space normal map
+///
+/// The map stores the x, y and z coordinates of the normal in the red, green
+/// and blue channels, mapped from [-1, 1] to [0, 1], along the tangent, the
+/// bitangent and the normal of the surface (the OpenGL convention, green up).
+/// The tangent frame follows the first UV set, whichever set the map is read
+/// through. Hits without tangent frame keep their normal.
+#[derive(Debug)]
+pub struct NormalMapped {
+    pub base: Arc<dyn Material>,
+    /// Read with `Texture::data`
+    pub normal_map: Texture,
+    /// Scale of the x and y coordinates of the mapped normals, 1 as stored
+    pub strength: f64,
+}
+
+impl Material for NormalMapped {
+    fn brdf(&self, hit: &SurfaceHit, to_light: &Direction, to_viewer: &Direction) -> Color {
+        self.base.brdf(hit, to_light, to_viewer)
+    }
+
+    fn sample(
+        &self,
+        hit: &SurfaceHit,
+        to_viewer: &Direction,
+        u: f64,
+        v: f64,
+    ) -> Option<BrdfSample> {
+        self.base.sample(hit, to_viewer, u, v)
+    }
+
+    fn shading_normal(&self, hit: &SurfaceHit) -> Direction {
+        let normal = self.base.shading_normal(hit);
+        let (texel, tangent) = match (self.normal_map.sample_at(hit), hit.tangent) {
+            (Some(texel), Some(tangent)) => (texel, tangent),
+            _ => return normal,
+        };
+        let t = (tangent.direction - normal * normal.dot(&tangent.direction))
+            .try_normalize(1e-12)
+            .unwrap_or_else(|| orthonormal_basis(&normal).0);
+        let b = normal.cross(&t) * tangent.handedness;
+        let mapped = texel.map(|c| 2.0 * c - 1.0);
+        let perturbed = t * (mapped.r * self.strength)
+            + b * (mapped.g * self.strength)
+            + normal * mapped.b.max(0.0);
+        perturbed.try_normalize(1e-12).unwrap_or(normal)
+    }
+
+    fn pdf(&self, hit: &SurfaceHit, to_light: &Direction, to_viewer: &Direction) -> f64 {
+        self.base.pdf(hit, to_light, to_viewer)
+    }
+
+    fn specular(&self, hit: &SurfaceHit) -> Option<Specular> {
+        self.base.specular(hit)
+    }
+
+    fn albedo(&self, hit: &SurfaceHit) -> Color {
+        self.base.albedo(hit)
+    }
+}
+
+/// Matte surface colored by the vertex colors of the mesh, the material of
+/// meshes that do not define any
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VertexColor;
+
+impl Material for VertexColor {
+    fn brdf(&self, hit: &SurfaceHit, _to_light: &Direction, _to_viewer: &Direction) -> Color {
+        hit.vertex_color / PI
+    }
+}
+
+/// Matte surface reflecting the light equally in all directions
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lambertian {
+    /// Fraction of the light reflected, per channel, multiplied with the
+    /// vertex colors and the texture
+    pub albedo: Color,
+    pub texture: Option<Texture>,
+}
+
+impl Material for Lambertian {
+    fn brdf(&self, hit: &SurfaceHit, _to_light: &Direction, _to_viewer: &Direction) -> Color {
+        albedo_at(self.albedo, &self.texture, hit) / PI
+    }
+}
+
+/// Glossy surface: a Lambertian base with Blinn-Phong highlights
+///
+/// The highlight lobe is normalized so that its energy does not change with
+/// the shininess, sharper highlights are brighter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlinnPhong {
+    /// Diffuse albedo, multiplied with the vertex colors and the texture
+    pub albedo: Color,
+    pub texture: Option<Texture>,
+    /// Color of the highlights
+    pub specular: Color,
+    /// Exponent of the highlight lobe, higher values give smaller highlights
+    pub shininess: f64,
+}
+
+impl Material for BlinnPhong {
+    fn brdf(&self, hit: &SurfaceHit, to_light: &Direction, to_viewer: &Direction) -> Color {
+        let half = (to_light + to_viewer).normalize();
+        let cos = hit.normal.dot(&half).max(0.0);
+        let lobe = (self.shininess + 8.0) / (8.0 * PI) * cos.powf(self.shininess);
+        albedo_at(self.albedo, &self.texture, hit) / PI + self.specular * lobe
+    }
+
+    fn albedo(&self, hit: &SurfaceHit) -> Color {
+        albedo_at(self.albedo, &self.texture, hit)
+    }
+}
+
+/// Perfect mirror, reflecting `color` multiplied with the vertex colors and
+/// the texture
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mirror {
+    pub color: Color,
+    pub texture: Option<Texture>,
+}
+
+impl Material for Mirror {
+    fn brdf(&self, _hit: &SurfaceHit, _to_light: &Direction, _to_viewer: &Direction) -> Color {
+        Color::BLACK
+    }
+
+    fn specular(&self, hit: &SurfaceHit) -> Option<Specular> {
+        Some(Specular::Reflection {
+            tint: albedo_at(self.color, &self.texture, hit),
+        })
+    }
+
+    fn albedo(&self, hit: &SurfaceHit) -> Color {
+        albedo_at(self.color, &self.texture, hit)
+    }
+}
+
+/// Clear glass or water: smooth dielectric tinting the light going through
+/// with `color`, multiplied with the vertex colors
+///
+/// The surfaces of the mesh are the boundary of the medium, their normals
+/// pointing out of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glass {
+    pub color: Color,
+    /// Index of refraction, e.g. 1.5 for glass and 1.33 for water
+    pub ior: f64,
+}
+
+impl Material for Glass {
+    fn brdf(&self, _hit: &SurfaceHit, _to_light: &Direction, _to_viewer: &Direction) -> Color {
+        Color::BLACK
+    }
+
+    fn specular(&self, hit: &SurfaceHit) -> Option<Specular> {
+        Some(Specular::Dielectric {
+            tint: self.color * hit.vertex_color,
+            ior: self.ior,
+        })
+    }
+
+    fn albedo(&self, hit: &SurfaceHit) -> Color {
+        self.color * hit.vertex_color
+    }
+}
+
+/// Physically based material of the glTF metallic-roughness model, as used
+/// by Blender and the glTF viewers
+///
+/// Specular reflection follows the GGX microfacet distribution with the
+/// Smith shadowing term and the Schlick approximation of Fresnel. Dielectrics
+/// reflect 4% at normal incidence and diffuse the rest, metals tint their
+/// reflection with the base color and do not diffuse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetallicRoughness {
+    /// Albedo of dielectrics, reflectance of metals, multiplied with the
+    /// vertex colors and the texture
+    pub base_color: Color,
+    pub texture: Option<Texture>,
+    /// 0 for dielectrics, 1 for metals
+    pub metallic: f64,
+    /// Perceptual roughness, 0 is a mirror, 1 is fully rough
+    pub roughness: f64,
+}
+
+impl MetallicRoughness {
+    /// Width of the GGX distribution
+    fn alpha(&self) -> f64 {
+        (self.roughness * self.roughness).max(1e-3)
+    }
+
+    /// GGX distribution of the microfacet normals, `cos` with the normal
+    fn distribution(&self, cos: f64) -> f64 {
+        let a2 = self.alpha() * self.alpha();
+        let d = cos * cos * (a2 - 1.0) + 1.0;
+        a2 / (PI * d * d)
+    }
+
+    /// Probability to sample the specular lobe rather than the diffuse one
+    fn specular_probability(&self) -> f64 {
+        0.5 + 0.5 * self.metallic
+    }
+}
+
+impl Material for MetallicRoughness {
+    fn brdf(&self, hit: &SurfaceHit, to_light: &Direction, to_viewer: &Direction) -> Color {
+        let n_l = hit.normal.dot(to_light);
+        let n_v = hit.normal.dot(to_viewer);
+        if n_l <= 0.0 || n_v <= 0.0 {
+            return Color::BLACK;
+        }
+        let half = (to_light + to_viewer).normalize();
+        let v_h = to_viewer.dot(&half).max(0.0);
+        // Smith-Schlick shadowing
+        let k = self.alpha() / 2.0;
+        let g1 = |cos: f64| cos / (cos * (1.0 - k) + k);
+        let specular =
+            self.distribution(hit.normal.dot(&half)) * g1(n_l) * g1(n_v) / (4.0 * n_l * n_v);
+        let schlick = (1.0 - v_h).powi(5);
+        let base_color = albedo_at(self.base_color, &self.texture, hit);
+        base_color.map(|base| {
+            let f0 = 0.04 * (1.0 - self.metallic) + base * self.metallic;
+            let fresnel = f0 + (1.0 - f0) * schlick;
+            let diffuse = (1.0 - fresnel) * (1.0 - self.metallic) * base / PI;
+            diffuse + fresnel * specular
+        })
+    }
+
+    /// Draw the specular lobe by its distribution of normals, or the diffuse
+    /// lobe by the cosine
+    fn sample(
+        &self,
+        hit: &SurfaceHit,
+        to_viewer: &Direction,
+        u: f64,
+        v: f64,
+    ) -> Option<BrdfSample> {
+        let specular = self.specular_probability();
+        let direction = if u < specular {
+            let u = u / specular;
+            let a2 = self.alpha() * self.alpha();
+            let cos = ((1.0 - u) / (1.0 + (a2 - 1.0) * u)).sqrt();
+            let sin = (1.0 - cos * cos).max(0.0).sqrt();
+            let phi = 2.0 * PI * v;
+            let (x, y) = orthonormal_basis(&hit.normal);
+            let half = (sin * phi.cos()) * x + (sin * phi.sin()) * y + cos * hit.normal;
+            2.0 * to_viewer.dot(&half) * half - to_viewer
+        } else {
+            cosine_direction(&hit.normal, (u - specular) / (1.0 - specular), v)
+        };
+        brdf_sample(self, hit, direction, to_viewer)
+    }
+
+    fn pdf(&self, hit: &SurfaceHit, to_light: &Direction, to_viewer: &Direction) -> f64 {
+        let n_l = hit.normal.dot(to_light);
+        if n_l <= 0.0 {
+            return 0.0;
+        }
+        let half = (to_light + to_viewer).normalize();
+        let v_h = to_viewer.dot(&half);
+        let specular_pdf = if v_h > 0.0 {
+            let n_h = hit.normal.dot(&half).max(0.0);
+            self.distribution(n_h) * n_h / (4.0 * v_h)
+        } else {
+            0.0
+        };
+        let specular = self.specular_probability();
+        specular * specular_pdf + (1.0 - specular) * n_l / PI
+    }
+
+    fn albedo(&self, hit: &SurfaceHit) -> Color {
+        albedo_at(self.base_color, &self.texture, hit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::mesh::Mesh;
+    use std::sync::Arc;
+
+    /// Reflects everything as pure red
+    #[derive(Debug)]
+    struct Red;
+
+    impl Material for Red {
+        fn brdf(&self, _: &SurfaceHit, _: &Direction, _: &Direction) -> Color {
+            Color::new(1.0 / PI, 0.0, 0.0)
+        }
+    }
+
+    #[test]
+    fn triangles_pick_their_material() {
+        let vertices = vec![
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(1.0, 0.0, 0.0),
+            Position::new(0.0, 1.0, 0.0),
+        ];
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, vec![[0, 1, 2], [0, 2, 1]]);
+        let hit = SurfaceHit {
+            position: Position::origin(),
+            normal: Direction::new(0.0, 0.0, 1.0),
+            vertex_color: Color::gray(0.5),
+            uvs: [None; MAX_UV_SETS],
+            tangent: None,
+        };
+        let up = hit.normal;
+        assert_eq!(mesh.material(1).brdf(&hit, &up, &up), Color::gray(0.5 / PI));
+
+        mesh.materials = vec![Arc::new(VertexColor), Arc::new(Red)];
+        mesh.triangle_materials = Some(vec![0, 1]);
+        assert_eq!(mesh.material(0).brdf(&hit, &up, &up), Color::gray(0.5 / PI));
+        assert_eq!(
+            mesh.material(1).brdf(&hit, &up, &up),
+            Color::new(1.0 / PI, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn metallic_roughness_sampling_matches_its_density() {
+        let hit = SurfaceHit {
+            position: Position::origin(),
+            normal: Direction::new(0.0, 0.0, 1.0),
+            vertex_color: Color::WHITE,
+            uvs: [None; MAX_UV_SETS],
+            tangent: None,
+        };
+        let to_viewer = Direction::new(0.3, 0.0, 1.0).normalize();
+        for &(metallic, roughness) in &[(0.0, 0.5), (1.0, 0.3), (0.5, 0.9)] {
+            let material = MetallicRoughness {
+                base_color: Color::gray(0.9),
+                texture: None,
+                metallic,
+                roughness,
+            };
+            // Directional albedo: never more than the incoming light, rough
+            // surfaces lose some to the single scattering model
+            let n = 64;
+            let mut albedo = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let (u, v) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                    if let Some(sample) = material.sample(&hit, &to_viewer, u, v) {
+                        let pdf = material.pdf(&hit, &sample.direction, &to_viewer);
+                        assert!((pdf - sample.pdf).abs() < 1e-9 * pdf.max(1.0));
+                        albedo += sample.weight.r;
+                    }
+                }
+            }
+            albedo /= (n * n) as f64;
+            assert!(albedo > 0.4 && albedo < 1.0, "{:?}: {}", material, albedo);
+        }
+    }
+
+    #[test]
+    fn blinn_phong_highlights_follow_the_mirror_direction() {
+        let hit = SurfaceHit {
+            position: Position::origin(),
+            normal: Direction::new(0.0, 1.0, 0.0),
+            vertex_color: Color::WHITE,
+            uvs: [None; MAX_UV_SETS],
+            tangent: None,
+        };
+        let material = BlinnPhong {
+            albedo: Color::gray(0.5),
+            texture: None,
+            specular: Color::WHITE,
+            shininess: 50.0,
+        };
+        let to_light = Direction::new(1.0, 1.0, 0.0).normalize();
+        let mirror = Direction::new(-1.0, 1.0, 0.0).normalize();
+        let off = Direction::new(1.0, 0.2, 0.0).normalize();
+        let diffuse = Lambertian {
+            albedo: Color::gray(0.5),
+            texture: None,
+        }
+        .brdf(&hit, &to_light, &mirror);
+        assert!(material.brdf(&hit, &to_light, &mirror).r > 10.0 * diffuse.r);
+        assert!((material.brdf(&hit, &to_light, &off).r - diffuse.r).abs() < 1e-3);
+    }
+
+    #[test]
+    fn textures_repeat_and_interpolate() {
+        // Black and white columns
+        let image = RgbImage::from_fn(2, 1, |x, _| ::image::Rgb([(x * 255) as u8; 3]));
+        let texture = Texture::new(Arc::new(image));
+        assert_eq!(texture.sample([0.25, 0.5]), Color::BLACK);
+        assert_eq!(texture.sample([0.75, 0.5]), Color::WHITE);
+        assert_eq!(texture.sample([1.75, -3.5]), Color::WHITE);
+        // Halfway between the pixel centers
+        assert!((texture.sample([0.5, 0.5]).r - 0.5).abs() < 1e-12);
+
+        let material = Lambertian {
+            albedo: Color::WHITE,
+            texture: Some(texture),
+        };
+        let mut hit = SurfaceHit {
+            position: Position::origin(),
+            normal: Direction::new(0.0, 0.0, 1.0),
+            vertex_color: Color::WHITE,
+            uvs: [Some([0.25, 0.5]), Some([0.75, 0.5]), None, None],
+            tangent: None,
+        };
+        let up = hit.normal;
+        assert_eq!(material.brdf(&hit, &up, &up), Color::BLACK);
+        // The same image read through the second UV set
+        let lightmapped = Lambertian {
+            albedo: Color::WHITE,
+            texture: material.texture.clone().map(|t| t.with_uv_set(1)),
+        };
+        assert_eq!(lightmapped.brdf(&hit, &up, &up), Color::gray(1.0 / PI));
+        hit.uvs = [None; MAX_UV_SETS];
+        assert_eq!(material.brdf(&hit, &up, &up), Color::gray(1.0 / PI));
+    }
+
+    #[test]
+    fn normal_maps_tilt_the_shading_normal() {
+        // Flat, then tilted toward +u: (0.6, 0, 0.8) stored in [0, 1]
+        let flat = RgbImage::from_pixel(1, 1, ::image::Rgb([128, 128, 255]));
+        let tilted = RgbImage::from_pixel(1, 1, ::image::Rgb([204, 128, 230]));
+        let mapped = |image: RgbImage| NormalMapped {
+            base: Arc::new(VertexColor),
+            normal_map: Texture::data(Arc::new(image)),
+            strength: 1.0,
+        };
+        let mut hit = SurfaceHit {
+            position: Position::origin(),
+            normal: Direction::new(0.0, 1.0, 0.0),
+            vertex_color: Color::WHITE,
+            uvs: [Some([0.5, 0.5]), None, None, None],
+            tangent: Some(Tangent {
+                direction: Direction::new(1.0, 0.0, 0.0),
+                handedness: 1.0,
+            }),
+        };
+        let normal = mapped(flat).shading_normal(&hit);
+        assert!((normal - hit.normal).norm() < 0.01);
+        let normal = mapped(tilted.clone()).shading_normal(&hit);
+        assert!((normal - Direction::new(0.6, 0.8, 0.0)).norm() < 0.01);
+        hit.tangent = None;
+        assert_eq!(mapped(tilted).shading_normal(&hit), hit.normal);
+    }
+
+    #[test]
+    fn glass_splits_the_light_by_fresnel() {
+        let up = Direction::new(0.0, 1.0, 0.0);
+        let glass = Specular::Dielectric {
+            tint: Color::WHITE,
+            ior: 1.5,
+        };
+        // 4% reflected at normal incidence, the rest goes straight through
+        let rays = glass.scatter(&-up, &up);
+        assert!((rays[0].1.r - 0.04).abs() < 1e-12);
+        assert!((rays[1].0 + up).norm() < 1e-12);
+        assert!((rays[1].1.r - 0.96).abs() < 1e-12);
+
+        // Refraction bends toward the normal going in, away from it going out
+        let incoming = Direction::new(1.0, -1.0, 0.0).normalize();
+        let rays = glass.scatter(&incoming, &up);
+        let sin_t = rays[1].0.x;
+        assert!((sin_t * 1.5 - incoming.x).abs() < 1e-12);
+        let rays = glass.scatter(&rays[1].0, &-up);
+        assert!((rays[1].0 - incoming).norm() < 1e-12);
+
+        // Total internal reflection past the critical angle of 41.8°
+        let grazing = Direction::new(0.8, 0.6, 0.0);
+        let rays = glass.scatter(&grazing, &up);
+        assert_eq!(rays, vec![(Direction::new(0.8, -0.6, 0.0), Color::WHITE)]);
+    }
+}
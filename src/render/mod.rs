@@ -1,3 +1,24 @@
+pub mod animation;
+pub mod bake;
+pub mod color;
 pub mod config;
+pub mod denoise;
+pub mod depth;
+pub mod environment;
+pub mod framebuffer;
+pub mod handle;
+pub mod hittable;
 pub mod image;
+pub mod lidar;
+pub mod light;
+pub mod material;
+pub mod preview;
+pub mod probes;
 pub mod ray_tracer;
+pub mod report;
+pub mod rng;
+pub mod sampler;
+pub mod scatter;
+pub mod scene;
+pub mod shared;
+pub mod video;
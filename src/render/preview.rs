@@ -0,0 +1,505 @@
+extern crate image;
+extern crate rand;
+extern crate rayon;
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use self::image::imageops::{self, FilterType};
+use self::image::Rgb;
+use self::rand::Rng;
+use self::rayon::prelude::*;
+use crate::geometry::ray::Ray;
+use crate::render::color::Color;
+use crate::render::config::{CameraConfig, RenderingConfig};
+use crate::render::image::{
+    lens_ray, primary_ray, project, render_image, to_display, with_threads, HdrRgbImage, RgbImage,
+};
+use crate::render::ray_tracer::{Sample, SampleKey};
+use crate::render::rng::SampleRng;
+use crate::render::sampler::lens_sample;
+
+/// Lowest frame rate of an interactive preview while the camera moves
+pub const MIN_PREVIEW_FPS: f64 = 15.0;
+
+/// Resolution of an interactive preview, lowered while the camera moves to
+/// keep the frame rate and brought back to full resolution once it stops
+///
+/// The resolution is given as a divisor of the width and height of the
+/// camera, a power of two so that the preview pixels cover whole pixels of
+/// the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveResolution {
+    /// Longest time a frame may take while the camera moves
+    frame_budget: Duration,
+    /// Largest divisor, e.g. 16 turns 1920x1080 into 120x67
+    max_scale: u32,
+    scale: u32,
+}
+
+impl AdaptiveResolution {
+    /// Start at full resolution
+    pub fn new(min_fps: f64, max_scale: u32) -> AdaptiveResolution {
+        AdaptiveResolution {
+            frame_budget: Duration::from_secs_f64(1.0 / min_fps),
+            max_scale: max_scale.max(1).next_power_of_two(),
+            scale: 1,
+        }
+    }
+
+    /// Divisor of the resolution of the next frame
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Whether the last frame was at full resolution
+    pub fn is_refined(&self) -> bool {
+        self.scale == 1
+    }
+
+    /// Choose the resolution of the next frame from the time the last one
+    /// took, rendered at `scale()`
+    ///
+    /// While the camera is `moving`, the time per pixel of the last frame
+    /// gives the largest resolution fitting in the budget. Once it stops, the
+    /// resolution doubles every frame up to the full one, so that the picture
+    /// refines quickly without a long wait on the first full frame.
+    pub fn update(&mut self, last_frame: Duration, moving: bool) -> u32 {
+        if moving {
+            // The render time goes with the number of pixels
+            let full_frame = last_frame.as_secs_f64() * (self.scale * self.scale) as f64;
+            let budget = self.frame_budget.as_secs_f64();
+            let mut scale = 1;
+            while scale < self.max_scale && full_frame / ((scale * scale) as f64) > budget {
+                scale *= 2;
+            }
+            self.scale = scale;
+        } else {
+            self.scale = (self.scale / 2).max(1);
+        }
+        self.scale
+    }
+}
+
+impl Default for AdaptiveResolution {
+    fn default() -> Self {
+        AdaptiveResolution::new(MIN_PREVIEW_FPS, 16)
+    }
+}
+
+/// Render the image at `1 / scale` of the camera resolution, then enlarge it
+/// back to the camera resolution without interpolation
+pub fn render_preview<F>(
+    ray_tracer: F,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    scale: u32,
+) -> RgbImage
+where
+    F: Fn(Ray, SampleKey) -> Color + Sync,
+{
+    let scale = scale.max(1);
+    if scale == 1 {
+        return render_image(ray_tracer, camera_config, rendering_config);
+    }
+    let reduced = CameraConfig {
+        width: (camera_config.width / scale).max(1),
+        height: (camera_config.height / scale).max(1),
+        ..*camera_config
+    };
+    let img = render_image(ray_tracer, &reduced, rendering_config);
+    imageops::resize(
+        &img,
+        camera_config.width,
+        camera_config.height,
+        FilterType::Nearest,
+    )
+}
+
+/// Pixels traced at every frame of an `InterleavedPreview`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelMask {
+    /// Every pixel at every frame
+    Full,
+    /// Half of the pixels at every frame, alternating like the squares of a
+    /// checkerboard
+    Checkerboard,
+    /// One pixel of every 4x4 block at every frame, in the order of a Bayer
+    /// matrix which spreads the pixels of a frame evenly
+    Bayer,
+}
+
+impl PixelMask {
+    pub const ALL: [PixelMask; 3] = [PixelMask::Full, PixelMask::Checkerboard, PixelMask::Bayer];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelMask::Full => "full",
+            PixelMask::Checkerboard => "checkerboard",
+            PixelMask::Bayer => "bayer",
+        }
+    }
+
+    /// Frames tracing every pixel once
+    pub fn phases(&self) -> u32 {
+        match self {
+            PixelMask::Full => 1,
+            PixelMask::Checkerboard => 2,
+            PixelMask::Bayer => 16,
+        }
+    }
+
+    pub fn traced(&self, x: u32, y: u32, frame: u32) -> bool {
+        const BAYER: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+        match self {
+            PixelMask::Full => true,
+            PixelMask::Checkerboard => (x + y + frame).is_multiple_of(2),
+            PixelMask::Bayer => BAYER[(y % 4) as usize][(x % 4) as usize] == frame % 16,
+        }
+    }
+}
+
+impl fmt::Display for PixelMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for PixelMask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PixelMask::ALL
+            .iter()
+            .cloned()
+            .find(|mask| mask.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown pixel mask {}, expected full, checkerboard or bayer",
+                    s
+                )
+            })
+    }
+}
+
+/// Interactive preview tracing a fraction of the pixels at every frame
+///
+/// The other pixels keep their previous value, moved to where the new camera
+/// sees them when it moves (reprojection by their depth), and pixels without
+/// value yet show their nearest neighbor. While the camera is still, every
+/// frame adds a sample to the pixels it traces, so the preview converges.
+pub struct InterleavedPreview {
+    camera: CameraConfig,
+    mask: PixelMask,
+    frame: u32,
+    /// Sum of the samples of every pixel, rows top first
+    sum: Vec<Color>,
+    samples: Vec<u32>,
+    /// Distance to the surface seen by the pixel, infinite when there is none
+    depth: Vec<f64>,
+}
+
+/// Farthest neighbor a pixel without value can borrow the value of
+const MAX_FILL_DISTANCE: i64 = 4;
+
+impl InterleavedPreview {
+    pub fn new(camera: &CameraConfig, mask: PixelMask) -> InterleavedPreview {
+        let pixels = (camera.width * camera.height) as usize;
+        InterleavedPreview {
+            camera: camera.clone(),
+            mask,
+            frame: 0,
+            sum: vec![Color::BLACK; pixels],
+            samples: vec![0; pixels],
+            depth: vec![f64::INFINITY; pixels],
+        }
+    }
+
+    /// Move the camera, keeping the pixels whose surface is still in view
+    ///
+    /// A new resolution or field of view starts over.
+    pub fn set_camera(&mut self, camera: &CameraConfig) {
+        if *camera == self.camera {
+            return;
+        }
+        let same_view = camera.width == self.camera.width
+            && camera.height == self.camera.height
+            && camera.fov == self.camera.fov
+            && camera.projection == self.camera.projection;
+        let mut moved = InterleavedPreview::new(camera, self.mask);
+        moved.frame = self.frame;
+        if same_view {
+            let (width, height) = (camera.width, camera.height);
+            let mut distance = vec![f64::INFINITY; self.sum.len()];
+            for (p, &depth) in self.depth.iter().enumerate() {
+                if self.samples[p] == 0 || !depth.is_finite() {
+                    continue;
+                }
+                let (x, y) = (p as u32 % width, p as u32 / width);
+                let ray = primary_ray(x as f64, (height - 1 - y) as f64, &self.camera);
+                let point = ray.position + ray.direction * depth;
+                let (i, j) = match project(&point, camera) {
+                    Some(ij) => ij,
+                    None => continue,
+                };
+                let (i, j) = (i.round(), j.round());
+                if i < 0.0 || j < 0.0 || i >= width as f64 || j >= height as f64 {
+                    continue;
+                }
+                let q = ((height - 1 - j as u32) * width + i as u32) as usize;
+                let new_depth = (point - camera.camera_position).norm();
+                // The closest surface hides the others
+                if new_depth < distance[q] {
+                    distance[q] = new_depth;
+                    moved.sum[q] = self.sum[p] / self.samples[p] as f64;
+                    moved.samples[q] = 1;
+                    moved.depth[q] = new_depth;
+                }
+            }
+        }
+        *self = moved;
+    }
+
+    /// Trace the pixels of the next frame, returning how many
+    pub fn render_frame<F>(&mut self, sample_tracer: F, rendering_config: &RenderingConfig) -> usize
+    where
+        F: Fn(Ray, SampleKey) -> Sample + Sync,
+    {
+        let (width, height) = (self.camera.width, self.camera.height);
+        let (frame, mask, camera) = (self.frame, self.mask, &self.camera);
+        let (samples, sample_tracer) = (&self.samples, &sample_tracer);
+        let traced: Vec<(usize, Sample)> = with_threads(rendering_config.threads, || {
+            (0..height)
+                .into_par_iter()
+                .flat_map_iter(|y| {
+                    (0..width)
+                        .filter(move |&x| mask.traced(x, y, frame))
+                        .map(move |x| {
+                            let p = (y * width + x) as usize;
+                            let (dx, dy) = sample_offset(rendering_config.seed, x, y, samples[p]);
+                            let j = height - 1 - y;
+                            let lens = lens_sample(rendering_config.seed, x, y, samples[p]);
+                            let ray = lens_ray(x as f64 + dx, j as f64 + dy, lens, camera);
+                            (
+                                p,
+                                sample_tracer(
+                                    ray,
+                                    SampleKey {
+                                        x,
+                                        y,
+                                        sample: samples[p],
+                                    },
+                                ),
+                            )
+                        })
+                })
+                .collect()
+        });
+        for &(p, sample) in &traced {
+            self.sum[p] += sample.color;
+            self.samples[p] += 1;
+            self.depth[p] = sample.depth;
+        }
+        self.frame = self.frame.wrapping_add(1);
+        traced.len()
+    }
+
+    /// Fraction of the pixels having a value, 1 once every pixel was traced
+    /// or reprojected
+    pub fn coverage(&self) -> f64 {
+        let covered = self.samples.iter().filter(|&&n| n > 0).count();
+        covered as f64 / self.samples.len().max(1) as f64
+    }
+
+    /// Linear colors of the preview, pixels without value showing their
+    /// nearest neighbor with one
+    pub fn hdr_image(&self) -> HdrRgbImage {
+        let (width, height) = (self.camera.width, self.camera.height);
+        let mean = |p: usize| self.sum[p] / self.samples[p] as f64;
+        HdrRgbImage::from_fn(width, height, |x, y| {
+            let p = (y * width + x) as usize;
+            let color = if self.samples[p] > 0 {
+                mean(p)
+            } else {
+                self.nearest_covered(x, y).map_or(Color::BLACK, mean)
+            };
+            Rgb(<[f64; 3]>::from(color).map(|c| c as f32))
+        })
+    }
+
+    pub fn image(&self, rendering_config: &RenderingConfig) -> RgbImage {
+        to_display(&self.hdr_image(), rendering_config)
+    }
+
+    /// Closest pixel with a value, in growing squares around (x, y)
+    fn nearest_covered(&self, x: u32, y: u32) -> Option<usize> {
+        let (width, height) = (self.camera.width as i64, self.camera.height as i64);
+        for radius in 1..=MAX_FILL_DISTANCE {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if dx.abs() != radius && dy.abs() != radius {
+                        continue;
+                    }
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                        continue;
+                    }
+                    let q = (ny * width + nx) as usize;
+                    if self.samples[q] > 0 {
+                        return Some(q);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Position of the next sample of a pixel having `count` samples, from its
+/// center: the center first, then at random whatever the sampler, so that
+/// the pixels of a still camera converge to their antialiased value
+fn sample_offset(seed: u64, x: u32, y: u32, count: u32) -> (f64, f64) {
+    if count == 0 {
+        return (0.0, 0.0);
+    }
+    let mut rng = SampleRng::for_pixel(seed, x, y, count);
+    (rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::Direction;
+
+    #[test]
+    fn resolution_drops_while_moving_and_refines_once_stopped() {
+        let mut resolution = AdaptiveResolution::new(15.0, 16);
+        assert!(resolution.is_refined());
+        // 400 ms at full resolution: a quarter of the pixels takes 100 ms,
+        // still too long, a sixteenth 25 ms
+        assert_eq!(resolution.update(Duration::from_millis(400), true), 4);
+        // Same scene at 1/4: no change
+        assert_eq!(resolution.update(Duration::from_millis(25), true), 4);
+        // A heavier view
+        assert_eq!(resolution.update(Duration::from_millis(100), true), 8);
+        // Never beyond the largest divisor
+        assert_eq!(resolution.update(Duration::from_secs(100), true), 16);
+
+        let refinement: Vec<u32> = (0..5)
+            .map(|_| resolution.update(Duration::from_millis(1), false))
+            .collect();
+        assert_eq!(refinement, vec![8, 4, 2, 1, 1]);
+        assert!(resolution.is_refined());
+
+        let camera = CameraConfig {
+            width: 40,
+            height: 30,
+            ..Default::default()
+        };
+        let preview = render_preview(
+            |ray, _| Color::new(ray.direction.x.max(0.0), 0.0, 0.0),
+            &camera,
+            &RenderingConfig::default(),
+            4,
+        );
+        assert_eq!(preview.dimensions(), (40, 30));
+        // Blocks of 4x4 pixels
+        assert_eq!(preview.get_pixel(36, 0), preview.get_pixel(39, 3));
+    }
+
+    #[test]
+    fn interleaved_preview_converges_and_follows_the_camera() {
+        // Wall facing the camera at z = 0, with vertical stripes
+        let tracer = |ray: Ray, _: SampleKey| {
+            let depth = -ray.position.z / ray.direction.z;
+            let x = ray.position.x + depth * ray.direction.x;
+            let stripe = if x.rem_euclid(2.0) < 1.0 { 1.0 } else { 0.2 };
+            Sample {
+                color: Color::new(stripe, stripe, stripe),
+                depth,
+                normal: Direction::new(0.0, 0.0, -1.0),
+                albedo: Color::WHITE,
+            }
+        };
+        // Stripes a few pixels wide
+        let camera = CameraConfig {
+            width: 32,
+            height: 24,
+            fov: 14.0,
+            ..Default::default()
+        };
+        let rendering_config = RenderingConfig::default();
+        let mut preview = InterleavedPreview::new(&camera, PixelMask::Checkerboard);
+        assert_eq!(preview.render_frame(tracer, &rendering_config), 32 * 24 / 2);
+        assert_eq!(preview.coverage(), 0.5);
+        preview.render_frame(tracer, &rendering_config);
+        assert_eq!(preview.coverage(), 1.0);
+        let full = render_image(
+            |ray, key| tracer(ray, key).color,
+            &camera,
+            &rendering_config,
+        );
+        assert_eq!(preview.image(&rendering_config), full);
+
+        // A small step sideways keeps most of the wall
+        let moved = CameraConfig {
+            camera_position: camera.camera_position + Direction::new(0.3, 0.0, 0.0),
+            ..camera.clone()
+        };
+        preview.set_camera(&moved);
+        assert!(preview.coverage() > 0.8, "{}", preview.coverage());
+        assert!(preview.coverage() < 1.0);
+        // Pixels are where the new camera sees their surface
+        let expected = render_image(|ray, key| tracer(ray, key).color, &moved, &rendering_config);
+        let hdr = preview.hdr_image();
+        let matching = expected
+            .enumerate_pixels()
+            .filter(|(x, y, p)| (hdr.get_pixel(*x, *y)[0] > 0.5) == (p[0] > 200))
+            .count();
+        assert!(matching as f64 > 0.9 * (32 * 24) as f64);
+        // Every pixel once in the 16 frames of the Bayer mask
+        assert_eq!(
+            (0..16)
+                .filter(|&f| PixelMask::Bayer.traced(5, 7, f))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn interleaved_previews_converge_with_noisy_tracers() {
+        // Noise as that of ambient occlusion, drawn for every sample of a
+        // pixel whatever the ray
+        let tracer = |_: Ray, key: SampleKey| {
+            let value: f64 = SampleRng::for_pixel(3, key.x, key.y, key.sample).gen();
+            Sample {
+                color: Color::gray(value),
+                depth: 1.0,
+                normal: Direction::new(0.0, 0.0, -1.0),
+                albedo: Color::WHITE,
+            }
+        };
+        let camera = CameraConfig {
+            width: 16,
+            height: 12,
+            ..Default::default()
+        };
+        let rendering_config = RenderingConfig::default();
+        let mut preview = InterleavedPreview::new(&camera, PixelMask::Full);
+        let mean = |preview: &InterleavedPreview| {
+            let hdr = preview.hdr_image();
+            hdr.pixels().map(|p| f64::from(p[0])).sum::<f64>() / (16 * 12) as f64
+        };
+        preview.render_frame(tracer, &rendering_config);
+        let first = mean(&preview);
+        preview.render_frame(tracer, &rendering_config);
+        assert_ne!(mean(&preview), first);
+        // 64 samples per pixel, within a few deviations of 1/2
+        for _ in 0..62 {
+            preview.render_frame(tracer, &rendering_config);
+        }
+        let hdr = preview.hdr_image();
+        assert!(hdr.pixels().all(|p| (p[0] - 0.5).abs() < 0.2));
+    }
+}
@@ -0,0 +1,350 @@
+extern crate rand;
+extern crate rayon;
+
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use self::rand::Rng;
+use self::rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::ray::Ray;
+use crate::geometry::types::{Direction, Position};
+use crate::render::color::Color;
+use crate::render::config::{self, ConfigError, RenderingConfig, CONFIG_VERSION};
+use crate::render::ray_tracer::{make_prepared_ray_tracer, SampleKey};
+use crate::render::rng::SampleRng;
+use crate::render::shared::PreparedScene;
+
+/// Coefficients of the spherical harmonics up to the second band
+pub const SH_COEFFICIENTS: usize = 9;
+
+/// Scale of the bands of the radiance turning it into irradiance, the
+/// convolution with the clamped cosine
+///
+/// # Reference
+/// * R. Ramamoorthi, P. Hanrahan, An Efficient Representation for Irradiance
+///   Environment Maps (SIGGRAPH 2001)
+const COSINE_BANDS: [f64; 3] = [PI, 2.0 * PI / 3.0, PI / 4.0];
+
+/// Real spherical harmonics up to the second band at `direction`,
+/// normalized, in the order of the coefficients of the probes: (0, 0), then
+/// (1, -1), (1, 0), (1, 1), then (2, -2) to (2, 2)
+pub fn sh_basis(direction: &Direction) -> [f64; SH_COEFFICIENTS] {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+/// Band of every coefficient of `sh_basis`
+fn band(coefficient: usize) -> usize {
+    match coefficient {
+        0 => 0,
+        1..=3 => 1,
+        _ => 2,
+    }
+}
+
+/// Where the probes go and how many rays they cast, see `bake_probes`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeConfig {
+    /// Box filled with probes
+    pub bounds: [Position; 2],
+    /// Probes along x, y and z, at the centers of as many cells of the box
+    pub resolution: [u32; 3],
+    /// Rays cast by every probe, rounded up to a square: they are spread in
+    /// as many strata of the sphere
+    pub samples: u32,
+}
+
+impl ProbeConfig {
+    /// `longest` probes along the longest side of `bounds`, and as many
+    /// along the others as keep the cells about cubic, at least one
+    pub fn fitting(bounds: &AxisAlignedBoundingBox, longest: u32) -> ProbeConfig {
+        let extent = bounds.bounds[1] - bounds.bounds[0];
+        let longest_side = extent.iter().cloned().fold(0.0, f64::max);
+        let resolution = |axis: usize| {
+            if longest_side > 0.0 {
+                ((longest as f64 * extent[axis] / longest_side).round() as u32).max(1)
+            } else {
+                1
+            }
+        };
+        ProbeConfig {
+            bounds: bounds.bounds,
+            resolution: [resolution(0), resolution(1), resolution(2)],
+            samples: 256,
+        }
+    }
+}
+
+/// Irradiance probes on a regular grid, as stored in probe files
+///
+/// Every probe holds the irradiance reaching it as spherical harmonics up to
+/// the second band, already convolved with the cosine: the irradiance on a
+/// surface of normal `n` is the sum of the coefficients weighted by
+/// `sh_basis(n)`, as done by real-time engines.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbeGrid {
+    pub version: u32,
+    /// Box filled with probes, as in `ProbeConfig`
+    pub bounds: [Position; 2],
+    pub resolution: [u32; 3],
+    /// Coefficients of the probes, x varying fastest, then y, then z
+    pub irradiance: Vec<[Color; SH_COEFFICIENTS]>,
+}
+
+impl Default for ProbeGrid {
+    /// Grid without probes
+    fn default() -> Self {
+        ProbeGrid {
+            version: CONFIG_VERSION,
+            bounds: [Position::origin(), Position::origin()],
+            resolution: [0, 0, 0],
+            irradiance: Vec::new(),
+        }
+    }
+}
+
+/// Cast the rays of a probe at the center of every cell of `config`, shaded
+/// with the integrator and lights of the scene, in parallel
+///
+/// Probes inside the objects see their back and are black, the engines
+/// reading the grid usually ignore them.
+pub fn bake_probes(prepared: &PreparedScene, config: &ProbeConfig) -> ProbeGrid {
+    let _span = tracing::info_span!("bake_probes", resolution = ?config.resolution).entered();
+    let scene = &prepared.scene;
+    // The colors, without the false colors of the debug views
+    let rendering = RenderingConfig {
+        debug_view: None,
+        ..scene.rendering.clone()
+    };
+    let tracer = make_prepared_ray_tracer(prepared, &scene.camera, &rendering);
+    let strata = (config.samples.max(1) as f64).sqrt().ceil() as u32;
+    let mut grid = ProbeGrid {
+        bounds: config.bounds,
+        resolution: config.resolution,
+        ..Default::default()
+    };
+    grid.irradiance = (0..grid.len())
+        .into_par_iter()
+        .map(|index| {
+            let origin = grid.position(index);
+            let mut rng = SampleRng::for_key(rendering.seed, index as u64);
+            let mut coefficients = [Color::BLACK; SH_COEFFICIENTS];
+            for stratum in 0..strata * strata {
+                // Uniform on the sphere, jittered in its stratum of height
+                // and azimuth
+                let u = ((stratum / strata) as f64 + rng.gen::<f64>()) / strata as f64;
+                let v = ((stratum % strata) as f64 + rng.gen::<f64>()) / strata as f64;
+                let z = 1.0 - 2.0 * u;
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * v;
+                let direction = Direction::new(r * phi.cos(), r * phi.sin(), z);
+                // Every probe is a pixel of its own, every stratum a sample
+                let key = SampleKey {
+                    x: index as u32,
+                    y: 0,
+                    sample: stratum,
+                };
+                let color = tracer(Ray::new(origin, direction), key);
+                for (coefficient, y) in coefficients.iter_mut().zip(sh_basis(&direction).iter()) {
+                    *coefficient += color * *y;
+                }
+            }
+            // Radiance projected on the basis, then convolved
+            let weight = 4.0 * PI / (strata * strata) as f64;
+            for (i, coefficient) in coefficients.iter_mut().enumerate() {
+                *coefficient *= weight * COSINE_BANDS[band(i)];
+            }
+            coefficients
+        })
+        .collect();
+    grid
+}
+
+impl ProbeGrid {
+    /// Load a probe file, in TOML when it ends in `.toml` and in JSON
+    /// otherwise
+    ///
+    /// Fails when the number of probes does not match the resolution.
+    pub fn load(path: &Path) -> Result<ProbeGrid, ConfigError> {
+        let grid: ProbeGrid = config::load_versioned(path)?;
+        if grid.irradiance.len() != grid.len() {
+            return Err(ConfigError::Invalid(format!(
+                "{} probes for a grid of {}",
+                grid.irradiance.len(),
+                grid.len()
+            )));
+        }
+        Ok(grid)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        fs::write(path, config::to_json(self) + "\n").map_err(ConfigError::Io)
+    }
+
+    /// Number of probes of the resolution
+    pub fn len(&self) -> usize {
+        self.resolution.iter().map(|&n| n as usize).product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Position of the probe of index `index`, at the center of its cell
+    pub fn position(&self, index: usize) -> Position {
+        let [nx, ny, _] = self.resolution;
+        let (nx, ny) = (nx as usize, ny as usize);
+        let cell = [index % nx, (index / nx) % ny, index / (nx * ny)];
+        let [min, max] = self.bounds;
+        let coordinate = |axis: usize| {
+            let t = (cell[axis] as f64 + 0.5) / self.resolution[axis] as f64;
+            min[axis] + t * (max[axis] - min[axis])
+        };
+        Position::new(coordinate(0), coordinate(1), coordinate(2))
+    }
+
+    /// Irradiance on a surface at `position` facing `normal`, normalized,
+    /// blending the eight probes around it
+    ///
+    /// Outside the grid, the probes of its border are used. Black when the
+    /// grid is empty.
+    pub fn irradiance(&self, position: &Position, normal: &Direction) -> Color {
+        if self.is_empty() {
+            return Color::BLACK;
+        }
+        let [min, max] = self.bounds;
+        // Cells around the position on every axis, with the weight of the
+        // upper one
+        let neighbours = |axis: usize| {
+            let n = self.resolution[axis] as usize;
+            let size = max[axis] - min[axis];
+            let t = if size > 0.0 {
+                (position[axis] - min[axis]) / size * n as f64 - 0.5
+            } else {
+                0.0
+            };
+            let t = t.max(0.0).min((n - 1) as f64);
+            let lower = (t.floor() as usize).min(n - 1);
+            ((lower, (lower + 1).min(n - 1)), t - lower as f64)
+        };
+        let cells = [neighbours(0), neighbours(1), neighbours(2)];
+        let basis = sh_basis(normal);
+        let [nx, ny, _] = self.resolution;
+        let (nx, ny) = (nx as usize, ny as usize);
+        let mut irradiance = Color::BLACK;
+        for corner in 0..8 {
+            let mut index = 0;
+            let mut weight = 1.0;
+            for (axis, &stride) in [1, nx, nx * ny].iter().enumerate() {
+                let ((lower, upper), t) = cells[axis];
+                let (cell, w) = match (corner >> axis) & 1 {
+                    0 => (lower, 1.0 - t),
+                    _ => (upper, t),
+                };
+                index += cell * stride;
+                weight *= w;
+            }
+            if weight > 0.0 {
+                let probe = &self.irradiance[index];
+                let value: Color = probe.iter().zip(basis.iter()).map(|(c, y)| *c * *y).sum();
+                irradiance += value * weight;
+            }
+        }
+        irradiance.map(|c| c.max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::config::MaterialConfig;
+    use crate::render::scene::Scene;
+
+    #[test]
+    fn probes_in_the_furnace_receive_pi_times_the_radiance() {
+        // Around a white box, every ray sees the radiance of the environment
+        let scene = Scene::white_furnace(
+            MaterialConfig {
+                color: Color::WHITE,
+                ..Default::default()
+            },
+            0.5,
+        );
+        let prepared = PreparedScene::new(scene).unwrap();
+        let bounds = prepared.scene.bounding_box();
+        let config = ProbeConfig {
+            // Probes at the corners of the bounding box, outside the rotated
+            // cube
+            bounds: [
+                bounds.bounds[0] - bounds.extent,
+                bounds.bounds[1] + bounds.extent,
+            ],
+            resolution: [2, 2, 2],
+            samples: 1000,
+        };
+        let grid = bake_probes(&prepared, &config);
+        assert_eq!(grid.irradiance.len(), 8);
+        for index in 0..grid.len() {
+            let position = grid.position(index);
+            assert!(position.x.abs() > 1.0 && position.y.abs() > 1.0);
+            for normal in &[Direction::x(), -Direction::y(), Direction::z()] {
+                let irradiance = grid.irradiance(&position, normal);
+                assert!(
+                    (irradiance.r - 0.5 * PI).abs() < 0.02 * PI,
+                    "{:?}",
+                    irradiance
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn irradiance_blends_the_probes_around() {
+        // Lit from +x only at the first probe, from everywhere at the second
+        let mut half = [Color::BLACK; SH_COEFFICIENTS];
+        half[0] = Color::gray(0.5 * PI / 0.282_095);
+        half[3] = Color::gray(0.5 * PI / 0.488_603);
+        let mut uniform = [Color::BLACK; SH_COEFFICIENTS];
+        uniform[0] = Color::gray(PI / 0.282_095);
+        let grid = ProbeGrid {
+            bounds: [Position::new(0.0, 0.0, 0.0), Position::new(2.0, 1.0, 1.0)],
+            resolution: [2, 1, 1],
+            irradiance: vec![half, uniform],
+            ..Default::default()
+        };
+        assert_eq!(grid.position(1), Position::new(1.5, 0.5, 0.5));
+        let at = |x: f64, normal: Direction| grid.irradiance(&Position::new(x, 0.5, 0.5), &normal);
+        assert!((at(0.5, Direction::x()).r - PI).abs() < 1e-4);
+        assert!(at(0.5, -Direction::x()).r.abs() < 1e-4);
+        // Halfway, and clamped beyond the probes
+        assert!((at(1.0, -Direction::x()).r - 0.5 * PI).abs() < 1e-4);
+        assert_eq!(at(-5.0, Direction::y()), at(0.5, Direction::y()));
+        assert_eq!(at(9.0, -Direction::x()), at(1.5, -Direction::x()));
+
+        let path = std::env::temp_dir().join("ray_ruster_probes_test.json");
+        grid.save(&path).unwrap();
+        assert_eq!(ProbeGrid::load(&path).unwrap(), grid);
+        let truncated = ProbeGrid {
+            irradiance: vec![half],
+            ..grid
+        };
+        truncated.save(&path).unwrap();
+        assert!(ProbeGrid::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,20 +1,29 @@
 extern crate image;
+extern crate rand;
 
-use crate::geometry::kdtree::{iter_intersect_ray, KdTree};
-use crate::geometry::mesh::Mesh;
+use std::cell::Cell;
+use std::sync::Arc;
+
+use self::rand::Rng;
+
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::import::Unit;
+use crate::geometry::instance::{Instance, Instances};
+use crate::geometry::kdtree::{occluded_packet, KdTree, TraversalStacks, PACKET_SIZE};
+use crate::geometry::mesh::{Mesh, Tangent};
 use crate::geometry::ray::Ray;
 use crate::geometry::types::{Direction, Position};
-use crate::render::config::{CameraConfig, NormalMode, RenderingConfig};
-
-pub fn clamp_u8(f: f64) -> u8 {
-    if f <= 0.0 {
-        return 0;
-    } else if f >= 255.0 {
-        return 255;
-    } else {
-        return f.ceil() as u8;
-    }
-}
+use crate::render::color::{srgb_to_linear, Color};
+use crate::render::config::{
+    CameraConfig, ConfigError, DebugView, Integrator, NormalMode, PathTermination, RenderingConfig,
+};
+use crate::render::framebuffer::Exposure;
+use crate::render::hittable::{HitSurface, Hittable};
+use crate::render::image::{render_hdr_image, HdrRgbImage};
+use crate::render::material::{cosine_direction, Material, SurfaceHit, MAX_UV_SETS};
+use crate::render::rng::SampleRng;
+use crate::render::scene::Scene;
+use crate::render::shared::PreparedScene;
 
 fn interpolation_n_phong(
     n1: &Direction,
@@ -25,28 +34,67 @@ fn interpolation_n_phong(
     return (*n1 * (1.0 - coord[0] - coord[1]) + coord[0] * *n2 + coord[1] * *n3).normalize();
 }
 
+/// Pixel of a camera ray, rows top first, and the index of its sample among
+/// those of the pixel, keying the random numbers of its shading
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SampleKey {
+    pub x: u32,
+    pub y: u32,
+    pub sample: u32,
+}
+
+/// What a camera ray sees: its color, and the auxiliary outputs (AOVs) of
+/// the first surface it hits, e.g. to debug the shading or for denoisers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub color: Color,
+    /// Distance to the hit, infinite when the ray misses the scene
+    pub depth: f64,
+    /// Shading normal at the hit, zero on misses
+    pub normal: Direction,
+    /// Base color of the material at the hit, black on misses
+    pub albedo: Color,
+}
+
 /// Return a function that given a ray will calculate its observed color
 /// i.e. background or object
 ///
 /// This function proceeds by iterating all the triangles in the mesh to
-/// look for intersections
+/// look for intersections, for camera, secondary and shadow rays alike
 pub fn make_naive_ray_tracer<'a>(
     mesh: &'a Mesh,
     camera_config: &'a CameraConfig,
     rendering_config: &'a RenderingConfig,
-) -> impl Fn(Ray) -> [u8; 3] + 'a {
-    move |ray| {
-        let all_triangle_indices_iter = 0..mesh.triangles.len();
-        let triangle_intersect = triangles_closest_intersection(
-            all_triangle_indices_iter.collect::<Vec<usize>>().iter(),
-            &ray,
-            mesh,
-        );
-        match triangle_intersect {
-            Some(intersect) => {
-                shade_triangle_hit(&intersect, mesh, camera_config, rendering_config)
-            }
-            None => [0, 0, 0],
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Color + 'a {
+    let tracer = make_naive_sample_tracer(mesh, camera_config, rendering_config, units);
+    move |ray, key| tracer(ray, key).color
+}
+
+/// Same as `make_naive_ray_tracer`, returning the AOVs with the color
+pub fn make_naive_sample_tracer<'a>(
+    mesh: &'a Mesh,
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Sample + 'a {
+    let shading = Shading::new(Geometry::Mesh(mesh), camera_config, rendering_config, units);
+    move |ray, key| {
+        let closest = |ray: &Ray, two_sided| {
+            triangles_closest_intersection(0..mesh.triangles.len(), ray, mesh, two_sided)
+        };
+        let occluded = |shadow_rays: &[(Ray, f64)]| {
+            shadow_rays
+                .iter()
+                .enumerate()
+                .filter(|(_, (shadow_ray, distance))| {
+                    occluded(0..mesh.triangles.len(), shadow_ray, *distance, mesh)
+                })
+                .fold(0, |mask: u64, (i, _)| mask | 1 << i)
+        };
+        match rendering_config.debug_view {
+            Some(view) => debug_sample(&shading, view, &ray, &closest, &Cell::new(0)),
+            None => trace_sample(&shading, &ray, key, &closest, &occluded),
         }
     }
 }
@@ -60,53 +108,687 @@ pub fn make_kdt_ray_tracer<'a>(
     kdt: &'a Box<KdTree>,
     camera_config: &'a CameraConfig,
     rendering_config: &'a RenderingConfig,
-) -> impl Fn(Ray) -> [u8; 3] + 'a {
-    move |ray| {
-        let box_iter = iter_intersect_ray(&kdt, &ray).leaves();
-        for box_intersect in box_iter {
-            let ref triangle_index = box_intersect.node.triangle_index.as_ref().unwrap();
-            let triangle_intersect =
-                triangles_closest_intersection(triangle_index.iter(), &ray, mesh);
-            if triangle_intersect.is_none() {
-                continue;
-            }
-            return shade_triangle_hit(
-                &triangle_intersect.unwrap(),
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Color + 'a {
+    let tracer = make_kdt_sample_tracer(mesh, kdt, camera_config, rendering_config, units);
+    move |ray, key| tracer(ray, key).color
+}
+
+/// Same as `make_kdt_ray_tracer`, returning the AOVs with the color
+pub fn make_kdt_sample_tracer<'a>(
+    mesh: &'a Mesh,
+    kdt: &'a Box<KdTree>,
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Sample + 'a {
+    let shading = Shading::new(Geometry::Mesh(mesh), camera_config, rendering_config, units);
+    let stacks = TraversalStacks::default();
+    move |ray, key| {
+        let nodes_visited = Cell::new(0);
+        let closest = |ray: &Ray, two_sided| {
+            let far = shading.far(&ray.position);
+            kdtree_closest_intersection(&stacks, kdt, mesh, ray, far, two_sided, &nodes_visited)
+        };
+        // Nothing behind the light can shadow it
+        let occluded = |shadow_rays: &[(Ray, f64)]| {
+            occluded_packet(kdt, shadow_rays, |triangles, shadow_ray, distance| {
+                occluded(triangles.iter().copied(), shadow_ray, distance, mesh)
+            })
+        };
+        match rendering_config.debug_view {
+            Some(view) => debug_sample(&shading, view, &ray, &closest, &nodes_visited),
+            None => trace_sample(&shading, &ray, key, &closest, &occluded),
+        }
+    }
+}
+
+/// Return a function giving the closest hit of a ray on `mesh` within a
+/// distance, in units of the ray direction, through its kd-tree, e.g. for
+/// sensors measuring distances rather than rendering
+///
+/// The triangles are hit from both sides.
+pub fn make_kdt_hit_finder<'a>(
+    mesh: &'a Mesh,
+    kdt: &'a Box<KdTree>,
+) -> impl Fn(&Ray, f64) -> Option<TriangleIntersect> + Sync + 'a {
+    let stacks = TraversalStacks::default();
+    move |ray, far| kdtree_closest_intersection(&stacks, kdt, mesh, ray, far, true, &Cell::new(0))
+}
+
+/// Closest hit of `ray` up to `far` among the triangles of `mesh`, through
+/// its kd-tree, counting the nodes visited
+///
+/// The leaves are visited front to back: once a hit is found, only the
+/// leaves entered before it can hold a closer one, e.g. where the boxes of a
+/// bounding volume hierarchy overlap.
+pub(crate) fn kdtree_closest_intersection<'a>(
+    stacks: &TraversalStacks<'a>,
+    kdt: &'a Box<KdTree>,
+    mesh: &Mesh,
+    ray: &Ray,
+    far: f64,
+    two_sided: bool,
+    nodes_visited: &Cell<u32>,
+) -> Option<TriangleIntersect> {
+    stacks.traverse(kdt, ray, far, |nodes| {
+        let mut closest: Option<(f64, TriangleIntersect)> = None;
+        let leaves = nodes
+            .inspect(|_| nodes_visited.set(nodes_visited.get() + 1))
+            .filter(|box_intersect| box_intersect.node.is_leaf());
+        for box_intersect in leaves {
+            if closest
+                .as_ref()
+                .is_some_and(|(t, _)| box_intersect.distance > *t)
+            {
+                break;
+            }
+            let triangle_index = box_intersect.node.triangle_index.as_ref().unwrap();
+            let hit = triangles_closest_intersection(
+                triangle_index.iter().copied(),
+                ray,
                 mesh,
-                camera_config,
-                rendering_config,
+                two_sided,
             );
+            if let Some(hit) = hit {
+                // In units of the ray direction, as the distances of the
+                // boxes
+                let t = (hit.intersection - ray.position).norm() / ray.direction.norm();
+                if closest.as_ref().is_none_or(|(closest_t, _)| t < *closest_t) {
+                    closest = Some((t, hit));
+                }
+            }
         }
+        closest.map(|(_, hit)| hit)
+    })
+}
 
-        return [0, 0, 0];
+/// Return a function that given a ray will calculate its observed color
+/// i.e. background or object
+///
+/// This function traces the models placed by `instances`, each stored once
+/// with its kd-tree, see `Instances`
+pub fn make_instanced_ray_tracer<'a>(
+    instances: &'a Instances,
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Color + 'a {
+    let tracer = make_instanced_sample_tracer(instances, camera_config, rendering_config, units);
+    move |ray, key| tracer(ray, key).color
+}
+
+/// Same as `make_instanced_ray_tracer`, returning the AOVs with the color
+pub fn make_instanced_sample_tracer<'a>(
+    instances: &'a Instances,
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Sample + 'a {
+    let shading = Shading::new(
+        Geometry::Instances(instances),
+        camera_config,
+        rendering_config,
+        units,
+    );
+    // The model trees are traversed during the traversal of the top level
+    // one, each level has stacks of its own
+    let top_stacks = TraversalStacks::default();
+    let stacks = TraversalStacks::default();
+    move |ray, key| {
+        let nodes_visited = Cell::new(0);
+        // The instances are visited front to back as the leaves of the
+        // kd-trees, their hits being compared by their parameter along the
+        // ray, which does not change in the coordinates of the models
+        let closest = |ray: &Ray, two_sided| {
+            let far = shading.far(&ray.position);
+            top_stacks.traverse(&instances.tree, ray, far, |nodes| {
+                let mut closest: Option<(f64, TriangleIntersect)> = None;
+                let leaves = nodes
+                    .inspect(|_| nodes_visited.set(nodes_visited.get() + 1))
+                    .filter(|box_intersect| box_intersect.node.is_leaf());
+                for box_intersect in leaves {
+                    if closest
+                        .as_ref()
+                        .is_some_and(|(t, _)| box_intersect.distance > *t)
+                    {
+                        break;
+                    }
+                    for &index in box_intersect.node.triangle_index.as_ref().unwrap() {
+                        let instance = &instances.instances[index];
+                        let prototype = instances.prototype(index);
+                        let local = instance.ray_to_object(ray);
+                        let hit = kdtree_closest_intersection(
+                            &stacks,
+                            &prototype.kdtree,
+                            &prototype.mesh,
+                            &local,
+                            far / instance.scale(),
+                            two_sided,
+                            &nodes_visited,
+                        );
+                        if let Some(hit) = hit {
+                            let t =
+                                (hit.intersection - local.position).norm() / local.direction.norm();
+                            if closest.as_ref().is_none_or(|(closest_t, _)| t < *closest_t) {
+                                let tangent = hit.tangent.map(|tangent| Tangent {
+                                    direction: instance.normal_to_world(&tangent.direction),
+                                    ..tangent
+                                });
+                                let hit = TriangleIntersect {
+                                    intersection: instance.to_world * hit.intersection,
+                                    tangent,
+                                    instance: Some(index),
+                                    ..hit
+                                };
+                                closest = Some((t, hit));
+                            }
+                        }
+                    }
+                }
+                closest.map(|(_, hit)| hit)
+            })
+        };
+        let occluded = |shadow_rays: &[(Ray, f64)]| {
+            occluded_packet(
+                &instances.tree,
+                shadow_rays,
+                |indices, shadow_ray, distance| {
+                    indices.iter().any(|&index| {
+                        let instance = &instances.instances[index];
+                        let prototype = instances.prototype(index);
+                        let local = instance.ray_to_object(shadow_ray);
+                        let rays = [(local, distance / instance.scale())];
+                        occluded_packet(&prototype.kdtree, &rays, |triangles, ray, distance| {
+                            occluded(triangles.iter().copied(), ray, distance, &prototype.mesh)
+                        }) != 0
+                    })
+                },
+            )
+        };
+        match rendering_config.debug_view {
+            Some(view) => debug_sample(&shading, view, &ray, &closest, &nodes_visited),
+            None => trace_sample(&shading, &ray, key, &closest, &occluded),
+        }
     }
 }
 
+/// Return a function that given a ray will calculate its observed color
+/// i.e. background or object
+///
+/// This function traces `hittables`, each one tested in turn: meshes through
+/// their kd-tree, and analytic surfaces as they are, see `Hittable`
+pub fn make_hittable_ray_tracer<'a>(
+    hittables: &'a [Box<dyn Hittable>],
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Color + 'a {
+    let tracer = make_hittable_sample_tracer(hittables, camera_config, rendering_config, units);
+    move |ray, key| tracer(ray, key).color
+}
+
+/// Same as `make_hittable_ray_tracer`, returning the AOVs with the color
+pub fn make_hittable_sample_tracer<'a>(
+    hittables: &'a [Box<dyn Hittable>],
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+    units: Unit,
+) -> impl Fn(Ray, SampleKey) -> Sample + 'a {
+    let shading = Shading::new(
+        Geometry::Hittables(hittables),
+        camera_config,
+        rendering_config,
+        units,
+    );
+    let stacks = TraversalStacks::default();
+    move |ray, key| {
+        // Every hittable only searches closer than the closest hit so far
+        let closest = |ray: &Ray, two_sided| {
+            let mut far = shading.far(&ray.position);
+            let mut closest = None;
+            for (index, hittable) in hittables.iter().enumerate() {
+                if let Some(hit) = hittable.closest_hit(&stacks, ray, far, two_sided) {
+                    far = (hit.intersection - ray.position).norm();
+                    closest = Some(TriangleIntersect {
+                        hittable: Some(index),
+                        ..hit
+                    });
+                }
+            }
+            closest
+        };
+        let occluded = |shadow_rays: &[(Ray, f64)]| {
+            shadow_rays
+                .iter()
+                .enumerate()
+                .filter(|(_, (ray, distance))| {
+                    hittables
+                        .iter()
+                        .any(|hittable| hittable.occluded(ray, *distance))
+                })
+                .fold(0, |mask: u64, (i, _)| mask | 1 << i)
+        };
+        match rendering_config.debug_view {
+            Some(view) => debug_sample(&shading, view, &ray, &closest, &Cell::new(0)),
+            None => trace_sample(&shading, &ray, key, &closest, &occluded),
+        }
+    }
+}
+
+/// Render the linear colors of `hittables` seen from `camera_config`, with
+/// the integrator and settings of `rendering_config`
+pub fn render_hittables(
+    hittables: &[Box<dyn Hittable>],
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    units: Unit,
+) -> HdrRgbImage {
+    let tracer = make_hittable_ray_tracer(hittables, camera_config, rendering_config, units);
+    render_hdr_image(tracer, camera_config, rendering_config)
+}
+
+/// Render the linear colors of `instances` seen from `camera_config`, with
+/// the integrator and settings of `rendering_config`
+pub fn render_instances(
+    instances: &Instances,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+    units: Unit,
+) -> HdrRgbImage {
+    let tracer = make_instanced_ray_tracer(instances, camera_config, rendering_config, units);
+    render_hdr_image(tracer, camera_config, rendering_config)
+}
+
+/// Return a function that given a ray will calculate its observed color
+/// i.e. background or object
+///
+/// This function traces a prepared scene: its instances when it has some,
+/// its merged mesh otherwise
+pub fn make_prepared_ray_tracer<'a>(
+    prepared: &'a PreparedScene,
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+) -> Box<dyn Fn(Ray, SampleKey) -> Color + Sync + 'a> {
+    let tracer = make_prepared_sample_tracer(prepared, camera_config, rendering_config);
+    Box::new(move |ray, key| tracer(ray, key).color)
+}
+
+/// Same as `make_prepared_ray_tracer`, returning the AOVs with the color
+pub fn make_prepared_sample_tracer<'a>(
+    prepared: &'a PreparedScene,
+    camera_config: &'a CameraConfig,
+    rendering_config: &'a RenderingConfig,
+) -> Box<dyn Fn(Ray, SampleKey) -> Sample + Sync + 'a> {
+    let units = prepared.scene.units;
+    match &prepared.instances {
+        Some(instances) => Box::new(make_instanced_sample_tracer(
+            instances,
+            camera_config,
+            rendering_config,
+            units,
+        )),
+        None => Box::new(make_kdt_sample_tracer(
+            prepared.mesh(),
+            prepared.kdtree(),
+            camera_config,
+            rendering_config,
+            units,
+        )),
+    }
+}
+
+/// Render the linear colors of a prepared scene seen from `camera_config`,
+/// with the integrator and settings of `rendering_config`
+pub fn render(
+    prepared: &PreparedScene,
+    camera_config: &CameraConfig,
+    rendering_config: &RenderingConfig,
+) -> HdrRgbImage {
+    let tracer = make_prepared_ray_tracer(prepared, camera_config, rendering_config);
+    render_hdr_image(tracer, camera_config, rendering_config)
+}
+
+/// Render the linear colors of a scene of several objects, each with its
+/// transform and material, from its camera with its settings and lights
+///
+/// The scene is prepared for this render alone, see `PreparedScene` to
+/// render it several times. Fails when a light cannot be built.
+pub fn render_scene(scene: &Scene) -> Result<HdrRgbImage, ConfigError> {
+    let prepared = PreparedScene::new(scene.clone())?;
+    Ok(render(
+        &prepared,
+        &prepared.scene.camera,
+        &prepared.scene.rendering,
+    ))
+}
+
+/// What the rays of a tracer hit
+#[derive(Clone, Copy)]
+enum Geometry<'a> {
+    Mesh(&'a Mesh),
+    /// Models placed several times, the hits naming their instance
+    Instances(&'a Instances),
+    /// Meshes and analytic surfaces, the hits naming their hittable
+    Hittables(&'a [Box<dyn Hittable>]),
+}
+
+/// What the tracers shade the hits with
+struct Shading<'a> {
+    geometry: Geometry<'a>,
+    rendering_config: &'a RenderingConfig,
+    /// Material of the meshes without materials
+    default_material: Arc<dyn Material>,
+    units: Unit,
+    /// Bounding box of the mesh, beyond which rays cannot hit anything
+    bounds: AxisAlignedBoundingBox,
+    /// Whether some hittables lie outside `bounds`, e.g. infinite planes
+    unbounded: bool,
+    /// Offset of the secondary rays from the surfaces they leave, scaled to
+    /// the mesh
+    epsilon: f64,
+    /// Distances from the camera to the front and the back of the bounding
+    /// box of the mesh, for `DebugView::Depth`
+    depth_range: [f64; 2],
+}
+
+impl<'a> Shading<'a> {
+    fn new(
+        geometry: Geometry<'a>,
+        camera_config: &'a CameraConfig,
+        rendering_config: &'a RenderingConfig,
+        units: Unit,
+    ) -> Self {
+        let (bounding_box, unbounded) = match geometry {
+            Geometry::Mesh(mesh) => (mesh.bounding_box(), false),
+            Geometry::Instances(instances) => (instances.bounding_box(), false),
+            Geometry::Hittables(hittables) => {
+                let boxes: Vec<AxisAlignedBoundingBox> =
+                    hittables.iter().filter_map(|h| h.bounding_box()).collect();
+                let bounding_box = match boxes.split_first() {
+                    Some((first, others)) => others.iter().fold(
+                        AxisAlignedBoundingBox::from_bounds(first.bounds),
+                        |union, other| union.union(other),
+                    ),
+                    None => AxisAlignedBoundingBox::new(&Vec::new()),
+                };
+                (bounding_box, boxes.len() < hittables.len())
+            }
+        };
+        let depth_range = match rendering_config.debug_view {
+            Some(DebugView::Depth) => {
+                let bounds = bounding_box.bounds;
+                let eye = camera_config.camera_position;
+                let corners = (0..8).map(|i| {
+                    let corner = |axis: usize| bounds[(i >> axis) & 1][axis];
+                    (Position::new(corner(0), corner(1), corner(2)) - eye).norm()
+                });
+                let inside =
+                    (0..3).all(|axis| bounds[0][axis] <= eye[axis] && eye[axis] <= bounds[1][axis]);
+                // The box is entered at its closest point, not corner
+                let nearest =
+                    Position::from(eye.coords.sup(&bounds[0].coords).inf(&bounds[1].coords));
+                let near = if inside { 0.0 } else { (nearest - eye).norm() };
+                [near, corners.fold(0.0, f64::max)]
+            }
+            _ => [0.0, 1.0],
+        };
+        Shading {
+            geometry,
+            rendering_config,
+            default_material: rendering_config.material.build(),
+            units,
+            epsilon: bounding_box
+                .ray_epsilon()
+                .unwrap_or_else(|| units.ray_epsilon()),
+            bounds: bounding_box,
+            unbounded,
+            depth_range,
+        }
+    }
+
+    /// Farthest a ray from `origin` can hit the mesh, the far clipping
+    /// distance of the units when some hittables are unbounded
+    fn far(&self, origin: &Position) -> f64 {
+        match self.unbounded {
+            true => self.units.clip_range().1,
+            false => self.bounds.farthest_distance(origin) + self.epsilon,
+        }
+    }
+
+    /// Mesh of the triangle hit, and its instance when the tracer traces
+    /// instances
+    fn hit_mesh(&self, intersect: &TriangleIntersect) -> (&'a Mesh, Option<&'a Instance>) {
+        match (self.geometry, intersect.instance) {
+            (Geometry::Mesh(mesh), _) => (mesh, None),
+            (Geometry::Instances(instances), Some(index)) => (
+                &*instances.prototype(index).mesh,
+                Some(&instances.instances[index]),
+            ),
+            (Geometry::Instances(_), None) => panic!("hit of instances without its instance"),
+            (Geometry::Hittables(_), _) => panic!("hit of hittables looked up in a mesh"),
+        }
+    }
+}
+
+/// Color and AOVs seen by a camera ray
+fn trace_sample<C, O>(
+    shading: &Shading,
+    ray: &Ray,
+    key: SampleKey,
+    closest: &C,
+    occluded: &O,
+) -> Sample
+where
+    C: Fn(&Ray, bool) -> Option<TriangleIntersect>,
+    O: Fn(&[(Ray, f64)]) -> u64,
+{
+    match closest(ray, false) {
+        Some(intersect) => {
+            let surface = surface_hit(shading, &intersect);
+            Sample {
+                color: integrate(shading, &surface, ray, key, closest, occluded),
+                depth: (intersect.intersection - ray.position).norm(),
+                normal: surface.hit.normal,
+                albedo: surface.material.albedo(&surface.hit),
+            }
+        }
+        None => Sample {
+            color: miss(shading, ray),
+            depth: f64::INFINITY,
+            normal: Direction::zeros(),
+            albedo: Color::BLACK,
+        },
+    }
+}
+
+/// Sample of a camera ray with the false colors of `view` instead of its
+/// shaded color, given how many kd-tree nodes `closest` visits
+///
+/// The colors are decoded from sRGB, so that the default output shows them
+/// as they are computed.
+fn debug_sample<C>(
+    shading: &Shading,
+    view: DebugView,
+    ray: &Ray,
+    closest: &C,
+    nodes_visited: &Cell<u32>,
+) -> Sample
+where
+    C: Fn(&Ray, bool) -> Option<TriangleIntersect>,
+{
+    let intersect = closest(ray, false);
+    let surface = intersect.as_ref().map(|i| surface_hit(shading, i));
+    let color = match (view, &intersect, &surface) {
+        (DebugView::KdTreeNodes { max }, _, _) => {
+            heatmap(nodes_visited.get() as f64 / max.max(1) as f64)
+        }
+        (_, Some(intersect), Some(surface)) => match view {
+            DebugView::Normals => {
+                let n = surface.hit.normal;
+                Color::new(n.x, n.y, n.z).map(|c| (c + 1.0) / 2.0)
+            }
+            DebugView::Depth => {
+                let [near, far] = shading.depth_range;
+                let depth = (intersect.intersection - ray.position).norm();
+                heatmap(1.0 - (depth - near) / (far - near).max(f64::MIN_POSITIVE))
+            }
+            DebugView::Barycentrics => {
+                let [u, v] = intersect.barycentric_coordinate;
+                Color::new(1.0 - u - v, u, v)
+            }
+            DebugView::TriangleIndex => {
+                let mut rng = SampleRng::for_key(
+                    shading.rendering_config.seed,
+                    intersect.triangle_index as u64,
+                );
+                Color::new(rng.gen(), rng.gen(), rng.gen())
+            }
+            DebugView::KdTreeNodes { .. } => unreachable!(),
+        },
+        _ => Color::BLACK,
+    };
+    let color = color.map(|c| srgb_to_linear(c.clamp(0.0, 1.0)));
+    Sample {
+        color,
+        depth: match &intersect {
+            Some(intersect) => (intersect.intersection - ray.position).norm(),
+            None => f64::INFINITY,
+        },
+        normal: surface.map_or(Direction::zeros(), |s| s.hit.normal),
+        albedo: color,
+    }
+}
+
+/// Blue for 0, then cyan, green, yellow, and red for 1 or more
+fn heatmap(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let ramp = |x: f64| x.clamp(0.0, 1.0);
+    Color::new(ramp(t - 2.0), ramp(t) - ramp(t - 3.0), 1.0 - ramp(t - 1.0))
+}
+
+/// Color of the hit of a camera ray with `rendering_config.integrator`
+fn integrate<C, O>(
+    shading: &Shading,
+    surface: &Surface,
+    ray: &Ray,
+    key: SampleKey,
+    closest: &C,
+    occluded: &O,
+) -> Color
+where
+    C: Fn(&Ray, bool) -> Option<TriangleIntersect>,
+    O: Fn(&[(Ray, f64)]) -> u64,
+{
+    let rendering_config = shading.rendering_config;
+    // The random numbers of a camera ray only depend on the seed, its pixel
+    // and its sample, so that renders do not depend on the threads; they
+    // follow the jitter and the lens point of the sample, see `lens_sample`
+    let mut rng = SampleRng::for_pixel(rendering_config.seed, key.x, key.y, key.sample);
+    let _camera: (f64, f64, f64, f64) = rng.gen();
+    match rendering_config.integrator {
+        Integrator::FacingRatio => {
+            let cos = surface.hit.normal.dot(&-ray.direction.normalize()).max(0.0);
+            Color::gray(cos)
+        }
+        Integrator::AmbientOcclusion { samples, distance } => {
+            let samples = samples.max(1);
+            let epsilon = shading.epsilon;
+            let (hit, face_normal) = (&surface.hit, &surface.face_normal);
+            let distance = distance.unwrap_or_else(|| shading.far(&hit.position));
+            let rays: Vec<(Ray, f64)> = (0..samples)
+                .map(|_| {
+                    let direction = cosine_direction(&hit.normal, rng.gen(), rng.gen());
+                    let offset = face_normal * epsilon * face_normal.dot(&direction).signum();
+                    let ray = Ray::new(hit.position + offset, direction);
+                    (ray, distance)
+                })
+                .collect();
+            let blocked: u32 = rays
+                .chunks(PACKET_SIZE)
+                .map(|packet| occluded(packet).count_ones())
+                .sum();
+            Color::gray((samples - blocked) as f64 / samples as f64)
+        }
+        Integrator::Whitted => shade_triangle_hit(shading, surface, ray, 0, closest, occluded),
+        Integrator::PathTracing => {
+            shade_path(shading, surface, ray, 0, &mut rng, closest, occluded)
+        }
+    }
+}
+
+/// Color seen along `ray` after `depth` specular bounces
+///
+/// `closest(ray, two_sided)` finds the closest hit, on the back of the
+/// triangles too when `two_sided`: camera rays only see their front, while
+/// rays refracted into a mesh leave it through the back.
+fn trace<C, O>(shading: &Shading, ray: &Ray, depth: u32, closest: &C, occluded: &O) -> Color
+where
+    C: Fn(&Ray, bool) -> Option<TriangleIntersect>,
+    O: Fn(&[(Ray, f64)]) -> u64,
+{
+    match closest(ray, depth > 0) {
+        Some(intersect) => {
+            let surface = surface_hit(shading, &intersect);
+            shade_triangle_hit(shading, &surface, ray, depth, closest, occluded)
+        }
+        None => miss(shading, ray),
+    }
+}
+
+/// Color of the environment or background seen by a ray missing the scene
+fn miss(shading: &Shading, ray: &Ray) -> Color {
+    let rendering_config = shading.rendering_config;
+    let direction = ray.direction.normalize();
+    let radiance = match &rendering_config.environment {
+        Some(environment) => environment.radiance(&direction),
+        None => rendering_config.background.radiance(&direction),
+    };
+    radiance * exposure_scale(rendering_config)
+}
+
 pub struct TriangleIntersect {
     pub triangle_index: usize,
     pub intersection: Position,
     pub barycentric_coordinate: [f64; 2],
+    /// Tangent frame at the intersection, for normal mapping
+    pub tangent: Option<Tangent>,
+    /// Instance of the triangle when tracing `Instances`, whose model holds
+    /// `triangle_index`; the intersection and the tangent are in the scene
+    /// coordinates
+    pub instance: Option<usize>,
+    /// Hittable hit when tracing hittables, see `Hittable::surface`
+    pub hittable: Option<usize>,
 }
 
-fn triangles_closest_intersection<'a, I>(
+/// Closest hit of `ray` among the triangles of `mesh` at `triangle_indices`,
+/// e.g. a range over the whole mesh or the triangles of a kd-tree leaf
+fn triangles_closest_intersection<I>(
     triangle_indices: I,
     ray: &Ray,
     mesh: &Mesh,
+    two_sided: bool,
 ) -> Option<TriangleIntersect>
 where
-    I: Iterator<Item = &'a usize>,
+    I: IntoIterator<Item = usize>,
 {
     let mut closest_triangle_index: usize = 0;
     let mut closest_intersection = Position::new(f64::NAN, f64::NAN, f64::NAN);
     let mut closest_bar_coord = [f64::NAN, f64::NAN];
     let mut hit = false;
     for triangle_index in triangle_indices {
-        let ref triangle = mesh.triangles[*triangle_index];
+        let ref triangle = mesh.triangles[triangle_index];
         let ref t0 = mesh.vertices[triangle[0]];
         let ref t1 = mesh.vertices[triangle[1]];
         let ref t2 = mesh.vertices[triangle[2]];
 
-        let intersection_opt = ray.intersect_triangle(t0, t1, t2);
+        let mut intersection_opt = ray.intersect_triangle(t0, t1, t2);
+        if two_sided && intersection_opt.is_none() {
+            // Swapping the corners swaps the barycentric coordinates
+            intersection_opt = ray
+                .intersect_triangle(t0, t2, t1)
+                .map(|(point, [u, v])| (point, [v, u]));
+        }
         if intersection_opt.is_some() {
             let (intersection_point, bar_coord) = intersection_opt.unwrap();
             // Init the value
@@ -114,7 +796,7 @@ where
                 || (closest_intersection - ray.position).norm_squared()
                     >= (intersection_point - ray.position).norm_squared()
             {
-                closest_triangle_index = *triangle_index;
+                closest_triangle_index = triangle_index;
                 closest_intersection = intersection_point;
                 closest_bar_coord = bar_coord;
             }
@@ -128,34 +810,768 @@ where
             triangle_index: closest_triangle_index,
             intersection: closest_intersection,
             barycentric_coordinate: closest_bar_coord,
+            tangent: mesh.tangent_at(closest_triangle_index, &closest_bar_coord),
+            instance: None,
+            hittable: None,
         }),
         _ => None,
     }
 }
 
-fn shade_triangle_hit(
-    intersect: &TriangleIntersect,
-    mesh: &Mesh,
-    camera_config: &CameraConfig,
-    rendering_config: &RenderingConfig,
-) -> [u8; 3] {
-    let closest_normal = match rendering_config.normal_mode {
-        NormalMode::Phong => {
-            let ref triangle = mesh.triangles[intersect.triangle_index];
-            interpolation_n_phong(
-                &mesh.vertex_normals[triangle[0]],
-                &mesh.vertex_normals[triangle[1]],
-                &mesh.vertex_normals[triangle[2]],
-                &intersect.barycentric_coordinate,
-            )
+/// Whether one of the triangles blocks `ray` before `distance`
+///
+/// Unlike camera rays, both sides of the triangles block the light, so that
+/// open surfaces cast shadows too.
+pub(crate) fn occluded<I>(triangle_indices: I, ray: &Ray, distance: f64, mesh: &Mesh) -> bool
+where
+    I: IntoIterator<Item = usize>,
+{
+    triangle_indices.into_iter().any(|triangle_index| {
+        let triangle = &mesh.triangles[triangle_index];
+        let t0 = &mesh.vertices[triangle[0]];
+        let t1 = &mesh.vertices[triangle[1]];
+        let t2 = &mesh.vertices[triangle[2]];
+        ray.intersect_triangle(t0, t1, t2)
+            .or_else(|| ray.intersect_triangle(t0, t2, t1))
+            .is_some_and(|(point, _)| (point - ray.position).norm() < distance)
+    })
+}
+
+/// Light reflected toward `to_viewer` by the surface hit, 1 being the top of
+/// the 8-bit range once encoded
+///
+/// The material is lit by all the lights of the rendering config, each one only
+/// when nothing stands between the hit and the light: the shadow rays toward
+/// the lights are handed to `occluded` together, up to `PACKET_SIZE` at a
+/// time, which returns the mask of the rays blocked before their distance.
+/// The sum is scaled by the manual exposure. Without lights, hits are lit
+/// from the viewer, unless the headlight is off.
+fn radiance<F>(
+    hit: &SurfaceHit,
+    material: &dyn Material,
+    face_normal: &Direction,
+    to_viewer: Direction,
+    shading: &Shading,
+    occluded: &F,
+) -> Color
+where
+    F: Fn(&[(Ray, f64)]) -> u64,
+{
+    let (rendering_config, units) = (shading.rendering_config, shading.units);
+    if rendering_config.lights.is_empty() && rendering_config.headlight {
+        // Irradiance of pi, so that a white matte surface facing the camera
+        // is white, and none behind the shading normal
+        let cos = to_viewer.dot(&hit.normal).max(0.0);
+        let brdf = material.brdf(hit, &to_viewer, &to_viewer);
+        return brdf * (cos * std::f64::consts::PI);
+    }
+    let epsilon = shading.epsilon;
+    let far = shading.far(&hit.position);
+    let mut lit = Vec::with_capacity(rendering_config.lights.len());
+    let mut shadow_rays = Vec::with_capacity(rendering_config.lights.len());
+    for light in &rendering_config.lights {
+        let sample = match light.illuminate(&hit.position, units) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let cos = sample.direction.dot(&hit.normal);
+        if cos <= 0.0 {
+            continue;
+        }
+        // Start on the side of the surface facing the light
+        let offset = face_normal * epsilon * face_normal.dot(&sample.direction).signum();
+        shadow_rays.push((
+            Ray::new(hit.position + offset, sample.direction),
+            sample.distance.min(far),
+        ));
+        lit.push((sample, cos));
+    }
+    let mut total = Color::BLACK;
+    for (packet, lights) in shadow_rays.chunks(PACKET_SIZE).zip(lit.chunks(PACKET_SIZE)) {
+        let blocked = occluded(packet);
+        for (i, (sample, cos)) in lights.iter().enumerate() {
+            if blocked & 1 << i == 0 {
+                total +=
+                    material.brdf(hit, &sample.direction, &to_viewer) * sample.illuminance * *cos;
+            }
+        }
+    }
+    total * exposure_scale(rendering_config)
+}
+
+/// Factor of the manual exposure; the automatic one is measured on the
+/// finished image by `image::to_display`
+fn exposure_scale(rendering_config: &RenderingConfig) -> f64 {
+    match rendering_config.exposure {
+        Exposure::Manual { ev } => ev.exp2() as f64,
+        Exposure::Auto => 1.0,
+    }
+}
+
+/// Surface hit by a ray, and how to shade it
+struct Surface<'s> {
+    /// With the shading normal of the material
+    hit: SurfaceHit,
+    material: &'s dyn Material,
+    /// Normal of the triangle, to offset the secondary rays
+    face_normal: Direction,
+}
+
+fn surface_hit<'s>(shading: &'s Shading, intersect: &TriangleIntersect) -> Surface<'s> {
+    let normal_mode = shading.rendering_config.normal_mode;
+    let surface = match (shading.geometry, intersect.hittable) {
+        (Geometry::Hittables(hittables), Some(index)) => {
+            hittables[index].surface(intersect, normal_mode)
         }
+        (Geometry::Hittables(_), None) => panic!("hit of hittables without its hittable"),
+        _ => {
+            let (mesh, instance) = shading.hit_mesh(intersect);
+            let surface = mesh_surface(mesh, intersect, normal_mode);
+            match instance {
+                Some(instance) => HitSurface {
+                    normal: instance.normal_to_world(&surface.normal),
+                    face_normal: instance.normal_to_world(&surface.face_normal),
+                    material: instance.material.as_deref().or(surface.material),
+                    ..surface
+                },
+                None => surface,
+            }
+        }
+    };
+    let material = surface
+        .material
+        .unwrap_or_else(|| shading.default_material.as_ref());
+    let mut hit = SurfaceHit {
+        position: intersect.intersection,
+        normal: surface.normal,
+        vertex_color: surface.vertex_color,
+        uvs: surface.uvs,
+        tangent: intersect.tangent,
+    };
+    hit.normal = material.shading_normal(&hit);
+    Surface {
+        hit,
+        material,
+        face_normal: surface.face_normal,
+    }
+}
+
+/// Surface of the triangle of `mesh` hit, in the coordinates of the mesh,
+/// without material when the mesh has none
+pub(crate) fn mesh_surface<'m>(
+    mesh: &'m Mesh,
+    intersect: &TriangleIntersect,
+    normal_mode: NormalMode,
+) -> HitSurface<'m> {
+    let triangle = &mesh.triangles[intersect.triangle_index];
+    let [u, v] = intersect.barycentric_coordinate;
+    let normal = match normal_mode {
+        NormalMode::Phong => interpolation_n_phong(
+            &mesh.vertex_normals[triangle[0]],
+            &mesh.vertex_normals[triangle[1]],
+            &mesh.vertex_normals[triangle[2]],
+            &intersect.barycentric_coordinate,
+        ),
         NormalMode::Triangle => mesh.triangle_normals[intersect.triangle_index],
     };
-    let color = clamp_u8(
-        (camera_config.camera_position - intersect.intersection)
-            .normalize()
-            .dot(&closest_normal)
-            * 255.0,
-    );
-    [color, color, color]
+    let vertex_color = match &mesh.vertex_colors {
+        Some(colors) => {
+            let color = |vertex: usize| Color::from(colors[vertex].map(f64::from));
+            color(triangle[0]) * (1.0 - u - v) + color(triangle[1]) * u + color(triangle[2]) * v
+        }
+        None => Color::WHITE,
+    };
+    let mut uvs = [None; MAX_UV_SETS];
+    for (set, uv) in uvs.iter_mut().enumerate() {
+        *uv = mesh.uv_set(set).map(|uvs| {
+            let [a, b, c] = [uvs[triangle[0]], uvs[triangle[1]], uvs[triangle[2]]];
+            [
+                (1.0 - u - v) * a[0] + u * b[0] + v * c[0],
+                (1.0 - u - v) * a[1] + u * b[1] + v * c[1],
+            ]
+        });
+    }
+    HitSurface {
+        normal,
+        face_normal: mesh.triangle_normals[intersect.triangle_index],
+        vertex_color,
+        uvs,
+        material: match mesh.materials.is_empty() {
+            true => None,
+            false => Some(mesh.material(intersect.triangle_index)),
+        },
+    }
+}
+
+/// Color of a hit of `ray`: the light of the light sources reflected by the
+/// material, and what its specular interface reflects and refracts, traced
+/// up to `max_depth` bounces
+fn shade_triangle_hit<C, O>(
+    shading: &Shading,
+    surface: &Surface,
+    ray: &Ray,
+    depth: u32,
+    closest: &C,
+    occluded: &O,
+) -> Color
+where
+    C: Fn(&Ray, bool) -> Option<TriangleIntersect>,
+    O: Fn(&[(Ray, f64)]) -> u64,
+{
+    let rendering_config = shading.rendering_config;
+    let (hit, material, face_normal) = (&surface.hit, surface.material, &surface.face_normal);
+    let to_viewer = -ray.direction;
+    let mut color = radiance(hit, material, face_normal, to_viewer, shading, occluded);
+
+    let specular = match material.specular(hit) {
+        Some(specular) if depth < rendering_config.max_depth => specular,
+        _ => return color,
+    };
+    let epsilon = shading.epsilon;
+    for (direction, weight) in specular.scatter(&ray.direction, &hit.normal) {
+        if weight == Color::BLACK {
+            continue;
+        }
+        // Start on the side of the surface the ray leaves to
+        let offset = face_normal * epsilon * face_normal.dot(&direction).signum();
+        let secondary = Ray::new(hit.position + offset, direction);
+        color += weight * trace(shading, &secondary, depth + 1, closest, occluded);
+    }
+    color
+}
+
+/// Color of a hit of a path: `shade_triangle_hit`, plus the light coming
+/// from one direction drawn by the material, followed until the path ends
+fn shade_path<C, O>(
+    shading: &Shading,
+    surface: &Surface,
+    ray: &Ray,
+    depth: u32,
+    rng: &mut SampleRng,
+    closest: &C,
+    occluded: &O,
+) -> Color
+where
+    C: Fn(&Ray, bool) -> Option<TriangleIntersect>,
+    O: Fn(&[(Ray, f64)]) -> u64,
+{
+    let rendering_config = shading.rendering_config;
+    let (hit, material, face_normal) = (&surface.hit, surface.material, &surface.face_normal);
+    let to_viewer = -ray.direction;
+    let mut color = radiance(hit, material, face_normal, to_viewer, shading, occluded);
+    if depth >= rendering_config.max_depth {
+        return color;
+    }
+
+    let epsilon = shading.epsilon;
+    // The environment lights the hits through its lights in `radiance`:
+    // only the directions no light reaches, those of the specular lobes, see
+    // it when they escape
+    let environment_lit = rendering_config.environment.is_some();
+    // Light coming from `direction`, the escaped rays seeing the background
+    // unless it is `lit`
+    let follow = |direction: Direction, rng: &mut SampleRng, lit: bool| {
+        let offset = face_normal * epsilon * face_normal.dot(&direction).signum();
+        let secondary = Ray::new(hit.position + offset, direction);
+        match closest(&secondary, true) {
+            Some(intersect) => {
+                let surface = surface_hit(shading, &intersect);
+                shade_path(
+                    shading,
+                    &surface,
+                    &secondary,
+                    depth + 1,
+                    rng,
+                    closest,
+                    occluded,
+                )
+            }
+            None if lit => Color::BLACK,
+            None => miss(shading, &secondary),
+        }
+    };
+    if let Some(specular) = material.specular(hit) {
+        for (direction, weight) in specular.scatter(&ray.direction, &hit.normal) {
+            if weight != Color::BLACK {
+                color += weight * follow(direction, rng, false);
+            }
+        }
+    }
+    let (u, v) = (rng.gen(), rng.gen());
+    let sample = match material.sample(hit, &to_viewer, u, v) {
+        Some(sample) if sample.weight != Color::BLACK => sample,
+        _ => return color,
+    };
+    let mut weight = sample.weight;
+    if let PathTermination::RussianRoulette { min_depth } = rendering_config.termination {
+        if depth >= min_depth {
+            // Paths carrying little light are likely to stop, the others
+            // make up for them
+            let survival = weight.r.max(weight.g).max(weight.b).clamp(0.05, 1.0);
+            if rng.gen::<f64>() >= survival {
+                return color;
+            }
+            weight = weight / survival;
+        }
+    }
+    color + weight * follow(sample.direction, rng, environment_lit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::color::linear_to_srgb;
+    use crate::render::config::LightConfig;
+    use crate::render::environment::Background;
+    use crate::render::light::LightUnits;
+    use crate::render::material::{Lambertian, Mirror};
+
+    /// Floor facing up, 20 meters across around the origin, and a `card`
+    /// above it, triangles 0 and 1
+    fn floor_and_card(card: [Position; 3]) -> Mesh {
+        let mut vertices = vec![
+            Position::new(-10.0, 0.0, -10.0),
+            Position::new(-10.0, 0.0, 10.0),
+            Position::new(10.0, 0.0, 0.0),
+        ];
+        vertices.extend(&card);
+        Mesh::from_vertices_and_triangles(vertices, vec![[0, 1, 2], [3, 4, 5]])
+    }
+
+    #[test]
+    fn point_lights_cast_shadows() {
+        // A smaller card high above the origin
+        let mesh = floor_and_card([
+            Position::new(-1.0, 5.0, -1.0),
+            Position::new(-1.0, 5.0, 1.0),
+            Position::new(1.0, 5.0, 0.0),
+        ]);
+        let kdt = KdTree::from_mesh(&mesh);
+        let camera_config = CameraConfig::default();
+        // Camera ray hitting the floor at (-2, 0, 0), next to the card
+        let shade = |lights: &[LightConfig]| {
+            let rendering_config = RenderingConfig {
+                normal_mode: NormalMode::Triangle,
+                lights: lights.iter().map(|light| light.build().unwrap()).collect(),
+                ..Default::default()
+            };
+            let ray = || {
+                Ray::new(
+                    Position::new(-2.0, 10.0, 0.0),
+                    Direction::new(0.0, -1.0, 0.0),
+                )
+            };
+            let units = Unit::Meters;
+            let naive = make_naive_ray_tracer(&mesh, &camera_config, &rendering_config, units);
+            let kdt = make_kdt_ray_tracer(&mesh, &kdt, &camera_config, &rendering_config, units);
+            let color = naive(ray(), SampleKey::default());
+            assert_eq!(color, kdt(ray(), SampleKey::default()));
+            color.r
+        };
+        let point = |x, y| LightConfig::Point {
+            position: Position::new(x, y, 0.0),
+            intensity: 1.0,
+            units: LightUnits::Candela,
+            color: Color::WHITE,
+        };
+        let sun = |x| LightConfig::Directional {
+            direction: Direction::new(x, -1.0, 0.0),
+            illuminance: 1.0,
+            color: Color::WHITE,
+        };
+
+        // 1 cd at 1 m gives 1 lux, reflected as 1 / pi
+        let reflected = 1.0 / std::f64::consts::PI;
+        assert!((shade(&[point(-2.0, 1.0)]) - reflected).abs() < 1e-9);
+        // Behind the card
+        assert_eq!(shade(&[point(2.0, 10.0)]), 0.0);
+        assert_eq!(shade(&[sun(-0.4)]), 0.0);
+        // Contributions add up
+        let total = shade(&[sun(0.0), point(-2.0, 1.0), point(2.0, 10.0)]);
+        assert!((total - 2.0 * reflected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn headlight_does_not_light_normals_facing_away() {
+        let mesh = Mesh::from_vertices_and_triangles(vec![Position::origin()], vec![]);
+        let camera_config = CameraConfig::default();
+        let rendering_config = RenderingConfig::default();
+        let shading = Shading::new(
+            Geometry::Mesh(&mesh),
+            &camera_config,
+            &rendering_config,
+            Unit::Meters,
+        );
+        let material = Lambertian {
+            albedo: Color::WHITE,
+            texture: None,
+        };
+        let shade = |normal: Direction| {
+            let hit = SurfaceHit {
+                position: Position::origin(),
+                normal,
+                vertex_color: Color::WHITE,
+                uvs: [None; MAX_UV_SETS],
+                tangent: None,
+            };
+            let occluded = |_: &[(Ray, f64)]| 0;
+            radiance(
+                &hit,
+                &material,
+                &Direction::y(),
+                Direction::y(),
+                &shading,
+                &occluded,
+            )
+        };
+        assert!((shade(Direction::y()).r - 1.0).abs() < 1e-12);
+        // Shading normals bent past the silhouette, e.g. by a normal map
+        assert_eq!(shade(-Direction::y()), Color::BLACK);
+    }
+
+    #[test]
+    fn debug_views_show_the_hits() {
+        let mesh = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-10.0, 0.0, -10.0),
+                Position::new(-10.0, 0.0, 10.0),
+                Position::new(10.0, 0.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let kdt = KdTree::from_mesh(&mesh);
+        let camera_config = CameraConfig {
+            camera_position: Position::new(0.0, 10.0, 0.0),
+            ..Default::default()
+        };
+        let view = |debug_view, kd_tree: bool| {
+            let rendering_config = RenderingConfig {
+                debug_view: Some(debug_view),
+                ..Default::default()
+            };
+            // Straight down to the front of the scene, at (0.25, 0.25, 0.5)
+            // in the triangle
+            let ray = Ray::new(
+                Position::new(0.0, 10.0, 0.0),
+                Direction::new(0.0, -1.0, 0.0),
+            );
+            let color = if kd_tree {
+                let tracer = make_kdt_ray_tracer(
+                    &mesh,
+                    &kdt,
+                    &camera_config,
+                    &rendering_config,
+                    Unit::Meters,
+                );
+                tracer(ray, SampleKey::default())
+            } else {
+                let tracer =
+                    make_naive_ray_tracer(&mesh, &camera_config, &rendering_config, Unit::Meters);
+                tracer(ray, SampleKey::default())
+            };
+            <[f64; 3]>::from(color.map(linear_to_srgb))
+        };
+        let close = |a: [f64; 3], b: [f64; 3]| a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-9);
+
+        assert!(close(view(DebugView::Normals, true), [0.5, 1.0, 0.5]));
+        assert!(close(
+            view(DebugView::Barycentrics, true),
+            [0.25, 0.25, 0.5]
+        ));
+        assert!(close(view(DebugView::Depth, true), [1.0, 0.0, 0.0]));
+        assert_eq!(
+            view(DebugView::TriangleIndex, true),
+            view(DebugView::TriangleIndex, false)
+        );
+        // At least the root is visited with the kd-tree, nothing without
+        let nodes = DebugView::KdTreeNodes { max: 1 };
+        assert!(close(view(nodes, true), [1.0, 0.0, 0.0]));
+        assert!(close(view(nodes, false), [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn integrators_shade_the_same_hit_differently() {
+        // A card a meter above the origin
+        let mesh = floor_and_card([
+            Position::new(-2.0, 1.0, -2.0),
+            Position::new(-2.0, 1.0, 2.0),
+            Position::new(2.0, 1.0, 0.0),
+        ]);
+        let kdt = KdTree::from_mesh(&mesh);
+        // Straight above the shaded points
+        let camera_config = CameraConfig {
+            camera_position: Position::new(-5.0, 10.0, 0.0),
+            ..Default::default()
+        };
+        let shade_sample = |integrator, x: f64, key: SampleKey| {
+            let rendering_config = RenderingConfig {
+                integrator,
+                max_depth: 1,
+                background: Background::Gradient {
+                    zenith: Color::gray(0.5),
+                    horizon: Color::gray(0.5),
+                    ground: Color::gray(0.5),
+                },
+                ..Default::default()
+            };
+            // Down on the floor, under the card at x = 0 or out in the open
+            let ray = Ray::new(Position::new(x, 0.5, 0.0), Direction::new(0.0, -1.0, 0.0));
+            let tracer =
+                make_kdt_ray_tracer(&mesh, &kdt, &camera_config, &rendering_config, Unit::Meters);
+            tracer(ray, key).r
+        };
+        let shade = |integrator, x: f64| shade_sample(integrator, x, SampleKey::default());
+        let occlusion = |distance| Integrator::AmbientOcclusion {
+            samples: 256,
+            distance,
+        };
+
+        assert_eq!(shade(Integrator::FacingRatio, 0.0), 1.0);
+        let under_card = shade(occlusion(None), 0.0);
+        assert!(under_card > 0.05 && under_card < 0.95, "{}", under_card);
+        assert_eq!(shade(occlusion(Some(0.5)), 0.0), 1.0);
+        assert!(shade(occlusion(None), -5.0) > under_card);
+        // Lit from the viewer, plus the sky seen by one bounce
+        assert!((shade(Integrator::Whitted, -5.0) - 1.0).abs() < 1e-9);
+        assert!((shade(Integrator::PathTracing, -5.0) - 1.5).abs() < 1e-9);
+        assert_eq!("path_tracing".parse(), Ok(Integrator::PathTracing));
+
+        // The same ray draws other directions for every sample of its pixel,
+        // the same ones on every run
+        let few = Integrator::AmbientOcclusion {
+            samples: 4,
+            distance: None,
+        };
+        let key = |sample| SampleKey { x: 3, y: 4, sample };
+        let draws: Vec<f64> = (0..8).map(|s| shade_sample(few, 0.0, key(s))).collect();
+        assert!(draws.iter().any(|&d| d != draws[0]), "{:?}", draws);
+        assert_eq!(shade_sample(few, 0.0, key(5)), draws[5]);
+    }
+
+    #[test]
+    fn secondary_rays_scale_with_the_scene() {
+        // A card above the origin, the whole a micrometer wide
+        let scale = 1e-7;
+        let mut mesh = floor_and_card([
+            Position::new(-2.0, 1.0, -2.0),
+            Position::new(-2.0, 1.0, 2.0),
+            Position::new(2.0, 1.0, 0.0),
+        ]);
+        mesh.scale(&Direction::repeat(scale));
+        let kdt = KdTree::from_mesh(&mesh);
+        let camera_config = CameraConfig::default();
+        let rendering_config = RenderingConfig {
+            integrator: Integrator::AmbientOcclusion {
+                samples: 256,
+                distance: None,
+            },
+            ..Default::default()
+        };
+        let tracer =
+            make_kdt_ray_tracer(&mesh, &kdt, &camera_config, &rendering_config, Unit::Meters);
+        // Down on the floor under the card, which a tenth of a millimeter
+        // off the floor would be far above
+        let ray = Ray::new(
+            Position::new(0.0, 0.5 * scale, 0.0),
+            Direction::new(0.0, -1.0, 0.0),
+        );
+        let under_card = tracer(ray, SampleKey::default()).r;
+        assert!(under_card > 0.05 && under_card < 0.95, "{}", under_card);
+    }
+
+    #[test]
+    fn mirrors_reflect_up_to_max_depth() {
+        // A mirror at 45° sending rays along +x down
+        let mut mesh = floor_and_card([
+            Position::new(-0.5, 1.5, -1.0),
+            Position::new(0.5, 0.5, 0.0),
+            Position::new(-0.5, 1.5, 1.0),
+        ]);
+        mesh.materials = vec![
+            Arc::new(Lambertian {
+                albedo: Color::WHITE,
+                texture: None,
+            }),
+            Arc::new(Mirror {
+                color: Color::WHITE,
+                texture: None,
+            }),
+        ];
+        mesh.triangle_materials = Some(vec![0, 1]);
+        let kdt = KdTree::from_mesh(&mesh);
+        let camera_config = CameraConfig::default();
+        let light = LightConfig::Point {
+            position: Position::new(0.0, 0.2, 0.0),
+            intensity: 1.0,
+            units: LightUnits::Candela,
+            color: Color::WHITE,
+        };
+        let shade = |max_depth| {
+            let rendering_config = RenderingConfig {
+                max_depth,
+                lights: vec![light.build().unwrap()],
+                ..Default::default()
+            };
+            let ray = || Ray::new(Position::new(-5.0, 1.0, 0.0), Direction::new(1.0, 0.0, 0.0));
+            let units = Unit::Meters;
+            let naive = make_naive_ray_tracer(&mesh, &camera_config, &rendering_config, units);
+            let kdt = make_kdt_ray_tracer(&mesh, &kdt, &camera_config, &rendering_config, units);
+            let color = naive(ray(), SampleKey::default());
+            assert_eq!(color, kdt(ray(), SampleKey::default()));
+            color.r
+        };
+
+        // The mirror alone is black, its reflection shows the floor lit with
+        // 1 cd at 0.2 m, the reflected ray starting a bit off the mirror
+        assert_eq!(shade(0), 0.0);
+        let reflected = 25.0 / std::f64::consts::PI;
+        assert!((shade(1) - reflected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn instances_render_as_their_copies() {
+        use crate::geometry::bvh::{TreeBuild, TreePreset};
+        use crate::geometry::instance::Instance;
+        use crate::render::scene::{Scene, Transform};
+
+        let floor = Arc::new(Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-10.0, 0.0, -10.0),
+                Position::new(-10.0, 0.0, 10.0),
+                Position::new(10.0, 0.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+        ));
+        let tetrahedron = Arc::new(Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-1.0, 0.0, -1.0),
+                Position::new(-1.0, 0.0, 1.0),
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 2, 1], [0, 1, 3], [1, 2, 3], [2, 0, 3]],
+        ));
+        let transforms: Vec<Transform> = (0..5)
+            .map(|i| Transform {
+                translation: Direction::new(2.5 * i as f64 - 5.0, 0.0, i as f64),
+                rotation: [0.0, 30.0 * i as f64, 0.0],
+                scale: 0.5 + 0.25 * i as f64,
+            })
+            .collect();
+        let scene = Scene::builder()
+            .add_mesh(Arc::clone(&floor))
+            .add_instances(Arc::clone(&tetrahedron), &transforms)
+            .build();
+        let merged = scene.to_mesh();
+        let kdt = KdTree::from_mesh(&merged);
+
+        let mut placements = vec![Instance::new(0, Transform::default().similarity(), None)];
+        for transform in &transforms {
+            placements.push(Instance::new(1, transform.similarity(), None));
+        }
+        let instances = Instances::new(
+            vec![floor, tetrahedron],
+            placements,
+            TreeBuild::Sah,
+            TreePreset::Balanced,
+            true,
+        );
+        assert_eq!(instances.triangle_count(), merged.triangles.len());
+
+        let camera_config = CameraConfig::look_at(
+            Position::new(0.0, 6.0, -12.0),
+            Position::new(0.0, 0.0, 2.0),
+            Direction::y(),
+            50.0,
+            48,
+            32,
+        );
+        let light = LightConfig::Point {
+            position: Position::new(3.0, 8.0, -2.0),
+            intensity: 100.0,
+            units: LightUnits::Candela,
+            color: Color::WHITE,
+        };
+        let rendering_config = RenderingConfig {
+            lights: vec![light.build().unwrap()],
+            ..Default::default()
+        };
+        let units = Unit::Meters;
+        let tracer = make_kdt_ray_tracer(&merged, &kdt, &camera_config, &rendering_config, units);
+        let expected = render_hdr_image(tracer, &camera_config, &rendering_config);
+        let image = render_instances(&instances, &camera_config, &rendering_config, units);
+        let different = image
+            .pixels()
+            .zip(expected.pixels())
+            .filter(|(a, b)| (0..3).any(|c| (a[c] - b[c]).abs() > 1e-4))
+            .count();
+        assert!(different <= 2, "{} pixels differ", different);
+        assert!(image.pixels().any(|p| p[0] > 0.0));
+    }
+
+    #[test]
+    fn hittables_shade_and_shadow_each_other() {
+        use crate::geometry::instance::Prototype;
+        use crate::render::hittable::{Plane, Sphere};
+
+        // Unit ball resting on an infinite floor, and a card of triangles
+        // over the floor far away
+        let card = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(99.0, 1.0, -1.0),
+                Position::new(99.0, 1.0, 1.0),
+                Position::new(101.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let hittables: Vec<Box<dyn Hittable>> = vec![
+            Box::new(Plane::new(Position::origin(), Direction::y())),
+            Box::new(Sphere::new(Position::new(0.0, 1.0, 0.0), 1.0)),
+            Box::new(Prototype {
+                kdtree: KdTree::from_mesh(&card),
+                mesh: Arc::new(card),
+            }),
+        ];
+        let camera_config = CameraConfig::default();
+        let shade = |light: (f64, f64), origin: Position| {
+            let light = LightConfig::Point {
+                position: Position::new(light.0, light.1, 0.0),
+                intensity: 1.0,
+                units: LightUnits::Candela,
+                color: Color::WHITE,
+            };
+            let rendering_config = RenderingConfig {
+                normal_mode: NormalMode::Triangle,
+                lights: vec![light.build().unwrap()],
+                ..Default::default()
+            };
+            let tracer = make_hittable_sample_tracer(
+                &hittables,
+                &camera_config,
+                &rendering_config,
+                Unit::Meters,
+            );
+            tracer(Ray::new(origin, -Direction::y()), SampleKey::default())
+        };
+        let reflected = 1.0 / std::f64::consts::PI;
+        let above = |x| Position::new(x, 10.0, 0.0);
+
+        // The floor beside the ball, lit from above then from behind the ball
+        let floor = shade((-2.0, 1.0), above(-2.0));
+        assert!((floor.color.r - reflected).abs() < 1e-9);
+        assert!((floor.depth - 10.0).abs() < 1e-9);
+        assert_eq!(shade((2.0, 2.0), above(-2.0)).color.r, 0.0);
+        // The top of the ball, in front of the floor
+        let top = shade((0.0, 3.0), above(0.0));
+        assert!((top.color.r - reflected).abs() < 1e-9);
+        assert!((top.depth - 8.0).abs() < 1e-9);
+        assert!((top.normal - Direction::y()).norm() < 1e-9);
+        // Far from the ball, the floor is hit all the same, in the shadow of
+        // the card
+        let shadowed = shade((100.0, 2.0), above(101.5));
+        assert!((shadowed.depth - 10.0).abs() < 1e-9);
+        assert_eq!(shadowed.color.r, 0.0);
+        let card = shade((100.0, 2.0), above(100.0));
+        assert!((card.depth - 9.0).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::geometry::kdtree::KdTreeStats;
+use crate::geometry::mesh::{Mesh, Tangent};
+use crate::geometry::types::{Direction, Position, Triangle};
+use crate::render::color::Color;
+use crate::render::config::MaterialConfig;
+use crate::render::shared::PreparedScene;
+
+/// Leaves holding more triangles than this are worth a warning: every ray
+/// through them tests them all
+const LARGE_LEAF_TRIANGLES: usize = 256;
+
+/// Average number of leaves a triangle is put in above which the kd-tree is
+/// worth a warning, usually because of large triangles in a dense mesh
+const HIGH_TRIANGLE_DUPLICATION: f64 = 8.0;
+
+/// What a prepared scene is made of, to predict how long it will take to
+/// render and how much memory it needs before starting
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneReport {
+    pub objects: usize,
+    pub triangles: usize,
+    pub vertices: usize,
+    /// Triangles of zero area, which no ray can hit
+    pub degenerate_triangles: usize,
+    pub kdtree: KdTreeStats,
+    pub kdtree_build_time: Duration,
+    /// Memory used by the merged mesh
+    pub mesh_bytes: usize,
+    /// Memory used by the textures, normal maps and environment image
+    pub texture_bytes: usize,
+}
+
+impl SceneReport {
+    /// Instanced scenes count the triangles and trees of their models at
+    /// every instance, but their memory once
+    pub fn new(prepared: &PreparedScene) -> SceneReport {
+        let instances = match &prepared.instances {
+            Some(instances) => instances,
+            None => {
+                let mesh = prepared.mesh();
+                return SceneReport {
+                    objects: prepared.scene.objects.len(),
+                    triangles: mesh.triangles.len(),
+                    vertices: mesh.vertices.len(),
+                    degenerate_triangles: degenerate_triangles(mesh),
+                    kdtree: prepared.kdtree().stats(),
+                    kdtree_build_time: prepared.kdtree_build_time(),
+                    mesh_bytes: mesh_bytes(mesh),
+                    texture_bytes: texture_bytes(prepared),
+                };
+            }
+        };
+        let models: Vec<(usize, KdTreeStats)> = instances
+            .prototypes
+            .iter()
+            .map(|model| (degenerate_triangles(&model.mesh), model.kdtree.stats()))
+            .collect();
+        // The leaves of the top level tree only lead to the models
+        let top = instances.tree.stats();
+        let mut kdtree = KdTreeStats {
+            nodes: top.nodes,
+            depth: top.depth,
+            bytes: top.bytes,
+            ..Default::default()
+        };
+        let (mut vertices, mut degenerate) = (0, 0);
+        for instance in &instances.instances {
+            let (model_degenerate, stats) = &models[instance.prototype];
+            vertices += instances.prototypes[instance.prototype].mesh.vertices.len();
+            degenerate += model_degenerate;
+            kdtree.nodes += stats.nodes;
+            kdtree.leaves += stats.leaves;
+            kdtree.depth = kdtree.depth.max(top.depth + stats.depth);
+            kdtree.max_leaf_triangles = kdtree.max_leaf_triangles.max(stats.max_leaf_triangles);
+            kdtree.triangle_references += stats.triangle_references;
+        }
+        kdtree.bytes += models.iter().map(|(_, stats)| stats.bytes).sum::<usize>();
+        SceneReport {
+            objects: prepared.scene.objects.len(),
+            triangles: instances.triangle_count(),
+            vertices,
+            degenerate_triangles: degenerate,
+            kdtree,
+            kdtree_build_time: prepared.instances_build_time,
+            mesh_bytes: instances
+                .prototypes
+                .iter()
+                .map(|model| mesh_bytes(&model.mesh))
+                .sum(),
+            texture_bytes: texture_bytes(prepared),
+        }
+    }
+
+    /// Memory used by the mesh, the kd-tree and the images, the rendered
+    /// image aside
+    pub fn total_bytes(&self) -> usize {
+        self.mesh_bytes + self.kdtree.bytes + self.texture_bytes
+    }
+
+    /// Pathological cases likely to make the render slow or wrong
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.degenerate_triangles > 0 {
+            warnings.push(format!(
+                "{} degenerate triangles, of zero area, will never be hit",
+                self.degenerate_triangles
+            ));
+        }
+        if self.kdtree.max_leaf_triangles > LARGE_LEAF_TRIANGLES {
+            warnings.push(format!(
+                "a kd-tree leaf holds {} triangles, rays through it will be slow",
+                self.kdtree.max_leaf_triangles
+            ));
+        }
+        let duplication = self.kdtree.triangle_references as f64 / self.triangles.max(1) as f64;
+        if duplication > HIGH_TRIANGLE_DUPLICATION {
+            warnings.push(format!(
+                "triangles are in {:.1} kd-tree leaves on average, \
+                 large triangles may cross many small ones",
+                duplication
+            ));
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for SceneReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "scene: {} objects, {} triangles, {} vertices",
+            self.objects, self.triangles, self.vertices
+        )?;
+        writeln!(
+            f,
+            "kd-tree: built in {:?}, {} nodes, {} leaves, depth {}, \
+             {:.1} triangles per leaf (at most {})",
+            self.kdtree_build_time,
+            self.kdtree.nodes,
+            self.kdtree.leaves,
+            self.kdtree.depth,
+            self.kdtree.triangle_references as f64 / self.kdtree.leaves.max(1) as f64,
+            self.kdtree.max_leaf_triangles
+        )?;
+        write!(
+            f,
+            "memory: {} in total, mesh {}, kd-tree {}, textures {}",
+            Bytes(self.total_bytes()),
+            Bytes(self.mesh_bytes),
+            Bytes(self.kdtree.bytes),
+            Bytes(self.texture_bytes)
+        )
+    }
+}
+
+/// Memory size, printed in the largest binary unit below it
+struct Bytes(usize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = ["B", "KiB", "MiB", "GiB"];
+        let mut size = self.0 as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit + 1 < units.len() {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", size, units[unit])
+    }
+}
+
+fn degenerate_triangles(mesh: &Mesh) -> usize {
+    (0..mesh.triangles.len())
+        .filter(|&t| is_degenerate(mesh, t))
+        .count()
+}
+
+/// Is the triangle flat, its vertices being aligned or merged
+fn is_degenerate(mesh: &Mesh, triangle: usize) -> bool {
+    let [a, b, c] = mesh.triangles[triangle];
+    let u = mesh.vertices[b] - mesh.vertices[a];
+    let v = mesh.vertices[c] - mesh.vertices[a];
+    let area = u.cross(&v).norm();
+    // Relative to the edges, so that the test holds at any scale
+    area.is_nan() || area <= 1e-12 * u.norm_squared().max(v.norm_squared())
+}
+
+fn mesh_bytes(mesh: &Mesh) -> usize {
+    mesh.vertices.len() * mem::size_of::<Position>()
+        + mesh.vertex_normals.len() * mem::size_of::<Direction>()
+        + mesh.triangles.len() * mem::size_of::<Triangle>()
+        + mesh.triangle_normals.len() * mem::size_of::<Direction>()
+        + mesh.polygons.as_ref().map_or(0, |polygons| {
+            polygons
+                .iter()
+                .map(|p| mem::size_of::<Vec<usize>>() + p.len() * mem::size_of::<usize>())
+                .sum()
+        })
+        + mesh.vertex_colors.as_ref().map_or(0, Vec::len) * mem::size_of::<[f32; 3]>()
+        + mesh.uvs.as_ref().map_or(0, Vec::len) * mem::size_of::<[f64; 2]>()
+        + mesh.extra_uvs.iter().map(Vec::len).sum::<usize>() * mem::size_of::<[f64; 2]>()
+        + mesh.vertex_tangents.as_ref().map_or(0, Vec::len) * mem::size_of::<Tangent>()
+        + mesh.triangle_materials.as_ref().map_or(0, Vec::len) * mem::size_of::<usize>()
+}
+
+/// Memory of the images of the materials and environment, counting the
+/// images shared by several materials once
+fn texture_bytes(prepared: &PreparedScene) -> usize {
+    let scene = &prepared.scene;
+    let materials = scene
+        .objects
+        .iter()
+        .map(|object| &object.material)
+        .chain(Some(&scene.rendering.material));
+    let mut seen = HashSet::new();
+    let mut bytes = 0;
+    for image in materials.flat_map(material_images) {
+        if seen.insert(Arc::as_ptr(image) as usize) {
+            bytes += image.as_raw().len();
+        }
+    }
+    if let Some(image) = scene.environment.as_ref().and_then(|e| e.image.as_ref()) {
+        bytes += image.pixels.len() * mem::size_of::<Color>();
+    }
+    bytes
+}
+
+fn material_images(material: &MaterialConfig) -> impl Iterator<Item = &Arc<image::RgbImage>> {
+    material
+        .texture_image
+        .iter()
+        .chain(material.normal_map_image.iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::scene::Scene;
+
+    #[test]
+    fn report_counts_and_warns_about_degenerate_triangles() {
+        let mesh = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(0.0, 0.0, 0.0),
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+                Position::new(2.0, 0.0, 0.0),
+            ],
+            // The second triangle is a segment
+            vec![[0, 1, 2], [0, 1, 3]],
+        );
+        let texture = Arc::new(image::RgbImage::new(4, 2));
+        let scene = Scene::builder()
+            .add_mesh(mesh)
+            .material(MaterialConfig {
+                texture_image: Some(texture.clone()),
+                normal_map_image: Some(texture),
+                ..Default::default()
+            })
+            .build();
+        let prepared = PreparedScene::new(scene).unwrap();
+
+        let report = SceneReport::new(&prepared);
+        assert_eq!(report.objects, 1);
+        assert_eq!(report.triangles, 2);
+        assert_eq!(report.degenerate_triangles, 1);
+        assert_eq!(report.texture_bytes, 4 * 2 * 3);
+        assert!(report.kdtree.leaves >= 1);
+        assert!(report.total_bytes() > report.mesh_bytes);
+        let warnings = report.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("1 degenerate"));
+        assert!(report
+            .to_string()
+            .starts_with("scene: 1 objects, 2 triangles, 4 vertices"));
+    }
+}
@@ -0,0 +1,123 @@
+extern crate rand;
+
+use self::rand::{Error, RngCore};
+
+/// Random number stream for a single sample of a pixel
+///
+/// The state is derived by hashing (seed, view, pixel, sample) rather than by
+/// advancing a shared generator, so the numbers drawn for a pixel do not
+/// depend on which thread or tile rendered it, nor on the order in which tiles
+/// were scheduled. Neighbouring pixels, or the two eyes of a stereo pair, get
+/// unrelated streams which avoids visible correlation seams.
+///
+/// The generator itself is SplitMix64, which is small and fast enough to be
+/// created for every sample.
+pub struct SampleRng {
+    state: u64,
+}
+
+impl SampleRng {
+    pub fn for_pixel(seed: u64, x: u32, y: u32, sample: u32) -> SampleRng {
+        SampleRng::for_view(seed, 0, x, y, sample)
+    }
+
+    /// Stream for a pixel of one of several views (e.g. stereo eyes)
+    /// rendered with the same seed
+    pub fn for_view(seed: u64, view: u32, x: u32, y: u32, sample: u32) -> SampleRng {
+        let mut h = mix(seed);
+        h = mix(h ^ u64::from(view));
+        h = mix(h ^ (u64::from(x) << 32 | u64::from(y)));
+        h = mix(h ^ u64::from(sample));
+        SampleRng { state: h }
+    }
+
+    /// Stream for anything else identified by a number, e.g. the nodes of
+    /// a kd-tree colored at random
+    pub fn for_key(seed: u64, key: u64) -> SampleRng {
+        SampleRng {
+            state: mix(mix(seed) ^ key),
+        }
+    }
+}
+
+/// SplitMix64 finalizer, a bijective hash with good avalanche
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let value = mix(self.state);
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        value
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn streams_are_reproducible() {
+        let mut a = SampleRng::for_pixel(7, 3, 5, 1);
+        let mut b = SampleRng::for_pixel(7, 3, 5, 1);
+        for _ in 0..4 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn neighbouring_streams_are_decorrelated() {
+        // Correlation between the first draw of horizontally adjacent pixels
+        let n = 4096;
+        let pairs: Vec<(f64, f64)> = (0..n)
+            .map(|i| {
+                let (x, y) = (i % 64, i / 64);
+                let a: f64 = SampleRng::for_pixel(1, x, y, 0).gen();
+                let b: f64 = SampleRng::for_pixel(1, x + 1, y, 0).gen();
+                (a, b)
+            })
+            .collect();
+        let mean = |f: &dyn Fn(&(f64, f64)) -> f64| pairs.iter().map(f).sum::<f64>() / n as f64;
+        let (ma, mb) = (mean(&|p| p.0), mean(&|p| p.1));
+        let cov = mean(&|p| (p.0 - ma) * (p.1 - mb));
+        let var_a = mean(&|p| (p.0 - ma) * (p.0 - ma));
+        let var_b = mean(&|p| (p.1 - mb) * (p.1 - mb));
+        assert!((cov / (var_a * var_b).sqrt()).abs() < 0.05);
+    }
+
+    #[test]
+    fn keyed_streams_follow_the_seed() {
+        let draw = |seed, key| -> u64 { SampleRng::for_key(seed, key).gen() };
+        assert_eq!(draw(3, 42), draw(3, 42));
+        assert_ne!(draw(3, 42), draw(4, 42));
+        assert_ne!(draw(3, 42), draw(3, 43));
+    }
+
+    #[test]
+    fn views_get_different_streams() {
+        let left: u64 = SampleRng::for_view(1, 0, 10, 10, 0).gen();
+        let right: u64 = SampleRng::for_view(1, 1, 10, 10, 0).gen();
+        assert_ne!(left, right);
+    }
+}
@@ -0,0 +1,160 @@
+extern crate rand;
+
+use std::fmt;
+use std::str::FromStr;
+
+use self::rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::render::rng::SampleRng;
+
+/// Placement of the samples inside a pixel
+///
+/// Samples are laid out on a grid of `ceil(sqrt(n))` columns filled row by
+/// row, which covers the pixel evenly for square sample counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerKind {
+    /// Centers of the grid cells: edges aligned with the grid still alias
+    #[default]
+    Regular,
+    /// Uniformly random positions in the pixel: aliasing turns into noise,
+    /// but samples may clump and leave gaps
+    Jittered,
+    /// A random position in every grid cell: noise without the gaps
+    Stratified,
+}
+
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 3] = [
+        SamplerKind::Regular,
+        SamplerKind::Jittered,
+        SamplerKind::Stratified,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SamplerKind::Regular => "regular",
+            SamplerKind::Jittered => "jittered",
+            SamplerKind::Stratified => "stratified",
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SamplerKind::ALL
+            .iter()
+            .cloned()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown sampler {}, expected regular, jittered or stratified",
+                    s
+                )
+            })
+    }
+}
+
+/// Positions of the `count` samples of the pixel (x, y), as offsets from its
+/// center in [-0.5, 0.5)
+///
+/// Random placements only depend on the seed and the pixel, so renders are
+/// reproducible whatever the number of threads.
+pub fn pixel_samples(kind: SamplerKind, count: u32, seed: u64, x: u32, y: u32) -> Vec<(f64, f64)> {
+    let columns = (count as f64).sqrt().ceil() as u32;
+    let rows = count.div_ceil(columns.max(1));
+    (0..count)
+        .map(|s| {
+            let (jitter_x, jitter_y) = match kind {
+                SamplerKind::Regular => (0.5, 0.5),
+                SamplerKind::Jittered | SamplerKind::Stratified => {
+                    let mut rng = SampleRng::for_pixel(seed, x, y, s);
+                    (rng.gen::<f64>(), rng.gen::<f64>())
+                }
+            };
+            match kind {
+                SamplerKind::Jittered => (jitter_x - 0.5, jitter_y - 0.5),
+                SamplerKind::Regular | SamplerKind::Stratified => (
+                    ((s % columns) as f64 + jitter_x) / columns as f64 - 0.5,
+                    ((s / columns) as f64 + jitter_y) / rows as f64 - 0.5,
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Point of the lens in [0, 1)² of sample `sample` of pixel (x, y), see
+/// `image::lens_ray`
+///
+/// It is drawn after the jitter of `pixel_samples` from the same stream, so
+/// that the two are unrelated.
+pub fn lens_sample(seed: u64, x: u32, y: u32, sample: u32) -> (f64, f64) {
+    let mut rng = SampleRng::for_pixel(seed, x, y, sample);
+    let _jitter: (f64, f64) = (rng.gen(), rng.gen());
+    (rng.gen(), rng.gen())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stratified_samples_cover_every_cell() {
+        let samples = pixel_samples(SamplerKind::Stratified, 9, 7, 3, 4);
+        let mut cells: Vec<(i32, i32)> = samples
+            .iter()
+            .map(|&(dx, dy)| {
+                assert!((-0.5..0.5).contains(&dx) && (-0.5..0.5).contains(&dy));
+                (((dx + 0.5) * 3.0) as i32, ((dy + 0.5) * 3.0) as i32)
+            })
+            .collect();
+        cells.sort_unstable();
+        cells.dedup();
+        assert_eq!(cells.len(), 9);
+
+        // Same pixel, same samples; other pixel, other samples
+        assert_eq!(samples, pixel_samples(SamplerKind::Stratified, 9, 7, 3, 4));
+        assert_ne!(samples, pixel_samples(SamplerKind::Stratified, 9, 7, 4, 4));
+        assert_eq!(
+            pixel_samples(SamplerKind::Regular, 1, 7, 3, 4),
+            vec![(0.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn samples_stay_in_their_pixel() {
+        for &kind in SamplerKind::ALL.iter() {
+            for count in 1..=17 {
+                let samples = pixel_samples(kind, count, 11, 5, 2);
+                assert_eq!(samples.len(), count as usize);
+                for &(dx, dy) in &samples {
+                    assert!(
+                        (-0.5..0.5).contains(&dx) && (-0.5..0.5).contains(&dy),
+                        "{} x{}: ({}, {})",
+                        kind,
+                        count,
+                        dx,
+                        dy
+                    );
+                }
+            }
+        }
+        // Square counts of the grid are centered on the pixel
+        for &count in [1, 4, 9, 16].iter() {
+            let samples = pixel_samples(SamplerKind::Regular, count, 0, 0, 0);
+            let (x, y) = samples
+                .iter()
+                .fold((0.0, 0.0), |(x, y), &(dx, dy)| (x + dx, y + dy));
+            assert!(x.abs() < 1e-12 && y.abs() < 1e-12, "x{}", count);
+        }
+    }
+}
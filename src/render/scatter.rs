@@ -0,0 +1,204 @@
+extern crate nalgebra as na;
+extern crate rand;
+
+use self::rand::Rng;
+
+use crate::geometry::mesh::Mesh;
+use crate::geometry::types::Direction;
+use crate::render::material::Texture;
+use crate::render::rng::SampleRng;
+use crate::render::scene::Transform;
+
+/// How `scatter` places copies of a model, e.g. grass or rocks, over a
+/// surface
+#[derive(Clone, Debug)]
+pub struct ScatterConfig {
+    /// Points drawn on the surface, before `density` thins them out
+    pub count: usize,
+    pub seed: u64,
+    /// Range of the uniform scale of the copies
+    pub scale: [f64; 2],
+    /// Range of the rotation of the copies around their up axis, in degrees
+    pub rotation: [f64; 2],
+    /// Tilt the up axis (+y) of the copies along the normal of the surface,
+    /// rather than keeping it vertical
+    pub align_to_normal: bool,
+    /// Grayscale image read through the first UV set of the surface: points
+    /// are kept with the probability of its gray level, none on black
+    pub density: Option<Texture>,
+}
+
+impl Default for ScatterConfig {
+    fn default() -> Self {
+        ScatterConfig {
+            count: 100,
+            seed: 0,
+            scale: [1.0, 1.0],
+            rotation: [0.0, 360.0],
+            align_to_normal: false,
+            density: None,
+        }
+    }
+}
+
+/// Placements of copies of a model over the triangles of `surface`, given in
+/// scene coordinates, rendered as instances of the model once added by
+/// `SceneBuilder::add_instances`
+///
+/// Points are drawn uniformly over the area of the surface, so large
+/// triangles get more of them, and the same seed gives the same placements.
+/// Fails when a density image is given but the surface has no texture
+/// coordinates.
+pub fn scatter(surface: &Mesh, config: &ScatterConfig) -> Result<Vec<Transform>, String> {
+    let uvs = match (&config.density, &surface.uvs) {
+        (Some(_), None) => return Err(String::from("the surface has no texture coordinates")),
+        (_, uvs) => uvs.as_ref(),
+    };
+    // Cumulated areas, to pick triangles by binary search
+    let mut total = 0.0;
+    let areas: Vec<f64> = surface
+        .triangles
+        .iter()
+        .map(|&[a, b, c]| {
+            let (a, b, c) = (
+                surface.vertices[a],
+                surface.vertices[b],
+                surface.vertices[c],
+            );
+            total += (b - a).cross(&(c - a)).norm() / 2.0;
+            total
+        })
+        .collect();
+    if total <= 0.0 {
+        return Ok(Vec::new());
+    }
+
+    let mut placements = Vec::new();
+    for point in 0..config.count {
+        let mut rng = SampleRng::for_key(config.seed, point as u64);
+        let target = rng.gen::<f64>() * total;
+        let triangle = areas
+            .partition_point(|&area| area <= target)
+            .min(areas.len() - 1);
+        // Uniform over the triangle, folding the square in two
+        let (mut u, mut v) = (rng.gen::<f64>(), rng.gen::<f64>());
+        if u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        let weights = [1.0 - u - v, u, v];
+        let corners = surface.triangles[triangle];
+        if let (Some(density), Some(uvs)) = (&config.density, uvs) {
+            let mut uv = [0.0; 2];
+            for (&corner, &weight) in corners.iter().zip(&weights) {
+                uv[0] += uvs[corner][0] * weight;
+                uv[1] += uvs[corner][1] * weight;
+            }
+            if rng.gen::<f64>() >= density.sample(uv).luminance() {
+                continue;
+            }
+        }
+        let position = corners
+            .iter()
+            .zip(&weights)
+            .fold(Direction::zeros(), |sum, (&corner, &weight)| {
+                sum + surface.vertices[corner].coords * weight
+            });
+
+        let yaw = na::UnitQuaternion::from_axis_angle(
+            &Direction::y_axis(),
+            lerp(config.rotation, rng.gen()).to_radians(),
+        );
+        let rotation = if config.align_to_normal {
+            let up = surface.triangle_normals[triangle];
+            na::UnitQuaternion::rotation_between(&Direction::y(), &up).unwrap_or_else(|| {
+                na::UnitQuaternion::from_axis_angle(&Direction::x_axis(), std::f64::consts::PI)
+            }) * yaw
+        } else {
+            yaw
+        };
+        let (x, y, z) = rotation.euler_angles();
+        placements.push(Transform {
+            translation: position,
+            rotation: [x.to_degrees(), y.to_degrees(), z.to_degrees()],
+            scale: lerp(config.scale, rng.gen()),
+        });
+    }
+    Ok(placements)
+}
+
+fn lerp([min, max]: [f64; 2], t: f64) -> f64 {
+    min + (max - min) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::Position;
+    use crate::render::image::RgbImage;
+    use crate::render::scene::Scene;
+    use crate::render::shared::PreparedScene;
+    use std::sync::Arc;
+
+    #[test]
+    fn instances_follow_the_area_and_density_of_the_surface() {
+        // A ground quad from x = 0 to 2, its uvs going from u = 0.25 to 0.75
+        let vertices = vec![
+            Position::new(0.0, 0.0, 0.0),
+            Position::new(2.0, 0.0, 0.0),
+            Position::new(2.0, 0.0, -1.0),
+            Position::new(0.0, 0.0, -1.0),
+        ];
+        let mut ground = Mesh::from_vertices_and_triangles(vertices, vec![[0, 1, 2], [0, 2, 3]]);
+        ground.uvs = Some(vec![[0.25, 0.0], [0.75, 0.0], [0.75, 1.0], [0.25, 1.0]]);
+        let config = ScatterConfig {
+            count: 200,
+            scale: [0.5, 2.0],
+            align_to_normal: true,
+            ..Default::default()
+        };
+        let placements = scatter(&ground, &config).unwrap();
+        assert_eq!(placements.len(), 200);
+        assert_eq!(placements, scatter(&ground, &config).unwrap());
+        for placement in &placements {
+            let t = placement.translation;
+            assert!((0.0..=2.0).contains(&t.x) && (-1.0..=0.0).contains(&t.z) && t.y == 0.0);
+            assert!((0.5..=2.0).contains(&placement.scale));
+            // Upright on the flat ground
+            let up = placement.similarity().isometry.rotation * Direction::y();
+            assert!((up - Direction::y()).norm() < 1e-9);
+        }
+        // Black texel center at u = 0.25, white at u = 0.75: nothing is left
+        // near x = 0, where the density is 0
+        let image = RgbImage::from_fn(2, 1, |x, _| ::image::Rgb([(x * 255) as u8; 3]));
+        let config = ScatterConfig {
+            density: Some(Texture::data(Arc::new(image))),
+            ..config
+        };
+        let thinned = scatter(&ground, &config).unwrap();
+        assert!(thinned.len() < placements.len());
+        assert!(thinned.iter().all(|p| p.translation.x > 0.01));
+        ground.uvs = None;
+        assert!(scatter(&ground, &config).is_err());
+
+        // The copies share their mesh, rendered and saved once
+        let pebble = Arc::new(ground);
+        let scene = Scene::builder()
+            .add_instances(pebble, &thinned[..3])
+            .build();
+        let prepared = PreparedScene::new(scene.clone()).unwrap();
+        let instances = prepared.instances.as_ref().unwrap();
+        assert_eq!(instances.prototypes.len(), 1);
+        assert_eq!(instances.instances.len(), 3);
+        let dir = tempfile::tempdir().unwrap();
+        scene.save(&dir.path().join("field.scene.json")).unwrap();
+        let saved = Scene::load(&dir.path().join("field.scene.json")).unwrap();
+        assert_eq!(saved.objects.len(), 3);
+        assert!(saved
+            .objects
+            .iter()
+            .all(|o| o.path == saved.objects[0].path));
+        let moved = saved.objects[2].transform.translation - thinned[2].translation;
+        assert!(moved.norm() < 1e-9);
+    }
+}
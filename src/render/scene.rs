@@ -0,0 +1,856 @@
+extern crate nalgebra as na;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::attributes::Attributes;
+use crate::geometry::bounding_box::AxisAlignedBoundingBox;
+use crate::geometry::import::{ImportOptions, Unit};
+use crate::geometry::mesh::Mesh;
+use crate::geometry::types::{Direction, Position, Triangle};
+use crate::render::color::Color;
+use crate::render::config::{
+    self, relative_to, CameraConfig, ConfigError, DisplacementConfig, EnvironmentConfig,
+    Integrator, LightConfig, MaterialConfig, RenderingConfig, ShapeConfig, TerrainConfig,
+    CONFIG_VERSION,
+};
+use crate::render::environment::Background;
+use crate::render::light::{LightPortal, LightUnits};
+use crate::render::material::Material;
+
+/// Placement of an object in the scene: scaled, then rotated, then translated
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: Direction,
+    /// Rotations around the x, y and z axes in degrees, applied in this order
+    pub rotation: [f64; 3],
+    /// Uniform scale, strictly positive
+    pub scale: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Direction::new(0.0, 0.0, 0.0),
+            rotation: [0.0, 0.0, 0.0],
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn similarity(&self) -> na::Similarity3<f64> {
+        let [x, y, z] = self.rotation;
+        na::Similarity3::from_parts(
+            na::Translation3::from(self.translation),
+            na::UnitQuaternion::from_euler_angles(x.to_radians(), y.to_radians(), z.to_radians()),
+            self.scale,
+        )
+    }
+}
+
+/// A mesh placed in the scene with its material
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneObject {
+    /// Model file, relative to the scene file once saved. Meshes built in code
+    /// have none until the scene is saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Conversion of the model file to the scene conventions, applied before
+    /// the transform
+    #[serde(default)]
+    pub import: ImportOptions,
+    #[serde(default)]
+    pub transform: Transform,
+    /// Left to the default, the materials of the model file are kept, e.g.
+    /// those of the MTL files of an OBJ
+    #[serde(default)]
+    pub material: MaterialConfig,
+    /// Build the model from `path` as a height map rather than a model file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terrain: Option<TerrainConfig>,
+    /// Build the model from its shape, without any `path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shape: Option<ShapeConfig>,
+    /// Relief added to the model when the scene is loaded, `mesh` being the
+    /// displaced model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub displacement: Option<DisplacementConfig>,
+    #[serde(skip)]
+    pub mesh: Option<Arc<Mesh>>,
+}
+
+/// Objects, lights and settings of a render, stored as JSON
+///
+/// The tracers see the objects merged in a single mesh, the material of each
+/// object kept per triangle, or as instances when they share models: see
+/// `ray_tracer::render_scene`, or `PreparedScene` to render a scene several
+/// times.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub version: u32,
+    /// Unit of the scene coordinates, models in other units are scaled to it
+    pub units: Unit,
+    /// Conventions of the model files, unless overridden per object
+    pub import: ImportOptions,
+    pub camera: CameraConfig,
+    pub rendering: RenderingConfig,
+    pub objects: Vec<SceneObject>,
+    pub lights: Vec<LightConfig>,
+    /// Openings through which the environment lights the inside of the scene
+    pub portals: Vec<LightPortal>,
+    /// Image surrounding the scene, seen by the rays missing the objects and
+    /// lighting them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<EnvironmentConfig>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene {
+            version: CONFIG_VERSION,
+            units: Unit::Meters,
+            import: Default::default(),
+            camera: Default::default(),
+            rendering: Default::default(),
+            objects: Vec::new(),
+            lights: Vec::new(),
+            portals: Vec::new(),
+            environment: None,
+        }
+    }
+}
+
+impl Scene {
+    pub fn builder() -> SceneBuilder {
+        SceneBuilder {
+            scene: Default::default(),
+        }
+    }
+
+    /// The Cornell box, to check the light bounced between surfaces: a room 2
+    /// units wide, deep and high, open toward the camera, with a red wall on
+    /// the left, a green one on the right, a tall and a short white box, and
+    /// a light under the middle of the ceiling
+    ///
+    /// The proportions, colors and camera are those of the reference
+    /// measurements of Cornell University, scaled to the room, the area light
+    /// of the ceiling being replaced by a point. It is rendered with path
+    /// tracing, Whitted giving the direct light alone.
+    pub fn cornell_box() -> Scene {
+        let white = Color::gray(0.73);
+        let red = Color::new(0.65, 0.05, 0.05);
+        let green = Color::new(0.12, 0.45, 0.15);
+        let mut builder = Scene::builder();
+        // Floor, ceiling, back, left and right, a hundredth of the room
+        // thick around it
+        let walls = [
+            (
+                Position::new(-1.0, -0.02, -1.0),
+                Position::new(1.0, 0.0, 1.0),
+                white,
+            ),
+            (
+                Position::new(-1.0, 2.0, -1.0),
+                Position::new(1.0, 2.02, 1.0),
+                white,
+            ),
+            (
+                Position::new(-1.0, 0.0, 1.0),
+                Position::new(1.0, 2.0, 1.02),
+                white,
+            ),
+            (
+                Position::new(-1.02, 0.0, -1.0),
+                Position::new(-1.0, 2.0, 1.0),
+                red,
+            ),
+            (
+                Position::new(1.0, 0.0, -1.0),
+                Position::new(1.02, 2.0, 1.0),
+                green,
+            ),
+        ];
+        for &(min, max, color) in walls.iter() {
+            builder = builder
+                .add_shape(ShapeConfig::Box {
+                    min,
+                    max,
+                    inside: false,
+                })
+                .material(MaterialConfig {
+                    color,
+                    ..Default::default()
+                });
+        }
+        // Height, position and turn of the tall block and the short one
+        let blocks = [(1.2, -0.33, 0.3, 17.0), (0.6, 0.33, -0.35, -17.0)];
+        for &(height, x, z, angle) in blocks.iter() {
+            builder = builder
+                .add_shape(ShapeConfig::Box {
+                    min: Position::new(-0.3, 0.0, -0.3),
+                    max: Position::new(0.3, height, 0.3),
+                    inside: false,
+                })
+                .transform(Transform {
+                    translation: Direction::new(x, 0.0, z),
+                    rotation: [0.0, angle, 0.0],
+                    scale: 1.0,
+                })
+                .material(MaterialConfig {
+                    color: white,
+                    ..Default::default()
+                });
+        }
+        builder
+            .add_light(LightConfig::Point {
+                position: Position::new(0.0, 1.9, 0.0),
+                intensity: 8.0,
+                units: LightUnits::Candela,
+                color: Color::WHITE,
+            })
+            .camera(CameraConfig::look_at(
+                Position::new(0.0, 1.0, -3.9),
+                Position::new(0.0, 1.0, 0.0),
+                Direction::y(),
+                39.3,
+                512,
+                512,
+            ))
+            .rendering(RenderingConfig {
+                integrator: Integrator::PathTracing,
+                ..Default::default()
+            })
+            .build()
+    }
+
+    /// The white furnace, to check that materials and integrators do not
+    /// create energy: a box of `material` seen from the outside, in an
+    /// environment of uniform `radiance` and no lights, rendered with path
+    /// tracing and without the headlight
+    ///
+    /// A material reflecting all the light, e.g. a white matte or mirror,
+    /// disappears into the environment: every pixel is `radiance`. The others
+    /// are darker, never brighter. The box being convex, the light it
+    /// reflects does not come back to it.
+    pub fn white_furnace(material: MaterialConfig, radiance: f64) -> Scene {
+        let environment = Color::gray(radiance);
+        Scene::builder()
+            .add_shape(ShapeConfig::Box {
+                min: Position::new(-1.0, -1.0, -1.0),
+                max: Position::new(1.0, 1.0, 1.0),
+                inside: false,
+            })
+            .material(material)
+            .transform(Transform {
+                rotation: [30.0, 40.0, 0.0],
+                ..Default::default()
+            })
+            .camera(CameraConfig::look_at(
+                Position::new(0.0, 0.0, -5.0),
+                Position::new(0.0, 0.0, 0.0),
+                Direction::y(),
+                40.0,
+                64,
+                64,
+            ))
+            .rendering(RenderingConfig {
+                integrator: Integrator::PathTracing,
+                headlight: false,
+                background: Background::Gradient {
+                    zenith: environment,
+                    horizon: environment,
+                    ground: environment,
+                },
+                ..Default::default()
+            })
+            .build()
+    }
+
+    /// Load a scene file and the models it references
+    ///
+    /// Files ending in `.toml` are read as TOML, the others as JSON, with the
+    /// same fields.
+    pub fn load(path: &Path) -> Result<Scene, ConfigError> {
+        let mut scene: Scene = config::load_versioned(path)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        for (i, object) in scene.objects.iter_mut().enumerate() {
+            if let Some(shape) = &object.shape {
+                object.mesh = Some(Arc::new(shape.build()));
+                object.material.load_textures(directory)?;
+                continue;
+            }
+            let mesh_path = match &object.path {
+                Some(mesh_path) => directory.join(mesh_path),
+                None => return Err(ConfigError::Invalid(format!("object {} has no path", i))),
+            };
+            let mut mesh = match &object.terrain {
+                Some(terrain) => terrain.load(&mesh_path)?,
+                None => Mesh::load_file(&mesh_path)
+                    .map_err(|e| ConfigError::Mesh(mesh_path.clone(), e))?,
+            };
+            if let Some(displacement) = &mut object.displacement {
+                displacement.load(directory)?;
+                displacement
+                    .apply(&mut mesh)
+                    .map_err(|e| ConfigError::Invalid(format!("{}: {}", mesh_path.display(), e)))?;
+            }
+            object.mesh = Some(Arc::new(mesh));
+            object.path = Some(mesh_path);
+            object.material.load_textures(directory)?;
+        }
+        scene.rendering.material.load_textures(directory)?;
+        for light in &mut scene.lights {
+            if let LightConfig::Spot { ies: Some(ies), .. } = light {
+                *ies = directory.join(&ies);
+            }
+        }
+        if let Some(environment) = &mut scene.environment {
+            environment.load(directory)?;
+        }
+        Ok(scene)
+    }
+
+    /// Save the scene file
+    ///
+    /// Meshes built in code are written next to it as `<scene>.<index>.obj`,
+    /// once for the objects sharing them, and model paths are stored relative
+    /// to the scene file when possible. Shapes are saved as their parameters.
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut scene = self.clone();
+        let mut written: Vec<(Arc<Mesh>, PathBuf)> = Vec::new();
+        for (i, object) in scene.objects.iter_mut().enumerate() {
+            object.path = match (&object.path, &object.mesh) {
+                _ if object.shape.is_some() => None,
+                (Some(mesh_path), _) => Some(relative_to(mesh_path, directory)),
+                (None, Some(mesh)) => {
+                    let file_name = match written.iter().find(|(m, _)| Arc::ptr_eq(m, mesh)) {
+                        Some((_, file_name)) => file_name.clone(),
+                        None => {
+                            let file_name = PathBuf::from(format!("{}.{}.obj", stem, i));
+                            mesh.save_obj(&directory.join(&file_name))
+                                .map_err(ConfigError::Io)?;
+                            written.push((mesh.clone(), file_name.clone()));
+                            file_name
+                        }
+                    };
+                    object.terrain = None;
+                    Some(file_name)
+                }
+                (None, None) => {
+                    return Err(ConfigError::Invalid(format!("object {} has no mesh", i)))
+                }
+            };
+            object.material.relative_paths(directory);
+            if let Some(displacement) = &mut object.displacement {
+                displacement.path = relative_to(&displacement.path, directory);
+            }
+        }
+        scene.rendering.material.relative_paths(directory);
+        for light in &mut scene.lights {
+            if let LightConfig::Spot { ies: Some(ies), .. } = light {
+                *ies = relative_to(ies, directory);
+            }
+        }
+        if let Some(environment) = &mut scene.environment {
+            environment.path = relative_to(&environment.path, directory);
+        }
+        fs::write(path, config::to_json(&scene) + "\n").map_err(ConfigError::Io)
+    }
+
+    /// Merge all the objects in a single mesh, in world coordinates
+    ///
+    /// Every object keeps its material. Objects without vertex colors are
+    /// white when other objects have some, objects without texture
+    /// coordinates get (0, 0) when other objects have some.
+    pub fn to_mesh(&self) -> Mesh {
+        let world: Vec<Option<Mesh>> = self
+            .objects
+            .iter()
+            .map(|object| self.object_to_world(object))
+            .collect();
+        self.merge_objects(world.iter().map(Option::as_ref))
+    }
+
+    /// Box around the vertices of the objects in world coordinates, empty at
+    /// the origin without any, e.g. to frame them with
+    /// `CameraConfig::framing`
+    pub fn bounding_box(&self) -> AxisAlignedBoundingBox {
+        let vertices: Vec<Position> = self
+            .objects
+            .iter()
+            .filter_map(|object| {
+                let mesh = object.mesh.as_ref()?;
+                let similarity = object.transform.similarity();
+                let conversion = object.import.or(&self.import).conversion(self.units);
+                Some(
+                    mesh.vertices
+                        .iter()
+                        .map(move |v| similarity * Position::from(conversion * v.coords)),
+                )
+            })
+            .flatten()
+            .collect();
+        AxisAlignedBoundingBox::new(&vertices)
+    }
+
+    /// Geometry of an object in world coordinates, without its material;
+    /// `None` when the object has no mesh
+    pub fn object_to_world(&self, object: &SceneObject) -> Option<Mesh> {
+        let mesh = object.mesh.as_ref()?;
+        let similarity = object.transform.similarity();
+        let conversion = object.import.or(&self.import).conversion(self.units);
+        let mut world =
+            Mesh::from_vertices_and_triangles(mesh.vertices.clone(), mesh.triangles.clone());
+        world.vertex_normals = mesh.vertex_normals.clone();
+        world.vertex_colors = mesh.vertex_colors.clone();
+        world.uvs = mesh.uvs.clone();
+        world.extra_uvs = mesh.extra_uvs.clone();
+        world.polygons = mesh.polygons.clone();
+        world.transform(&na::Affine3::from_matrix_unchecked(
+            similarity.to_homogeneous() * conversion.to_homogeneous(),
+        ));
+        world.attributes = mesh.attributes.clone();
+        Some(world)
+    }
+
+    /// Merge the world geometry of the objects, as given by `object_to_world`
+    /// for every object in order, and give them their material
+    pub fn merge_objects<'a, I>(&self, world_meshes: I) -> Mesh
+    where
+        I: IntoIterator<Item = Option<&'a Mesh>>,
+    {
+        let mut vertices: Vec<Position> = Vec::new();
+        let mut vertex_normals: Vec<Direction> = Vec::new();
+        let mut triangles: Vec<Triangle> = Vec::new();
+        let mut polygons: Vec<Vec<usize>> = Vec::new();
+        let mut vertex_colors: Vec<[f32; 3]> = Vec::new();
+        let mut colored = false;
+        let mut uvs: Vec<[f64; 2]> = Vec::new();
+        let mut textured = false;
+        // As many sets as the mesh having the most, zeros for the others
+        let mut extra_uvs: Vec<Vec<[f64; 2]>> = Vec::new();
+        let mut materials: Vec<Arc<dyn Material>> = Vec::new();
+        let mut triangle_materials: Vec<usize> = Vec::new();
+        let mut merged: Vec<&Mesh> = Vec::new();
+
+        for (object, mesh) in self.objects.iter().zip(world_meshes) {
+            let mesh = match mesh {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            merged.push(mesh);
+            let offset = vertices.len();
+            colored |= mesh.vertex_colors.is_some();
+            textured |= mesh.uvs.is_some();
+            polygons.extend(mesh.faces().map(|f| f.iter().map(|i| i + offset).collect()));
+            vertices.extend(&mesh.vertices);
+            vertex_normals.extend(&mesh.vertex_normals);
+            triangles.extend(
+                mesh.triangles
+                    .iter()
+                    .map(|t| [t[0] + offset, t[1] + offset, t[2] + offset]),
+            );
+            match &mesh.vertex_colors {
+                Some(colors) => vertex_colors.extend(colors),
+                None => vertex_colors.extend(mesh.vertices.iter().map(|_| [1.0; 3])),
+            }
+            match &mesh.uvs {
+                Some(mesh_uvs) => uvs.extend(mesh_uvs),
+                None => uvs.extend(mesh.vertices.iter().map(|_| [0.0; 2])),
+            }
+            while extra_uvs.len() < mesh.extra_uvs.len() {
+                extra_uvs.push(vec![[0.0; 2]; offset]);
+            }
+            for (set, merged_uvs) in extra_uvs.iter_mut().enumerate() {
+                match mesh.extra_uvs.get(set) {
+                    Some(mesh_uvs) => merged_uvs.extend(mesh_uvs),
+                    None => merged_uvs.extend(mesh.vertices.iter().map(|_| [0.0; 2])),
+                }
+            }
+            // Materials of the model file, e.g. of the MTL files of an OBJ,
+            // unless the object has its own
+            let file_materials = object.mesh.as_ref().filter(|source| {
+                !source.materials.is_empty()
+                    && source.triangles.len() == mesh.triangles.len()
+                    && object.material == MaterialConfig::default()
+            });
+            match file_materials {
+                Some(source) => {
+                    let first = materials.len();
+                    triangle_materials.extend(
+                        (0..mesh.triangles.len()).map(|t| {
+                            first + source.triangle_materials.as_ref().map_or(0, |m| m[t])
+                        }),
+                    );
+                    materials.extend(source.materials.iter().cloned());
+                }
+                None => {
+                    triangle_materials.extend(mesh.triangles.iter().map(|_| materials.len()));
+                    materials.push(object.material.build());
+                }
+            }
+        }
+
+        let mut mesh = Mesh::from_vertices_and_triangles(vertices, triangles);
+        mesh.vertex_normals = vertex_normals;
+        if polygons.iter().any(|p| p.len() > 3) {
+            mesh.polygons = Some(polygons);
+        }
+        if colored {
+            mesh.vertex_colors = Some(vertex_colors);
+        }
+        if textured {
+            mesh.uvs = Some(uvs);
+            mesh.extra_uvs = extra_uvs;
+            mesh.compute_tangents();
+        }
+        mesh.attributes = Attributes::concatenate(
+            merged
+                .iter()
+                .map(|m| (&m.attributes, m.vertices.len(), m.triangles.len())),
+        );
+        mesh.materials = materials;
+        mesh.triangle_materials = Some(triangle_materials);
+        mesh
+    }
+}
+
+/// Fluent construction of scenes from code
+///
+/// `transform` and `material` apply to the object added last:
+///
+/// ```no_run
+/// # use ray_ruster::render::scene::{Scene, Transform};
+/// # use ray_ruster::render::color::Color;
+/// # use ray_ruster::render::config::MaterialConfig;
+/// # use std::path::Path;
+/// let scene = Scene::builder()
+///     .add_mesh_file(Path::new("data/ram.off"))
+///     .unwrap()
+///     .transform(Transform { scale: 2.0, ..Default::default() })
+///     .material(MaterialConfig { color: Color::new(0.8, 0.2, 0.2), ..Default::default() })
+///     .build();
+/// scene.save(Path::new("ram.scene.json")).unwrap();
+/// ```
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+impl SceneBuilder {
+    pub fn camera(mut self, camera: CameraConfig) -> Self {
+        self.scene.camera = camera;
+        self
+    }
+
+    pub fn rendering(mut self, rendering: RenderingConfig) -> Self {
+        self.scene.rendering = rendering;
+        self
+    }
+
+    /// Add a mesh built in code
+    pub fn add_mesh<M: Into<Arc<Mesh>>>(mut self, mesh: M) -> Self {
+        self.scene.objects.push(SceneObject {
+            path: None,
+            import: Default::default(),
+            transform: Default::default(),
+            material: Default::default(),
+            terrain: None,
+            shape: None,
+            displacement: None,
+            mesh: Some(mesh.into()),
+        });
+        self
+    }
+
+    /// Add a shape, e.g. a box, built right away
+    pub fn add_shape(mut self, shape: ShapeConfig) -> Self {
+        self = self.add_mesh(shape.build());
+        self.last_object().shape = Some(shape);
+        self
+    }
+
+    /// Add copies of a mesh built in code, one object per placement, e.g.
+    /// from `scatter::scatter`
+    ///
+    /// The objects share the mesh, rendered as instances of it and written
+    /// once when the scene is saved; `transform` and `material` apply to the
+    /// last copy only.
+    pub fn add_instances<M: Into<Arc<Mesh>>>(mut self, mesh: M, transforms: &[Transform]) -> Self {
+        let mesh = mesh.into();
+        for transform in transforms {
+            self = self.add_mesh(mesh.clone());
+            self.last_object().transform = *transform;
+        }
+        self
+    }
+
+    /// Add a model file, loaded right away
+    pub fn add_mesh_file(mut self, path: &Path) -> Result<Self, ConfigError> {
+        let mesh = Mesh::load_file(path).map_err(|e| ConfigError::Mesh(path.to_path_buf(), e))?;
+        self = self.add_mesh(mesh);
+        self.last_object().path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// Unit of the scene coordinates, meters by default
+    pub fn units(mut self, units: Unit) -> Self {
+        self.scene.units = units;
+        self
+    }
+
+    /// Conventions of the model files, unless overridden per object
+    pub fn default_import(mut self, import: ImportOptions) -> Self {
+        self.scene.import = import;
+        self
+    }
+
+    pub fn import(mut self, import: ImportOptions) -> Self {
+        self.last_object().import = import;
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.last_object().transform = transform;
+        self
+    }
+
+    pub fn material(mut self, material: MaterialConfig) -> Self {
+        self.last_object().material = material;
+        self
+    }
+
+    pub fn add_light(mut self, light: LightConfig) -> Self {
+        self.scene.lights.push(light);
+        self
+    }
+
+    pub fn add_portal(mut self, portal: LightPortal) -> Self {
+        self.scene.portals.push(portal);
+        self
+    }
+
+    /// Environment of the scene, with its image already loaded
+    pub fn environment(mut self, environment: EnvironmentConfig) -> Self {
+        self.scene.environment = Some(environment);
+        self
+    }
+
+    pub fn build(self) -> Scene {
+        self.scene
+    }
+
+    fn last_object(&mut self) -> &mut SceneObject {
+        self.scene
+            .objects
+            .last_mut()
+            .expect("add a mesh to the scene before setting its transform or material")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::config::ShadingModel;
+    use crate::render::image::HdrRgbImage;
+    use crate::render::ray_tracer;
+
+    fn triangle() -> Mesh {
+        Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(0.0, 0.0, 0.0),
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+        )
+    }
+
+    #[test]
+    fn built_scene_round_trips_through_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let scene = Scene::builder()
+            .add_mesh(triangle())
+            .transform(Transform {
+                translation: Direction::new(0.0, 0.0, 5.0),
+                rotation: [0.0, 0.0, 90.0],
+                scale: 2.0,
+            })
+            .material(MaterialConfig {
+                model: ShadingModel::BlinnPhong,
+                color: Color::new(1.0, 0.5, 0.0),
+                ..Default::default()
+            })
+            .add_mesh(triangle())
+            .import(ImportOptions {
+                units: Some(Unit::Centimeters),
+                ..Default::default()
+            })
+            .add_light(LightConfig::Point {
+                position: Position::new(0.0, 10.0, 0.0),
+                intensity: 100.0,
+                units: LightUnits::Watts,
+                color: Color::WHITE,
+            })
+            .build();
+
+        let path = dir.path().join("test.json");
+        scene.save(&path).unwrap();
+        assert!(dir.path().join("test.0.obj").exists());
+        let loaded = Scene::load(&path).unwrap();
+        assert_eq!(loaded.objects.len(), 2);
+        assert_eq!(loaded.objects[0].transform, scene.objects[0].transform);
+        assert_eq!(loaded.objects[1].import, scene.objects[1].import);
+        assert_eq!(loaded.lights, scene.lights);
+
+        let mesh = loaded.to_mesh();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [3, 4, 5]]);
+        // (1, 0, 0) scaled, rotated a quarter turn around z and translated
+        assert!((mesh.vertices[1] - Position::new(0.0, 2.0, 5.0)).norm() < 1e-9);
+        // (1, 0, 0) in centimeters
+        assert!((mesh.vertices[4] - Position::new(0.01, 0.0, 0.0)).norm() < 1e-9);
+        assert_eq!(loaded.objects[0].material, scene.objects[0].material);
+        assert!(mesh.vertex_colors.is_none());
+        assert_eq!(mesh.triangle_materials, Some(vec![0, 1]));
+        assert_eq!(mesh.materials.len(), 2);
+    }
+
+    #[test]
+    fn cornell_box_bounces_the_colors_of_the_walls() {
+        let mut scene = Scene::cornell_box();
+        assert_eq!(scene.objects.len(), 7);
+        let bounds = scene.bounding_box();
+        assert!((bounds.bounds[0] - Position::new(-1.02, -0.02, -1.0)).norm() < 1e-12);
+        assert!((bounds.bounds[1] - Position::new(1.02, 2.02, 1.02)).norm() < 1e-12);
+
+        scene.camera.width = 16;
+        scene.camera.height = 16;
+        scene.rendering.samples_per_pixel = 16;
+        let render = |integrator| {
+            let mut scene = scene.clone();
+            scene.rendering.integrator = integrator;
+            ray_tracer::render_scene(&scene).unwrap()
+        };
+        let direct = render(Integrator::Whitted);
+        // Red on the left, green on the right, halfway up
+        let left = direct.get_pixel(1, 8);
+        assert!(left[0] > 2.0 * left[1], "{:?}", left);
+        let right = direct.get_pixel(14, 8);
+        assert!(right[1] > 2.0 * right[0], "{:?}", right);
+        // The ceiling, lit at a grazing angle, gets more from the bounces
+        let indirect = render(Integrator::PathTracing);
+        let ceiling = |image: &HdrRgbImage| image.get_pixel(8, 1)[1];
+        assert!(ceiling(&indirect) > ceiling(&direct));
+    }
+
+    #[test]
+    fn shapes_are_saved_as_their_parameters() {
+        let dir = tempfile::tempdir().unwrap();
+        let shape = ShapeConfig::Box {
+            min: Position::new(-1.0, 0.0, -1.0),
+            max: Position::new(1.0, 2.0, 1.0),
+            inside: false,
+        };
+        let scene = Scene::builder()
+            .add_shape(shape)
+            .transform(Transform {
+                translation: Direction::new(0.0, 0.0, 5.0),
+                ..Default::default()
+            })
+            .material(MaterialConfig {
+                color: Color::new(1.0, 0.5, 0.0),
+                ..Default::default()
+            })
+            .build();
+        let path = dir.path().join("box.json");
+        scene.save(&path).unwrap();
+        assert!(!dir.path().join("box.0.obj").exists());
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains(r#""kind": "box""#), "{}", json);
+        assert!(!json.contains("inside"), "{}", json);
+
+        let loaded = Scene::load(&path).unwrap();
+        assert_eq!(loaded.objects[0].shape, Some(shape));
+        assert_eq!(loaded.objects[0].path, None);
+        let bounds = loaded.bounding_box().bounds;
+        assert_eq!(
+            bounds,
+            [Position::new(-1.0, 0.0, 4.0), Position::new(1.0, 2.0, 6.0)]
+        );
+        assert_eq!(loaded.to_mesh().triangles.len(), 12);
+    }
+
+    #[test]
+    fn scenes_are_read_from_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        triangle()
+            .save_obj(&dir.path().join("triangle.obj"))
+            .unwrap();
+        let path = dir.path().join("scene.toml");
+        fs::write(
+            &path,
+            r#"
+version = 1
+
+[camera]
+camera_position = [0, 1, -5]
+width = 32
+height = 24
+
+[rendering]
+integrator = { kind = "path_tracing" }
+samples_per_pixel = 4
+
+[[objects]]
+path = "triangle.obj"
+transform = { translation = [0, 0, 5], scale = 2 }
+material = { model = "mirror" }
+
+[[objects]]
+shape = { kind = "box", min = [-1, 0, -1], max = [1, 2, 1] }
+material = { color = [1, 0.5, 0] }
+
+[[lights]]
+kind = "point"
+position = [0, 10, 0]
+intensity = 100
+"#,
+        )
+        .unwrap();
+        let scene = Scene::load(&path).unwrap();
+        assert_eq!(scene.camera.camera_position, Position::new(0.0, 1.0, -5.0));
+        assert_eq!((scene.camera.width, scene.camera.height), (32, 24));
+        assert_eq!(scene.rendering.integrator, Integrator::PathTracing);
+        assert_eq!(scene.rendering.samples_per_pixel, 4);
+        assert_eq!(scene.objects.len(), 2);
+        assert_eq!(scene.objects[0].path, Some(dir.path().join("triangle.obj")));
+        assert_eq!(scene.objects[0].transform.scale, 2.0);
+        assert_eq!(scene.objects[0].material.model, ShadingModel::Mirror);
+        assert_eq!(scene.objects[1].material.color, Color::new(1.0, 0.5, 0.0));
+        assert_eq!(
+            scene.lights,
+            vec![LightConfig::Point {
+                position: Position::new(0.0, 10.0, 0.0),
+                intensity: 100.0,
+                units: LightUnits::Candela,
+                color: Color::WHITE,
+            }]
+        );
+        assert_eq!(scene.to_mesh().triangles.len(), 13);
+
+        fs::write(&path, "version = 1000\n").unwrap();
+        assert!(matches!(
+            Scene::load(&path),
+            Err(ConfigError::UnsupportedVersion(1000))
+        ));
+        fs::write(&path, "objects = 3\n").unwrap();
+        assert!(matches!(Scene::load(&path), Err(ConfigError::Json(_))));
+    }
+}
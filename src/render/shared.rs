@@ -0,0 +1,562 @@
+extern crate nalgebra as na;
+
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::geometry::import::{ImportOptions, Unit};
+use crate::geometry::instance::{Instance, Instances};
+use crate::geometry::kdtree::KdTree;
+use crate::geometry::mesh::Mesh;
+use crate::render::config::{ConfigError, MaterialConfig};
+use crate::render::scene::{Scene, SceneObject, Transform};
+
+/// Scene ready to be rendered: the objects merged in a single mesh, its
+/// kd-tree and the light sources
+///
+/// Scenes placing a model several times are prepared as `instances` of
+/// their models instead, which the renders trace: the models are stored
+/// once, and the merged mesh is only built when first asked for.
+///
+/// A prepared scene never changes, edits prepare a new one (see
+/// `SharedScene`), so render threads can keep using it without locking.
+/// Consecutive versions share what the edit did not touch: the objects
+/// converted to world coordinates, and the trees when no geometry moved.
+#[derive(Debug)]
+pub struct PreparedScene {
+    /// The scene, with `rendering.lights` built from its lights and
+    /// environment
+    pub scene: Scene,
+    /// The objects as instances of their models, when some model is placed
+    /// by several objects
+    pub instances: Option<Arc<Instances>>,
+    /// Time spent building `instances`, by a previous version when they
+    /// were reused
+    pub instances_build_time: Duration,
+    merged: OnceLock<Merged>,
+    /// Every object of `scene` in world coordinates, except in instanced
+    /// scenes
+    objects: Vec<PreparedObject>,
+}
+
+/// The objects merged in a single mesh, with its kd-tree
+#[derive(Debug)]
+struct Merged {
+    mesh: Mesh,
+    kdtree: Arc<Box<KdTree>>,
+    /// Time spent building `kdtree`, by a previous version when it was
+    /// reused
+    kdtree_build_time: Duration,
+}
+
+/// An object of the scene in world coordinates, with what it was computed
+/// from
+#[derive(Clone, Debug)]
+struct PreparedObject {
+    key: GeometryKey,
+    world: Option<Arc<Mesh>>,
+}
+
+/// Everything the world geometry of an object depends on
+#[derive(Clone, Debug, PartialEq)]
+struct GeometryKey {
+    /// Address of the shared model, models are never modified in place
+    mesh: Option<usize>,
+    transform: Transform,
+    import: ImportOptions,
+    units: Unit,
+}
+
+impl GeometryKey {
+    fn new(scene: &Scene, object: &SceneObject) -> GeometryKey {
+        GeometryKey {
+            mesh: object.mesh.as_ref().map(|mesh| Arc::as_ptr(mesh) as usize),
+            transform: object.transform,
+            import: object.import.or(&scene.import),
+            units: scene.units,
+        }
+    }
+
+    /// Is the model of the object, converted the same way, the one of
+    /// `other`
+    fn same_model(&self, other: &GeometryKey) -> bool {
+        self.mesh.is_some() && self.mesh == other.mesh && self.import == other.import
+    }
+}
+
+impl PreparedScene {
+    /// Merge the objects, or make them instances of their models when some
+    /// are placed several times, reorder them for memory locality unless
+    /// `rendering.optimize_layout` is off, build their trees as
+    /// `rendering.tree_build` and `rendering.tree_preset` say, the lights and
+    /// the environment, seen through the portals if there are any
+    ///
+    /// Fails when a light profile cannot be loaded.
+    pub fn new(scene: Scene) -> Result<PreparedScene, ConfigError> {
+        PreparedScene::derive(scene, None)
+    }
+
+    /// Prepare `scene`, reusing the parts of `previous` that it shares
+    ///
+    /// Objects are matched by position, so edits that keep the list of
+    /// objects (transforms, materials, lights, camera...) reuse the most.
+    pub fn derive(
+        mut scene: Scene,
+        previous: Option<&PreparedScene>,
+    ) -> Result<PreparedScene, ConfigError> {
+        let _span = tracing::info_span!("prepare_scene", objects = scene.objects.len()).entered();
+        scene.rendering.lights = scene
+            .lights
+            .iter()
+            .map(|light| light.build())
+            .collect::<Result<_, _>>()?;
+        scene.rendering.environment = scene
+            .environment
+            .as_ref()
+            .and_then(|environment| environment.build())
+            .map(Arc::new);
+        if let (Some(environment), Some(config)) =
+            (&scene.rendering.environment, &scene.environment)
+        {
+            // Interiors are only lit through their portals
+            if scene.portals.is_empty() {
+                for light in environment.lights(config.lights) {
+                    scene.rendering.lights.push(Arc::new(light));
+                }
+            } else {
+                for light in environment.portal_lights(&scene.portals, config.lights) {
+                    scene.rendering.lights.push(Arc::new(light));
+                }
+            }
+        }
+
+        let keys: Vec<GeometryKey> = scene
+            .objects
+            .iter()
+            .map(|object| GeometryKey::new(&scene, object))
+            .collect();
+        let instanced = keys
+            .iter()
+            .enumerate()
+            .any(|(i, key)| keys[..i].iter().any(|other| key.same_model(other)));
+        // Instanced scenes only convert the objects when they are merged
+        let previous_objects = previous
+            .filter(|p| p.instances.is_some() == instanced)
+            .map_or(&[][..], |p| &p.objects[..]);
+        let objects: Vec<PreparedObject> = scene
+            .objects
+            .iter()
+            .zip(keys)
+            .enumerate()
+            .map(|(i, (object, key))| match previous_objects.get(i) {
+                Some(prepared) if prepared.key == key => prepared.clone(),
+                _ => PreparedObject {
+                    world: match instanced {
+                        true => None,
+                        false => scene.object_to_world(object).map(Arc::new),
+                    },
+                    key,
+                },
+            })
+            .collect();
+
+        let same_geometry = previous.is_some_and(|p| {
+            p.scene.rendering.optimize_layout == scene.rendering.optimize_layout
+                && p.scene.rendering.tree_build == scene.rendering.tree_build
+                && p.scene.rendering.tree_preset == scene.rendering.tree_preset
+        }) && previous_objects.len() == objects.len()
+            && previous_objects
+                .iter()
+                .zip(&objects)
+                .all(|(a, b)| a.key == b.key);
+        let mut prepared = PreparedScene {
+            scene,
+            instances: None,
+            instances_build_time: Duration::ZERO,
+            merged: OnceLock::new(),
+            objects,
+        };
+        if instanced {
+            // The instances hold the materials of the objects
+            let reused = previous
+                .filter(|p| {
+                    same_geometry
+                        && p.scene
+                            .objects
+                            .iter()
+                            .zip(&prepared.scene.objects)
+                            .all(|(a, b)| a.material == b.material)
+                })
+                .and_then(|p| Some((Arc::clone(p.instances.as_ref()?), p.instances_build_time)));
+            let (instances, build_time) = reused.unwrap_or_else(|| {
+                let start = Instant::now();
+                let instances = Arc::new(instantiate(&prepared.scene));
+                (instances, start.elapsed())
+            });
+            prepared.instances = Some(instances);
+            prepared.instances_build_time = build_time;
+        } else {
+            let previous = previous
+                .filter(|_| same_geometry)
+                .and_then(|p| p.merged.get());
+            let merged = prepared.merge(previous);
+            prepared.merged = OnceLock::from(merged);
+        }
+        Ok(prepared)
+    }
+
+    /// The objects merged in a single mesh, merged on the first call in
+    /// instanced scenes
+    pub fn mesh(&self) -> &Mesh {
+        &self.merged().mesh
+    }
+
+    /// Kd-tree of `mesh`
+    pub fn kdtree(&self) -> &Arc<Box<KdTree>> {
+        &self.merged().kdtree
+    }
+
+    /// Time spent building `kdtree`, by a previous version when it was
+    /// reused
+    pub fn kdtree_build_time(&self) -> Duration {
+        self.merged().kdtree_build_time
+    }
+
+    /// Triangles of the scene, those of the models being counted at every
+    /// instance
+    pub fn triangle_count(&self) -> usize {
+        match &self.instances {
+            Some(instances) => instances.triangle_count(),
+            None => self.mesh().triangles.len(),
+        }
+    }
+
+    fn merged(&self) -> &Merged {
+        self.merged.get_or_init(|| self.merge(None))
+    }
+
+    /// Merge the objects, reusing the kd-tree of `previous`
+    fn merge(&self, previous: Option<&Merged>) -> Merged {
+        let scene = &self.scene;
+        let converted: Vec<Option<Mesh>> = self
+            .objects
+            .iter()
+            .zip(&scene.objects)
+            .map(|(prepared, object)| match prepared.world {
+                Some(_) => None,
+                None => scene.object_to_world(object),
+            })
+            .collect();
+        let mut mesh = scene.merge_objects(
+            self.objects
+                .iter()
+                .zip(&converted)
+                .map(|(prepared, converted)| prepared.world.as_deref().or(converted.as_ref())),
+        );
+        if scene.rendering.optimize_layout {
+            mesh.optimize_layout();
+        }
+        let (kdtree, kdtree_build_time) = match previous {
+            Some(previous) => (Arc::clone(&previous.kdtree), previous.kdtree_build_time),
+            None => {
+                let start = Instant::now();
+                let kdtree = Arc::new(KdTree::build(
+                    &mesh,
+                    scene.rendering.tree_build,
+                    scene.rendering.tree_preset,
+                ));
+                (kdtree, start.elapsed())
+            }
+        };
+        Merged {
+            mesh,
+            kdtree,
+            kdtree_build_time,
+        }
+    }
+}
+
+/// The objects of `scene` as instances: the objects sharing a model, converted
+/// the same way, are placed by their transform in its scene coordinates, the
+/// other ones are models of their own in world coordinates
+fn instantiate(scene: &Scene) -> Instances {
+    let mut models: Vec<Arc<Mesh>> = Vec::new();
+    // Key of the shared models, with their index in `models`
+    let mut shared: Vec<(GeometryKey, usize)> = Vec::new();
+    let mut instances = Vec::new();
+    let keys: Vec<GeometryKey> = scene
+        .objects
+        .iter()
+        .map(|object| GeometryKey::new(scene, object))
+        .collect();
+    for (object, key) in scene.objects.iter().zip(&keys) {
+        let source = match &object.mesh {
+            Some(source) => source,
+            None => continue,
+        };
+        let placements = keys.iter().filter(|other| key.same_model(other)).count();
+        let (index, to_world) = if placements > 1 {
+            let existing = shared.iter().find(|(other, _)| key.same_model(other));
+            let index = match existing {
+                Some(&(_, index)) => index,
+                None => {
+                    models.push(Arc::new(model(scene, object, source, Transform::default())));
+                    shared.push((key.clone(), models.len() - 1));
+                    models.len() - 1
+                }
+            };
+            (index, object.transform.similarity())
+        } else {
+            models.push(Arc::new(model(scene, object, source, object.transform)));
+            (models.len() - 1, na::Similarity3::identity())
+        };
+        // Materials of the model file, e.g. of the MTL files of an OBJ,
+        // unless the object has its own
+        let file_materials =
+            !source.materials.is_empty() && object.material == MaterialConfig::default();
+        let material = match file_materials {
+            true => None,
+            false => Some(object.material.build()),
+        };
+        instances.push(Instance::new(index, to_world, material));
+    }
+    Instances::new(
+        models,
+        instances,
+        scene.rendering.tree_build,
+        scene.rendering.tree_preset,
+        scene.rendering.optimize_layout,
+    )
+}
+
+/// `source`, the model of `object`, in the scene coordinates, placed by
+/// `transform`, with the materials of its file
+fn model(scene: &Scene, object: &SceneObject, source: &Mesh, transform: Transform) -> Mesh {
+    let placed = SceneObject {
+        transform,
+        ..object.clone()
+    };
+    let mut mesh = scene.object_to_world(&placed).expect("object with a model");
+    if !source.materials.is_empty() {
+        mesh.materials = source.materials.clone();
+        mesh.triangle_materials = source.triangle_materials.clone();
+    }
+    if mesh.uvs.is_some() {
+        mesh.compute_tangents();
+    }
+    mesh
+}
+
+/// Handle on the current version of a scene, shared between the viewer, the
+/// render threads and background loaders
+///
+/// Cloning the handle is cheap and all the clones see the same scene.
+/// Readers take a `snapshot`, which stays valid and unchanged for as long as
+/// they hold it. Writers edit a copy of the scene, whose meshes are shared
+/// with the current one, prepare what changed and then publish it: renders
+/// in flight finish with the version they started with, the next snapshot
+/// returns the new one.
+#[derive(Clone, Debug)]
+pub struct SharedScene {
+    current: Arc<RwLock<Arc<PreparedScene>>>,
+    /// Serializes the writers, so that concurrent edits are not lost
+    edits: Arc<Mutex<()>>,
+}
+
+impl SharedScene {
+    pub fn new(prepared: PreparedScene) -> SharedScene {
+        SharedScene {
+            current: Arc::new(RwLock::new(Arc::new(prepared))),
+            edits: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Current version of the scene
+    pub fn snapshot(&self) -> Arc<PreparedScene> {
+        // The lock only guards the swap of a pointer, a panicking writer
+        // cannot leave it inconsistent
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&current)
+    }
+
+    /// Apply `edit` to a copy of the current scene and publish the result
+    ///
+    /// The scene is prepared again without blocking the readers. Nothing is
+    /// published if the preparation fails.
+    pub fn update<F>(&self, edit: F) -> Result<Arc<PreparedScene>, ConfigError>
+    where
+        F: FnOnce(&mut Scene),
+    {
+        let _edits = self.edits.lock().unwrap_or_else(PoisonError::into_inner);
+        let current = self.snapshot();
+        let mut scene = current.scene.clone();
+        edit(&mut scene);
+        let prepared = Arc::new(PreparedScene::derive(scene, Some(&current))?);
+        self.publish(Arc::clone(&prepared));
+        Ok(prepared)
+    }
+
+    /// Replace the scene, e.g. once a background loader has prepared a new
+    /// file
+    pub fn replace(&self, prepared: PreparedScene) {
+        let _edits = self.edits.lock().unwrap_or_else(PoisonError::into_inner);
+        self.publish(Arc::new(prepared));
+    }
+
+    fn publish(&self, prepared: Arc<PreparedScene>) {
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = prepared;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::{Direction, Position};
+    use crate::render::color::Color;
+    use crate::render::config::{CameraConfig, MaterialConfig};
+    use crate::render::ray_tracer;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn snapshots_outlive_updates() {
+        assert_send_sync::<Mesh>();
+        assert_send_sync::<KdTree>();
+        assert_send_sync::<Scene>();
+        assert_send_sync::<SharedScene>();
+
+        let triangle = Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(0.0, 0.0, 0.0),
+                Position::new(1.0, 0.0, 0.0),
+                Position::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let scene = Scene::builder().add_mesh(triangle).build();
+        let shared = SharedScene::new(PreparedScene::new(scene).unwrap());
+        let before = shared.snapshot();
+
+        let editor = shared.clone();
+        thread::spawn(move || {
+            editor
+                .update(|scene| {
+                    scene.objects[0].transform = Transform {
+                        scale: 2.0,
+                        ..Default::default()
+                    };
+                })
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+
+        let after = shared.snapshot();
+        assert_eq!(before.mesh().vertices[1], Position::new(1.0, 0.0, 0.0));
+        assert_eq!(after.mesh().vertices[1], Position::new(2.0, 0.0, 0.0));
+        // The model itself is shared between the versions
+        let mesh = |prepared: &PreparedScene| prepared.scene.objects[0].mesh.clone().unwrap();
+        assert!(Arc::ptr_eq(&mesh(&before), &mesh(&after)));
+
+        shared.update(|scene| scene.objects.clear()).unwrap();
+        assert!(shared.snapshot().mesh().triangles.is_empty());
+    }
+
+    #[test]
+    fn updates_reuse_what_did_not_change() {
+        let triangle = || {
+            Mesh::from_vertices_and_triangles(
+                vec![
+                    Position::new(0.0, 0.0, 0.0),
+                    Position::new(1.0, 0.0, 0.0),
+                    Position::new(0.0, 1.0, 0.0),
+                ],
+                vec![[0, 1, 2]],
+            )
+        };
+        let scene = Scene::builder()
+            .add_mesh(triangle())
+            .add_mesh(triangle())
+            .build();
+        let shared = SharedScene::new(PreparedScene::new(scene).unwrap());
+        let first = shared.snapshot();
+
+        let recolored = shared
+            .update(|scene| {
+                scene.objects[1].material = MaterialConfig {
+                    color: Color::new(1.0, 0.0, 0.0),
+                    ..Default::default()
+                }
+            })
+            .unwrap();
+        assert!(Arc::ptr_eq(first.kdtree(), recolored.kdtree()));
+
+        let moved = shared
+            .update(|scene| scene.objects[1].transform.translation.x = 5.0)
+            .unwrap();
+        assert!(!Arc::ptr_eq(recolored.kdtree(), moved.kdtree()));
+        let world = |prepared: &PreparedScene, i: usize| prepared.objects[i].world.clone().unwrap();
+        assert!(Arc::ptr_eq(&world(&recolored, 0), &world(&moved, 0)));
+        assert!(!Arc::ptr_eq(&world(&recolored, 1), &world(&moved, 1)));
+        assert_eq!(moved.mesh().vertices[4], Position::new(6.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn shared_models_are_rendered_as_instances() {
+        let quad = Arc::new(Mesh::from_vertices_and_triangles(
+            vec![
+                Position::new(-1.0, -1.0, 0.0),
+                Position::new(1.0, -1.0, 0.0),
+                Position::new(1.0, 1.0, 0.0),
+                Position::new(-1.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        ));
+        let placed = |x: f64| Transform {
+            translation: Direction::new(x, 0.0, 0.0),
+            ..Default::default()
+        };
+        let scene = |second: Arc<Mesh>| {
+            Scene::builder()
+                .add_mesh(Arc::clone(&quad))
+                .transform(placed(-1.5))
+                .add_mesh(second)
+                .transform(placed(1.5))
+                .camera(CameraConfig::look_at(
+                    Position::new(0.0, 0.0, 6.0),
+                    Position::origin(),
+                    Direction::y(),
+                    60.0,
+                    16,
+                    16,
+                ))
+                .build()
+        };
+        let copy = Mesh::from_vertices_and_triangles(quad.vertices.clone(), quad.triangles.clone());
+        let instanced = PreparedScene::new(scene(Arc::clone(&quad))).unwrap();
+        let merged = PreparedScene::new(scene(Arc::new(copy))).unwrap();
+        assert!(merged.instances.is_none());
+        let instances = instanced.instances.as_ref().unwrap();
+        assert_eq!(instances.prototypes.len(), 1);
+        assert_eq!(instances.instances.len(), 2);
+        assert_eq!(instanced.triangle_count(), 4);
+        // Merged all the same when asked for
+        assert_eq!(instanced.mesh().vertices.len(), 8);
+
+        let render = |p: &PreparedScene| ray_tracer::render(p, &p.scene.camera, &p.scene.rendering);
+        let (a, b) = (render(&instanced), render(&merged));
+        let differing = a
+            .pixels()
+            .zip(b.pixels())
+            .filter(|(a, b)| a.0.iter().zip(&b.0).any(|(a, b)| (a - b).abs() > 1e-4))
+            .count();
+        assert!(differing <= 2, "{} pixels differ", differing);
+
+        // Edits which do not touch the objects keep their instances
+        let shared = SharedScene::new(instanced);
+        let before = shared.snapshot();
+        let after = shared.update(|scene| scene.camera.width = 8).unwrap();
+        let instances = |p: &PreparedScene| Arc::clone(p.instances.as_ref().unwrap());
+        assert!(Arc::ptr_eq(&instances(&before), &instances(&after)));
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::render::image::RgbImage;
+
+/// Layout of the frames written to the encoder
+///
+/// * `Raw`: frames are written back to back as packed 8-bit RGB rows, top row
+///   first, without any header. The encoder must be told the frame size, e.g.
+///   `ffmpeg -f rawvideo -pix_fmt rgb24 -s 300x300 -r 25 -i - out.mp4`
+/// * `Ppm`: each frame is preceded by a binary PPM header
+///   (`P6\n<width> <height>\n255\n`), e.g.
+///   `ffmpeg -f image2pipe -c:v ppm -r 25 -i - out.mp4`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameFormat {
+    Raw,
+    Ppm,
+}
+
+/// Streams rendered frames to an external encoder through stdout or a
+/// named pipe, so animations do not need to be written as image sequences.
+pub struct FramePipe {
+    writer: Box<dyn Write>,
+    format: FrameFormat,
+    dimensions: Option<(u32, u32)>,
+}
+
+impl FramePipe {
+    pub fn stdout(format: FrameFormat) -> FramePipe {
+        FramePipe::new(Box::new(io::stdout()), format)
+    }
+
+    /// Open a named pipe (or a regular file) for writing, `-` being stdout
+    pub fn open(path: &Path, format: FrameFormat) -> io::Result<FramePipe> {
+        if path == Path::new("-") {
+            return Ok(FramePipe::stdout(format));
+        }
+        let file = File::create(path)?;
+        Ok(FramePipe::new(Box::new(file), format))
+    }
+
+    pub fn new(writer: Box<dyn Write>, format: FrameFormat) -> FramePipe {
+        FramePipe {
+            writer: Box::new(BufWriter::new(writer)),
+            format,
+            dimensions: None,
+        }
+    }
+
+    /// Write a frame, all the frames of a stream must have the same size
+    pub fn write_frame(&mut self, img: &RgbImage) -> io::Result<()> {
+        match self.dimensions {
+            None => self.dimensions = Some(img.dimensions()),
+            Some(dimensions) if dimensions != img.dimensions() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "frame is {:?} but the stream was started with {:?}",
+                        img.dimensions(),
+                        dimensions
+                    ),
+                ));
+            }
+            Some(_) => {}
+        }
+        if self.format == FrameFormat::Ppm {
+            write!(self.writer, "P6\n{} {}\n255\n", img.width(), img.height())?;
+        }
+        self.writer.write_all(img.as_raw())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Writer keeping what the pipe wrote readable by the test
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(value: u8) -> RgbImage {
+        RgbImage::from_fn(2, 1, |x, _| image::Rgb([value, x as u8, 7]))
+    }
+
+    #[test]
+    fn ppm_frames_have_a_header_each() {
+        let output = Shared::default();
+        let mut pipe = FramePipe::new(Box::new(output.clone()), FrameFormat::Ppm);
+        pipe.write_frame(&frame(1)).unwrap();
+        pipe.write_frame(&frame(2)).unwrap();
+        pipe.finish().unwrap();
+        let mut expected = b"P6\n2 1\n255\n".to_vec();
+        expected.extend(&[1, 0, 7, 1, 1, 7]);
+        expected.extend(b"P6\n2 1\n255\n");
+        expected.extend(&[2, 0, 7, 2, 1, 7]);
+        assert_eq!(*output.0.borrow(), expected);
+    }
+
+    #[test]
+    fn raw_frames_are_back_to_back_and_keep_their_size() {
+        let output = Shared::default();
+        let mut pipe = FramePipe::new(Box::new(output.clone()), FrameFormat::Raw);
+        pipe.write_frame(&frame(1)).unwrap();
+        pipe.write_frame(&frame(2)).unwrap();
+        let error = pipe.write_frame(&RgbImage::new(1, 2)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        pipe.finish().unwrap();
+        assert_eq!(*output.0.borrow(), vec![1, 0, 7, 1, 1, 7, 2, 0, 7, 2, 1, 7]);
+    }
+}
@@ -0,0 +1,211 @@
+extern crate cairo;
+extern crate gdk;
+extern crate glib;
+extern crate gtk;
+
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::render::image::{difference_heatmap, RgbImage};
+use crate::viewer::image_view::{to_rgba, to_surface};
+
+/// Interval between two swaps in flicker mode
+const FLICKER_INTERVAL_MS: u32 = 500;
+
+/// How the two images are combined
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareMode {
+    /// A on the left of the cursor, B on the right
+    Wipe,
+    /// Alternate between A and B
+    Flicker,
+    /// False color heatmap of the difference
+    Difference,
+}
+
+const MODES: [(CompareMode, &str); 3] = [
+    (CompareMode::Wipe, "Wipe"),
+    (CompareMode::Flicker, "Flicker"),
+    (CompareMode::Difference, "Difference"),
+];
+
+struct CompareState {
+    a: Option<cairo::ImageSurface>,
+    b: Option<cairo::ImageSurface>,
+    difference: Option<cairo::ImageSurface>,
+    size: (f64, f64),
+    mode: CompareMode,
+    /// Horizontal position of the wipe, in screen coordinates
+    wipe_x: Option<f64>,
+    /// In flicker mode, is B displayed
+    showing_b: bool,
+}
+
+/// Side by side evaluation of two renders, e.g. before and after a parameter
+/// change, by wiping or flickering between them or looking at their difference
+///
+/// In wipe mode the split follows the cursor; in flicker mode a click swaps
+/// the images in addition to the automatic swap.
+pub struct CompareView {
+    container: gtk::Box,
+    area: gtk::DrawingArea,
+    state: Rc<RefCell<CompareState>>,
+}
+
+impl CompareView {
+    pub fn new() -> CompareView {
+        let container = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let toolbar = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+        let modes = gtk::ComboBoxText::new();
+        for (_, label) in MODES.iter() {
+            modes.append_text(label);
+        }
+        modes.set_active(Some(0));
+        let status = gtk::Label::new(Some("A | B"));
+        toolbar.pack_start(&modes, false, false, 2);
+        toolbar.pack_start(&status, false, false, 2);
+
+        let area = gtk::DrawingArea::new();
+        area.set_hexpand(true);
+        area.set_vexpand(true);
+        area.add_events(gdk::EventMask::POINTER_MOTION_MASK | gdk::EventMask::BUTTON_PRESS_MASK);
+        container.pack_start(&toolbar, false, false, 2);
+        container.pack_start(&area, true, true, 0);
+
+        let state = Rc::new(RefCell::new(CompareState {
+            a: None,
+            b: None,
+            difference: None,
+            size: (0.0, 0.0),
+            mode: CompareMode::Wipe,
+            wipe_x: None,
+            showing_b: false,
+        }));
+
+        {
+            let state = state.clone();
+            area.connect_draw(move |area, cr| {
+                let state = state.borrow();
+                cr.set_source_rgb(0.2, 0.2, 0.2);
+                cr.paint();
+                let offset = (
+                    ((area.get_allocated_width() as f64 - state.size.0) / 2.0).round(),
+                    ((area.get_allocated_height() as f64 - state.size.1) / 2.0).round(),
+                );
+                let paint = |surface: &Option<cairo::ImageSurface>, clip: Option<(f64, f64)>| {
+                    if let Some(surface) = surface {
+                        cr.save();
+                        if let Some((from, to)) = clip {
+                            cr.rectangle(from, 0.0, to - from, area.get_allocated_height() as f64);
+                            cr.clip();
+                        }
+                        cr.set_source_surface(surface, offset.0, offset.1);
+                        cr.paint();
+                        cr.restore();
+                    }
+                };
+                match state.mode {
+                    CompareMode::Wipe => {
+                        let width = area.get_allocated_width() as f64;
+                        let split = state.wipe_x.unwrap_or(width / 2.0);
+                        paint(&state.a, Some((0.0, split)));
+                        paint(&state.b, Some((split, width)));
+                        cr.set_source_rgb(1.0, 1.0, 1.0);
+                        cr.set_line_width(1.0);
+                        cr.move_to(split.round() + 0.5, 0.0);
+                        cr.line_to(split.round() + 0.5, area.get_allocated_height() as f64);
+                        cr.stroke();
+                    }
+                    CompareMode::Flicker if state.showing_b => paint(&state.b, None),
+                    CompareMode::Flicker => paint(&state.a, None),
+                    CompareMode::Difference => paint(&state.difference, None),
+                }
+                Inhibit(false)
+            });
+        }
+        {
+            let state = state.clone();
+            area.connect_motion_notify_event(move |area, event| {
+                let mut state = state.borrow_mut();
+                if state.mode == CompareMode::Wipe {
+                    state.wipe_x = Some(event.get_position().0);
+                    area.queue_draw();
+                }
+                Inhibit(false)
+            });
+        }
+        {
+            let state = state.clone();
+            let status = status.clone();
+            area.connect_button_press_event(move |area, _| {
+                let mut state = state.borrow_mut();
+                if state.mode == CompareMode::Flicker {
+                    state.showing_b = !state.showing_b;
+                    status.set_text(if state.showing_b { "B" } else { "A" });
+                    area.queue_draw();
+                }
+                Inhibit(false)
+            });
+        }
+        {
+            let state = state.clone();
+            let area = area.clone();
+            let status = status.clone();
+            modes.connect_changed(move |modes| {
+                let mode = MODES[modes.get_active().unwrap_or(0) as usize].0;
+                state.borrow_mut().mode = mode;
+                status.set_text(match mode {
+                    CompareMode::Wipe => "A | B",
+                    CompareMode::Flicker => "A",
+                    CompareMode::Difference => "|A - B|",
+                });
+                area.queue_draw();
+            });
+        }
+        {
+            let state = state.clone();
+            let area = area.clone();
+            gtk::timeout_add(FLICKER_INTERVAL_MS, move || {
+                let mut state = state.borrow_mut();
+                if state.mode == CompareMode::Flicker {
+                    state.showing_b = !state.showing_b;
+                    status.set_text(if state.showing_b { "B" } else { "A" });
+                    area.queue_draw();
+                }
+                glib::Continue(true)
+            });
+        }
+
+        CompareView {
+            container,
+            area,
+            state,
+        }
+    }
+
+    /// The widget to add to a window or a container
+    pub fn widget(&self) -> &gtk::Box {
+        &self.container
+    }
+
+    /// Set the two images to compare, the difference is only available when
+    /// they have the same dimensions
+    pub fn set_images(&self, a: &RgbImage, b: &RgbImage) {
+        let mut state = self.state.borrow_mut();
+        state.a = to_surface(&to_rgba(a));
+        state.b = to_surface(&to_rgba(b));
+        state.difference = difference_heatmap(a, b).and_then(|d| to_surface(&to_rgba(&d)));
+        state.size = (
+            a.width().max(b.width()) as f64,
+            a.height().max(b.height()) as f64,
+        );
+        self.area.queue_draw();
+    }
+}
+
+impl Default for CompareView {
+    fn default() -> Self {
+        CompareView::new()
+    }
+}
@@ -0,0 +1,730 @@
+extern crate cairo;
+extern crate gdk;
+extern crate glib;
+extern crate gtk;
+extern crate image;
+
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+use self::image::{Rgba, RgbaImage};
+use crate::geometry::types::Position;
+use crate::render::config::CameraConfig;
+use crate::render::handle::RenderHandle;
+use crate::render::image::{project, RgbImage};
+use crate::render::preview::{render_preview, AdaptiveResolution, InterleavedPreview, PixelMask};
+use crate::render::ray_tracer::{make_prepared_ray_tracer, make_prepared_sample_tracer};
+use crate::render::shared::PreparedScene;
+use crate::viewer::stats::{self, FrameRate, RenderStats};
+
+/// Zoom levels are powers of two, so that every image pixel covers a whole
+/// number of screen pixels when zoomed in
+const MIN_ZOOM: f64 = 1.0 / 16.0;
+const MAX_ZOOM: f64 = 64.0;
+
+/// Interval between two looks at the progress of a watched render
+const STATS_INTERVAL_MS: u32 = 250;
+
+/// Interval between two frames of a live preview, when they render faster
+const PREVIEW_INTERVAL_MS: u32 = 10;
+
+/// Angle the camera of a live preview turns by per key press
+const TURN_DEGREES: f64 = 5.0;
+
+struct ViewState {
+    image: Option<RgbaImage>,
+    surface: Option<cairo::ImageSurface>,
+    zoom: f64,
+    /// Screen position of the top left corner of the image
+    offset: (f64, f64),
+    /// Last pointer position while panning
+    drag: Option<(f64, f64)>,
+    /// Camera the image was rendered from, for the overlays
+    camera: Option<CameraConfig>,
+    overlays: Vec<Box<dyn Fn(&Overlay)>>,
+    /// Whether the heads-up display of the statistics is shown
+    stats_visible: bool,
+    frame_rate: FrameRate,
+    /// Render watched by the view, see `ImageView::watch_render`
+    render: Option<RenderStats>,
+}
+
+impl ViewState {
+    /// Image pixel under the given screen position
+    fn pixel_at(&self, position: (f64, f64)) -> Option<(u32, u32)> {
+        let image = self.image.as_ref()?;
+        let x = ((position.0 - self.offset.0) / self.zoom).floor();
+        let y = ((position.1 - self.offset.1) / self.zoom).floor();
+        if x < 0.0 || y < 0.0 || x >= image.width() as f64 || y >= image.height() as f64 {
+            return None;
+        }
+        Some((x as u32, y as u32))
+    }
+}
+
+/// Drawing surface handed to the overlays of an `ImageView` at every redraw
+///
+/// The cairo context is in image pixels, the origin at the top left corner
+/// of the image and the lines one screen pixel wide, so that the overlays
+/// follow the zoom and the panning.
+pub struct Overlay<'a> {
+    pub cr: &'a cairo::Context,
+    /// Camera of the image, see `ImageView::set_camera`
+    pub camera: Option<&'a CameraConfig>,
+    /// Screen pixels per image pixel
+    pub zoom: f64,
+}
+
+impl<'a> Overlay<'a> {
+    /// Position of `point` in the image, `None` without camera or when the
+    /// camera does not see it
+    pub fn project(&self, point: &Position) -> Option<(f64, f64)> {
+        image_position(point, self.camera?)
+    }
+
+    /// Stroke the segments whose ends the camera sees, e.g. the frustums of
+    /// sensors, in the current color
+    pub fn lines(&self, segments: &[(Position, Position)]) {
+        for (a, b) in segments {
+            if let (Some(a), Some(b)) = (self.project(a), self.project(b)) {
+                self.cr.move_to(a.0, a.1);
+                self.cr.line_to(b.0, b.1);
+            }
+        }
+        self.cr.stroke();
+    }
+
+    /// Write `text` next to `point`, at the same size whatever the zoom
+    pub fn label(&self, point: &Position, text: &str) {
+        if let Some((x, y)) = self.project(point) {
+            self.cr.save();
+            self.cr.translate(x, y);
+            self.cr.scale(1.0 / self.zoom, 1.0 / self.zoom);
+            self.cr.move_to(4.0, -4.0);
+            self.cr.show_text(text);
+            self.cr.restore();
+        }
+    }
+}
+
+/// Position in the image of the render of `camera` where `point` is seen,
+/// in pixels from the top left corner of the image, the center of the top
+/// left pixel being (0.5, 0.5)
+fn image_position(point: &Position, camera: &CameraConfig) -> Option<(f64, f64)> {
+    let (i, j) = project(point, camera)?;
+    Some((i + 0.5, camera.height as f64 - 0.5 - j))
+}
+
+/// Move of the camera of a live preview, see `ImageView::preview`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CameraMove {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+    TurnLeft,
+    TurnRight,
+    TurnUp,
+    TurnDown,
+}
+
+impl CameraMove {
+    fn from_key(key: gdk::enums::key::Key) -> Option<CameraMove> {
+        use self::gdk::enums::key;
+        match key {
+            key::w => Some(CameraMove::Forward),
+            key::s => Some(CameraMove::Backward),
+            key::a => Some(CameraMove::Left),
+            key::d => Some(CameraMove::Right),
+            key::r => Some(CameraMove::Up),
+            key::f => Some(CameraMove::Down),
+            key::Left => Some(CameraMove::TurnLeft),
+            key::Right => Some(CameraMove::TurnRight),
+            key::Up => Some(CameraMove::TurnUp),
+            key::Down => Some(CameraMove::TurnDown),
+            _ => None,
+        }
+    }
+
+    /// `camera` moved by `step` along its axes, or turned by `TURN_DEGREES`
+    fn apply(self, camera: &CameraConfig, step: f64) -> CameraConfig {
+        let (sin, cos) = TURN_DEGREES.to_radians().sin_cos();
+        let mut moved = camera.clone();
+        match self {
+            CameraMove::Forward => moved.camera_position += step * camera.z,
+            CameraMove::Backward => moved.camera_position -= step * camera.z,
+            CameraMove::Left => moved.camera_position -= step * camera.x,
+            CameraMove::Right => moved.camera_position += step * camera.x,
+            CameraMove::Up => moved.camera_position += step * camera.y,
+            CameraMove::Down => moved.camera_position -= step * camera.y,
+            // Rotations in the plane of two axes, whatever their handedness
+            CameraMove::TurnLeft | CameraMove::TurnRight => {
+                let sin = if self == CameraMove::TurnLeft {
+                    -sin
+                } else {
+                    sin
+                };
+                moved.z = cos * camera.z + sin * camera.x;
+                moved.x = cos * camera.x - sin * camera.z;
+            }
+            CameraMove::TurnUp | CameraMove::TurnDown => {
+                let sin = if self == CameraMove::TurnDown {
+                    -sin
+                } else {
+                    sin
+                };
+                moved.z = cos * camera.z + sin * camera.y;
+                moved.y = cos * camera.y - sin * camera.z;
+            }
+        }
+        moved
+    }
+}
+
+/// State of the live preview of `ImageView::preview`
+struct LivePreview {
+    scene: Arc<PreparedScene>,
+    camera: CameraConfig,
+    step: f64,
+    mode: PreviewMode,
+    /// The camera moved since the last frame
+    moved: bool,
+}
+
+/// How a live preview renders its frames
+enum PreviewMode {
+    /// Every pixel, at a resolution lowered while the camera moves
+    Adaptive {
+        resolution: AdaptiveResolution,
+        /// The last frame shows the camera at full resolution
+        refined: bool,
+    },
+    /// The pixels of a mask at every frame, see `InterleavedPreview`
+    Interleaved {
+        preview: InterleavedPreview,
+        /// Frames since the camera last moved
+        frames: u32,
+        /// Frames giving every pixel all its samples
+        converged: u32,
+    },
+}
+
+impl LivePreview {
+    /// Render the next frame, `None` once the image is refined
+    fn frame(&mut self) -> Option<RgbImage> {
+        let moving = std::mem::replace(&mut self.moved, false);
+        let rendering = &self.scene.scene.rendering;
+        match &mut self.mode {
+            PreviewMode::Adaptive {
+                resolution,
+                refined,
+            } => {
+                if *refined && !moving {
+                    return None;
+                }
+                let scale = resolution.scale();
+                let start = Instant::now();
+                let image = render_preview(
+                    make_prepared_ray_tracer(&self.scene, &self.camera, rendering),
+                    &self.camera,
+                    rendering,
+                    scale,
+                );
+                resolution.update(start.elapsed(), moving);
+                *refined = scale == 1 && !moving;
+                Some(image)
+            }
+            PreviewMode::Interleaved {
+                preview,
+                frames,
+                converged,
+            } => {
+                if moving {
+                    preview.set_camera(&self.camera);
+                    *frames = 0;
+                }
+                if *frames >= *converged {
+                    return None;
+                }
+                preview.render_frame(
+                    make_prepared_sample_tracer(&self.scene, &self.camera, rendering),
+                    rendering,
+                );
+                *frames += 1;
+                Some(preview.image(rendering))
+            }
+        }
+    }
+}
+
+/// Image display supporting pixel-perfect zoom and panning, with a readout
+/// of the value of the pixel under the cursor
+///
+/// * scroll: zoom in or out around the cursor
+/// * left drag: pan
+/// * right click: back to 1:1, centered
+/// * i: show or hide the statistics of the view, its render and its camera
+///
+/// `preview` turns it into a live preview of a scene whose camera moves with
+/// the keys.
+///
+/// Overlays, e.g. the positions of sensors placed by another tool, are drawn
+/// over the image with `add_overlay`.
+pub struct ImageView {
+    container: gtk::Box,
+    area: gtk::DrawingArea,
+    state: Rc<RefCell<ViewState>>,
+}
+
+impl ImageView {
+    pub fn new() -> ImageView {
+        let container = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let area = gtk::DrawingArea::new();
+        let readout = gtk::Label::new(None);
+        readout.set_xalign(0.0);
+        area.set_hexpand(true);
+        area.set_vexpand(true);
+        area.add_events(
+            gdk::EventMask::POINTER_MOTION_MASK
+                | gdk::EventMask::BUTTON_PRESS_MASK
+                | gdk::EventMask::BUTTON_RELEASE_MASK
+                | gdk::EventMask::SCROLL_MASK
+                | gdk::EventMask::KEY_PRESS_MASK,
+        );
+        area.set_can_focus(true);
+        container.pack_start(&area, true, true, 0);
+        container.pack_start(&readout, false, false, 2);
+
+        let state = Rc::new(RefCell::new(ViewState {
+            image: None,
+            surface: None,
+            zoom: 1.0,
+            offset: (0.0, 0.0),
+            drag: None,
+            camera: None,
+            overlays: Vec::new(),
+            stats_visible: false,
+            frame_rate: FrameRate::default(),
+            render: None,
+        }));
+
+        {
+            let state = state.clone();
+            area.connect_draw(move |_, cr| {
+                let mut state = state.borrow_mut();
+                state.frame_rate.tick(Instant::now());
+                cr.set_source_rgb(0.2, 0.2, 0.2);
+                cr.paint();
+                cr.save();
+                if let Some(ref surface) = state.surface {
+                    if state.image.is_some() {
+                        cr.translate(state.offset.0.round(), state.offset.1.round());
+                        cr.scale(state.zoom, state.zoom);
+                        cr.set_source_surface(surface, 0.0, 0.0);
+                        // No interpolation: each image pixel is a flat square
+                        cr.get_source().set_filter(cairo::Filter::Nearest);
+                        cr.paint();
+                        let overlay = Overlay {
+                            cr,
+                            camera: state.camera.as_ref(),
+                            zoom: state.zoom,
+                        };
+                        for draw in &state.overlays {
+                            cr.save();
+                            cr.set_source_rgb(1.0, 1.0, 0.0);
+                            cr.set_line_width(1.0 / state.zoom);
+                            cr.set_font_size(12.0);
+                            draw(&overlay);
+                            cr.restore();
+                        }
+                    }
+                }
+                cr.restore();
+                if state.stats_visible {
+                    let lines = stats::stats_lines(
+                        state.frame_rate.fps(),
+                        state.render.as_ref(),
+                        stats::resident_memory(),
+                        state.camera.as_ref(),
+                    );
+                    draw_stats(cr, &lines);
+                }
+                Inhibit(false)
+            });
+        }
+        {
+            let state = state.clone();
+            area.connect_scroll_event(move |area, event| {
+                let mut state = state.borrow_mut();
+                let factor = match event.get_direction() {
+                    gdk::ScrollDirection::Up => 2.0,
+                    gdk::ScrollDirection::Down => 0.5,
+                    gdk::ScrollDirection::Smooth if event.get_delta().1 < 0.0 => 2.0,
+                    gdk::ScrollDirection::Smooth if event.get_delta().1 > 0.0 => 0.5,
+                    _ => return Inhibit(false),
+                };
+                let zoom = (state.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+                // Keep the point under the cursor in place
+                let (px, py) = event.get_position();
+                let ratio = zoom / state.zoom;
+                state.offset = (
+                    px - (px - state.offset.0) * ratio,
+                    py - (py - state.offset.1) * ratio,
+                );
+                state.zoom = zoom;
+                area.queue_draw();
+                Inhibit(true)
+            });
+        }
+        {
+            let state = state.clone();
+            area.connect_button_press_event(move |area, event| {
+                let mut state = state.borrow_mut();
+                // For the keys
+                area.grab_focus();
+                match event.get_button() {
+                    1 => state.drag = Some(event.get_position()),
+                    3 => {
+                        state.zoom = 1.0;
+                        state.offset = centered_offset(area, state.image.as_ref(), 1.0);
+                        area.queue_draw();
+                    }
+                    _ => return Inhibit(false),
+                }
+                Inhibit(true)
+            });
+        }
+        {
+            let state = state.clone();
+            area.connect_key_press_event(move |area, event| match event.get_keyval() {
+                gdk::enums::key::i => {
+                    let mut state = state.borrow_mut();
+                    state.stats_visible = !state.stats_visible;
+                    area.queue_draw();
+                    Inhibit(true)
+                }
+                _ => Inhibit(false),
+            });
+        }
+        {
+            let state = state.clone();
+            area.connect_button_release_event(move |_, _| {
+                state.borrow_mut().drag = None;
+                Inhibit(false)
+            });
+        }
+        {
+            let state = state.clone();
+            area.connect_motion_notify_event(move |area, event| {
+                let mut state = state.borrow_mut();
+                let position = event.get_position();
+                if let Some(last) = state.drag {
+                    state.offset.0 += position.0 - last.0;
+                    state.offset.1 += position.1 - last.1;
+                    state.drag = Some(position);
+                    area.queue_draw();
+                }
+                let text = match (state.pixel_at(position), state.image.as_ref()) {
+                    (Some((x, y)), Some(image)) => {
+                        let p = image.get_pixel(x, y);
+                        format!(
+                            "({}, {})  R {}  G {}  B {}  A {}  zoom {}",
+                            x,
+                            y,
+                            p[0],
+                            p[1],
+                            p[2],
+                            p[3],
+                            zoom_label(state.zoom)
+                        )
+                    }
+                    _ => format!("zoom {}", zoom_label(state.zoom)),
+                };
+                readout.set_text(&text);
+                Inhibit(false)
+            });
+        }
+
+        ImageView {
+            container,
+            area,
+            state,
+        }
+    }
+
+    /// The widget to add to a window or a container
+    pub fn widget(&self) -> &gtk::Box {
+        &self.container
+    }
+
+    pub fn set_image(&self, image: &RgbImage) {
+        self.set_rgba_image(to_rgba(image));
+    }
+
+    /// Display a new image, keeping the current zoom and position so that
+    /// successive renders can be compared in place
+    pub fn set_rgba_image(&self, image: RgbaImage) {
+        let mut state = self.state.borrow_mut();
+        state.surface = to_surface(&image);
+        state.image = Some(image);
+        self.area.queue_draw();
+    }
+
+    /// Camera the image was rendered from, for the overlays to place what
+    /// they draw
+    pub fn set_camera(&self, camera: &CameraConfig) {
+        self.state.borrow_mut().camera = Some(camera.clone());
+        self.area.queue_draw();
+    }
+
+    /// Draw `overlay` over the image at every redraw, after the overlays
+    /// added before, in yellow unless it picks another color
+    pub fn add_overlay<F>(&self, overlay: F)
+    where
+        F: Fn(&Overlay) + 'static,
+    {
+        self.state.borrow_mut().overlays.push(Box::new(overlay));
+        self.area.queue_draw();
+    }
+
+    /// Show or hide the statistics, as the i key does
+    pub fn set_stats_visible(&self, visible: bool) {
+        self.state.borrow_mut().stats_visible = visible;
+        self.area.queue_draw();
+    }
+
+    /// Follow the progress of the render of `handle` in the statistics, until
+    /// it is finished or cancelled
+    pub fn watch_render(&self, handle: RenderHandle, samples_per_pixel: u32) {
+        let state = self.state.clone();
+        let area = self.area.clone();
+        gtk::timeout_add(STATS_INTERVAL_MS, move || {
+            let progress = handle.progress();
+            let mut state = state.borrow_mut();
+            state.render = Some(RenderStats {
+                progress,
+                samples_per_pixel,
+            });
+            if state.stats_visible {
+                area.queue_draw();
+            }
+            let finished =
+                progress.total_tiles > 0 && progress.finished_tiles == progress.total_tiles;
+            glib::Continue(!finished && !handle.is_cancelled())
+        });
+    }
+
+    /// Render `scene` live from its camera, which the keys move
+    ///
+    /// * w, s: forward, backward
+    /// * a, d: left, right
+    /// * r, f: up, down
+    /// * arrows: turn
+    ///
+    /// The camera moves by `step` per key press. Without `mask`, the
+    /// resolution drops to keep `MIN_PREVIEW_FPS` while it moves; once it
+    /// stops, the image refines up to the full resolution. With a mask, every
+    /// frame traces the pixels of the mask at full resolution, the others
+    /// following the camera from the previous frames, until every pixel has
+    /// the samples of the scene.
+    pub fn preview(&self, scene: Arc<PreparedScene>, step: f64, mask: Option<PixelMask>) {
+        let camera = scene.scene.camera.clone();
+        let mode = match mask {
+            Some(mask) => PreviewMode::Interleaved {
+                preview: InterleavedPreview::new(&camera, mask),
+                frames: 0,
+                converged: mask.phases() * scene.scene.rendering.samples_per_pixel.max(1),
+            },
+            None => PreviewMode::Adaptive {
+                resolution: AdaptiveResolution::default(),
+                refined: false,
+            },
+        };
+        let preview = Rc::new(RefCell::new(LivePreview {
+            scene,
+            camera,
+            step,
+            mode,
+            moved: false,
+        }));
+        {
+            let preview = preview.clone();
+            self.area.connect_key_press_event(move |_, event| {
+                match CameraMove::from_key(event.get_keyval()) {
+                    Some(camera_move) => {
+                        let mut preview = preview.borrow_mut();
+                        preview.camera = camera_move.apply(&preview.camera, preview.step);
+                        preview.moved = true;
+                        Inhibit(true)
+                    }
+                    None => Inhibit(false),
+                }
+            });
+        }
+        // For the keys
+        self.area.grab_focus();
+        let state = self.state.clone();
+        let area = self.area.clone();
+        gtk::timeout_add(PREVIEW_INTERVAL_MS, move || {
+            let mut preview = preview.borrow_mut();
+            if let Some(image) = preview.frame() {
+                let mut state = state.borrow_mut();
+                let image = to_rgba(&image);
+                state.surface = to_surface(&image);
+                state.image = Some(image);
+                state.camera = Some(preview.camera.clone());
+                area.queue_draw();
+            }
+            glib::Continue(true)
+        });
+    }
+}
+
+/// Write the lines of the statistics in the top left corner of the view,
+/// white on a dark box, whatever the zoom
+fn draw_stats(cr: &cairo::Context, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    cr.save();
+    cr.set_font_size(12.0);
+    let line_height = 16.0;
+    let width = lines
+        .iter()
+        .map(|line| cr.text_extents(line).x_advance)
+        .fold(0.0, f64::max);
+    cr.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+    cr.rectangle(
+        4.0,
+        4.0,
+        width + 12.0,
+        lines.len() as f64 * line_height + 8.0,
+    );
+    cr.fill();
+    cr.set_source_rgb(1.0, 1.0, 1.0);
+    for (i, line) in lines.iter().enumerate() {
+        cr.move_to(10.0, 4.0 + (i + 1) as f64 * line_height);
+        cr.show_text(line);
+    }
+    cr.restore();
+}
+
+impl Default for ImageView {
+    fn default() -> Self {
+        ImageView::new()
+    }
+}
+
+fn zoom_label(zoom: f64) -> String {
+    if zoom >= 1.0 {
+        format!("{}:1", zoom)
+    } else {
+        format!("1:{}", 1.0 / zoom)
+    }
+}
+
+fn centered_offset(area: &gtk::DrawingArea, image: Option<&RgbaImage>, zoom: f64) -> (f64, f64) {
+    match image {
+        Some(image) => (
+            ((area.get_allocated_width() as f64 - image.width() as f64 * zoom) / 2.0).round(),
+            ((area.get_allocated_height() as f64 - image.height() as f64 * zoom) / 2.0).round(),
+        ),
+        None => (0.0, 0.0),
+    }
+}
+
+pub(crate) fn to_rgba(image: &RgbImage) -> RgbaImage {
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let p = image.get_pixel(x, y);
+        Rgba([p[0], p[1], p[2], 255])
+    })
+}
+
+/// Copy the image into a cairo surface (premultiplied, native endian ARGB)
+pub(crate) fn to_surface(image: &RgbaImage) -> Option<cairo::ImageSurface> {
+    let mut surface = cairo::ImageSurface::create(
+        cairo::Format::ARgb32,
+        image.width() as i32,
+        image.height() as i32,
+    )
+    .ok()?;
+    let stride = surface.get_stride() as usize;
+    {
+        let mut data = surface.get_data().ok()?;
+        for (x, y, p) in image.enumerate_pixels() {
+            let a = p[3] as u32;
+            let premultiply = |c: u8| ((c as u32 * a + 127) / 255) as u8;
+            let argb = u32::from_be_bytes([
+                p[3],
+                premultiply(p[0]),
+                premultiply(p[1]),
+                premultiply(p[2]),
+            ]);
+            let offset = y as usize * stride + x as usize * 4;
+            data[offset..offset + 4].copy_from_slice(&argb.to_ne_bytes());
+        }
+    }
+    Some(surface)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::types::Direction;
+
+    #[test]
+    fn overlays_land_on_the_pixels_of_the_points() {
+        let camera = CameraConfig::look_at(
+            Position::new(0.0, 0.0, -10.0),
+            Position::new(0.0, 0.0, 0.0),
+            Direction::y(),
+            60.0,
+            40,
+            30,
+        );
+        // The axis goes through the middle of the image
+        let (x, y) = image_position(&Position::new(0.0, 0.0, 5.0), &camera).unwrap();
+        assert!((x - 20.0).abs() < 1e-9 && (y - 15.0).abs() < 1e-9);
+        // Up in the scene is up in the image
+        let (_, y) = image_position(&Position::new(0.0, 1.0, 0.0), &camera).unwrap();
+        assert!(y < 15.0);
+        assert!(image_position(&Position::new(0.0, 0.0, -20.0), &camera).is_none());
+    }
+
+    #[test]
+    fn camera_moves_follow_the_camera_axes() {
+        let camera = CameraConfig::look_at(
+            Position::new(0.0, 0.0, -10.0),
+            Position::new(0.0, 0.0, 0.0),
+            Direction::y(),
+            60.0,
+            40,
+            30,
+        );
+        let forward = CameraMove::Forward.apply(&camera, 2.0);
+        assert!((forward.camera_position - Position::new(0.0, 0.0, -8.0)).norm() < 1e-9);
+        let back = CameraMove::Backward.apply(&forward, 2.0);
+        assert!((back.camera_position - camera.camera_position).norm() < 1e-9);
+
+        // Turning keeps the axes orthonormal and the center of the image
+        // moves the other way
+        let turned = CameraMove::TurnRight.apply(&camera, 2.0);
+        assert_eq!(turned.camera_position, camera.camera_position);
+        assert!((turned.z.norm() - 1.0).abs() < 1e-9 && turned.z.dot(&turned.x).abs() < 1e-9);
+        let (x, _) = image_position(&Position::new(0.0, 0.0, 0.0), &turned).unwrap();
+        assert!(x < 20.0);
+        let (_, y) = image_position(
+            &Position::new(0.0, 0.0, 0.0),
+            &CameraMove::TurnUp.apply(&camera, 2.0),
+        )
+        .unwrap();
+        assert!(y > 15.0);
+        let left = CameraMove::TurnLeft.apply(&turned, 2.0);
+        assert!((left.z - camera.z).norm() < 1e-9 && (left.x - camera.x).norm() < 1e-9);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod compare_view;
+pub mod image_view;
+pub mod stats;
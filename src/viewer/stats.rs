@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::render::config::CameraConfig;
+use crate::render::handle::Progress;
+
+/// Frames per second of a view, from the times of its last redraws
+#[derive(Clone, Debug, Default)]
+pub struct FrameRate {
+    /// Redraws of the last second, oldest first
+    frames: VecDeque<Instant>,
+}
+
+impl FrameRate {
+    /// Record a redraw at `now`
+    pub fn tick(&mut self, now: Instant) {
+        while let Some(&oldest) = self.frames.front() {
+            if now.duration_since(oldest) < Duration::from_secs(1) {
+                break;
+            }
+            self.frames.pop_front();
+        }
+        self.frames.push_back(now);
+    }
+
+    /// Redraws per second over the last second, `None` before two redraws
+    pub fn fps(&self) -> Option<f64> {
+        let (first, last) = (self.frames.front()?, self.frames.back()?);
+        let span = last.duration_since(*first).as_secs_f64();
+        if self.frames.len() < 2 || span == 0.0 {
+            return None;
+        }
+        Some((self.frames.len() - 1) as f64 / span)
+    }
+}
+
+/// State of the render shown by a view, see `ImageView::watch_render`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderStats {
+    pub progress: Progress,
+    pub samples_per_pixel: u32,
+}
+
+impl RenderStats {
+    /// Camera rays traced per second so far, `None` before the first tile
+    pub fn rays_per_second(&self) -> Option<f64> {
+        let seconds = self.progress.elapsed.as_secs_f64();
+        if self.progress.rays == 0 || seconds == 0.0 {
+            return None;
+        }
+        Some(self.progress.rays as f64 / seconds)
+    }
+}
+
+/// Memory used by the process, in bytes, `None` where `/proc` does not tell
+pub fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Lines of the heads-up display of a view, the figures missing left out
+pub fn stats_lines(
+    fps: Option<f64>,
+    render: Option<&RenderStats>,
+    memory: Option<u64>,
+    camera: Option<&CameraConfig>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(fps) = fps {
+        lines.push(format!("view: {:.1} fps", fps));
+    }
+    if let Some(render) = render {
+        let progress = &render.progress;
+        lines.push(format!(
+            "render: {:.0}% of {} tiles, {} spp, {:.1?}",
+            progress.percent(),
+            progress.total_tiles,
+            render.samples_per_pixel,
+            progress.elapsed
+        ));
+        if let Some(rays) = render.rays_per_second() {
+            lines.push(format!("rays: {:.2} M/s", rays / 1e6));
+        }
+    }
+    if let Some(memory) = memory {
+        lines.push(format!(
+            "memory: {:.0} MiB",
+            memory as f64 / (1024.0 * 1024.0)
+        ));
+    }
+    if let Some(camera) = camera {
+        let (p, z) = (&camera.camera_position, &camera.z);
+        lines.push(format!(
+            "camera: ({:.2}, {:.2}, {:.2}) toward ({:.2}, {:.2}, {:.2})",
+            p.x, p.y, p.z, z.x, z.y, z.z
+        ));
+        lines.push(format!(
+            "fov {}°, {}x{}, {}",
+            camera.fov, camera.width, camera.height, camera.projection
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_rates_count_the_last_second() {
+        let start = Instant::now();
+        let mut frame_rate = FrameRate::default();
+        frame_rate.tick(start);
+        assert_eq!(frame_rate.fps(), None);
+        for i in 1..=60 {
+            frame_rate.tick(start + Duration::from_millis(i * 20));
+        }
+        assert!((frame_rate.fps().unwrap() - 50.0).abs() < 1e-6);
+        // After a pause, only the new frames count
+        frame_rate.tick(start + Duration::from_secs(5));
+        frame_rate.tick(start + Duration::from_millis(5100));
+        assert!((frame_rate.fps().unwrap() - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stats_leave_out_what_is_unknown() {
+        assert!(stats_lines(None, None, None, None).is_empty());
+        let render = RenderStats {
+            progress: Progress {
+                finished_tiles: 1,
+                total_tiles: 4,
+                rays: 3_000_000,
+                elapsed: Duration::from_secs(2),
+            },
+            samples_per_pixel: 16,
+        };
+        assert_eq!(render.rays_per_second(), Some(1.5e6));
+        let camera = CameraConfig::default();
+        let lines = stats_lines(Some(30.0), Some(&render), Some(64 << 20), Some(&camera));
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "view: 30.0 fps");
+        assert!(lines[1].starts_with("render: 25% of 4 tiles, 16 spp"));
+        assert_eq!(lines[2], "rays: 1.50 M/s");
+        assert_eq!(lines[3], "memory: 64 MiB");
+        if cfg!(target_os = "linux") {
+            assert!(resident_memory().unwrap() > 0);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+extern crate ray_ruster;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ray_ruster::render::color::Color;
+use ray_ruster::render::config::{EnvironmentConfig, MaterialConfig, ShadingModel};
+use ray_ruster::render::environment::HdrImage;
+use ray_ruster::render::image::HdrRgbImage;
+use ray_ruster::render::ray_tracer::render_scene;
+use ray_ruster::render::scene::Scene;
+
+/// Radiance of the environment of the furnace
+const RADIANCE: f64 = 0.5;
+
+fn render_furnace(material: MaterialConfig) -> HdrRgbImage {
+    let mut scene = Scene::white_furnace(material, RADIANCE);
+    scene.camera.width = 24;
+    scene.camera.height = 24;
+    scene.rendering.samples_per_pixel = 4;
+    render_scene(&scene).unwrap()
+}
+
+fn channels(image: &HdrRgbImage) -> impl Iterator<Item = f64> + '_ {
+    image.pixels().flat_map(|p| p.0.iter().map(|&c| c as f64))
+}
+
+fn mean(image: &HdrRgbImage) -> f64 {
+    channels(image).sum::<f64>() / (3 * image.width() * image.height()) as f64
+}
+
+#[test]
+fn white_materials_disappear_in_the_furnace() {
+    for &model in [ShadingModel::Lambert, ShadingModel::Mirror].iter() {
+        let image = render_furnace(MaterialConfig {
+            model,
+            color: Color::WHITE,
+            ..Default::default()
+        });
+        for c in channels(&image) {
+            assert!((c - RADIANCE).abs() < 1e-5, "{}: {}", model, c);
+        }
+    }
+}
+
+#[test]
+fn environments_light_the_furnace_once() {
+    let mut scene = Scene::white_furnace(
+        MaterialConfig {
+            model: ShadingModel::Lambert,
+            color: Color::WHITE,
+            ..Default::default()
+        },
+        RADIANCE,
+    );
+    scene.environment = Some(EnvironmentConfig {
+        path: PathBuf::new(),
+        intensity: 1.0,
+        rotation: 0.0,
+        lights: 256,
+        image: Some(Arc::new(HdrImage {
+            width: 4,
+            height: 2,
+            pixels: vec![Color::gray(RADIANCE); 8],
+        })),
+    });
+    scene.camera.width = 24;
+    scene.camera.height = 24;
+    scene.rendering.samples_per_pixel = 4;
+    let image = render_scene(&scene).unwrap();
+    // The lights standing for the environment only approach its integral
+    for c in channels(&image) {
+        assert!((c - RADIANCE).abs() < 0.05 * RADIANCE, "{}", c);
+    }
+}
+
+#[test]
+fn materials_do_not_create_energy_in_the_furnace() {
+    // Rough metal, losing the light its microfacets shadow
+    let metal = render_furnace(MaterialConfig {
+        model: ShadingModel::Pbr,
+        color: Color::WHITE,
+        metallic: 1.0,
+        roughness: 0.5,
+        ..Default::default()
+    });
+    let average = mean(&metal);
+    assert!(
+        average < RADIANCE && average > 0.5 * RADIANCE,
+        "{}",
+        average
+    );
+
+    // Glass splits the light between reflection and refraction, the paths
+    // cut at the maximum depth are lost
+    let glass = render_furnace(MaterialConfig {
+        model: ShadingModel::Glass,
+        color: Color::WHITE,
+        ..Default::default()
+    });
+    for c in channels(&glass) {
+        assert!(c < RADIANCE * (1.0 + 1e-5), "{}", c);
+    }
+    assert!(mean(&glass) > 0.5 * RADIANCE);
+}